@@ -0,0 +1,72 @@
+//! Lookup speed of the IP database tables.
+//!
+//! Builds a range table of the size of a full IPv4 country table (about 250,000 ranges with gaps
+//! between them) and an AS name table, then looks up pseudo random addresses and AS numbers.
+//! Half of the addresses fall into gaps, as private and unassigned space does.
+//!
+//! Run with `cargo bench --bench ipdb`. Throughput is reported in lookups per second.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use netpulsar_lib::db::range::{CountryCode, NameTable, RangeTable};
+use std::hint::black_box;
+
+const RANGES: u32 = 250_000;
+const AS_NAMES: u32 = 100_000;
+/// Lookups per iteration
+const LOOKUPS: usize = 10_000;
+
+/// Ranges of half the width of their spacing, so every other block of addresses is a gap
+fn country_table() -> RangeTable<u32, CountryCode> {
+    let step = u32::MAX / RANGES;
+    let countries = [CountryCode::new("JP"), CountryCode::new("US"), CountryCode::new("DE")];
+    (0..RANGES)
+        .map(|i| {
+            let start = i * step;
+            (start..=start + step / 2, countries[i as usize % countries.len()])
+        })
+        .collect()
+}
+
+fn as_names() -> NameTable {
+    (0..AS_NAMES).map(|asn| (asn * 3, format!("AS{} Example Networks", asn * 3))).collect()
+}
+
+/// Xorshift so that lookups do not walk the table in order
+fn keys(count: usize, max: u32) -> Vec<u32> {
+    let mut state: u32 = 0x9E37_79B9;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % max
+        })
+        .collect()
+}
+
+fn range_lookup(c: &mut Criterion) {
+    let table = country_table();
+    let addrs = keys(LOOKUPS, u32::MAX);
+    let names = as_names();
+    let asns = keys(LOOKUPS, AS_NAMES * 3);
+    let mut group = c.benchmark_group("ipdb_lookup");
+    group.throughput(Throughput::Elements(LOOKUPS as u64));
+    group.bench_function("range_table", |b| {
+        b.iter(|| {
+            for addr in addrs.iter() {
+                black_box(table.get(addr));
+            }
+        })
+    });
+    group.bench_function("name_table", |b| {
+        b.iter(|| {
+            for asn in asns.iter() {
+                black_box(names.get(asn));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, range_lookup);
+criterion_main!(benches);
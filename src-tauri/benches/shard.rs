@@ -0,0 +1,218 @@
+//! Packet accounting throughput of the capture threads.
+//!
+//! Replays synthetic 1500 byte TCP frames between one local address and 2000 remote hosts.
+//! `shared_locked` is the accounting before per-thread shards: for each frame every thread
+//! locks the local IP map, the traffic, interface, remote host and connection maps and the
+//! IP database of the shared storage, and looks up the remote host in the IP database.
+//! `per_thread_shard` is the current path: each thread updates its own StatShard and
+//! the shards are merged once per batch.
+//! The IP database is loaded as the app loads it. Without downloaded range tables the
+//! per-frame lookup of `shared_locked` is nearly free, and one core shows no lock contention.
+//!
+//! Run with `cargo bench --bench shard`. Throughput is reported in frames per second.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use netpulsar_lib::net::decap::{self, TunnelAttribution};
+use netpulsar_lib::net::host::RemoteHostInfo;
+use netpulsar_lib::net::packet::PacketFrame;
+use netpulsar_lib::net::shard::get_direction;
+use netpulsar_lib::net::socket::{SocketConnection, TransportProtocol};
+use netpulsar_lib::net::stat::NetStatStrage;
+use netpulsar_lib::net::traffic::{Direction, TrafficInfo};
+use nex::packet::frame::ParseOption;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+const REMOTE_HOSTS: u32 = 2000;
+const FRAME_LEN: usize = 1500;
+/// Frames each thread replays per iteration
+const BATCH: usize = 20_000;
+
+/// Ethernet, IPv4 and TCP headers followed by a zero payload
+fn tcp_frame(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_LEN);
+    frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&((FRAME_LEN - 14) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    frame.extend_from_slice(&src.octets());
+    frame.extend_from_slice(&dst.octets());
+    frame.extend_from_slice(&src_port.to_be_bytes());
+    frame.extend_from_slice(&dst_port.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 0x50, 0x10, 0xff, 0xff, 0, 0, 0, 0]);
+    frame.resize(FRAME_LEN, 0);
+    frame
+}
+
+/// Decoded frames in both directions, alternating over the remote hosts
+fn synthetic_frames() -> Vec<PacketFrame> {
+    (0..REMOTE_HOSTS)
+        .flat_map(|i| {
+            let remote = Ipv4Addr::from(u32::from(Ipv4Addr::new(20, 0, 0, 0)) + i * 7919);
+            let port = 40000 + (i % 20000) as u16;
+            [tcp_frame(LOCAL_IP, remote, port, 443), tcp_frame(remote, LOCAL_IP, 443, port)]
+        })
+        .map(|data| {
+            let decoded = decap::decode_frame(&data, ParseOption::default(), TunnelAttribution::Outer);
+            PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded)
+        })
+        .collect()
+}
+
+fn count(traffic: &mut TrafficInfo, direction: Direction, len: usize) {
+    match direction {
+        Direction::Egress => {
+            traffic.packet_sent += 1;
+            traffic.bytes_sent += len as u64;
+        }
+        Direction::Ingress => {
+            traffic.packet_received += 1;
+            traffic.bytes_received += len as u64;
+        }
+    }
+}
+
+/// Accounting of a frame as done before shards: all storage maps stay locked for the frame
+fn locked_update(storage: &NetStatStrage, frame: PacketFrame) {
+    let (local_ip_map, mut traffic, mut interface_traffic, mut remote_hosts, mut connections, ipdb) = match (
+        storage.local_ip_map.lock(),
+        storage.traffic.lock(),
+        storage.interface_traffic.lock(),
+        storage.remote_hosts.lock(),
+        storage.connection_map.lock(),
+        storage.ipdb.lock(),
+    ) {
+        (Ok(a), Ok(b), Ok(c), Ok(d), Ok(e), Ok(f)) => (a, b, c, d, e, f),
+        _ => return,
+    };
+    let direction = frame.ip.as_ref().and_then(|ip| get_direction(ip, &local_ip_map));
+    count(
+        interface_traffic.entry(frame.if_name.clone()).or_insert(TrafficInfo::new()),
+        direction.unwrap_or(Direction::Ingress),
+        frame.packet_len,
+    );
+    drop(interface_traffic);
+    let (direction, ipv4) = match (direction, frame.ip.as_ref().and_then(|ip| ip.ipv4.as_ref())) {
+        (Some(direction), Some(ipv4)) => (direction, ipv4),
+        _ => return,
+    };
+    let tcp = match frame.transport.as_ref().and_then(|transport| transport.tcp.as_ref()) {
+        Some(tcp) => tcp,
+        None => return,
+    };
+    count(&mut traffic, direction, frame.packet_len);
+    let ethernet = frame.datalink.as_ref().and_then(|datalink| datalink.ethernet.as_ref());
+    let (mac_addr, local_ip_addr, local_port, remote_ip_addr, remote_port) = match direction {
+        Direction::Egress => (
+            ethernet.map(|e| e.destination.address()).unwrap_or_default(),
+            ipv4.source,
+            tcp.source,
+            ipv4.destination,
+            tcp.destination,
+        ),
+        Direction::Ingress => (
+            ethernet.map(|e| e.source.address()).unwrap_or_default(),
+            ipv4.destination,
+            tcp.destination,
+            ipv4.source,
+            tcp.source,
+        ),
+    };
+    let interface_name = match local_ip_map.get(&IpAddr::V4(local_ip_addr)) {
+        Some(name) => name.clone(),
+        None => String::from("unknown"),
+    };
+    let remote_host = remote_hosts
+        .entry(IpAddr::V4(remote_ip_addr))
+        .or_insert(RemoteHostInfo::new(mac_addr, IpAddr::V4(remote_ip_addr)));
+    count(&mut remote_host.traffic_info, direction, frame.packet_len);
+    if let Some(ipv4_info) = ipdb.get_ipv4_info(remote_ip_addr) {
+        remote_host.country_code = ipv4_info.country_code;
+        remote_host.country_name = ipv4_info.country_name;
+        remote_host.asn = ipv4_info.asn;
+        remote_host.as_name = ipv4_info.as_name;
+    }
+    let connection = SocketConnection {
+        interface_name: interface_name,
+        local_ip_addr: IpAddr::V4(local_ip_addr),
+        local_port: local_port,
+        remote_ip_addr: IpAddr::V4(remote_ip_addr),
+        remote_port: remote_port,
+        protocol: TransportProtocol::TCP,
+    };
+    count(connections.entry(connection).or_insert(TrafficInfo::new()), direction, frame.packet_len);
+}
+
+fn new_storage() -> Arc<NetStatStrage> {
+    let storage = Arc::new(NetStatStrage::new());
+    let mut local_ip_map: HashMap<IpAddr, String> = HashMap::new();
+    local_ip_map.insert(IpAddr::V4(LOCAL_IP), String::from("eth0"));
+    storage.set_local_ip_map(local_ip_map);
+    storage.load_ipdb();
+    storage
+}
+
+/// Run `threads` threads replaying BATCH frames each, iters times. Returns the total time
+fn run_threads<F>(threads: usize, iters: u64, frames: &[PacketFrame], per_frame: F) -> Duration
+where
+    F: Fn(PacketFrame) + Sync,
+{
+    let start = Instant::now();
+    for _ in 0..iters {
+        std::thread::scope(|scope| {
+            for t in 0..threads {
+                let per_frame = &per_frame;
+                scope.spawn(move || {
+                    for frame in frames.iter().cycle().skip(t * 97).take(BATCH) {
+                        per_frame(frame.clone());
+                    }
+                });
+            }
+        });
+    }
+    start.elapsed()
+}
+
+fn shard_update(c: &mut Criterion) {
+    let frames = synthetic_frames();
+    let mut group = c.benchmark_group("shard_update");
+    for threads in [1usize, 4] {
+        group.throughput(Throughput::Elements((BATCH * threads) as u64));
+        group.bench_with_input(BenchmarkId::new("shared_locked", threads), &threads, |b, &threads| {
+            let storage = new_storage();
+            b.iter_custom(|iters| run_threads(threads, iters, &frames, |frame| locked_update(&storage, frame)));
+        });
+        group.bench_with_input(BenchmarkId::new("per_thread_shard", threads), &threads, |b, &threads| {
+            let storage = new_storage();
+            b.iter_custom(|iters| run_threads_with_shards(&storage, threads, iters, &frames));
+        });
+    }
+    group.finish();
+}
+
+/// Each thread owns a shard for the whole batch. The merge is part of the measured time
+fn run_threads_with_shards(storage: &Arc<NetStatStrage>, threads: usize, iters: u64, frames: &[PacketFrame]) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iters {
+        std::thread::scope(|scope| {
+            for t in 0..threads {
+                let shard = storage.new_shard();
+                scope.spawn(move || {
+                    for frame in frames.iter().cycle().skip(t * 97).take(BATCH) {
+                        if let Ok(mut shard) = shard.lock() {
+                            shard.update(frame.clone());
+                        }
+                    }
+                });
+            }
+        });
+        storage.flush_shards();
+    }
+    start.elapsed()
+}
+
+criterion_group!(benches, shard_update);
+criterion_main!(benches);
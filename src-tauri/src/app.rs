@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use crate::db::error::DbError;
 
 // env
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -27,3 +28,20 @@ impl AppInfo {
         }
     }
 }
+
+/// Payload of the init event
+#[derive(Serialize, Debug, Clone)]
+pub struct InitStatus {
+    pub message: String,
+    /// Database problems found while loading. The app keeps running with fallbacks
+    pub errors: Vec<DbError>,
+}
+
+impl InitStatus {
+    pub fn new(message: &str) -> InitStatus {
+        InitStatus {
+            message: message.to_string(),
+            errors: Vec::new(),
+        }
+    }
+}
@@ -1,13 +1,31 @@
-use crate::net::host::HostDisplayInfo;
-use crate::net::packet::PacketFrame;
+use crate::net::decap::VlanTrafficDisplayInfo;
+use crate::net::dissect::PacketDetail;
+use crate::db::import::{ImportedIpData, IpImportOptions, IpImportSummary};
+use crate::db::ip::{IpDatabaseMemoryReport, LookupBenchmark, DEFAULT_BENCHMARK_ITERATIONS};
+use crate::db::label::IpLabel;
+use crate::db::threat::ThreatFeedStatus;
+use crate::db::update::{DatabaseUpdateInfo, DatabaseUpdateResult, DatabaseUpdater};
+use crate::net::host::{AsTrafficDisplayInfo, CountryTrafficDisplayInfo, HostDisplayInfo, LabelTrafficDisplayInfo};
+use crate::net::interface::InterfaceTrafficDisplayInfo;
 use crate::net::stat::NetStatData;
 use crate::net::stat::Overview;
-use crate::net::pcap::CaptureReport;
+use crate::net::pcap::CaptureStats;
+use crate::net::ring::{CaptureRing, CaptureRingConfig, RingDumpInfo, RingTrigger};
+use crate::net::session::{CaptureFrame, CaptureSessionInfo, CaptureSessionOptions, CaptureSessionStore};
+use crate::net::scan::{PortScanOption, PortScanProgress, PortScanResult};
 use crate::process::ProcessDisplayInfo;
 use crate::net::socket::SocketDisplayInfo;
 use crate::net::socket::SocketInfoOption;
 use crate::net::stat::NetStatStrage;
-use crate::app::AppInfo;
+use crate::net::beacon::BeaconInfo;
+use crate::net::dnstunnel::DnsDomainInfo;
+use crate::net::signature::RuleMatch;
+use crate::db::rule::RuleSetStatus;
+use crate::net::security::{SecurityConfig, SecurityEvent, OVERVIEW_NOTIFICATIONS};
+use crate::net::threat::{ThreatHit, OVERVIEW_THREAT_HITS};
+use crate::net::stream::{FollowStream, FollowStreamStore, StreamFormat, StreamProgress, StreamView, DEFAULT_STREAM_MAX_BYTES, MAX_STREAM_BYTES};
+use crate::net::socket::SocketConnection;
+use crate::app::{AppInfo, InitStatus};
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
@@ -18,23 +36,28 @@ use tauri::{Manager, State};
 pub async fn start_background_task(handle: tauri::AppHandle) -> Result<(), String> {
     // Load IPDB
     log::info!("Loading IPDB...");
-    match handle.emit_all("init", "Loading IPDB...") {
+    match handle.emit_all("init", InitStatus::new("Loading IPDB...")) {
         Ok(_) => {}
         Err(e) => {
             log::error!("Error: {:?}", e);
         }
     }
     let netstat_strage = handle.state::<Arc<NetStatStrage>>();
-    netstat_strage.load_ipdb();
+    let db_errors = netstat_strage.load_ipdb();
+    netstat_strage.load_labeldb();
+    netstat_strage.set_security_config(crate::config::AppConfig::load().security);
     log::info!("Starting background task...");
-    match handle.emit_all("init", "Starting background task...") {
+    let mut status = InitStatus::new("Starting background task...");
+    status.errors = db_errors;
+    match handle.emit_all("init", status.clone()) {
         Ok(_) => {}
         Err(e) => {
             log::error!("Error: {:?}", e);
         }
     }
     crate::task::start_background_task(&handle);
-    match handle.emit_all("init", "Init complete") {
+    status.message = String::from("Init complete");
+    match handle.emit_all("init", status) {
         Ok(_) => {}
         Err(e) => {
             log::error!("Error: {:?}", e);
@@ -103,18 +126,151 @@ pub async fn run_dep_installer(software_name: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn start_packet_capture(app_handle: tauri::AppHandle) -> CaptureReport {
-    let mut report = CaptureReport::new();
-    let (tx, rx): (Sender<PacketFrame>, Receiver<PacketFrame>) = channel();
+pub fn start_packet_capture(
+    app_handle: tauri::AppHandle,
+    store: State<'_, Arc<Mutex<CaptureSessionStore>>>,
+    options: Option<CaptureSessionOptions>,
+) -> Result<u32, String> {
+    let options = options.unwrap_or(CaptureSessionOptions::new());
+    let (capture_options, iface) = options.to_capture_options()?;
+    let handle = match store.lock() {
+        Ok(mut store) => store.add(iface.name.clone(), options),
+        Err(e) => return Err(e.to_string()),
+    };
+    let id = match handle.info.lock() {
+        Ok(info) => info.id,
+        Err(e) => return Err(e.to_string()),
+    };
+    let (tx, rx): (Sender<CaptureFrame>, Receiver<CaptureFrame>) = channel();
+    let session_handle = handle.clone();
+    thread::spawn(move || {
+        crate::net::session::start_capture_session(&session_handle, capture_options, iface, tx)
+    });
+    thread::spawn(move || {
+        while let Ok(frame) = rx.recv() {
+            match app_handle.emit_all("packet_frame", frame) {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("Error: {:?}", e);
+                }
+            }
+        }
+        let info = match handle.info.lock() {
+            Ok(info) => info.clone(),
+            Err(e) => {
+                log::error!("Error: {:?}", e);
+                return;
+            }
+        };
+        match app_handle.emit_all("capture_finished", info) {
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Error: {:?}", e);
+            }
+        }
+    });
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn list_captures(store: State<'_, Arc<Mutex<CaptureSessionStore>>>) -> Result<Vec<CaptureSessionInfo>, String> {
+    match store.lock() {
+        Ok(store) => Ok(store.list()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn stop_capture(store: State<'_, Arc<Mutex<CaptureSessionStore>>>, id: u32) -> Result<(), String> {
+    match store.lock() {
+        Ok(store) => store.stop(id),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn remove_capture(store: State<'_, Arc<Mutex<CaptureSessionStore>>>, id: u32) -> Result<(), String> {
+    match store.lock() {
+        Ok(mut store) => store.remove(id),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_packet_detail(
+    store: State<'_, Arc<Mutex<CaptureSessionStore>>>,
+    id: u32,
+    capture_no: usize,
+) -> Result<PacketDetail, String> {
+    match store.lock() {
+        Ok(store) => store.get_packet_detail(id, capture_no),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_capture_ring_config(capture_ring: State<'_, Arc<CaptureRing>>) -> CaptureRingConfig {
+    capture_ring.get_config()
+}
+
+#[tauri::command]
+pub fn set_capture_ring_config(capture_ring: State<'_, Arc<CaptureRing>>, config: CaptureRingConfig) {
+    let mut app_config = crate::config::AppConfig::load();
+    app_config.capture_ring = config.clone();
+    app_config.save();
+    capture_ring.set_config(config);
+}
+
+/// Dump the capture ring of an interface, or of all interfaces if not set
+#[tauri::command]
+pub fn dump_capture_ring(
+    capture_ring: State<'_, Arc<CaptureRing>>,
+    if_name: Option<String>,
+) -> Result<Vec<RingDumpInfo>, String> {
+    let if_names: Vec<String> = if_name.into_iter().collect();
+    let dumps = capture_ring.trigger(&if_names, RingTrigger::Manual);
+    if dumps.is_empty() {
+        return Err("No capture ring to dump".to_string());
+    }
+    Ok(dumps)
+}
+
+#[tauri::command]
+pub fn list_ring_dumps(capture_ring: State<'_, Arc<CaptureRing>>) -> Vec<RingDumpInfo> {
+    capture_ring.list_dumps()
+}
+
+/// Run a port scan. Progress events and the result carry the scan id.
+/// Emit stop_port_scan with the scan id as payload to cancel it
+#[tauri::command]
+pub async fn start_port_scan(
+    app_handle: tauri::AppHandle,
+    opt: PortScanOption,
+) -> Result<PortScanResult, String> {
+    let scan_id = crate::net::scan::next_scan_id();
+    let (tx, rx): (Sender<PortScanProgress>, Receiver<PortScanProgress>) = channel();
     let stop = Arc::new(Mutex::new(false));
     let stop_handle = stop.clone();
-    let default_interface = netdev::get_default_interface().unwrap();
-    let pcap_option = crate::net::pcap::PacketCaptureOptions::from_interface(&default_interface);
-    let pacp_handler = thread::spawn(move || {
-        crate::net::pcap::start_capture(pcap_option, tx, &stop, default_interface)
-    });
-    let stop_pcap_event = app_handle.listen_global("stop_pcap", move |event| {
-        log::info!("got stop_pcap with payload {:?}", event.payload());
+    // Announce the id before the targets are resolved
+    match tx.send(PortScanProgress {
+        scan_id: scan_id,
+        completed: 0,
+        total: 0,
+        ip_addr: None,
+        port: None,
+        status: None,
+    }) {
+        Ok(_) => {}
+        Err(_) => {}
+    }
+    let scan_handler =
+        thread::spawn(move || crate::net::scan::start_port_scan(opt, tx, &stop));
+    let stop_scan_event = app_handle.listen_global("stop_port_scan", move |event| {
+        log::info!("got stop_port_scan with payload {:?}", event.payload());
+        let target_id: Option<u32> = event.payload().and_then(|payload| serde_json::from_str(payload).ok());
+        if target_id != Some(scan_id) {
+            return;
+        }
         match stop_handle.lock() {
             Ok(mut stop) => {
                 *stop = true;
@@ -124,33 +280,123 @@ pub async fn start_packet_capture(app_handle: tauri::AppHandle) -> CaptureReport
             }
         }
     });
-    let print_handler = thread::spawn(move || {
-        while let Ok(frame) = rx.recv() {
-            match app_handle.emit_all("packet_frame", frame) {
+    let progress_handler = thread::spawn(move || {
+        while let Ok(mut progress) = rx.recv() {
+            progress.scan_id = scan_id;
+            match app_handle.emit_all("port_scan_progress", progress) {
                 Ok(_) => {}
                 Err(e) => {
                     log::error!("Error: {:?}", e);
                 }
             }
         }
-        app_handle.unlisten(stop_pcap_event);
+        app_handle.unlisten(stop_scan_event);
     });
-    match pacp_handler.join() {
-        Ok(r) => {
-            log::info!("pacp_handler: {:?}", r);
-            report = r;
-        }
+    let result = match scan_handler.join() {
+        Ok(r) => r,
         Err(e) => {
             log::error!("Error: {:?}", e);
+            Err("Port scan thread panicked".to_string())
         }
-    }
-    match print_handler.join() {
+    };
+    match progress_handler.join() {
         Ok(_) => {}
         Err(e) => {
             log::error!("Error: {:?}", e);
         }
     }
-    report
+    result.map(|mut result| {
+        result.scan_id = scan_id;
+        result
+    })
+}
+
+#[tauri::command]
+pub fn start_follow_stream(
+    app_handle: tauri::AppHandle,
+    store: State<'_, Arc<Mutex<FollowStreamStore>>>,
+    connection: SocketConnection,
+    max_bytes: Option<usize>,
+) -> Result<u32, String> {
+    let max_bytes = max_bytes
+        .unwrap_or(DEFAULT_STREAM_MAX_BYTES)
+        .min(MAX_STREAM_BYTES);
+    let stream = FollowStream::new(connection, max_bytes);
+    let (id, stream, stop) = match store.lock() {
+        Ok(mut store) => store.add(stream),
+        Err(e) => return Err(e.to_string()),
+    };
+    let (tx, rx): (Sender<StreamProgress>, Receiver<StreamProgress>) = channel();
+    thread::spawn(move || {
+        match crate::net::stream::start_follow_stream(id, &stream, &stop, tx) {
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("follow stream {} error: {}", id, e);
+            }
+        }
+    });
+    thread::spawn(move || {
+        while let Ok(progress) = rx.recv() {
+            match app_handle.emit_all("follow_stream_progress", progress) {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("Error: {:?}", e);
+                }
+            }
+        }
+    });
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn stop_follow_stream(store: State<'_, Arc<Mutex<FollowStreamStore>>>, id: u32) -> Result<(), String> {
+    match store.lock() {
+        Ok(store) => store.stop(id),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn remove_follow_stream(store: State<'_, Arc<Mutex<FollowStreamStore>>>, id: u32) -> Result<(), String> {
+    match store.lock() {
+        Ok(mut store) => store.remove(id),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_follow_stream(
+    store: State<'_, Arc<Mutex<FollowStreamStore>>>,
+    id: u32,
+    format: StreamFormat,
+) -> Result<StreamView, String> {
+    let stream = match store.lock() {
+        Ok(store) => store.get(id)?,
+        Err(e) => return Err(e.to_string()),
+    };
+    let view = match stream.lock() {
+        Ok(stream) => stream.to_view(format),
+        Err(e) => return Err(e.to_string()),
+    };
+    Ok(view)
+}
+
+#[tauri::command]
+pub fn save_follow_stream(
+    store: State<'_, Arc<Mutex<FollowStreamStore>>>,
+    id: u32,
+    path: String,
+    format: StreamFormat,
+) -> Result<(), String> {
+    let stream = match store.lock() {
+        Ok(store) => store.get(id)?,
+        Err(e) => return Err(e.to_string()),
+    };
+    let result = match stream.lock() {
+        Ok(stream) => stream.save(&path, format),
+        Err(e) => Err(e.to_string()),
+    };
+    result
 }
 
 #[tauri::command]
@@ -178,6 +424,81 @@ pub fn get_remote_hosts(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<Host
     }
 }
 
+/// Number of top hosts listed per country or AS
+const AGGREGATE_TOP_HOSTS: usize = 5;
+
+#[tauri::command]
+pub fn get_countries(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<CountryTrafficDisplayInfo> {
+    match netstat.lock() {
+        Ok(data) => data.get_countries(None, AGGREGATE_TOP_HOSTS),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_autonomous_systems(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<AsTrafficDisplayInfo> {
+    match netstat.lock() {
+        Ok(data) => data.get_autonomous_systems(None, AGGREGATE_TOP_HOSTS),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_label_groups(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<LabelTrafficDisplayInfo> {
+    match netstat.lock() {
+        Ok(data) => data.get_label_groups(AGGREGATE_TOP_HOSTS),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_labels(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Vec<IpLabel> {
+    netstat_strage.get_labeldb().labels()
+}
+
+#[tauri::command]
+pub fn set_labels(netstat_strage: State<'_, Arc<NetStatStrage>>, labels: Vec<IpLabel>) -> Result<(), String> {
+    netstat_strage.set_labels(labels)
+}
+
+#[tauri::command]
+pub fn get_interface_traffic(
+    netstat: State<'_, Arc<Mutex<NetStatData>>>,
+) -> Vec<InterfaceTrafficDisplayInfo> {
+    match netstat.lock() {
+        Ok(data) => data.get_interface_traffic(),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_vlan_traffic(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<VlanTrafficDisplayInfo> {
+    match netstat.lock() {
+        Ok(data) => data.get_vlan_traffic(),
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_capture_stats(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Vec<CaptureStats> {
+    netstat_strage.get_capture_stats()
+}
+
 #[tauri::command]
 pub fn get_process_info(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<ProcessDisplayInfo> {
     match netstat.lock() {
@@ -190,14 +511,25 @@ pub fn get_process_info(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Vec<Proc
 }
 
 #[tauri::command]
-pub fn get_overview(netstat: State<'_, Arc<Mutex<NetStatData>>>) -> Overview {
-    match netstat.lock() {
+pub fn get_overview(
+    netstat: State<'_, Arc<Mutex<NetStatData>>>,
+    netstat_strage: State<'_, Arc<NetStatStrage>>,
+) -> Overview {
+    let mut overview = match netstat.lock() {
         Ok(data) => data.get_overview(),
         Err(e) => {
             log::error!("Error: {:?}", e);
             Overview::new()
         }
-    }
+    };
+    overview.capture_stats = netstat_strage.get_capture_stats();
+    overview.threat_hits = netstat_strage.get_threat_hits(Some(OVERVIEW_THREAT_HITS));
+    overview.notifications = netstat_strage
+        .get_security_events(Some(OVERVIEW_NOTIFICATIONS))
+        .iter()
+        .map(|event| event.to_notification())
+        .collect();
+    overview
 }
 
 #[tauri::command]
@@ -251,6 +583,223 @@ pub async fn get_database_config() -> crate::config::DatabaseConfig {
     crate::config::DatabaseConfig::new()
 }
 
+fn database_updater() -> Result<DatabaseUpdater, String> {
+    let db_dir = match crate::db::update::db_dir_path() {
+        Some(db_dir) => db_dir,
+        None => return Err(String::from("Config directory not found")),
+    };
+    let config = crate::config::AppConfig::load();
+    Ok(DatabaseUpdater::new(config.database.base_url, db_dir))
+}
+
+#[tauri::command]
+pub async fn check_database_updates() -> Result<Vec<DatabaseUpdateInfo>, String> {
+    database_updater()?.check().await
+}
+
+#[tauri::command]
+pub async fn update_databases(
+    app_handle: tauri::AppHandle,
+    netstat_strage: State<'_, Arc<NetStatStrage>>,
+) -> Result<DatabaseUpdateResult, String> {
+    let updater = database_updater()?;
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(100);
+    let emit_handle = app_handle.clone();
+    let emit_task = tokio::spawn(async move {
+        while let Some(progress) = progress_rx.recv().await {
+            match emit_handle.emit_all("db_update_progress", progress) {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("Error: {:?}", e);
+                }
+            }
+        }
+    });
+    let result = updater.update(progress_tx).await;
+    let _ = emit_task.await;
+    let result = result?;
+    if result.ipdb_changed() {
+        netstat_strage.reload_ipdb()?;
+        log::info!("IP database reloaded");
+    }
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn import_ip_database(
+    netstat_strage: State<'_, Arc<NetStatStrage>>,
+    options: IpImportOptions,
+) -> Result<IpImportSummary, String> {
+    // Large files take a while to convert. Keep it off the async runtime
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    thread::spawn(move || {
+        let result = crate::db::import::import_file(&options).and_then(|data| {
+            let summary = data.summary();
+            // A broken previous import is replaced
+            let mut imported = match crate::db::import::load_imported() {
+                Ok(imported) => imported.unwrap_or_default(),
+                Err(e) => {
+                    log::error!("Discarding imported IP database: {}", e);
+                    ImportedIpData::default()
+                }
+            };
+            imported.merge(data);
+            crate::db::import::save_imported(&imported)?;
+            Ok(summary)
+        });
+        let _ = tx.send(result);
+    });
+    let summary = match rx.await {
+        Ok(result) => result?,
+        Err(e) => return Err(e.to_string()),
+    };
+    netstat_strage.reload_ipdb()?;
+    log::info!("IP database imported: {:?}", summary);
+    Ok(summary)
+}
+
+#[tauri::command]
+pub async fn clear_imported_ip_database(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Result<(), String> {
+    crate::db::import::clear_imported()?;
+    netstat_strage.reload_ipdb()?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_ipdb_memory_report(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Result<IpDatabaseMemoryReport, String> {
+    match netstat_strage.ipdb.lock() {
+        Ok(ipdb) => Ok(ipdb.memory_report()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Upper bound so a benchmark cannot hold the IP database lock for long
+const MAX_BENCHMARK_ITERATIONS: usize = 1_000_000;
+
+#[tauri::command]
+pub async fn benchmark_ipdb_lookup(
+    netstat_strage: State<'_, Arc<NetStatStrage>>,
+    iterations: Option<usize>,
+) -> Result<LookupBenchmark, String> {
+    let iterations = iterations
+        .unwrap_or(DEFAULT_BENCHMARK_ITERATIONS)
+        .min(MAX_BENCHMARK_ITERATIONS);
+    let ipdb = netstat_strage.ipdb.clone();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    thread::spawn(move || {
+        let result = match ipdb.lock() {
+            Ok(ipdb) => Ok(ipdb.benchmark_lookup(iterations)),
+            Err(e) => Err(e.to_string()),
+        };
+        let _ = tx.send(result);
+    });
+    match rx.await {
+        Ok(result) => result,
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_threat_feed_status(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Vec<ThreatFeedStatus> {
+    netstat_strage.get_threat_feed_status()
+}
+
+/// Download all remote feeds now and reload the threat database
+#[tauri::command]
+pub async fn refresh_threat_feeds(
+    netstat_strage: State<'_, Arc<NetStatStrage>>,
+) -> Result<Vec<ThreatFeedStatus>, String> {
+    let netstat_strage = Arc::clone(&netstat_strage);
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    thread::spawn(move || {
+        let config = crate::config::AppConfig::load().threat_intel;
+        let (downloaded, errors) = crate::net::threat::refresh_feeds(&config, true);
+        for e in &errors {
+            log::error!("Failed to download threat feed: {}", e);
+        }
+        log::info!("Threat feeds downloaded: {}", downloaded);
+        netstat_strage.load_threatdb(&config);
+        let _ = tx.send(netstat_strage.get_threat_feed_status());
+    });
+    match rx.await {
+        Ok(status) => Ok(status),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_threat_hits(netstat_strage: State<'_, Arc<NetStatStrage>>, limit: Option<usize>) -> Vec<ThreatHit> {
+    netstat_strage.get_threat_hits(limit)
+}
+
+#[tauri::command]
+pub fn clear_threat_hits(netstat_strage: State<'_, Arc<NetStatStrage>>) {
+    netstat_strage.clear_threat_hits();
+}
+
+#[tauri::command]
+pub fn get_rule_status(netstat_strage: State<'_, Arc<NetStatStrage>>) -> RuleSetStatus {
+    netstat_strage.get_rule_status()
+}
+
+/// Read the rule files again
+#[tauri::command]
+pub async fn reload_rules(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Result<RuleSetStatus, String> {
+    let netstat_strage = Arc::clone(&netstat_strage);
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    thread::spawn(move || {
+        netstat_strage.load_rules(&crate::config::AppConfig::load().rules);
+        let _ = tx.send(netstat_strage.get_rule_status());
+    });
+    match rx.await {
+        Ok(status) => Ok(status),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_rule_matches(netstat_strage: State<'_, Arc<NetStatStrage>>, limit: Option<usize>) -> Vec<RuleMatch> {
+    netstat_strage.get_rule_matches(limit)
+}
+
+#[tauri::command]
+pub fn clear_rule_matches(netstat_strage: State<'_, Arc<NetStatStrage>>) {
+    netstat_strage.clear_rule_matches();
+}
+
+#[tauri::command]
+pub fn get_security_config(netstat_strage: State<'_, Arc<NetStatStrage>>) -> SecurityConfig {
+    netstat_strage.get_security_config()
+}
+
+#[tauri::command]
+pub fn set_security_config(netstat_strage: State<'_, Arc<NetStatStrage>>, config: SecurityConfig) {
+    let mut app_config = crate::config::AppConfig::load();
+    app_config.security = config.clone();
+    app_config.save();
+    netstat_strage.set_security_config(config);
+}
+
+#[tauri::command]
+pub fn get_security_events(netstat_strage: State<'_, Arc<NetStatStrage>>, limit: Option<usize>) -> Vec<SecurityEvent> {
+    netstat_strage.get_security_events(limit)
+}
+
+#[tauri::command]
+pub fn clear_security_events(netstat_strage: State<'_, Arc<NetStatStrage>>) {
+    netstat_strage.clear_security_events();
+}
+
+#[tauri::command]
+pub fn get_beacons(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Vec<BeaconInfo> {
+    netstat_strage.get_beacons()
+}
+
+#[tauri::command]
+pub fn get_dns_domains(netstat_strage: State<'_, Arc<NetStatStrage>>) -> Vec<DnsDomainInfo> {
+    netstat_strage.get_dns_domains()
+}
+
 #[tauri::command]
 pub async fn get_routes() -> Vec<crate::route::Route> {
     crate::route::get_routes()
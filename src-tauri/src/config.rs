@@ -1,3 +1,11 @@
+use crate::db::rule::RuleConfig;
+use crate::db::threat::ThreatIntelConfig;
+use crate::db::update::{self, DatabaseKind, DatabaseStatus, DEFAULT_DB_BASE_URL};
+use crate::net::decap::TunnelAttribution;
+use crate::net::ring::CaptureRingConfig;
+use crate::net::sampling::SamplingConfig;
+use crate::net::security::SecurityConfig;
+use crate::net::traffic::RateWindows;
 use crate::sys;
 use crate::thread_log::LogLevel;
 use crate::thread_log::DEFAULT_LOG_FILE_PATH;
@@ -15,6 +23,21 @@ pub struct AppConfig {
     pub display: DisplayConfig,
     /// Privacy configuration.
     pub privacy: PrivacyConfig,
+    /// Pre-trigger capture ring configuration.
+    #[serde(default)]
+    pub capture_ring: CaptureRingConfig,
+    /// Database update configuration.
+    #[serde(default)]
+    pub database: DatabaseUpdateConfig,
+    /// Threat intelligence feed configuration.
+    #[serde(default)]
+    pub threat_intel: ThreatIntelConfig,
+    /// Detector thresholds.
+    #[serde(default)]
+    pub security: SecurityConfig,
+    /// Signature rule configuration.
+    #[serde(default)]
+    pub rules: RuleConfig,
 }
 
 impl AppConfig {
@@ -24,6 +47,11 @@ impl AppConfig {
             network: NetworkConfig::new(),
             display: DisplayConfig::new(),
             privacy: PrivacyConfig::new(),
+            capture_ring: CaptureRingConfig::new(),
+            database: DatabaseUpdateConfig::new(),
+            threat_intel: ThreatIntelConfig::new(),
+            security: SecurityConfig::new(),
+            rules: RuleConfig::new(),
         }
     }
     pub fn load() -> AppConfig {
@@ -100,6 +128,20 @@ pub struct NetworkConfig {
     pub reverse_dns: bool,
     /// Entry TTL in milliseconds. If no traffic is seen for this duration, the entry will be removed.
     pub entry_ttl: u64,
+    /// Attribute tunneled traffic to the inner or the outer flow.
+    #[serde(default)]
+    pub tunnel_attribution: TunnelAttribution,
+    /// Frame sampling for background capture.
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+    /// Bytes kept of each frame in background capture. 0 keeps whole frames.
+    /// A small snaplen cuts payloads, which breaks the capture ring dumps, TLS SNI names,
+    /// rogue DHCP detection, DNS tunneling detection and payload rule matching.
+    #[serde(default)]
+    pub snaplen: usize,
+    /// Smoothing windows of the traffic rates.
+    #[serde(default)]
+    pub rate_windows: RateWindows,
 }
 
 impl NetworkConfig {
@@ -108,6 +150,10 @@ impl NetworkConfig {
             interfaces: Vec::new(),
             reverse_dns: false,
             entry_ttl: 60000,
+            tunnel_attribution: TunnelAttribution::default(),
+            sampling: SamplingConfig::new(),
+            snaplen: 0,
+            rate_windows: RateWindows::new(),
         }
     }
 }
@@ -155,6 +201,27 @@ impl PrivacyConfig {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DatabaseUpdateConfig {
+    /// Base URL of the database manifest and files.
+    pub base_url: String,
+}
+
+impl DatabaseUpdateConfig {
+    pub fn new() -> DatabaseUpdateConfig {
+        DatabaseUpdateConfig {
+            base_url: DEFAULT_DB_BASE_URL.to_owned(),
+        }
+    }
+}
+
+impl Default for DatabaseUpdateConfig {
+    fn default() -> Self {
+        DatabaseUpdateConfig::new()
+    }
+}
+
+/// Databases in use. Paths are empty for the embedded ones
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DatabaseConfig {
     pub ipv4_asn_db_path: String,
@@ -164,34 +231,36 @@ pub struct DatabaseConfig {
     pub country_db_path: String,
     pub asn_db_path: String,
     pub tcp_service_db_path: String,
+    pub base_url: String,
+    pub databases: Vec<DatabaseStatus>,
+    /// Empty if nothing was imported
+    pub imported_db_path: String,
 }
 
 impl DatabaseConfig {
     pub fn new() -> DatabaseConfig {
-        /* DatabaseConfig {
-            ipv4_asn_db_path: db::ip::Ipv4Asn::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            ipv6_asn_db_path: db::ip::Ipv6Asn::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            ipv4_country_db_path: db::ip::Ipv4Country::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            ipv6_country_db_path: db::ip::Ipv6Country::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            country_db_path: db::ip::Country::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            asn_db_path: db::ip::AutonomousSystem::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-            tcp_service_db_path: db::service::TcpService::bin_file_path().map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "".to_owned()),
-        } */
+        let databases = update::get_database_status();
+        let path_of = |kind: DatabaseKind| -> String {
+            databases
+                .iter()
+                .find(|status| status.kind == kind)
+                .map(|status| status.path.clone())
+                .unwrap_or_default()
+        };
         DatabaseConfig {
-            ipv4_asn_db_path: String::new(),
-            ipv6_asn_db_path: String::new(),
-            ipv4_country_db_path: String::new(),
-            ipv6_country_db_path: String::new(),
-            country_db_path: String::new(),
-            asn_db_path: String::new(),
-            tcp_service_db_path: String::new(),
+            ipv4_asn_db_path: path_of(DatabaseKind::Ipv4Asn),
+            ipv6_asn_db_path: path_of(DatabaseKind::Ipv6Asn),
+            ipv4_country_db_path: path_of(DatabaseKind::Ipv4Country),
+            ipv6_country_db_path: path_of(DatabaseKind::Ipv6Country),
+            country_db_path: path_of(DatabaseKind::Country),
+            asn_db_path: path_of(DatabaseKind::As),
+            tcp_service_db_path: path_of(DatabaseKind::TcpService),
+            base_url: AppConfig::load().database.base_url,
+            databases: databases,
+            imported_db_path: match crate::db::import::imported_file_path() {
+                Some(path) if path.exists() => path.to_string_lossy().to_string(),
+                _ => String::new(),
+            },
         }
     }
 }
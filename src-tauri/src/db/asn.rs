@@ -1,4 +1,7 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::db::range::NameTable;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
@@ -23,28 +26,29 @@ impl AutonomousSystem {
     }
 }
 
+impl DbRecord for AutonomousSystem {
+    const MIN_SIZE: usize = 12;
+}
+
+fn to_map(autonomous_vec: Vec<AutonomousSystem>) -> NameTable {
+    autonomous_vec
+        .into_iter()
+        .map(|autonomous| (autonomous.asn, autonomous.as_name))
+        .collect()
+}
+
 #[cfg(feature = "bundle")]
-pub fn get_map() -> HashMap<u32, String> {
-    let mut autonomous_map: HashMap<u32, String> = HashMap::new();
-    let autonomous_vec: Vec<AutonomousSystem> = bincode::deserialize(AS_BIN).unwrap();
-    for autonomous in autonomous_vec {
-        autonomous_map.insert(autonomous.asn, autonomous.as_name);
-    }
-    autonomous_map
+pub fn get_map() -> Result<NameTable, DbError> {
+    let autonomous_vec: Vec<AutonomousSystem> = bin::decode_records(AS_BIN, AS_BIN_NAME)?;
+    Ok(to_map(autonomous_vec))
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> HashMap<u32, String> {
-    let mut autonomous_map: HashMap<u32, String> = HashMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let autonomous_vec: Vec<AutonomousSystem> = bincode::deserialize(&f).unwrap();
-            for autonomous in autonomous_vec {
-                autonomous_map.insert(autonomous.asn, autonomous.as_name);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    autonomous_map
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<NameTable, DbError> {
+    Err(DbError::NotBundled { file_name: AS_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<NameTable, DbError> {
+    let autonomous_vec: Vec<AutonomousSystem> = bin::read_records(&file_path, AS_BIN_NAME)?;
+    Ok(to_map(autonomous_vec))
 }
@@ -0,0 +1,276 @@
+use crate::db::error::DbError;
+use crate::db::update::{self, DatabaseKind};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Optional header of database files: magic, format version, reserved, record count
+pub const DB_MAGIC: &[u8; 4] = b"NPDB";
+pub const DB_FORMAT_VERSION: u16 = 1;
+const DB_HEADER_SIZE: usize = 16;
+/// bincode length prefix of a Vec
+const LENGTH_PREFIX_SIZE: usize = 8;
+
+/// A record of a database file
+pub trait DbRecord: DeserializeOwned {
+    /// Smallest encoded size. Rejects truncated files before decoding
+    const MIN_SIZE: usize;
+    fn is_valid(&self) -> bool {
+        true
+    }
+}
+
+/// Split off the header if present. Files without one are accepted as plain bincode
+fn split_header<'a>(bytes: &'a [u8], file_name: &str) -> Result<(Option<u64>, &'a [u8]), DbError> {
+    if !bytes.starts_with(DB_MAGIC) {
+        return Ok((None, bytes));
+    }
+    if bytes.len() < DB_HEADER_SIZE {
+        return Err(DbError::InvalidHeader { file_name: file_name.to_owned() });
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version == 0 || version > DB_FORMAT_VERSION {
+        return Err(DbError::UnsupportedVersion { file_name: file_name.to_owned(), version: version });
+    }
+    let mut count = [0u8; 8];
+    count.copy_from_slice(&bytes[8..16]);
+    Ok((Some(u64::from_le_bytes(count)), &bytes[DB_HEADER_SIZE..]))
+}
+
+/// Same encoding as bincode::deserialize, but the whole payload must be used
+fn decode_exact<T: DeserializeOwned>(payload: &[u8], file_name: &str) -> Result<T, DbError> {
+    match bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(payload)
+    {
+        Ok(value) => Ok(value),
+        Err(e) => Err(DbError::Decode { file_name: file_name.to_owned(), message: e.to_string() }),
+    }
+}
+
+/// Decode and validate a list of records
+pub fn decode_records<T: DbRecord>(bytes: &[u8], file_name: &str) -> Result<Vec<T>, DbError> {
+    let (expected, payload) = split_header(bytes, file_name)?;
+    if payload.len() < LENGTH_PREFIX_SIZE {
+        return Err(DbError::Truncated { file_name: file_name.to_owned() });
+    }
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&payload[..LENGTH_PREFIX_SIZE]);
+    let length = u64::from_le_bytes(length);
+    if let Some(expected) = expected {
+        if expected != length {
+            return Err(DbError::RecordCount { file_name: file_name.to_owned(), expected: expected, actual: length });
+        }
+    }
+    if length.saturating_mul(T::MIN_SIZE as u64) > (payload.len() - LENGTH_PREFIX_SIZE) as u64 {
+        return Err(DbError::Truncated { file_name: file_name.to_owned() });
+    }
+    let records: Vec<T> = decode_exact(payload, file_name)?;
+    if records.is_empty() {
+        return Err(DbError::Empty { file_name: file_name.to_owned() });
+    }
+    if let Some(index) = records.iter().position(|record| !record.is_valid()) {
+        return Err(DbError::InvalidRecord { file_name: file_name.to_owned(), index: index });
+    }
+    Ok(records)
+}
+
+pub fn read_records<T: DbRecord>(path: &PathBuf, file_name: &str) -> Result<Vec<T>, DbError> {
+    match std::fs::read(path) {
+        Ok(bytes) => decode_records(&bytes, file_name),
+        Err(e) => Err(DbError::Io { file_name: file_name.to_owned(), message: e.to_string() }),
+    }
+}
+
+/// Encode a value with the header
+pub fn encode<T: Serialize>(value: &T, record_count: u64, file_name: &str) -> Result<Vec<u8>, DbError> {
+    let payload = match bincode::serialize(value) {
+        Ok(payload) => payload,
+        Err(e) => return Err(DbError::Decode { file_name: file_name.to_owned(), message: e.to_string() }),
+    };
+    let mut bytes: Vec<u8> = Vec::with_capacity(DB_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(DB_MAGIC);
+    bytes.extend_from_slice(&DB_FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&[0u8; 2]);
+    bytes.extend_from_slice(&record_count.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Decode a value written by encode. Returns it with the record count of the header
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], file_name: &str) -> Result<(T, u64), DbError> {
+    match split_header(bytes, file_name)? {
+        (Some(record_count), payload) => Ok((decode_exact(payload, file_name)?, record_count)),
+        (None, _) => Err(DbError::InvalidHeader { file_name: file_name.to_owned() }),
+    }
+}
+
+/// The installed file if it is valid, otherwise the embedded copy.
+/// Problems are added to errors. The table is empty if neither can be used
+pub fn load_table<T: Default>(
+    kind: DatabaseKind,
+    from_file: fn(PathBuf) -> Result<T, DbError>,
+    embedded: fn() -> Result<T, DbError>,
+    errors: &mut Vec<DbError>,
+) -> T {
+    load_table_from(update::installed_file_path(kind), from_file, embedded, errors)
+}
+
+/// load_table with the installed file path already resolved
+fn load_table_from<T: Default>(
+    path: Option<PathBuf>,
+    from_file: fn(PathBuf) -> Result<T, DbError>,
+    embedded: fn() -> Result<T, DbError>,
+    errors: &mut Vec<DbError>,
+) -> T {
+    if let Some(path) = path {
+        match from_file(path) {
+            Ok(table) => return table,
+            Err(e) => errors.push(e),
+        }
+    }
+    match embedded() {
+        Ok(table) => table,
+        Err(e) => {
+            errors.push(e);
+            T::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::InitStatus;
+    use serde::Deserialize;
+
+    const FILE_NAME: &str = "test.bin";
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct TestRecord {
+        start: u32,
+        end: u32,
+    }
+
+    impl DbRecord for TestRecord {
+        const MIN_SIZE: usize = 8;
+        fn is_valid(&self) -> bool {
+            self.start <= self.end
+        }
+    }
+
+    fn records() -> Vec<TestRecord> {
+        vec![TestRecord { start: 1, end: 2 }, TestRecord { start: 3, end: 4 }]
+    }
+
+    fn decode_test(bytes: &[u8]) -> Result<Vec<TestRecord>, DbError> {
+        decode_records(bytes, FILE_NAME)
+    }
+
+    fn records_from_file(path: PathBuf) -> Result<Vec<TestRecord>, DbError> {
+        read_records(&path, FILE_NAME)
+    }
+
+    fn embedded_records() -> Result<Vec<TestRecord>, DbError> {
+        Ok(vec![TestRecord { start: 0, end: 0 }])
+    }
+
+    fn not_bundled() -> Result<Vec<TestRecord>, DbError> {
+        Err(DbError::NotBundled { file_name: FILE_NAME.to_owned() })
+    }
+
+    #[test]
+    fn records_with_and_without_header() {
+        let bytes = encode(&records(), 2, FILE_NAME).unwrap();
+        assert_eq!(decode_test(&bytes).unwrap(), records());
+        let plain = bincode::serialize(&records()).unwrap();
+        assert_eq!(decode_test(&plain).unwrap(), records());
+    }
+
+    #[test]
+    fn bad_magic_is_not_read_as_header() {
+        let mut bytes = encode(&records(), 2, FILE_NAME).unwrap();
+        bytes[3] = b'X';
+        // Read as plain bincode, where the header bytes make an impossible length
+        assert_eq!(decode_test(&bytes), Err(DbError::Truncated { file_name: FILE_NAME.to_owned() }));
+        assert_eq!(decode_test(b"NPDB\x01\x00"), Err(DbError::InvalidHeader { file_name: FILE_NAME.to_owned() }));
+        assert!(matches!(decode::<Vec<TestRecord>>(&bincode::serialize(&records()).unwrap(), FILE_NAME), Err(DbError::InvalidHeader { .. })));
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = encode(&records(), 2, FILE_NAME).unwrap();
+        bytes[4..6].copy_from_slice(&(DB_FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_test(&bytes),
+            Err(DbError::UnsupportedVersion { file_name: FILE_NAME.to_owned(), version: DB_FORMAT_VERSION + 1 })
+        );
+        bytes[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert!(matches!(decode_test(&bytes), Err(DbError::UnsupportedVersion { version: 0, .. })));
+    }
+
+    #[test]
+    fn truncated_records() {
+        let bytes = encode(&records(), 2, FILE_NAME).unwrap();
+        let truncated = Err(DbError::Truncated { file_name: FILE_NAME.to_owned() });
+        assert_eq!(decode_test(&bytes[..bytes.len() - 4]), truncated);
+        assert_eq!(decode_test(&bytes[..DB_HEADER_SIZE + 4]), truncated);
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(decode_test(&trailing), Err(DbError::Decode { .. })));
+    }
+
+    #[test]
+    fn record_count_mismatch() {
+        let bytes = encode(&records(), 3, FILE_NAME).unwrap();
+        assert_eq!(
+            decode_test(&bytes),
+            Err(DbError::RecordCount { file_name: FILE_NAME.to_owned(), expected: 3, actual: 2 })
+        );
+    }
+
+    #[test]
+    fn invalid_and_empty_records() {
+        let mut invalid = records();
+        invalid[1].start = 5;
+        let bytes = encode(&invalid, 2, FILE_NAME).unwrap();
+        assert_eq!(decode_test(&bytes), Err(DbError::InvalidRecord { file_name: FILE_NAME.to_owned(), index: 1 }));
+        let bytes = encode(&Vec::<TestRecord>::new(), 0, FILE_NAME).unwrap();
+        assert_eq!(decode_test(&bytes), Err(DbError::Empty { file_name: FILE_NAME.to_owned() }));
+    }
+
+    #[test]
+    fn invalid_installed_file_falls_back_to_embedded() {
+        let path = std::env::temp_dir().join(format!("netpulsar-bin-{}.bin", std::process::id()));
+        let mut bytes = encode(&records(), 2, FILE_NAME).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        std::fs::write(&path, &bytes).unwrap();
+        let mut errors: Vec<DbError> = Vec::new();
+        let table = load_table_from(Some(path.clone()), records_from_file, embedded_records, &mut errors);
+        assert_eq!(table, embedded_records().unwrap());
+        assert_eq!(errors, vec![DbError::Truncated { file_name: FILE_NAME.to_owned() }]);
+
+        // Reported to the UI with the init event
+        let mut status = InitStatus::new("Starting background task...");
+        status.errors = errors;
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["errors"].as_array().unwrap().len(), 1);
+        assert!(json["errors"][0].to_string().contains(FILE_NAME));
+
+        // Neither source usable
+        let mut errors: Vec<DbError> = Vec::new();
+        let table = load_table_from(Some(path.clone()), records_from_file, not_bundled, &mut errors);
+        let _ = std::fs::remove_file(&path);
+        assert!(table.is_empty());
+        assert_eq!(errors.len(), 2);
+        // A valid installed file is used as is
+        let mut errors: Vec<DbError> = Vec::new();
+        std::fs::write(&path, encode(&records(), 2, FILE_NAME).unwrap()).unwrap();
+        let table = load_table_from(Some(path.clone()), records_from_file, embedded_records, &mut errors);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(table, records());
+        assert!(errors.is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -23,28 +25,30 @@ impl Country {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> HashMap<String, String> {
+impl DbRecord for Country {
+    const MIN_SIZE: usize = 16;
+}
+
+fn to_map(country_vec: Vec<Country>) -> HashMap<String, String> {
     let mut country_map: HashMap<String, String> = HashMap::new();
-    let country_vec: Vec<Country> = bincode::deserialize(COUNTRY_BIN).unwrap();
     for country in country_vec {
         country_map.insert(country.country_code, country.country_name);
     }
     country_map
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> HashMap<String, String> {
-    let mut country_map: HashMap<String, String> = HashMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let country_vec: Vec<Country> = bincode::deserialize(&f).unwrap();
-            for country in country_vec {
-                country_map.insert(country.country_code, country.country_name);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    country_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<HashMap<String, String>, DbError> {
+    let country_vec: Vec<Country> = bin::decode_records(COUNTRY_BIN, COUNTRY_BIN_NAME)?;
+    Ok(to_map(country_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<HashMap<String, String>, DbError> {
+    Err(DbError::NotBundled { file_name: COUNTRY_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<HashMap<String, String>, DbError> {
+    let country_vec: Vec<Country> = bin::read_records(&file_path, COUNTRY_BIN_NAME)?;
+    Ok(to_map(country_vec))
 }
@@ -0,0 +1,66 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// Failure to read or validate a database file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbError {
+    /// The file could not be read
+    Io { file_name: String, message: String },
+    /// Starts with the magic bytes but the header is incomplete
+    InvalidHeader { file_name: String },
+    /// Written by a newer version
+    UnsupportedVersion { file_name: String, version: u16 },
+    /// Shorter than the declared records need
+    Truncated { file_name: String },
+    Decode { file_name: String, message: String },
+    /// Header and payload disagree on the number of records
+    RecordCount { file_name: String, expected: u64, actual: u64 },
+    InvalidRecord { file_name: String, index: usize },
+    Empty { file_name: String },
+    /// Built without the bundle feature
+    NotBundled { file_name: String },
+}
+
+impl DbError {
+    pub fn file_name(&self) -> &str {
+        match self {
+            DbError::Io { file_name, .. }
+            | DbError::InvalidHeader { file_name }
+            | DbError::UnsupportedVersion { file_name, .. }
+            | DbError::Truncated { file_name }
+            | DbError::Decode { file_name, .. }
+            | DbError::RecordCount { file_name, .. }
+            | DbError::InvalidRecord { file_name, .. }
+            | DbError::Empty { file_name }
+            | DbError::NotBundled { file_name } => file_name,
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io { file_name, message } => write!(f, "{}: {}", file_name, message),
+            DbError::InvalidHeader { file_name } => write!(f, "{}: invalid header", file_name),
+            DbError::UnsupportedVersion { file_name, version } => {
+                write!(f, "{}: unsupported format version {}", file_name, version)
+            }
+            DbError::Truncated { file_name } => write!(f, "{}: file is truncated", file_name),
+            DbError::Decode { file_name, message } => write!(f, "{}: {}", file_name, message),
+            DbError::RecordCount { file_name, expected, actual } => {
+                write!(f, "{}: expected {} records, found {}", file_name, expected, actual)
+            }
+            DbError::InvalidRecord { file_name, index } => write!(f, "{}: invalid record at {}", file_name, index),
+            DbError::Empty { file_name } => write!(f, "{}: no records", file_name),
+            DbError::NotBundled { file_name } => write!(f, "{}: not bundled with this build", file_name),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl Serialize for DbError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
@@ -0,0 +1,899 @@
+use crate::db::asn::AutonomousSystem;
+use crate::db::bin::{self, DbRecord};
+use crate::db::country::Country;
+use crate::db::error::DbError;
+use crate::db::ip::IpDatabase;
+use crate::db::ipv4_asn::Ipv4Asn;
+use crate::db::ipv4_country::Ipv4Country;
+use crate::db::ipv6_asn::Ipv6Asn;
+use crate::db::ipv6_country::Ipv6Country;
+use crate::db::mmdb::{MmdbReader, MmdbValue};
+use crate::db::range::CountryCode;
+use crate::db::update;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+/// Imported data in the db directory. Applied over the bundled or downloaded databases
+pub const IMPORTED_DB_FILE_NAME: &str = "imported.bin";
+
+/// ::ffff:0:0/96
+const IPV4_MAPPED_PREFIX: u128 = 0xffff_0000_0000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpImportFormat {
+    /// MaxMind DB (GeoIP2, GeoLite2, DB-IP). Country, city and ASN databases
+    Mmdb,
+    /// GeoIP2/GeoLite2 CSV blocks file. Country and city blocks need the locations file
+    GeoIpCsv,
+    /// ip2asn/IPtoASN TSV (range_start, range_end, AS_number, country_code, AS_description)
+    Ip2AsnTsv,
+    /// IP2Location LITE CSV (DB1, DB3, DB5 and ASN)
+    Ip2LocationCsv,
+}
+
+impl IpImportFormat {
+    pub fn detect(path: &Path) -> Result<IpImportFormat, String> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "mmdb" => Ok(IpImportFormat::Mmdb),
+            "tsv" => Ok(IpImportFormat::Ip2AsnTsv),
+            "csv" => {
+                let header = read_first_line(path)?;
+                if header.starts_with("network,") {
+                    Ok(IpImportFormat::GeoIpCsv)
+                } else {
+                    Ok(IpImportFormat::Ip2LocationCsv)
+                }
+            }
+            _ => Err(format!("Unknown database format: {}", path.display())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpImportOptions {
+    /// Detected from the file if not set
+    pub format: Option<IpImportFormat>,
+    pub path: String,
+    /// GeoIP CSV locations file. Found next to the blocks file if not set
+    pub locations_path: Option<String>,
+}
+
+/// City, coordinates and organization of a network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeoLocation {
+    pub city: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub organization: String,
+}
+
+impl GeoLocation {
+    pub fn new() -> GeoLocation {
+        GeoLocation {
+            city: String::new(),
+            latitude: None,
+            longitude: None,
+            organization: String::new(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.city.is_empty() && self.latitude.is_none() && self.longitude.is_none() && self.organization.is_empty()
+    }
+    fn key(&self) -> String {
+        format!("{}|{:?}|{:?}|{}", self.city, self.latitude, self.longitude, self.organization)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Geo {
+    pub ip_from: u32,
+    pub ip_to: u32,
+    /// Index into the locations
+    pub location: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ipv6Geo {
+    pub ip_from: u128,
+    pub ip_to: u128,
+    /// Index into the locations
+    pub location: u32,
+}
+
+/// Imported ranges and names in the project's own layout
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImportedIpData {
+    pub ipv4_country: Vec<Ipv4Country>,
+    pub ipv6_country: Vec<Ipv6Country>,
+    pub ipv4_asn: Vec<Ipv4Asn>,
+    pub ipv6_asn: Vec<Ipv6Asn>,
+    pub ipv4_geo: Vec<Ipv4Geo>,
+    pub ipv6_geo: Vec<Ipv6Geo>,
+    pub countries: Vec<Country>,
+    pub autonomous_systems: Vec<AutonomousSystem>,
+    pub locations: Vec<GeoLocation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpImportSummary {
+    pub ipv4_country_ranges: usize,
+    pub ipv6_country_ranges: usize,
+    pub ipv4_asn_ranges: usize,
+    pub ipv6_asn_ranges: usize,
+    pub ipv4_geo_ranges: usize,
+    pub ipv6_geo_ranges: usize,
+    pub countries: usize,
+    pub autonomous_systems: usize,
+    pub locations: usize,
+}
+
+impl ImportedIpData {
+    pub fn summary(&self) -> IpImportSummary {
+        IpImportSummary {
+            ipv4_country_ranges: self.ipv4_country.len(),
+            ipv6_country_ranges: self.ipv6_country.len(),
+            ipv4_asn_ranges: self.ipv4_asn.len(),
+            ipv6_asn_ranges: self.ipv6_asn.len(),
+            ipv4_geo_ranges: self.ipv4_geo.len(),
+            ipv6_geo_ranges: self.ipv6_geo.len(),
+            countries: self.countries.len(),
+            autonomous_systems: self.autonomous_systems.len(),
+            locations: self.locations.len(),
+        }
+    }
+    /// Number of ranges in all tables. Stored in the file header
+    pub fn range_count(&self) -> u64 {
+        (self.ipv4_country.len()
+            + self.ipv6_country.len()
+            + self.ipv4_asn.len()
+            + self.ipv6_asn.len()
+            + self.ipv4_geo.len()
+            + self.ipv6_geo.len()) as u64
+    }
+    /// Index of the first range ending before it starts or pointing to a missing location,
+    /// counted across the tables in file order
+    pub fn invalid_range_index(&self) -> Option<usize> {
+        let location_count = self.locations.len();
+        self.ipv4_country
+            .iter()
+            .map(|r| r.is_valid())
+            .chain(self.ipv6_country.iter().map(|r| r.is_valid()))
+            .chain(self.ipv4_asn.iter().map(|r| r.is_valid()))
+            .chain(self.ipv6_asn.iter().map(|r| r.is_valid()))
+            .chain(self.ipv4_geo.iter().map(|r| r.ip_from <= r.ip_to && (r.location as usize) < location_count))
+            .chain(self.ipv6_geo.iter().map(|r| r.ip_from <= r.ip_to && (r.location as usize) < location_count))
+            .position(|valid| !valid)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ipv4_country.is_empty()
+            && self.ipv6_country.is_empty()
+            && self.ipv4_asn.is_empty()
+            && self.ipv6_asn.is_empty()
+            && self.ipv4_geo.is_empty()
+            && self.ipv6_geo.is_empty()
+    }
+    /// Range tables present in other replace ours. Names are merged
+    pub fn merge(&mut self, other: ImportedIpData) {
+        if !other.ipv4_country.is_empty() {
+            self.ipv4_country = other.ipv4_country;
+        }
+        if !other.ipv6_country.is_empty() {
+            self.ipv6_country = other.ipv6_country;
+        }
+        if !other.ipv4_asn.is_empty() {
+            self.ipv4_asn = other.ipv4_asn;
+        }
+        if !other.ipv6_asn.is_empty() {
+            self.ipv6_asn = other.ipv6_asn;
+        }
+        let offset = self.locations.len() as u32;
+        if !other.ipv4_geo.is_empty() {
+            self.ipv4_geo = other
+                .ipv4_geo
+                .into_iter()
+                .map(|geo| Ipv4Geo { location: geo.location + offset, ..geo })
+                .collect();
+        }
+        if !other.ipv6_geo.is_empty() {
+            self.ipv6_geo = other
+                .ipv6_geo
+                .into_iter()
+                .map(|geo| Ipv6Geo { location: geo.location + offset, ..geo })
+                .collect();
+        }
+        self.locations.extend(other.locations);
+        self.compact_locations();
+        let mut countries: HashMap<String, String> = self
+            .countries
+            .drain(..)
+            .map(|c| (c.country_code, c.country_name))
+            .collect();
+        countries.extend(other.countries.into_iter().map(|c| (c.country_code, c.country_name)));
+        self.countries = countries
+            .into_iter()
+            .map(|(country_code, country_name)| Country { country_code: country_code, country_name: country_name })
+            .collect();
+        let mut autonomous_systems: HashMap<u32, String> = self
+            .autonomous_systems
+            .drain(..)
+            .map(|a| (a.asn, a.as_name))
+            .collect();
+        autonomous_systems.extend(other.autonomous_systems.into_iter().map(|a| (a.asn, a.as_name)));
+        self.autonomous_systems = autonomous_systems
+            .into_iter()
+            .map(|(asn, as_name)| AutonomousSystem { asn: asn, as_name: as_name })
+            .collect();
+    }
+    /// Drop locations no longer referenced by a range
+    fn compact_locations(&mut self) {
+        let mut new_index: HashMap<u32, u32> = HashMap::new();
+        let mut locations: Vec<GeoLocation> = Vec::new();
+        let mut remap = |location: u32| -> u32 {
+            *new_index.entry(location).or_insert_with(|| {
+                locations.push(self.locations[location as usize].clone());
+                (locations.len() - 1) as u32
+            })
+        };
+        for geo in self.ipv4_geo.iter_mut() {
+            geo.location = remap(geo.location);
+        }
+        for geo in self.ipv6_geo.iter_mut() {
+            geo.location = remap(geo.location);
+        }
+        self.locations = locations;
+    }
+    /// Replace the tables of the IP database this import has data for
+    pub fn apply(&self, ipdb: &mut IpDatabase) {
+        if !self.ipv4_country.is_empty() {
+            ipdb.ipv4_country_map = self
+                .ipv4_country
+                .iter()
+                .map(|r| (r.ip_from..=r.ip_to, CountryCode::new(&r.country_code)))
+                .collect();
+        }
+        if !self.ipv6_country.is_empty() {
+            ipdb.ipv6_country_map = self
+                .ipv6_country
+                .iter()
+                .map(|r| (r.ip_from..=r.ip_to, CountryCode::new(&r.country_code)))
+                .collect();
+        }
+        if !self.ipv4_asn.is_empty() {
+            ipdb.ipv4_asn_map = self.ipv4_asn.iter().map(|r| (r.ip_from..=r.ip_to, r.asn)).collect();
+        }
+        if !self.ipv6_asn.is_empty() {
+            ipdb.ipv6_asn_map = self.ipv6_asn.iter().map(|r| (r.ip_from..=r.ip_to, r.asn)).collect();
+        }
+        if !self.locations.is_empty() {
+            ipdb.ipv4_geo_map = self.ipv4_geo.iter().map(|r| (r.ip_from..=r.ip_to, r.location)).collect();
+            ipdb.ipv6_geo_map = self.ipv6_geo.iter().map(|r| (r.ip_from..=r.ip_to, r.location)).collect();
+            ipdb.geo_locations = self.locations.clone();
+        }
+        for country in &self.countries {
+            ipdb.country_map
+                .insert(country.country_code.clone(), country.country_name.clone());
+        }
+        if !self.autonomous_systems.is_empty() {
+            ipdb.autonomous_map.extend(
+                self.autonomous_systems
+                    .iter()
+                    .map(|autonomous_system| (autonomous_system.asn, autonomous_system.as_name.as_str())),
+            );
+        }
+    }
+}
+
+pub fn imported_file_path() -> Option<PathBuf> {
+    update::db_dir_path().map(|dir| dir.join(IMPORTED_DB_FILE_NAME))
+}
+
+/// Previously imported data, if any. A file that fails the integrity checks is an error
+pub fn load_imported() -> Result<Option<ImportedIpData>, DbError> {
+    let path = match imported_file_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(None),
+    };
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Err(DbError::Io {
+                file_name: IMPORTED_DB_FILE_NAME.to_owned(),
+                message: e.to_string(),
+            })
+        }
+    };
+    let (data, record_count): (ImportedIpData, u64) = bin::decode(&bytes, IMPORTED_DB_FILE_NAME)?;
+    if data.range_count() != record_count {
+        return Err(DbError::RecordCount {
+            file_name: IMPORTED_DB_FILE_NAME.to_owned(),
+            expected: record_count,
+            actual: data.range_count(),
+        });
+    }
+    if let Some(index) = data.invalid_range_index() {
+        return Err(DbError::InvalidRecord { file_name: IMPORTED_DB_FILE_NAME.to_owned(), index: index });
+    }
+    Ok(Some(data))
+}
+
+pub fn save_imported(data: &ImportedIpData) -> Result<(), String> {
+    let path = match imported_file_path() {
+        Some(path) => path,
+        None => return Err(String::from("Config directory not found")),
+    };
+    if let Some(dir) = path.parent() {
+        match fs::create_dir_all(dir) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Failed to create {}: {}", dir.display(), e)),
+        }
+    }
+    let bytes = match bin::encode(data, data.range_count(), IMPORTED_DB_FILE_NAME) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(e.to_string()),
+    };
+    match fs::write(&path, bytes) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+    }
+}
+
+pub fn clear_imported() -> Result<(), String> {
+    match imported_file_path() {
+        Some(path) if path.exists() => match fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Convert a database file. Nothing is saved or applied
+pub fn import_file(options: &IpImportOptions) -> Result<ImportedIpData, String> {
+    let path = PathBuf::from(&options.path);
+    let format = match options.format {
+        Some(format) => format,
+        None => IpImportFormat::detect(&path)?,
+    };
+    let mut builder = ImportBuilder::new();
+    match format {
+        IpImportFormat::Mmdb => import_mmdb(&path, &mut builder)?,
+        IpImportFormat::GeoIpCsv => {
+            let locations_path = match &options.locations_path {
+                Some(locations_path) if !locations_path.is_empty() => Some(PathBuf::from(locations_path)),
+                _ => find_locations_file(&path),
+            };
+            import_geoip_csv(&path, locations_path, &mut builder)?
+        }
+        IpImportFormat::Ip2AsnTsv => import_ip2asn_tsv(&path, &mut builder)?,
+        IpImportFormat::Ip2LocationCsv => import_ip2location_csv(&path, &mut builder)?,
+    }
+    let data = builder.finish();
+    if data.is_empty() {
+        return Err(format!("No usable data in {}", path.display()));
+    }
+    Ok(data)
+}
+
+/// Country, AS and location of one range in the source file
+#[derive(Debug, Clone)]
+struct ImportRecord {
+    country_code: String,
+    country_name: String,
+    asn: u32,
+    as_name: String,
+    location: GeoLocation,
+}
+
+impl ImportRecord {
+    fn new() -> ImportRecord {
+        ImportRecord {
+            country_code: String::new(),
+            country_name: String::new(),
+            asn: 0,
+            as_name: String::new(),
+            location: GeoLocation::new(),
+        }
+    }
+}
+
+/// Ranges are kept as u128 with the IPv4 ones in their own lists
+struct ImportBuilder {
+    ipv4_country: Vec<(u128, u128, String)>,
+    ipv6_country: Vec<(u128, u128, String)>,
+    ipv4_asn: Vec<(u128, u128, u32)>,
+    ipv6_asn: Vec<(u128, u128, u32)>,
+    ipv4_geo: Vec<(u128, u128, u32)>,
+    ipv6_geo: Vec<(u128, u128, u32)>,
+    countries: HashMap<String, String>,
+    autonomous_systems: HashMap<u32, String>,
+    locations: Vec<GeoLocation>,
+    location_ids: HashMap<String, u32>,
+}
+
+impl ImportBuilder {
+    fn new() -> ImportBuilder {
+        ImportBuilder {
+            ipv4_country: Vec::new(),
+            ipv6_country: Vec::new(),
+            ipv4_asn: Vec::new(),
+            ipv6_asn: Vec::new(),
+            ipv4_geo: Vec::new(),
+            ipv6_geo: Vec::new(),
+            countries: HashMap::new(),
+            autonomous_systems: HashMap::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+        }
+    }
+    /// Rows ending before they start or IPv4 rows past 255.255.255.255 are skipped
+    fn add(&mut self, is_ipv4: bool, from: u128, to: u128, record: &ImportRecord) {
+        if from > to || (is_ipv4 && to > u32::MAX as u128) {
+            return;
+        }
+        let country_code = record.country_code.trim();
+        if is_valid_country_code(country_code) {
+            let list = if is_ipv4 { &mut self.ipv4_country } else { &mut self.ipv6_country };
+            push_range(list, from, to, country_code.to_uppercase());
+            if !record.country_name.is_empty() {
+                self.countries
+                    .insert(country_code.to_uppercase(), record.country_name.clone());
+            }
+        }
+        if record.asn != 0 {
+            let list = if is_ipv4 { &mut self.ipv4_asn } else { &mut self.ipv6_asn };
+            push_range(list, from, to, record.asn);
+            if !record.as_name.is_empty() {
+                self.autonomous_systems.insert(record.asn, record.as_name.clone());
+            }
+        }
+        if !record.location.is_empty() {
+            let key = record.location.key();
+            let location = match self.location_ids.get(&key) {
+                Some(id) => *id,
+                None => {
+                    self.locations.push(record.location.clone());
+                    let id = (self.locations.len() - 1) as u32;
+                    self.location_ids.insert(key, id);
+                    id
+                }
+            };
+            let list = if is_ipv4 { &mut self.ipv4_geo } else { &mut self.ipv6_geo };
+            push_range(list, from, to, location);
+        }
+    }
+    fn add_network(&mut self, net: IpNet, record: &ImportRecord) {
+        match net {
+            IpNet::V4(net) => self.add(true, u32::from(net.network()) as u128, u32::from(net.broadcast()) as u128, record),
+            IpNet::V6(net) => self.add_range(u128::from(net.network()), u128::from(net.broadcast()), false, record),
+        }
+    }
+    /// IPv6 range, stored as IPv4 when it is in ::ffff:0:0/96
+    fn add_range(&mut self, from: u128, to: u128, is_ipv4: bool, record: &ImportRecord) {
+        if is_ipv4 {
+            self.add(true, from, to, record);
+        } else if from >= IPV4_MAPPED_PREFIX && to <= IPV4_MAPPED_PREFIX | 0xffff_ffff {
+            self.add(true, from - IPV4_MAPPED_PREFIX, to - IPV4_MAPPED_PREFIX, record);
+        } else {
+            self.add(false, from, to, record);
+        }
+    }
+    fn finish(self) -> ImportedIpData {
+        ImportedIpData {
+            ipv4_country: self
+                .ipv4_country
+                .into_iter()
+                .map(|(from, to, country_code)| Ipv4Country { ip_from: from as u32, ip_to: to as u32, country_code: country_code })
+                .collect(),
+            ipv6_country: self
+                .ipv6_country
+                .into_iter()
+                .map(|(from, to, country_code)| Ipv6Country { ip_from: from, ip_to: to, country_code: country_code })
+                .collect(),
+            ipv4_asn: self
+                .ipv4_asn
+                .into_iter()
+                .map(|(from, to, asn)| Ipv4Asn { ip_from: from as u32, ip_to: to as u32, asn: asn })
+                .collect(),
+            ipv6_asn: self
+                .ipv6_asn
+                .into_iter()
+                .map(|(from, to, asn)| Ipv6Asn { ip_from: from, ip_to: to, asn: asn })
+                .collect(),
+            ipv4_geo: self
+                .ipv4_geo
+                .into_iter()
+                .map(|(from, to, location)| Ipv4Geo { ip_from: from as u32, ip_to: to as u32, location: location })
+                .collect(),
+            ipv6_geo: self
+                .ipv6_geo
+                .into_iter()
+                .map(|(from, to, location)| Ipv6Geo { ip_from: from, ip_to: to, location: location })
+                .collect(),
+            countries: self
+                .countries
+                .into_iter()
+                .map(|(country_code, country_name)| Country { country_code: country_code, country_name: country_name })
+                .collect(),
+            autonomous_systems: self
+                .autonomous_systems
+                .into_iter()
+                .map(|(asn, as_name)| AutonomousSystem { asn: asn, as_name: as_name })
+                .collect(),
+            locations: self.locations,
+        }
+    }
+}
+
+/// Append a range, extending the previous one if adjacent with the same value
+fn push_range<V: PartialEq>(list: &mut Vec<(u128, u128, V)>, from: u128, to: u128, value: V) {
+    if let Some(last) = list.last_mut() {
+        if last.1.checked_add(1) == Some(from) && last.2 == value {
+            last.1 = to;
+            return;
+        }
+    }
+    list.push((from, to, value));
+}
+
+fn is_valid_country_code(country_code: &str) -> bool {
+    match country_code {
+        "" | "-" | "ZZ" | "None" => false,
+        _ => country_code.len() == 2,
+    }
+}
+
+fn read_first_line(path: &Path) -> Result<String, String> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+    };
+    let mut line = String::new();
+    match BufReader::new(file).read_line(&mut line) {
+        Ok(_) => Ok(line.trim_start_matches('\u{feff}').trim().to_string()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn open_lines(path: &Path) -> Result<std::io::Lines<BufReader<fs::File>>, String> {
+    match fs::File::open(path) {
+        Ok(file) => Ok(BufReader::new(file).lines()),
+        Err(e) => Err(format!("Failed to open {}: {}", path.display(), e)),
+    }
+}
+
+/// Split a CSV line. Handles quoted fields and doubled quotes
+pub fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn mmdb_record(value: &MmdbValue) -> ImportRecord {
+    let mut record = ImportRecord::new();
+    let country = match value.get(&["country"]) {
+        Some(country) => Some(country),
+        None => value.get(&["registered_country"]),
+    };
+    if let Some(country) = country {
+        record.country_code = country.get_str(&["iso_code"]).unwrap_or("").to_string();
+        record.country_name = country.get_str(&["names", "en"]).unwrap_or("").to_string();
+    }
+    record.asn = value
+        .get(&["autonomous_system_number"])
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as u32;
+    record.as_name = value
+        .get_str(&["autonomous_system_organization"])
+        .unwrap_or("")
+        .to_string();
+    record.location.city = value.get_str(&["city", "names", "en"]).unwrap_or("").to_string();
+    record.location.latitude = value.get(&["location", "latitude"]).and_then(|v| v.as_f64());
+    record.location.longitude = value.get(&["location", "longitude"]).and_then(|v| v.as_f64());
+    record.location.organization = value
+        .get_str(&["organization"])
+        .or_else(|| value.get_str(&["traits", "organization"]))
+        .or_else(|| value.get_str(&["isp"]))
+        .unwrap_or("")
+        .to_string();
+    record
+}
+
+fn import_mmdb(path: &Path, builder: &mut ImportBuilder) -> Result<(), String> {
+    let reader = MmdbReader::open(&path.to_path_buf())?;
+    // Most networks share a record. Decode each once
+    let mut records: HashMap<usize, ImportRecord> = HashMap::new();
+    for network in reader.networks()? {
+        if !records.contains_key(&network.data_offset) {
+            let value = reader.decode_data(network.data_offset)?;
+            records.insert(network.data_offset, mmdb_record(&value));
+        }
+        let record = &records[&network.data_offset];
+        match IpNet::new(network.ip_addr, network.prefix_len) {
+            Ok(net) => builder.add_network(net, record),
+            Err(e) => return Err(format!("Invalid network in {}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// GeoLite2-City-Blocks-IPv4.csv -> GeoLite2-City-Locations-en.csv
+fn find_locations_file(blocks_path: &Path) -> Option<PathBuf> {
+    let file_name = blocks_path.file_name()?.to_string_lossy().to_string();
+    for blocks in ["Blocks-IPv4", "Blocks-IPv6"] {
+        if file_name.contains(blocks) {
+            let path = blocks_path.with_file_name(file_name.replace(blocks, "Locations-en"));
+            if path.exists() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// Column index by name in a header line
+fn header_index(header: &[String]) -> HashMap<String, usize> {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.trim().trim_start_matches('\u{feff}').to_string(), i))
+        .collect()
+}
+
+fn csv_field<'a>(fields: &'a [String], columns: &HashMap<String, usize>, name: &str) -> &'a str {
+    match columns.get(name).and_then(|i| fields.get(*i)) {
+        Some(field) => field.trim(),
+        None => "",
+    }
+}
+
+fn import_geoip_csv(path: &Path, locations_path: Option<PathBuf>, builder: &mut ImportBuilder) -> Result<(), String> {
+    let mut lines = open_lines(path)?;
+    let header = match lines.next() {
+        Some(Ok(line)) => split_csv_line(&line, ','),
+        _ => return Err(format!("Empty file: {}", path.display())),
+    };
+    let columns = header_index(&header);
+    if !columns.contains_key("network") {
+        return Err(format!("No network column in {}", path.display()));
+    }
+    // geoname_id -> country code, country name, city
+    let mut locations: HashMap<String, (String, String, String)> = HashMap::new();
+    if columns.contains_key("geoname_id") {
+        let locations_path = match locations_path {
+            Some(locations_path) => locations_path,
+            None => return Err(String::from("The locations file is required for country and city blocks")),
+        };
+        let mut location_lines = open_lines(&locations_path)?;
+        let location_columns = match location_lines.next() {
+            Some(Ok(line)) => header_index(&split_csv_line(&line, ',')),
+            _ => return Err(format!("Empty file: {}", locations_path.display())),
+        };
+        for line in location_lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(e.to_string()),
+            };
+            let fields = split_csv_line(&line, ',');
+            locations.insert(
+                csv_field(&fields, &location_columns, "geoname_id").to_string(),
+                (
+                    csv_field(&fields, &location_columns, "country_iso_code").to_string(),
+                    csv_field(&fields, &location_columns, "country_name").to_string(),
+                    csv_field(&fields, &location_columns, "city_name").to_string(),
+                ),
+            );
+        }
+    }
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Err(e.to_string()),
+        };
+        let fields = split_csv_line(&line, ',');
+        let net = match csv_field(&fields, &columns, "network").parse::<IpNet>() {
+            Ok(net) => net,
+            Err(_) => continue,
+        };
+        let mut record = ImportRecord::new();
+        let mut geoname_id = csv_field(&fields, &columns, "geoname_id");
+        if geoname_id.is_empty() {
+            geoname_id = csv_field(&fields, &columns, "registered_country_geoname_id");
+        }
+        if let Some((country_code, country_name, city)) = locations.get(geoname_id) {
+            record.country_code = country_code.clone();
+            record.country_name = country_name.clone();
+            record.location.city = city.clone();
+        }
+        record.asn = csv_field(&fields, &columns, "autonomous_system_number")
+            .parse()
+            .unwrap_or(0);
+        record.as_name = csv_field(&fields, &columns, "autonomous_system_organization").to_string();
+        record.location.latitude = csv_field(&fields, &columns, "latitude").parse().ok();
+        record.location.longitude = csv_field(&fields, &columns, "longitude").parse().ok();
+        record.location.organization = match csv_field(&fields, &columns, "organization") {
+            "" => csv_field(&fields, &columns, "isp").to_string(),
+            organization => organization.to_string(),
+        };
+        builder.add_network(net, &record);
+    }
+    Ok(())
+}
+
+/// Address or decimal number. IPv4 when written as IPv4 or as a 32 bit number
+fn parse_ip_number(s: &str, is_ipv4_file: bool) -> Option<(u128, bool)> {
+    let s = s.trim();
+    if let Ok(ipv4) = s.parse::<Ipv4Addr>() {
+        return Some((u32::from(ipv4) as u128, true));
+    }
+    if let Ok(ipv6) = s.parse::<Ipv6Addr>() {
+        return Some((u128::from(ipv6), false));
+    }
+    match s.parse::<u128>() {
+        Ok(n) => Some((n, is_ipv4_file)),
+        Err(_) => None,
+    }
+}
+
+fn import_ip2asn_tsv(path: &Path, builder: &mut ImportBuilder) -> Result<(), String> {
+    let is_ipv4_file = path.to_string_lossy().contains("v4");
+    for line in open_lines(path)? {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Err(e.to_string()),
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let (from, to, is_ipv4) = match (parse_ip_number(fields[0], is_ipv4_file), parse_ip_number(fields[1], is_ipv4_file)) {
+            (Some((from, is_ipv4)), Some((to, to_is_ipv4))) if is_ipv4 == to_is_ipv4 => (from, to, is_ipv4),
+            _ => continue,
+        };
+        let mut record = ImportRecord::new();
+        record.asn = fields[2].trim().parse().unwrap_or(0);
+        record.country_code = fields[3].trim().to_string();
+        record.as_name = fields[4].trim().to_string();
+        builder.add_range(from, to, is_ipv4, &record);
+    }
+    Ok(())
+}
+
+fn import_ip2location_csv(path: &Path, builder: &mut ImportBuilder) -> Result<(), String> {
+    for line in open_lines(path)? {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Err(e.to_string()),
+        };
+        let fields = split_csv_line(&line, ',');
+        if fields.len() < 4 {
+            continue;
+        }
+        let (from, to) = match (fields[0].trim().parse::<u128>(), fields[1].trim().parse::<u128>()) {
+            (Ok(from), Ok(to)) => (from, to),
+            _ => continue,
+        };
+        let is_ipv4 = to <= u32::MAX as u128;
+        let mut record = ImportRecord::new();
+        if fields.len() == 5 && fields[2].contains('/') {
+            // ASN: ip_from, ip_to, cidr, asn, as
+            record.asn = fields[3].trim().parse().unwrap_or(0);
+            record.as_name = fields[4].trim().to_string();
+        } else {
+            // DB1: ip_from, ip_to, country_code, country_name [, region_name, city_name [, latitude, longitude]]
+            if !is_valid_country_code(fields[2].trim()) {
+                continue;
+            }
+            record.country_code = fields[2].trim().to_string();
+            record.country_name = fields[3].trim().to_string();
+            if fields.len() >= 6 && fields[5].trim() != "-" {
+                record.location.city = fields[5].trim().to_string();
+            }
+            if fields.len() >= 8 {
+                record.location.latitude = fields[6].trim().parse().ok();
+                record.location.longitude = fields[7].trim().parse().ok();
+            }
+        }
+        builder.add_range(from, to, is_ipv4, &record);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("netpulsar-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn ip2asn_rows_with_invalid_ranges_are_skipped() {
+        let path = temp_file(
+            "ip2asn-v4-u32.tsv",
+            "16777216\t16777471\t13335\tUS\tCLOUDFLARENET\n\
+             16778240\t16777472\t4134\tCN\tCHINANET\n\
+             4294967040\t4294967551\t64512\tZZ\tTOO-LARGE\n\
+             1.0.4.0\t2001:db8::\t38803\tAU\tMIXED\n",
+        );
+        let mut builder = ImportBuilder::new();
+        import_ip2asn_tsv(&path, &mut builder).unwrap();
+        let data = builder.finish();
+        assert_eq!(data.ipv4_asn, vec![Ipv4Asn { ip_from: 16777216, ip_to: 16777471, asn: 13335 }]);
+        assert!(data.ipv6_asn.is_empty());
+        assert_eq!(data.invalid_range_index(), None);
+        // Nothing of the skipped rows is kept
+        assert_eq!(data.ipv4_country.len(), 1);
+        assert_eq!(data.ipv4_country[0].country_code, "US");
+        assert!(data.ipv6_country.is_empty());
+        assert_eq!(data.autonomous_systems.len(), 1);
+        assert_eq!(data.autonomous_systems[0].asn, 13335);
+        let mut ipdb = IpDatabase::new();
+        data.apply(&mut ipdb);
+        assert_eq!(ipdb.get_ipv4_info(Ipv4Addr::new(1, 0, 0, 1)).map(|info| info.asn), Some(13335));
+        assert!(ipdb.get_ipv4_info(Ipv4Addr::new(1, 0, 1, 0)).is_none());
+        assert!(ipdb.get_ipv4_info(Ipv4Addr::new(1, 0, 4, 0)).is_none());
+        assert!(ipdb.get_ipv4_info(Ipv4Addr::new(255, 255, 255, 0)).is_none());
+    }
+
+    #[test]
+    fn ip2location_rows_with_invalid_ranges_are_skipped() {
+        let path = temp_file(
+            "IP2LOCATION-LITE-DB1.CSV",
+            "\"16777216\",\"16777471\",\"US\",\"United States of America\"\n\
+             \"16778240\",\"16777472\",\"CN\",\"China\"\n",
+        );
+        let mut builder = ImportBuilder::new();
+        import_ip2location_csv(&path, &mut builder).unwrap();
+        let data = builder.finish();
+        assert_eq!(data.ipv4_country.len(), 1);
+        assert_eq!(data.ipv4_country[0].ip_from, 16777216);
+        assert_eq!(data.ipv4_country[0].ip_to, 16777471);
+    }
+
+    #[test]
+    fn invalid_ranges_are_found() {
+        let mut data = ImportedIpData::default();
+        data.ipv4_asn.push(Ipv4Asn { ip_from: 1, ip_to: 2, asn: 1 });
+        data.ipv4_asn.push(Ipv4Asn { ip_from: 4, ip_to: 3, asn: 2 });
+        assert_eq!(data.invalid_range_index(), Some(1));
+        data.ipv4_asn.pop();
+        data.ipv6_geo.push(Ipv6Geo { ip_from: 1, ip_to: 2, location: 0 });
+        assert_eq!(data.invalid_range_index(), Some(1));
+    }
+}
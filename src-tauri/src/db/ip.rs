@@ -1,66 +1,114 @@
 use crate::db;
-use rangemap::RangeInclusiveMap;
+use crate::db::bin;
+use crate::db::error::DbError;
+use crate::db::import::GeoLocation;
+use crate::db::update::DatabaseKind;
+use crate::db::range::{CountryCode, NameTable, RangeTable};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    mem::size_of,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Instant,
 };
 
-/// In-memory IP database with range map and hash map
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Lookup addresses per benchmark run when not specified
+pub const DEFAULT_BENCHMARK_ITERATIONS: usize = 100_000;
+
+/// In-memory IP database with sorted range tables and hash map
+#[derive(Debug, Clone, PartialEq)]
 pub struct IpDatabase {
-    pub ipv4_country_map: RangeInclusiveMap<u32, String>,
-    pub ipv6_country_map: RangeInclusiveMap<u128, String>,
-    pub ipv4_asn_map: RangeInclusiveMap<u32, u32>,
-    pub ipv6_asn_map: RangeInclusiveMap<u128, u32>,
+    pub ipv4_country_map: RangeTable<u32, CountryCode>,
+    pub ipv6_country_map: RangeTable<u128, CountryCode>,
+    pub ipv4_asn_map: RangeTable<u32, u32>,
+    pub ipv6_asn_map: RangeTable<u128, u32>,
     pub country_map: HashMap<String, String>,
-    pub autonomous_map: HashMap<u32, String>,
+    pub autonomous_map: NameTable,
+    /// Index into geo_locations. Only filled by imported databases
+    pub ipv4_geo_map: RangeTable<u32, u32>,
+    pub ipv6_geo_map: RangeTable<u128, u32>,
+    pub geo_locations: Vec<GeoLocation>,
 }
 
 impl IpDatabase {
     pub fn new() -> IpDatabase {
         IpDatabase {
-            ipv4_country_map: RangeInclusiveMap::new(),
-            ipv6_country_map: RangeInclusiveMap::new(),
-            ipv4_asn_map: RangeInclusiveMap::new(),
-            ipv6_asn_map: RangeInclusiveMap::new(),
+            ipv4_country_map: RangeTable::new(),
+            ipv6_country_map: RangeTable::new(),
+            ipv4_asn_map: RangeTable::new(),
+            ipv6_asn_map: RangeTable::new(),
             country_map: HashMap::new(),
-            autonomous_map: HashMap::new(),
+            autonomous_map: NameTable::new(),
+            ipv4_geo_map: RangeTable::new(),
+            ipv6_geo_map: RangeTable::new(),
+            geo_locations: Vec::new(),
         }
     }
-    pub fn load() -> Result<IpDatabase, Box<dyn std::error::Error>> {
+    /// Load every table. An invalid installed file falls back to the embedded copy.
+    /// Tables without a usable source stay empty. Returns the problems found
+    pub fn load() -> (IpDatabase, Vec<DbError>) {
         let mut ip_db = IpDatabase::new();
-        ip_db.load_ipv4_country_map()?;
-        ip_db.load_ipv6_country_map()?;
-        ip_db.load_ipv4_asn_map()?;
-        ip_db.load_ipv6_asn_map()?;
-        ip_db.load_country_map()?;
-        ip_db.load_autonomous_map()?;
-        Ok(ip_db)
+        let mut errors: Vec<DbError> = Vec::new();
+        ip_db.load_ipv4_country_map(&mut errors);
+        ip_db.load_ipv6_country_map(&mut errors);
+        ip_db.load_ipv4_asn_map(&mut errors);
+        ip_db.load_ipv6_asn_map(&mut errors);
+        ip_db.load_country_map(&mut errors);
+        ip_db.load_autonomous_map(&mut errors);
+        match db::import::load_imported() {
+            Ok(Some(imported)) => imported.apply(&mut ip_db),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+        (ip_db, errors)
     }
-    pub fn load_ipv4_country_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.ipv4_country_map = db::ipv4_country::get_map();
-        Ok(())
+    pub fn load_ipv4_country_map(&mut self, errors: &mut Vec<DbError>) {
+        self.ipv4_country_map = bin::load_table(
+            DatabaseKind::Ipv4Country,
+            db::ipv4_country::get_map_from_file,
+            db::ipv4_country::get_map,
+            errors,
+        );
     }
-    pub fn load_ipv6_country_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.ipv6_country_map = db::ipv6_country::get_map();
-        Ok(())
+    pub fn load_ipv6_country_map(&mut self, errors: &mut Vec<DbError>) {
+        self.ipv6_country_map = bin::load_table(
+            DatabaseKind::Ipv6Country,
+            db::ipv6_country::get_map_from_file,
+            db::ipv6_country::get_map,
+            errors,
+        );
     }
-    pub fn load_ipv4_asn_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.ipv4_asn_map = db::ipv4_asn::get_map();
-        Ok(())
+    pub fn load_ipv4_asn_map(&mut self, errors: &mut Vec<DbError>) {
+        self.ipv4_asn_map = bin::load_table(
+            DatabaseKind::Ipv4Asn,
+            db::ipv4_asn::get_map_from_file,
+            db::ipv4_asn::get_map,
+            errors,
+        );
     }
-    pub fn load_ipv6_asn_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.ipv6_asn_map = db::ipv6_asn::get_map();
-        Ok(())
+    pub fn load_ipv6_asn_map(&mut self, errors: &mut Vec<DbError>) {
+        self.ipv6_asn_map = bin::load_table(
+            DatabaseKind::Ipv6Asn,
+            db::ipv6_asn::get_map_from_file,
+            db::ipv6_asn::get_map,
+            errors,
+        );
     }
-    pub fn load_country_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.country_map = db::country::get_map();
-        Ok(())
+    pub fn load_country_map(&mut self, errors: &mut Vec<DbError>) {
+        self.country_map = bin::load_table(
+            DatabaseKind::Country,
+            db::country::get_map_from_file,
+            db::country::get_map,
+            errors,
+        );
     }
-    pub fn load_autonomous_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.autonomous_map = db::asn::get_map();
-        Ok(())
+    pub fn load_autonomous_map(&mut self, errors: &mut Vec<DbError>) {
+        self.autonomous_map = bin::load_table(
+            DatabaseKind::As,
+            db::asn::get_map_from_file,
+            db::asn::get_map,
+            errors,
+        );
     }
     pub fn get_ipv4_info(&self, ipv4_addr: Ipv4Addr) -> Option<IpInfo> {
         let mut ip_info = IpInfo::new(IpAddr::V4(ipv4_addr));
@@ -70,7 +118,7 @@ impl IpDatabase {
         match self.ipv4_country_map.get(&(ip_addr_int as u32)) {
             Some(country_code) => {
                 ip_info.country_code = country_code.to_string();
-                match self.country_map.get(country_code) {
+                match self.country_map.get(country_code.as_str()) {
                     Some(country_name) => {
                         ip_info.country_name = country_name.to_string();
                     }
@@ -91,10 +139,14 @@ impl IpDatabase {
             }
             None => {}
         }
+        if let Some(location) = self.ipv4_geo_map.get(&(ip_addr_int as u32)) {
+            ip_info.set_location(&self.geo_locations[*location as usize]);
+        }
         if (ip_info.country_code.is_empty()
             || ip_info.country_code == "ZZ"
             || ip_info.country_code == "-")
             && ip_info.asn == 0
+            && ip_info.city.is_empty()
         {
             return None;
         } else {
@@ -109,7 +161,7 @@ impl IpDatabase {
         match self.ipv6_country_map.get(&(ip_addr_int)) {
             Some(country_code) => {
                 ip_info.country_code = country_code.to_string();
-                match self.country_map.get(country_code) {
+                match self.country_map.get(country_code.as_str()) {
                     Some(country_name) => {
                         ip_info.country_name = country_name.to_string();
                     }
@@ -130,16 +182,130 @@ impl IpDatabase {
             }
             None => {}
         }
+        if let Some(location) = self.ipv6_geo_map.get(&ip_addr_int) {
+            ip_info.set_location(&self.geo_locations[*location as usize]);
+        }
         if (ip_info.country_code.is_empty()
             || ip_info.country_code == "ZZ"
             || ip_info.country_code == "-")
             && ip_info.asn == 0
+            && ip_info.city.is_empty()
         {
             return None;
         } else {
             Some(ip_info)
         }
     }
+    /// Estimated heap usage of each table
+    pub fn memory_report(&self) -> IpDatabaseMemoryReport {
+        let country_bytes = self.country_map.capacity() * (size_of::<String>() * 2 + 1)
+            + self
+                .country_map
+                .iter()
+                .map(|(code, name)| code.capacity() + name.capacity())
+                .sum::<usize>();
+        let location_bytes = self.geo_locations.capacity() * size_of::<GeoLocation>()
+            + self
+                .geo_locations
+                .iter()
+                .map(|location| location.city.capacity() + location.organization.capacity())
+                .sum::<usize>();
+        let tables = vec![
+            TableMemoryUsage::new("ipv4_country", self.ipv4_country_map.len(), self.ipv4_country_map.heap_size()),
+            TableMemoryUsage::new("ipv6_country", self.ipv6_country_map.len(), self.ipv6_country_map.heap_size()),
+            TableMemoryUsage::new("ipv4_asn", self.ipv4_asn_map.len(), self.ipv4_asn_map.heap_size()),
+            TableMemoryUsage::new("ipv6_asn", self.ipv6_asn_map.len(), self.ipv6_asn_map.heap_size()),
+            TableMemoryUsage::new("country", self.country_map.len(), country_bytes),
+            TableMemoryUsage::new("as", self.autonomous_map.len(), self.autonomous_map.heap_size()),
+            TableMemoryUsage::new("ipv4_geo", self.ipv4_geo_map.len(), self.ipv4_geo_map.heap_size()),
+            TableMemoryUsage::new("ipv6_geo", self.ipv6_geo_map.len(), self.ipv6_geo_map.heap_size()),
+            TableMemoryUsage::new("geo_locations", self.geo_locations.len(), location_bytes),
+        ];
+        IpDatabaseMemoryReport {
+            total_bytes: tables.iter().map(|table| table.bytes).sum(),
+            tables: tables,
+            process_resident_bytes: crate::sys::get_process_resident_memory(),
+        }
+    }
+    /// Time lookups of pseudo-random addresses. Same sequence on every run
+    pub fn benchmark_lookup(&self, iterations: usize) -> LookupBenchmark {
+        let iterations = iterations.max(1);
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let ipv4_addrs: Vec<Ipv4Addr> = (0..iterations).map(|_| Ipv4Addr::from(next() as u32)).collect();
+        // Global unicast 2000::/3, where the tables have data
+        let ipv6_addrs: Vec<Ipv6Addr> = (0..iterations)
+            .map(|_| {
+                let addr = ((next() as u128) << 64) | next() as u128;
+                Ipv6Addr::from((addr >> 3) | (1u128 << 125))
+            })
+            .collect();
+        let mut ipv4_hits: usize = 0;
+        let start = Instant::now();
+        for addr in &ipv4_addrs {
+            if std::hint::black_box(self.get_ipv4_info(*addr)).is_some() {
+                ipv4_hits += 1;
+            }
+        }
+        let ipv4_elapsed = start.elapsed();
+        let mut ipv6_hits: usize = 0;
+        let start = Instant::now();
+        for addr in &ipv6_addrs {
+            if std::hint::black_box(self.get_ipv6_info(*addr)).is_some() {
+                ipv6_hits += 1;
+            }
+        }
+        let ipv6_elapsed = start.elapsed();
+        LookupBenchmark {
+            iterations: iterations,
+            ipv4_ns_per_lookup: ipv4_elapsed.as_nanos() as f64 / iterations as f64,
+            ipv6_ns_per_lookup: ipv6_elapsed.as_nanos() as f64 / iterations as f64,
+            ipv4_hits: ipv4_hits,
+            ipv6_hits: ipv6_hits,
+        }
+    }
+}
+
+/// Estimated heap usage of one table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TableMemoryUsage {
+    pub name: String,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl TableMemoryUsage {
+    pub fn new(name: &str, entries: usize, bytes: usize) -> TableMemoryUsage {
+        TableMemoryUsage {
+            name: name.to_string(),
+            entries: entries,
+            bytes: bytes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpDatabaseMemoryReport {
+    pub tables: Vec<TableMemoryUsage>,
+    pub total_bytes: usize,
+    /// Resident memory of the whole process where the OS reports it
+    pub process_resident_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LookupBenchmark {
+    pub iterations: usize,
+    pub ipv4_ns_per_lookup: f64,
+    pub ipv6_ns_per_lookup: f64,
+    /// Addresses found in the database
+    pub ipv4_hits: usize,
+    pub ipv6_hits: usize,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -152,6 +318,10 @@ pub struct IpInfo {
     pub as_name: String,
     pub country_code: String,
     pub country_name: String,
+    pub city: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub organization: String,
 }
 
 impl IpInfo {
@@ -165,6 +335,16 @@ impl IpInfo {
             as_name: String::new(),
             country_code: String::new(),
             country_name: String::new(),
+            city: String::new(),
+            latitude: None,
+            longitude: None,
+            organization: String::new(),
         }
     }
+    pub fn set_location(&mut self, location: &GeoLocation) {
+        self.city = location.city.clone();
+        self.latitude = location.latitude;
+        self.longitude = location.longitude;
+        self.organization = location.organization.clone();
+    }
 }
@@ -1,6 +1,9 @@
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::db::range::RangeTable;
 use rangemap::RangeInclusiveMap;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 #[cfg(feature = "bundle")]
 pub const IPV4_ASN_BIN: &[u8] = include_bytes!("../../../resources/db/ipv4-asn.bin");
@@ -24,28 +27,33 @@ impl Ipv4Asn {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> RangeInclusiveMap<u32, u32> {
+impl DbRecord for Ipv4Asn {
+    const MIN_SIZE: usize = 12;
+    fn is_valid(&self) -> bool {
+        self.ip_from <= self.ip_to
+    }
+}
+
+fn to_map(ipv4_asn_vec: Vec<Ipv4Asn>) -> RangeTable<u32, u32> {
     let mut ipv4_asn_map: RangeInclusiveMap<u32, u32> = RangeInclusiveMap::new();
-    let ipv4_asn_vec: Vec<Ipv4Asn> = bincode::deserialize(IPV4_ASN_BIN).unwrap();
     for ipv4_asn in ipv4_asn_vec {
         ipv4_asn_map.insert(ipv4_asn.ip_from..=ipv4_asn.ip_to, ipv4_asn.asn);
     }
-    ipv4_asn_map
+    RangeTable::from(ipv4_asn_map)
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> RangeInclusiveMap<u32, u32> {
-    let mut ipv4_asn_map: RangeInclusiveMap<u32, u32> = RangeInclusiveMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let ipv4_asn_vec: Vec<Ipv4Asn> = bincode::deserialize(&f).unwrap();
-            for ipv4_asn in ipv4_asn_vec {
-                ipv4_asn_map.insert(ipv4_asn.ip_from..=ipv4_asn.ip_to, ipv4_asn.asn);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    ipv4_asn_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<RangeTable<u32, u32>, DbError> {
+    let ipv4_asn_vec: Vec<Ipv4Asn> = bin::decode_records(IPV4_ASN_BIN, IPV4_ASN_BIN_NAME)?;
+    Ok(to_map(ipv4_asn_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<RangeTable<u32, u32>, DbError> {
+    Err(DbError::NotBundled { file_name: IPV4_ASN_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<RangeTable<u32, u32>, DbError> {
+    let ipv4_asn_vec: Vec<Ipv4Asn> = bin::read_records(&file_path, IPV4_ASN_BIN_NAME)?;
+    Ok(to_map(ipv4_asn_vec))
 }
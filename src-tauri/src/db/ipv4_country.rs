@@ -1,6 +1,9 @@
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::db::range::{CountryCode, RangeTable};
 use rangemap::RangeInclusiveMap;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 #[cfg(feature = "bundle")]
 pub const IPV4_COUNTRY_BIN: &[u8] = include_bytes!("../../../resources/db/ipv4-country.bin");
@@ -24,34 +27,36 @@ impl Ipv4Country {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> RangeInclusiveMap<u32, String> {
-    let mut ipv4_country_map: RangeInclusiveMap<u32, String> = RangeInclusiveMap::new();
-    let ipv4_country_vec: Vec<Ipv4Country> = bincode::deserialize(IPV4_COUNTRY_BIN).unwrap();
+impl DbRecord for Ipv4Country {
+    const MIN_SIZE: usize = 16;
+    fn is_valid(&self) -> bool {
+        self.ip_from <= self.ip_to
+    }
+}
+
+fn to_map(ipv4_country_vec: Vec<Ipv4Country>) -> RangeTable<u32, CountryCode> {
+    let mut ipv4_country_map: RangeInclusiveMap<u32, CountryCode> = RangeInclusiveMap::new();
     for ipv4_country in ipv4_country_vec {
         ipv4_country_map.insert(
             ipv4_country.ip_from..=ipv4_country.ip_to,
-            ipv4_country.country_code,
+            CountryCode::new(&ipv4_country.country_code),
         );
     }
-    ipv4_country_map
+    RangeTable::from(ipv4_country_map)
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> RangeInclusiveMap<u32, String> {
-    let mut ipv4_country_map: RangeInclusiveMap<u32, String> = RangeInclusiveMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let ipv4_country_vec: Vec<Ipv4Country> = bincode::deserialize(&f).unwrap();
-            for ipv4_country in ipv4_country_vec {
-                ipv4_country_map.insert(
-                    ipv4_country.ip_from..=ipv4_country.ip_to,
-                    ipv4_country.country_code,
-                );
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    ipv4_country_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<RangeTable<u32, CountryCode>, DbError> {
+    let ipv4_country_vec: Vec<Ipv4Country> = bin::decode_records(IPV4_COUNTRY_BIN, IPV4_COUNTRY_BIN_NAME)?;
+    Ok(to_map(ipv4_country_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<RangeTable<u32, CountryCode>, DbError> {
+    Err(DbError::NotBundled { file_name: IPV4_COUNTRY_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<RangeTable<u32, CountryCode>, DbError> {
+    let ipv4_country_vec: Vec<Ipv4Country> = bin::read_records(&file_path, IPV4_COUNTRY_BIN_NAME)?;
+    Ok(to_map(ipv4_country_vec))
 }
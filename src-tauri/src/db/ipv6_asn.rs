@@ -1,6 +1,9 @@
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::db::range::RangeTable;
 use rangemap::RangeInclusiveMap;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 #[cfg(feature = "bundle")]
 pub const IPV6_ASN_BIN: &[u8] = include_bytes!("../../../resources/db/ipv6-asn.bin");
@@ -24,28 +27,33 @@ impl Ipv6Asn {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> RangeInclusiveMap<u128, u32> {
+impl DbRecord for Ipv6Asn {
+    const MIN_SIZE: usize = 36;
+    fn is_valid(&self) -> bool {
+        self.ip_from <= self.ip_to
+    }
+}
+
+fn to_map(ipv6_asn_vec: Vec<Ipv6Asn>) -> RangeTable<u128, u32> {
     let mut ipv6_asn_map: RangeInclusiveMap<u128, u32> = RangeInclusiveMap::new();
-    let ipv6_asn_vec: Vec<Ipv6Asn> = bincode::deserialize(IPV6_ASN_BIN).unwrap();
     for ipv6_asn in ipv6_asn_vec {
         ipv6_asn_map.insert(ipv6_asn.ip_from..=ipv6_asn.ip_to, ipv6_asn.asn);
     }
-    ipv6_asn_map
+    RangeTable::from(ipv6_asn_map)
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> RangeInclusiveMap<u128, u32> {
-    let mut ipv6_asn_map: RangeInclusiveMap<u128, u32> = RangeInclusiveMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let ipv6_asn_vec: Vec<Ipv6Asn> = bincode::deserialize(&f).unwrap();
-            for ipv6_asn in ipv6_asn_vec {
-                ipv6_asn_map.insert(ipv6_asn.ip_from..=ipv6_asn.ip_to, ipv6_asn.asn);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    ipv6_asn_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<RangeTable<u128, u32>, DbError> {
+    let ipv6_asn_vec: Vec<Ipv6Asn> = bin::decode_records(IPV6_ASN_BIN, IPV6_ASN_BIN_NAME)?;
+    Ok(to_map(ipv6_asn_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<RangeTable<u128, u32>, DbError> {
+    Err(DbError::NotBundled { file_name: IPV6_ASN_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<RangeTable<u128, u32>, DbError> {
+    let ipv6_asn_vec: Vec<Ipv6Asn> = bin::read_records(&file_path, IPV6_ASN_BIN_NAME)?;
+    Ok(to_map(ipv6_asn_vec))
 }
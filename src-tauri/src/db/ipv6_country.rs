@@ -1,6 +1,9 @@
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::db::range::{CountryCode, RangeTable};
 use rangemap::RangeInclusiveMap;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 #[cfg(feature = "bundle")]
 pub const IPV6_COUNTRY_BIN: &[u8] = include_bytes!("../../../resources/db/ipv6-country.bin");
@@ -24,34 +27,36 @@ impl Ipv6Country {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> RangeInclusiveMap<u128, String> {
-    let mut ipv6_country_map: RangeInclusiveMap<u128, String> = RangeInclusiveMap::new();
-    let ipv6_country_vec: Vec<Ipv6Country> = bincode::deserialize(IPV6_COUNTRY_BIN).unwrap();
+impl DbRecord for Ipv6Country {
+    const MIN_SIZE: usize = 40;
+    fn is_valid(&self) -> bool {
+        self.ip_from <= self.ip_to
+    }
+}
+
+fn to_map(ipv6_country_vec: Vec<Ipv6Country>) -> RangeTable<u128, CountryCode> {
+    let mut ipv6_country_map: RangeInclusiveMap<u128, CountryCode> = RangeInclusiveMap::new();
     for ipv6_country in ipv6_country_vec {
         ipv6_country_map.insert(
             ipv6_country.ip_from..=ipv6_country.ip_to,
-            ipv6_country.country_code,
+            CountryCode::new(&ipv6_country.country_code),
         );
     }
-    ipv6_country_map
+    RangeTable::from(ipv6_country_map)
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> RangeInclusiveMap<u128, String> {
-    let mut ipv6_country_map: RangeInclusiveMap<u128, String> = RangeInclusiveMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let ipv6_country_vec: Vec<Ipv6Country> = bincode::deserialize(&f).unwrap();
-            for ipv6_country in ipv6_country_vec {
-                ipv6_country_map.insert(
-                    ipv6_country.ip_from..=ipv6_country.ip_to,
-                    ipv6_country.country_code,
-                );
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    ipv6_country_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<RangeTable<u128, CountryCode>, DbError> {
+    let ipv6_country_vec: Vec<Ipv6Country> = bin::decode_records(IPV6_COUNTRY_BIN, IPV6_COUNTRY_BIN_NAME)?;
+    Ok(to_map(ipv6_country_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<RangeTable<u128, CountryCode>, DbError> {
+    Err(DbError::NotBundled { file_name: IPV6_COUNTRY_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<RangeTable<u128, CountryCode>, DbError> {
+    let ipv6_country_vec: Vec<Ipv6Country> = bin::read_records(&file_path, IPV6_COUNTRY_BIN_NAME)?;
+    Ok(to_map(ipv6_country_vec))
 }
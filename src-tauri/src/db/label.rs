@@ -0,0 +1,118 @@
+use crate::sys;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+pub const LABEL_DB_FILE_NAME: &str = "netpulsar-labels.json";
+
+/// User-defined name for an address or network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IpLabel {
+    /// CIDR (10.8.0.0/16) or a single address (192.0.2.10)
+    pub cidr: String,
+    pub name: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl IpLabel {
+    pub fn new(cidr: String, name: String) -> IpLabel {
+        IpLabel {
+            cidr: cidr,
+            name: name,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Parse a CIDR or a single address as a host network
+pub fn parse_cidr(cidr: &str) -> Result<IpNet, String> {
+    let cidr = cidr.trim();
+    match cidr.parse::<IpNet>() {
+        Ok(net) => Ok(net.trunc()),
+        Err(_) => match cidr.parse::<IpAddr>() {
+            Ok(ip_addr) => Ok(IpNet::from(ip_addr)),
+            Err(_) => Err(format!("Invalid CIDR: {}", cidr)),
+        },
+    }
+}
+
+/// User label database stored in the config directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelDatabase {
+    labels: Vec<IpLabel>,
+    /// Parsed networks, longest prefix first. Index into labels
+    nets: Vec<(IpNet, usize)>,
+}
+
+impl LabelDatabase {
+    pub fn new() -> LabelDatabase {
+        LabelDatabase {
+            labels: Vec::new(),
+            nets: Vec::new(),
+        }
+    }
+    /// Load the label file. A missing file is an empty database
+    pub fn load() -> Result<LabelDatabase, String> {
+        let path = match sys::get_user_file_path(LABEL_DB_FILE_NAME) {
+            Some(path) => path,
+            None => return Err(String::from("Config directory not found")),
+        };
+        if !path.exists() {
+            return Ok(LabelDatabase::new());
+        }
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let labels: Vec<IpLabel> = match serde_json::from_str(&content) {
+            Ok(labels) => labels,
+            Err(e) => return Err(format!("Failed to parse {}: {}", path.display(), e)),
+        };
+        let mut db = LabelDatabase::new();
+        db.set_labels(labels)?;
+        Ok(db)
+    }
+    pub fn save(&self) -> Result<(), String> {
+        let path = match sys::get_user_file_path(LABEL_DB_FILE_NAME) {
+            Some(path) => path,
+            None => return Err(String::from("Config directory not found")),
+        };
+        let content = match serde_json::to_string_pretty(&self.labels) {
+            Ok(content) => content,
+            Err(e) => return Err(e.to_string()),
+        };
+        match std::fs::write(&path, content) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+        }
+    }
+    pub fn labels(&self) -> Vec<IpLabel> {
+        self.labels.clone()
+    }
+    /// Replace all labels. Fails without changes if a CIDR or a name is invalid
+    pub fn set_labels(&mut self, labels: Vec<IpLabel>) -> Result<(), String> {
+        let mut nets: Vec<(IpNet, usize)> = Vec::new();
+        for (i, label) in labels.iter().enumerate() {
+            if label.name.trim().is_empty() {
+                return Err(format!("Label for {} has no name", label.cidr));
+            }
+            nets.push((parse_cidr(&label.cidr)?, i));
+        }
+        // Stable sort keeps the earlier label first among equal prefixes
+        nets.sort_by(|a, b| b.0.prefix_len().cmp(&a.0.prefix_len()));
+        self.labels = labels;
+        self.nets = nets;
+        Ok(())
+    }
+    /// Most specific label containing the address
+    pub fn lookup(&self, ip_addr: &IpAddr) -> Option<&IpLabel> {
+        self.nets
+            .iter()
+            .find(|(net, _)| net.contains(ip_addr))
+            .map(|(_, i)| &self.labels[*i])
+    }
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
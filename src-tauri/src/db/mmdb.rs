@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+/// Start of the metadata section
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+/// Zero bytes between the search tree and the data section
+const DATA_SECTION_SEPARATOR_SIZE: usize = 16;
+/// Guard against pointer loops in broken files
+const MAX_DECODE_DEPTH: usize = 64;
+
+/// Decoded MaxMind DB data field
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmdbValue {
+    String(String),
+    Double(f64),
+    Float(f32),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Bool(bool),
+    Map(HashMap<String, MmdbValue>),
+    Array(Vec<MmdbValue>),
+}
+
+impl MmdbValue {
+    /// Nested map lookup, e.g. ["country", "names", "en"]
+    pub fn get(&self, path: &[&str]) -> Option<&MmdbValue> {
+        let mut value = self;
+        for key in path {
+            match value {
+                MmdbValue::Map(map) => value = map.get(*key)?,
+                _ => return None,
+            }
+        }
+        Some(value)
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MmdbValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            MmdbValue::Uint(n) => u64::try_from(*n).ok(),
+            MmdbValue::Int(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            MmdbValue::Double(n) => Some(*n),
+            MmdbValue::Float(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+    pub fn get_str(&self, path: &[&str]) -> Option<&str> {
+        self.get(path).and_then(|v| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MmdbMetadata {
+    pub node_count: usize,
+    pub record_size: usize,
+    pub ip_version: u16,
+    pub database_type: String,
+    pub build_epoch: u64,
+}
+
+/// A network in the search tree with the offset of its record in the data section
+#[derive(Debug, Clone, Copy)]
+pub struct MmdbNetwork {
+    pub ip_addr: IpAddr,
+    pub prefix_len: u8,
+    pub data_offset: usize,
+}
+
+/// Reader of MaxMind DB (.mmdb) files
+pub struct MmdbReader {
+    buf: Vec<u8>,
+    pub metadata: MmdbMetadata,
+    /// Start of the data section
+    data_start: usize,
+}
+
+impl MmdbReader {
+    pub fn open(path: &PathBuf) -> Result<MmdbReader, String> {
+        match std::fs::read(path) {
+            Ok(buf) => MmdbReader::from_bytes(buf),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+    pub fn from_bytes(buf: Vec<u8>) -> Result<MmdbReader, String> {
+        let marker_pos = match buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+        {
+            Some(pos) => pos,
+            None => return Err(String::from("Not a MaxMind DB file: metadata not found")),
+        };
+        let metadata_start = marker_pos + METADATA_MARKER.len();
+        let (metadata, _) = decode(&buf, metadata_start, metadata_start, 0)?;
+        let field = |key: &str| -> Result<u64, String> {
+            match metadata.get(&[key]).and_then(|v| v.as_u64()) {
+                Some(n) => Ok(n),
+                None => Err(format!("Invalid metadata: {} missing", key)),
+            }
+        };
+        let metadata = MmdbMetadata {
+            node_count: field("node_count")? as usize,
+            record_size: field("record_size")? as usize,
+            ip_version: field("ip_version")? as u16,
+            database_type: metadata.get_str(&["database_type"]).unwrap_or("").to_string(),
+            build_epoch: metadata.get(&["build_epoch"]).and_then(|v| v.as_u64()).unwrap_or(0),
+        };
+        match metadata.record_size {
+            24 | 28 | 32 => {}
+            size => return Err(format!("Unsupported record size: {}", size)),
+        }
+        let data_start = match metadata.node_count.checked_mul(metadata.record_size) {
+            Some(tree_bits) => tree_bits / 4 + DATA_SECTION_SEPARATOR_SIZE,
+            None => return Err(String::from("Invalid search tree size")),
+        };
+        if data_start > marker_pos {
+            return Err(String::from("Invalid search tree size"));
+        }
+        Ok(MmdbReader {
+            buf: buf,
+            metadata: metadata,
+            data_start: data_start,
+        })
+    }
+    /// Left (bit 0) or right (bit 1) record of a node
+    fn read_record(&self, node: usize, bit: u8) -> usize {
+        let node_size = self.metadata.record_size / 4;
+        let b = &self.buf[node * node_size..(node + 1) * node_size];
+        match (self.metadata.record_size, bit) {
+            (24, 0) => be_uint(&b[0..3]),
+            (24, _) => be_uint(&b[3..6]),
+            (28, 0) => (((b[3] & 0xF0) as usize) << 20) | be_uint(&b[0..3]),
+            (28, _) => (((b[3] & 0x0F) as usize) << 24) | be_uint(&b[4..7]),
+            (_, 0) => be_uint(&b[0..4]),
+            (_, _) => be_uint(&b[4..8]),
+        }
+    }
+    /// Node of ::/96 where IPv4 addresses live in an IPv6 tree
+    fn ipv4_start_node(&self) -> usize {
+        if self.metadata.ip_version != 6 {
+            return 0;
+        }
+        let mut node = 0;
+        for _ in 0..96 {
+            if node >= self.metadata.node_count {
+                break;
+            }
+            node = self.read_record(node, 0);
+        }
+        node
+    }
+    /// Every network with data. IPv4 networks in an IPv6 tree are reported once as IPv4
+    pub fn networks(&self) -> Result<Vec<MmdbNetwork>, String> {
+        let node_count = self.metadata.node_count;
+        let bit_count: u8 = if self.metadata.ip_version == 6 { 128 } else { 32 };
+        let ipv4_start = self.ipv4_start_node();
+        let mut networks: Vec<MmdbNetwork> = Vec::new();
+        // (node, network bits, depth)
+        let mut stack: Vec<(usize, u128, u8)> = vec![(0, 0, 0)];
+        // Each node has one parent. A node reached twice means a cycle or shared subtree
+        let mut visited: Vec<bool> = vec![false; node_count];
+        while let Some((node, bits, depth)) = stack.pop() {
+            if node > node_count {
+                let data_offset = match (node - node_count).checked_sub(DATA_SECTION_SEPARATOR_SIZE) {
+                    Some(data_offset) => data_offset,
+                    None => return Err(format!("Invalid record {} in the search tree", node)),
+                };
+                networks.push(self.to_network(bits, depth, bit_count, data_offset));
+                continue;
+            }
+            if node == node_count || depth >= bit_count {
+                continue;
+            }
+            if visited[node] {
+                return Err(format!("Node {} is reached twice in the search tree", node));
+            }
+            visited[node] = true;
+            // Push the right side first so networks come out in address order
+            for bit in [1u8, 0u8] {
+                let child = self.read_record(node, bit);
+                let child_bits = bits | ((bit as u128) << (bit_count - depth - 1));
+                // ::ffff:0:0/96 and 2002::/16 alias the IPv4 subtree
+                if bit_count == 128 && child == ipv4_start && ipv4_start < node_count && !(depth + 1 == 96 && child_bits == 0) {
+                    continue;
+                }
+                stack.push((child, child_bits, depth + 1));
+            }
+        }
+        Ok(networks)
+    }
+    fn to_network(&self, bits: u128, prefix_len: u8, bit_count: u8, data_offset: usize) -> MmdbNetwork {
+        if bit_count == 32 {
+            return MmdbNetwork {
+                ip_addr: IpAddr::V4(Ipv4Addr::from(bits as u32)),
+                prefix_len: prefix_len,
+                data_offset: data_offset,
+            };
+        }
+        if prefix_len >= 96 && bits >> 32 == 0 {
+            return MmdbNetwork {
+                ip_addr: IpAddr::V4(Ipv4Addr::from(bits as u32)),
+                prefix_len: prefix_len - 96,
+                data_offset: data_offset,
+            };
+        }
+        MmdbNetwork {
+            ip_addr: IpAddr::V6(Ipv6Addr::from(bits)),
+            prefix_len: prefix_len,
+            data_offset: data_offset,
+        }
+    }
+    /// Decode the record at an offset in the data section
+    pub fn decode_data(&self, data_offset: usize) -> Result<MmdbValue, String> {
+        let (value, _) = decode(&self.buf, self.data_start, self.data_start + data_offset, 0)?;
+        Ok(value)
+    }
+}
+
+fn be_uint(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+}
+
+fn take<'a>(buf: &'a [u8], pos: usize, len: usize) -> Result<&'a [u8], String> {
+    match buf.get(pos..pos + len) {
+        Some(bytes) => Ok(bytes),
+        None => Err(String::from("Unexpected end of data")),
+    }
+}
+
+/// Decode a field at pos. Pointers are relative to base. Returns the value and the next position
+fn decode(buf: &[u8], base: usize, pos: usize, depth: usize) -> Result<(MmdbValue, usize), String> {
+    if depth > MAX_DECODE_DEPTH {
+        return Err(String::from("Data nested too deep"));
+    }
+    let ctrl = take(buf, pos, 1)?[0];
+    let mut pos = pos + 1;
+    let mut data_type = ctrl >> 5;
+    if data_type == 1 {
+        // Pointer
+        let ss = ((ctrl >> 3) & 0x3) as usize;
+        let vvv = (ctrl & 0x7) as usize;
+        let bytes = take(buf, pos, ss + 1)?;
+        let pointer = match ss {
+            0 => (vvv << 8) | be_uint(bytes),
+            1 => ((vvv << 16) | be_uint(bytes)) + 2048,
+            2 => ((vvv << 24) | be_uint(bytes)) + 526336,
+            _ => be_uint(bytes),
+        };
+        let (value, _) = decode(buf, base, base + pointer, depth + 1)?;
+        return Ok((value, pos + ss + 1));
+    }
+    if data_type == 0 {
+        data_type = 7 + take(buf, pos, 1)?[0];
+        pos += 1;
+    }
+    let mut size = (ctrl & 0x1f) as usize;
+    if size >= 29 {
+        let extra = size - 28;
+        let bytes = take(buf, pos, extra)?;
+        size = match extra {
+            1 => 29 + be_uint(bytes),
+            2 => 285 + be_uint(bytes),
+            _ => 65821 + be_uint(bytes),
+        };
+        pos += extra;
+    }
+    match data_type {
+        2 => {
+            let bytes = take(buf, pos, size)?;
+            Ok((MmdbValue::String(String::from_utf8_lossy(bytes).to_string()), pos + size))
+        }
+        3 => {
+            let bytes = take(buf, pos, 8)?;
+            let mut array = [0u8; 8];
+            array.copy_from_slice(bytes);
+            Ok((MmdbValue::Double(f64::from_be_bytes(array)), pos + 8))
+        }
+        4 => Ok((MmdbValue::Bytes(take(buf, pos, size)?.to_vec()), pos + size)),
+        5 | 6 | 9 | 10 => {
+            let bytes = take(buf, pos, size)?;
+            let n = bytes.iter().fold(0u128, |acc, b| (acc << 8) | *b as u128);
+            Ok((MmdbValue::Uint(n), pos + size))
+        }
+        7 => {
+            // Each entry takes at least two bytes
+            let mut map = HashMap::with_capacity(size.min(buf.len().saturating_sub(pos) / 2));
+            for _ in 0..size {
+                let (key, next) = decode(buf, base, pos, depth + 1)?;
+                let (value, next) = decode(buf, base, next, depth + 1)?;
+                pos = next;
+                if let MmdbValue::String(key) = key {
+                    map.insert(key, value);
+                }
+            }
+            Ok((MmdbValue::Map(map), pos))
+        }
+        8 => {
+            let bytes = take(buf, pos, size)?;
+            let n = bytes.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+            Ok((MmdbValue::Int(n as i32), pos + size))
+        }
+        11 => {
+            let mut array = Vec::with_capacity(size.min(buf.len().saturating_sub(pos)));
+            for _ in 0..size {
+                let (value, next) = decode(buf, base, pos, depth + 1)?;
+                pos = next;
+                array.push(value);
+            }
+            Ok((MmdbValue::Array(array), pos))
+        }
+        14 => Ok((MmdbValue::Bool(size != 0), pos)),
+        15 => {
+            let bytes = take(buf, pos, 4)?;
+            let mut array = [0u8; 4];
+            array.copy_from_slice(bytes);
+            Ok((MmdbValue::Float(f32::from_be_bytes(array)), pos + 4))
+        }
+        t => Err(format!("Unsupported data type: {}", t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_string(s: &str) -> Vec<u8> {
+        let mut bytes = vec![(2 << 5) | s.len() as u8];
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    fn encode_uint64(n: u64) -> Vec<u8> {
+        let mut bytes = vec![8, 9 - 7];
+        bytes.extend_from_slice(&n.to_be_bytes());
+        bytes
+    }
+
+    /// 24 bit record search tree, data section and metadata
+    fn build_db(node_count: u64, records: &[(u32, u32)], data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (left, right) in records {
+            buf.extend_from_slice(&left.to_be_bytes()[1..]);
+            buf.extend_from_slice(&right.to_be_bytes()[1..]);
+        }
+        buf.extend_from_slice(&[0u8; DATA_SECTION_SEPARATOR_SIZE]);
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(METADATA_MARKER);
+        buf.push((7 << 5) | 3);
+        for (key, value) in [("node_count", node_count), ("record_size", 24), ("ip_version", 4)] {
+            buf.extend(encode_string(key));
+            buf.extend(encode_uint64(value));
+        }
+        buf
+    }
+
+    #[test]
+    fn networks_with_data() {
+        let buf = build_db(1, &[(1 + DATA_SECTION_SEPARATOR_SIZE as u32, 1)], &encode_string("JP"));
+        let reader = MmdbReader::from_bytes(buf).unwrap();
+        let networks = reader.networks().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].ip_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(networks[0].prefix_len, 1);
+        assert_eq!(reader.decode_data(networks[0].data_offset), Ok(MmdbValue::String(String::from("JP"))));
+    }
+
+    #[test]
+    fn record_pointing_into_separator_is_an_error() {
+        let buf = build_db(1, &[(5, 1)], &[]);
+        let reader = MmdbReader::from_bytes(buf).unwrap();
+        assert!(reader.networks().is_err());
+    }
+
+    #[test]
+    fn cycle_in_search_tree_is_an_error() {
+        // Both records of node 0 point back at it
+        let buf = build_db(1, &[(0, 0)], &[]);
+        let reader = MmdbReader::from_bytes(buf).unwrap();
+        assert!(reader.networks().is_err());
+        // Node 1 points back at node 0
+        let data_record = 2 + DATA_SECTION_SEPARATOR_SIZE as u32;
+        let buf = build_db(2, &[(1, data_record), (0, data_record)], &encode_string("JP"));
+        let reader = MmdbReader::from_bytes(buf).unwrap();
+        assert!(reader.networks().is_err());
+    }
+
+    #[test]
+    fn oversized_node_count_is_an_error() {
+        let buf = build_db(u64::MAX / 8, &[(1, 1)], &[]);
+        assert!(MmdbReader::from_bytes(buf).is_err());
+    }
+
+    #[test]
+    fn oversized_array_is_an_error() {
+        // Array of 16843036 elements with no data after it
+        let data = [(0 << 5) | 31, 11 - 7, 0xff, 0xff, 0xff];
+        let buf = build_db(1, &[(1 + DATA_SECTION_SEPARATOR_SIZE as u32, 1)], &data);
+        let reader = MmdbReader::from_bytes(buf).unwrap();
+        assert!(reader.decode_data(0).is_err());
+    }
+}
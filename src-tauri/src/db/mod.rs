@@ -1,13 +1,22 @@
 #![allow(unused)]
 
 pub mod asn;
+pub mod bin;
 pub mod country;
+pub mod error;
+pub mod import;
 pub mod ip;
+pub mod label;
 pub mod ipv4_asn;
 pub mod ipv4_country;
 pub mod ipv6_asn;
 pub mod ipv6_country;
+pub mod mmdb;
 pub mod oui;
+pub mod range;
+pub mod rule;
 pub mod service;
+pub mod threat;
 pub mod tcp_service;
 pub mod udp_service;
+pub mod update;
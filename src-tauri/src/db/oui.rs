@@ -1,4 +1,6 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -23,28 +25,30 @@ impl Oui {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> HashMap<String, String> {
+impl DbRecord for Oui {
+    const MIN_SIZE: usize = 16;
+}
+
+fn to_map(oui_vec: Vec<Oui>) -> HashMap<String, String> {
     let mut oui_map: HashMap<String, String> = HashMap::new();
-    let oui_vec: Vec<Oui> = bincode::deserialize(OUI_BIN).unwrap();
     for oui in oui_vec {
         oui_map.insert(oui.mac_prefix, oui.vendor_name);
     }
     oui_map
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> HashMap<String, String> {
-    let mut oui_map: HashMap<String, String> = HashMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let oui_vec: Vec<Oui> = bincode::deserialize(&f).unwrap();
-            for oui in oui_vec {
-                oui_map.insert(oui.mac_prefix, oui.vendor_name);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    oui_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<HashMap<String, String>, DbError> {
+    let oui_vec: Vec<Oui> = bin::decode_records(OUI_BIN, OUI_BIN_NAME)?;
+    Ok(to_map(oui_vec))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<HashMap<String, String>, DbError> {
+    Err(DbError::NotBundled { file_name: OUI_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<HashMap<String, String>, DbError> {
+    let oui_vec: Vec<Oui> = bin::read_records(&file_path, OUI_BIN_NAME)?;
+    Ok(to_map(oui_vec))
 }
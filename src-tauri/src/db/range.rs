@@ -0,0 +1,261 @@
+use rangemap::{RangeInclusiveMap, StepLite};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+
+/// ISO 3166-1 alpha-2 code stored inline. Replaces a String per range
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct CountryCode([u8; 2]);
+
+impl CountryCode {
+    pub fn new(code: &str) -> CountryCode {
+        let bytes = code.as_bytes();
+        let mut code = [0u8; 2];
+        for (i, b) in bytes.iter().take(2).enumerate() {
+            code[i] = b.to_ascii_uppercase();
+        }
+        CountryCode(code)
+    }
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|b| *b == 0).unwrap_or(2);
+        std::str::from_utf8(&self.0[..len]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Non-overlapping inclusive ranges in sorted arrays. Lookup is a binary search
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeTable<K, V> {
+    starts: Vec<K>,
+    ends: Vec<K>,
+    values: Vec<V>,
+}
+
+impl<K: Ord + Copy, V> RangeTable<K, V> {
+    pub fn new() -> RangeTable<K, V> {
+        RangeTable {
+            starts: Vec::new(),
+            ends: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+    pub fn get(&self, key: &K) -> Option<&V> {
+        // Last range starting at or before key
+        let index = self.starts.partition_point(|start| start <= key);
+        if index == 0 {
+            return None;
+        }
+        if self.ends[index - 1] >= *key {
+            Some(&self.values[index - 1])
+        } else {
+            None
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (RangeInclusive<K>, &V)> {
+        self.starts
+            .iter()
+            .zip(self.ends.iter())
+            .zip(self.values.iter())
+            .map(|((start, end), value)| (*start..=*end, value))
+    }
+    /// Heap bytes used by the arrays
+    pub fn heap_size(&self) -> usize {
+        self.starts.capacity() * size_of::<K>() * 2 + self.values.capacity() * size_of::<V>()
+    }
+}
+
+impl<K: Ord + Copy, V> Default for RangeTable<K, V> {
+    fn default() -> RangeTable<K, V> {
+        RangeTable::new()
+    }
+}
+
+impl<K: Ord + Copy + StepLite, V: Eq + Clone> From<RangeInclusiveMap<K, V>> for RangeTable<K, V> {
+    fn from(map: RangeInclusiveMap<K, V>) -> RangeTable<K, V> {
+        let len = map.len();
+        let mut table = RangeTable {
+            starts: Vec::with_capacity(len),
+            ends: Vec::with_capacity(len),
+            values: Vec::with_capacity(len),
+        };
+        for (range, value) in map.into_iter() {
+            table.starts.push(*range.start());
+            table.ends.push(*range.end());
+            table.values.push(value);
+        }
+        table
+    }
+}
+
+/// Later ranges overwrite earlier ones where they overlap, as with RangeInclusiveMap::insert
+impl<K: Ord + Copy + StepLite, V: Eq + Clone> FromIterator<(RangeInclusive<K>, V)> for RangeTable<K, V> {
+    fn from_iter<I: IntoIterator<Item = (RangeInclusive<K>, V)>>(iter: I) -> RangeTable<K, V> {
+        let map: RangeInclusiveMap<K, V> = iter.into_iter().collect();
+        RangeTable::from(map)
+    }
+}
+
+/// Names keyed by number in one string buffer. Replaces a HashMap<u32, String>
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NameTable {
+    keys: Vec<u32>,
+    /// End of each name in text
+    ends: Vec<u32>,
+    text: String,
+}
+
+impl NameTable {
+    pub fn new() -> NameTable {
+        NameTable {
+            keys: Vec::new(),
+            ends: Vec::new(),
+            text: String::new(),
+        }
+    }
+    pub fn get(&self, key: &u32) -> Option<&str> {
+        match self.keys.binary_search(key) {
+            Ok(index) => {
+                let start = if index == 0 { 0 } else { self.ends[index - 1] as usize };
+                Some(&self.text[start..self.ends[index] as usize])
+            }
+            Err(_) => None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.keys.iter().enumerate().map(move |(index, key)| {
+            let start = if index == 0 { 0 } else { self.ends[index - 1] as usize };
+            (*key, &self.text[start..self.ends[index] as usize])
+        })
+    }
+    /// Add or replace names. Rebuilds the table
+    pub fn extend<'a, I: IntoIterator<Item = (u32, &'a str)>>(&mut self, names: I) {
+        let mut entries: Vec<(u32, String)> = self.iter().map(|(key, name)| (key, name.to_string())).collect();
+        entries.extend(names.into_iter().map(|(key, name)| (key, name.to_string())));
+        *self = NameTable::from_entries(entries);
+    }
+    /// Later entries win for duplicate keys
+    fn from_entries(mut entries: Vec<(u32, String)>) -> NameTable {
+        // Stable sort keeps input order within a key
+        entries.sort_by_key(|(key, _)| *key);
+        let mut table = NameTable::new();
+        for (key, name) in entries {
+            if table.keys.last() == Some(&key) {
+                table.keys.pop();
+                table.ends.pop();
+                let start = table.ends.last().copied().unwrap_or(0) as usize;
+                table.text.truncate(start);
+            }
+            table.text.push_str(&name);
+            table.keys.push(key);
+            table.ends.push(table.text.len() as u32);
+        }
+        table.keys.shrink_to_fit();
+        table.ends.shrink_to_fit();
+        table.text.shrink_to_fit();
+        table
+    }
+    pub fn heap_size(&self) -> usize {
+        self.keys.capacity() * size_of::<u32>() + self.ends.capacity() * size_of::<u32>() + self.text.capacity()
+    }
+}
+
+impl FromIterator<(u32, String)> for NameTable {
+    fn from_iter<I: IntoIterator<Item = (u32, String)>>(iter: I) -> NameTable {
+        NameTable::from_entries(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> RangeTable<u32, &'static str> {
+        vec![(10..=19, "a"), (30..=39, "b"), (40..=40, "c"), (u32::MAX - 1..=u32::MAX, "d")]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn range_boundaries() {
+        let table = table();
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.get(&10), Some(&"a"));
+        assert_eq!(table.get(&19), Some(&"a"));
+        assert_eq!(table.get(&30), Some(&"b"));
+        assert_eq!(table.get(&39), Some(&"b"));
+        // Adjacent range with another value
+        assert_eq!(table.get(&40), Some(&"c"));
+        assert_eq!(table.get(&(u32::MAX - 1)), Some(&"d"));
+        assert_eq!(table.get(&u32::MAX), Some(&"d"));
+    }
+
+    #[test]
+    fn keys_outside_ranges() {
+        let table = table();
+        assert_eq!(table.get(&0), None);
+        assert_eq!(table.get(&9), None);
+        // Gaps
+        assert_eq!(table.get(&20), None);
+        assert_eq!(table.get(&29), None);
+        assert_eq!(table.get(&41), None);
+        assert_eq!(table.get(&(u32::MAX - 2)), None);
+        assert_eq!(RangeTable::<u32, u32>::new().get(&0), None);
+    }
+
+    #[test]
+    fn later_ranges_overwrite_and_equal_neighbours_merge() {
+        let table: RangeTable<u32, &str> = vec![(0..=100, "x"), (50..=60, "y"), (101..=200, "x")].into_iter().collect();
+        assert_eq!(table.get(&49), Some(&"x"));
+        assert_eq!(table.get(&50), Some(&"y"));
+        assert_eq!(table.get(&60), Some(&"y"));
+        assert_eq!(table.get(&61), Some(&"x"));
+        assert_eq!(table.get(&200), Some(&"x"));
+        assert_eq!(
+            table.iter().map(|(range, value)| (range, *value)).collect::<Vec<_>>(),
+            vec![(0..=49, "x"), (50..=60, "y"), (61..=200, "x")]
+        );
+    }
+
+    #[test]
+    fn name_table_lookup() {
+        let mut names: NameTable = vec![(64500, "B".to_string()), (1, "A".to_string()), (u32::MAX, "Z".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(names.get(&1), Some("A"));
+        assert_eq!(names.get(&64500), Some("B"));
+        assert_eq!(names.get(&u32::MAX), Some("Z"));
+        assert_eq!(names.get(&0), None);
+        assert_eq!(names.get(&2), None);
+        names.extend(vec![(64500, "B2"), (7, ""), (64500, "B3")]);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names.get(&64500), Some("B3"));
+        assert_eq!(names.get(&7), Some(""));
+        assert_eq!(names.get(&u32::MAX), Some("Z"));
+        assert_eq!(names.iter().map(|(key, _)| key).collect::<Vec<u32>>(), vec![1, 7, 64500, u32::MAX]);
+    }
+
+    #[test]
+    fn country_code() {
+        assert_eq!(CountryCode::new("jp").as_str(), "JP");
+        assert_eq!(CountryCode::new("USA").as_str(), "US");
+        assert_eq!(CountryCode::new("").as_str(), "");
+    }
+}
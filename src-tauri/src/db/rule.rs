@@ -0,0 +1,1029 @@
+//! Parser and matcher for a subset of the Suricata/Snort rule language.
+//! Supported: the rule header, content with nocase/offset/depth, flow direction, msg, sid and rev.
+use crate::db::label::parse_cidr;
+use crate::sys;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Rule files in the config directory
+pub const RULE_DIR_NAME: &str = "rules";
+pub const RULE_FILE_EXTENSION: &str = "rules";
+/// Errors kept per file
+const MAX_FILE_ERRORS: usize = 20;
+/// Nesting of variables and lists
+const MAX_SPEC_DEPTH: usize = 8;
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAG_RST: u8 = 0x04;
+const TCP_FLAG_ACK: u8 = 0x10;
+/// Rules with up to this many destination ports are indexed by port
+const MAX_INDEXED_PORTS: u32 = 64;
+/// Keywords without effect on matching
+const IGNORED_KEYWORDS: [&str; 7] = ["classtype", "reference", "metadata", "priority", "gid", "fast_pattern", "rawbytes"];
+/// Variables used by community rule sets. Addresses default to $HOME_NET
+const SERVER_VARS: [&str; 13] = [
+    "HTTP_SERVERS",
+    "SMTP_SERVERS",
+    "SQL_SERVERS",
+    "DNS_SERVERS",
+    "TELNET_SERVERS",
+    "AIM_SERVERS",
+    "DC_SERVERS",
+    "DNP3_SERVER",
+    "DNP3_CLIENT",
+    "MODBUS_CLIENT",
+    "MODBUS_SERVER",
+    "ENIP_CLIENT",
+    "ENIP_SERVER",
+];
+const PORT_VARS: [(&str, &str); 11] = [
+    ("HTTP_PORTS", "80"),
+    ("SHELLCODE_PORTS", "!80"),
+    ("ORACLE_PORTS", "1521"),
+    ("SSH_PORTS", "22"),
+    ("DNP3_PORTS", "20000"),
+    ("MODBUS_PORTS", "502"),
+    ("FILE_DATA_PORTS", "[$HTTP_PORTS,110,143]"),
+    ("FTP_PORTS", "21"),
+    ("GENEVE_PORTS", "6081"),
+    ("VXLAN_PORTS", "4789"),
+    ("TEREDO_PORTS", "3544"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RuleConfig {
+    pub enabled: bool,
+    /// Value of $HOME_NET
+    pub home_net: String,
+    /// Value of $EXTERNAL_NET
+    pub external_net: String,
+}
+
+impl RuleConfig {
+    pub fn new() -> RuleConfig {
+        RuleConfig {
+            enabled: true,
+            home_net: String::from("[10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,fc00::/7,fe80::/10]"),
+            external_net: String::from("!$HOME_NET"),
+        }
+    }
+    /// Address and port variables rules may refer to
+    fn vars(&self) -> HashMap<String, String> {
+        let mut vars: HashMap<String, String> = HashMap::new();
+        vars.insert(String::from("HOME_NET"), self.home_net.clone());
+        vars.insert(String::from("EXTERNAL_NET"), self.external_net.clone());
+        for name in SERVER_VARS {
+            vars.insert(name.to_string(), String::from("$HOME_NET"));
+        }
+        for (name, value) in PORT_VARS {
+            vars.insert(name.to_string(), value.to_string());
+        }
+        vars
+    }
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        RuleConfig::new()
+    }
+}
+
+pub fn rule_dir_path() -> Option<PathBuf> {
+    sys::get_config_dir_path().map(|path| path.join(RULE_DIR_NAME))
+}
+
+/// Rule files in the directory, sorted by name
+pub fn rule_file_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path.extension().map_or(false, |ext| ext.to_string_lossy().to_lowercase() == RULE_FILE_EXTENSION)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+/// Names, sizes and modification times of the rule files. Changes when a file is edited, added or removed
+pub fn rule_files_fingerprint(dir: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    rule_file_paths(dir)
+        .into_iter()
+        .map(|path| {
+            let (len, modified) = match std::fs::metadata(&path) {
+                Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+                Err(_) => (0, None),
+            };
+            (path, len, modified)
+        })
+        .collect()
+}
+
+/// What to do on a match. Matches are only reported, nothing is dropped
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleAction {
+    Alert,
+    Log,
+    Drop,
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleProtocol {
+    Ip,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+/// Networks of an address spec. Empty include matches any address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressSet {
+    include: Vec<IpNet>,
+    exclude: Vec<IpNet>,
+}
+
+impl AddressSet {
+    pub fn contains(&self, ip_addr: &IpAddr) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|net| net.contains(ip_addr)))
+            && !self.exclude.iter().any(|net| net.contains(ip_addr))
+    }
+}
+
+/// Port ranges of a port spec. Empty include matches any port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSet {
+    include: Vec<(u16, u16)>,
+    exclude: Vec<(u16, u16)>,
+}
+
+impl PortSet {
+    fn is_any(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+    /// A packet without ports only matches any
+    pub fn contains(&self, port: Option<u16>) -> bool {
+        match port {
+            Some(port) => {
+                (self.include.is_empty() || self.include.iter().any(|(start, end)| *start <= port && port <= *end))
+                    && !self.exclude.iter().any(|(start, end)| *start <= port && port <= *end)
+            }
+            None => self.is_any(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMatch {
+    pub pattern: Vec<u8>,
+    pub negated: bool,
+    pub nocase: bool,
+    pub offset: usize,
+    pub depth: Option<usize>,
+}
+
+impl ContentMatch {
+    fn matches(&self, payload: &[u8]) -> bool {
+        let end = match self.depth {
+            Some(depth) => payload.len().min(self.offset.saturating_add(depth)),
+            None => payload.len(),
+        };
+        let found = match payload.get(self.offset..end) {
+            Some(window) if !self.pattern.is_empty() && window.len() >= self.pattern.len() => {
+                window.windows(self.pattern.len()).any(|candidate| {
+                    if self.nocase {
+                        candidate.eq_ignore_ascii_case(&self.pattern)
+                    } else {
+                        candidate == self.pattern.as_slice()
+                    }
+                })
+            }
+            _ => false,
+        };
+        found != self.negated
+    }
+}
+
+/// flow keyword. Without stream tracking, the client is the side that sent the SYN of a TCP flow
+/// or the first datagram of a UDP flow. Flows whose start was not seen match no direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FlowSpec {
+    pub to_server: Option<bool>,
+    /// established approximated as a TCP segment other than SYN or RST on a flow whose SYN was seen.
+    /// The handshake itself is not checked
+    pub established: Option<bool>,
+}
+
+/// Fields of a packet rules are matched against
+#[derive(Debug, Clone)]
+pub struct PacketFields<'a> {
+    pub protocol: RuleProtocol,
+    pub src_ip_addr: IpAddr,
+    pub dst_ip_addr: IpAddr,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub tcp_flags: Option<u8>,
+    /// Whether the packet was sent by the client of its flow. None if the start of the flow was not seen
+    pub to_server: Option<bool>,
+    pub payload: &'a [u8],
+}
+
+impl PacketFields<'_> {
+    fn is_to_server(&self) -> Option<bool> {
+        if let Some(flags) = self.tcp_flags {
+            if flags & TCP_FLAG_SYN != 0 {
+                return Some(flags & TCP_FLAG_ACK == 0);
+            }
+        }
+        self.to_server
+    }
+    fn is_established(&self) -> Option<bool> {
+        let flags = self.tcp_flags?;
+        Some(self.to_server.is_some() && flags & (TCP_FLAG_SYN | TCP_FLAG_RST) == 0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub action: RuleAction,
+    pub protocol: RuleProtocol,
+    pub src: AddressSet,
+    pub src_ports: PortSet,
+    pub dst: AddressSet,
+    pub dst_ports: PortSet,
+    /// <> matches both directions
+    pub bidirectional: bool,
+    pub contents: Vec<ContentMatch>,
+    pub flow: FlowSpec,
+    pub sid: u32,
+    pub rev: u32,
+    pub msg: String,
+}
+
+impl Rule {
+    pub fn has_content(&self) -> bool {
+        !self.contents.is_empty()
+    }
+    pub fn matches(&self, packet: &PacketFields) -> bool {
+        let protocol = match self.protocol {
+            RuleProtocol::Ip => true,
+            protocol => protocol == packet.protocol,
+        };
+        if !protocol {
+            return false;
+        }
+        let forward = self.src.contains(&packet.src_ip_addr)
+            && self.src_ports.contains(packet.src_port)
+            && self.dst.contains(&packet.dst_ip_addr)
+            && self.dst_ports.contains(packet.dst_port);
+        let reverse = self.bidirectional
+            && self.src.contains(&packet.dst_ip_addr)
+            && self.src_ports.contains(packet.dst_port)
+            && self.dst.contains(&packet.src_ip_addr)
+            && self.dst_ports.contains(packet.src_port);
+        if !forward && !reverse {
+            return false;
+        }
+        if let Some(to_server) = self.flow.to_server {
+            if packet.is_to_server() != Some(to_server) {
+                return false;
+            }
+        }
+        if let Some(established) = self.flow.established {
+            // Only TCP has connection state
+            if let Some(packet_established) = packet.is_established() {
+                if packet_established != established {
+                    return false;
+                }
+            }
+        }
+        self.contents.iter().all(|content| content.matches(packet.payload))
+    }
+}
+
+/// Split on the separator outside of quotes, brackets and escapes
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut depth: usize = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => {
+                escaped = true;
+                current.push(c);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            '[' if !quoted => {
+                depth += 1;
+                current.push(c);
+            }
+            ']' if !quoted => {
+                depth = depth.saturating_sub(1);
+                current.push(c);
+            }
+            c if c == separator && !quoted && depth == 0 => {
+                parts.push(std::mem::take(&mut current));
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+fn parse_address(spec: &str, vars: &HashMap<String, String>, depth: usize) -> Result<AddressSet, String> {
+    let spec = spec.trim();
+    if depth > MAX_SPEC_DEPTH {
+        return Err(format!("Address nested too deeply: {}", spec));
+    }
+    if let Some(rest) = spec.strip_prefix('!') {
+        let inner = parse_address(rest, vars, depth + 1)?;
+        if inner.include.is_empty() || !inner.exclude.is_empty() {
+            return Err(format!("Unsupported negation: {}", spec));
+        }
+        return Ok(AddressSet {
+            include: Vec::new(),
+            exclude: inner.include,
+        });
+    }
+    if let Some(list) = spec.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        let mut set = AddressSet {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+        for item in split_top_level(list, ',') {
+            let item = parse_address(&item, vars, depth + 1)?;
+            if item.include.is_empty() && item.exclude.is_empty() {
+                // any in a list
+                return Ok(item);
+            }
+            set.include.extend(item.include);
+            set.exclude.extend(item.exclude);
+        }
+        return Ok(set);
+    }
+    if let Some(name) = spec.strip_prefix('$') {
+        return match vars.get(name) {
+            Some(value) => parse_address(value, vars, depth + 1),
+            None => Err(format!("Unknown variable: {}", spec)),
+        };
+    }
+    if spec.eq_ignore_ascii_case("any") {
+        return Ok(AddressSet {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        });
+    }
+    Ok(AddressSet {
+        include: vec![parse_cidr(spec)?],
+        exclude: Vec::new(),
+    })
+}
+
+fn parse_port_number(value: &str, default: u16) -> Result<u16, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(default);
+    }
+    match value.parse::<u16>() {
+        Ok(port) => Ok(port),
+        Err(_) => Err(format!("Invalid port: {}", value)),
+    }
+}
+
+fn parse_ports(spec: &str, vars: &HashMap<String, String>, depth: usize) -> Result<PortSet, String> {
+    let spec = spec.trim();
+    if depth > MAX_SPEC_DEPTH {
+        return Err(format!("Port nested too deeply: {}", spec));
+    }
+    if let Some(rest) = spec.strip_prefix('!') {
+        let inner = parse_ports(rest, vars, depth + 1)?;
+        if inner.include.is_empty() || !inner.exclude.is_empty() {
+            return Err(format!("Unsupported negation: {}", spec));
+        }
+        return Ok(PortSet {
+            include: Vec::new(),
+            exclude: inner.include,
+        });
+    }
+    if let Some(list) = spec.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        let mut set = PortSet {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+        for item in split_top_level(list, ',') {
+            let item = parse_ports(&item, vars, depth + 1)?;
+            if item.is_any() {
+                return Ok(item);
+            }
+            set.include.extend(item.include);
+            set.exclude.extend(item.exclude);
+        }
+        return Ok(set);
+    }
+    if let Some(name) = spec.strip_prefix('$') {
+        return match vars.get(name) {
+            Some(value) => parse_ports(value, vars, depth + 1),
+            None => Err(format!("Unknown variable: {}", spec)),
+        };
+    }
+    if spec.eq_ignore_ascii_case("any") {
+        return Ok(PortSet {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        });
+    }
+    let range = match spec.split_once(':') {
+        Some((start, end)) => (parse_port_number(start, 0)?, parse_port_number(end, u16::MAX)?),
+        None => {
+            let port = parse_port_number(spec, 0)?;
+            (port, port)
+        }
+    };
+    if range.0 > range.1 {
+        return Err(format!("Invalid port range: {}", spec));
+    }
+    Ok(PortSet {
+        include: vec![range],
+        exclude: Vec::new(),
+    })
+}
+
+/// Value of a content keyword. Text between pipes is hex
+fn parse_content(value: &str) -> Result<(Vec<u8>, bool), String> {
+    let value = value.trim();
+    let (negated, value) = match value.strip_prefix('!') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, value),
+    };
+    let value = match value.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(value) => value,
+        None => return Err(format!("Content not quoted: {}", value)),
+    };
+    let mut pattern: Vec<u8> = Vec::new();
+    let mut chars = value.chars();
+    let mut hex = false;
+    let mut hex_digits = String::new();
+    while let Some(c) = chars.next() {
+        if hex {
+            match c {
+                '|' => {
+                    if !hex_digits.is_empty() {
+                        return Err(format!("Odd number of hex digits in content: {}", value));
+                    }
+                    hex = false;
+                }
+                c if c.is_ascii_hexdigit() => {
+                    hex_digits.push(c);
+                    if hex_digits.len() == 2 {
+                        pattern.push(u8::from_str_radix(&hex_digits, 16).unwrap_or(0));
+                        hex_digits.clear();
+                    }
+                }
+                c if c.is_whitespace() => {}
+                _ => return Err(format!("Invalid hex in content: {}", value)),
+            }
+            continue;
+        }
+        match c {
+            '|' => hex = true,
+            '\\' => match chars.next() {
+                Some(escaped) => {
+                    let mut buf = [0u8; 4];
+                    pattern.extend(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                None => return Err(format!("Trailing escape in content: {}", value)),
+            },
+            c => {
+                let mut buf = [0u8; 4];
+                pattern.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    if hex {
+        return Err(format!("Unterminated hex in content: {}", value));
+    }
+    if pattern.is_empty() {
+        return Err(String::from("Empty content"));
+    }
+    Ok((pattern, negated))
+}
+
+fn parse_flow(value: &str) -> Result<FlowSpec, String> {
+    let mut flow = FlowSpec::default();
+    for option in value.split(',').map(|option| option.trim().to_lowercase()) {
+        match option.as_str() {
+            "to_server" | "from_client" => flow.to_server = Some(true),
+            "to_client" | "from_server" => flow.to_server = Some(false),
+            "established" => flow.established = Some(true),
+            "not_established" => flow.established = Some(false),
+            "stateless" => {}
+            _ => return Err(format!("Unsupported flow option: {}", option)),
+        }
+    }
+    Ok(flow)
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.unwrap_or("").trim();
+    match value.parse::<T>() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(format!("Invalid {}: {}", key, value)),
+    }
+}
+
+/// Parse one rule. vars resolves $NAME in addresses and ports
+pub fn parse_rule(text: &str, vars: &HashMap<String, String>) -> Result<Rule, String> {
+    let text = text.trim();
+    let (header, options) = match (text.find('('), text.rfind(')')) {
+        (Some(start), Some(end)) if start < end => (&text[..start], &text[start + 1..end]),
+        _ => return Err(String::from("Missing rule options")),
+    };
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    if fields.len() != 7 {
+        return Err(format!("Invalid rule header: {}", header.trim()));
+    }
+    let action = match fields[0].to_lowercase().as_str() {
+        "alert" => RuleAction::Alert,
+        "log" => RuleAction::Log,
+        "drop" => RuleAction::Drop,
+        "reject" | "rejectsrc" | "rejectdst" | "rejectboth" => RuleAction::Reject,
+        action => return Err(format!("Unsupported action: {}", action)),
+    };
+    let protocol = match fields[1].to_lowercase().as_str() {
+        "ip" | "any" => RuleProtocol::Ip,
+        "tcp" | "tcp-pkt" => RuleProtocol::Tcp,
+        "udp" => RuleProtocol::Udp,
+        "icmp" => RuleProtocol::Icmp,
+        protocol => return Err(format!("Unsupported protocol: {}", protocol)),
+    };
+    let bidirectional = match fields[4] {
+        "->" => false,
+        "<>" => true,
+        direction => return Err(format!("Invalid direction: {}", direction)),
+    };
+    let mut rule = Rule {
+        action: action,
+        protocol: protocol,
+        src: parse_address(fields[2], vars, 0)?,
+        src_ports: parse_ports(fields[3], vars, 0)?,
+        dst: parse_address(fields[5], vars, 0)?,
+        dst_ports: parse_ports(fields[6], vars, 0)?,
+        bidirectional: bidirectional,
+        contents: Vec::new(),
+        flow: FlowSpec::default(),
+        sid: 0,
+        rev: 0,
+        msg: String::new(),
+    };
+    for option in split_top_level(options, ';') {
+        let option = option.trim();
+        if option.is_empty() {
+            continue;
+        }
+        let (key, value) = match option.split_once(':') {
+            Some((key, value)) => (key.trim().to_lowercase(), Some(value.trim())),
+            None => (option.to_lowercase(), None),
+        };
+        match key.as_str() {
+            "msg" => {
+                rule.msg = value.unwrap_or("").trim_matches('"').replace("\\;", ";").replace("\\\"", "\"");
+            }
+            "sid" => rule.sid = parse_number(&key, value)?,
+            "rev" => rule.rev = parse_number(&key, value)?,
+            "content" => {
+                let (pattern, negated) = parse_content(value.unwrap_or(""))?;
+                rule.contents.push(ContentMatch {
+                    pattern: pattern,
+                    negated: negated,
+                    nocase: false,
+                    offset: 0,
+                    depth: None,
+                });
+            }
+            "nocase" | "offset" | "depth" => {
+                let content = match rule.contents.last_mut() {
+                    Some(content) => content,
+                    None => return Err(format!("{} without content", key)),
+                };
+                match key.as_str() {
+                    "nocase" => content.nocase = true,
+                    "offset" => content.offset = parse_number(&key, value)?,
+                    _ => content.depth = Some(parse_number(&key, value)?),
+                }
+            }
+            "flow" => rule.flow = parse_flow(value.unwrap_or(""))?,
+            key if IGNORED_KEYWORDS.contains(&key) => {}
+            key => return Err(format!("Unsupported keyword: {}", key)),
+        }
+    }
+    if rule.sid == 0 {
+        return Err(String::from("Missing sid"));
+    }
+    Ok(rule)
+}
+
+/// Result of loading a rule file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleFileStatus {
+    pub name: String,
+    pub rule_count: usize,
+    /// Rules that failed to parse or use unsupported keywords
+    pub skipped: usize,
+    /// Line number and reason of the first skipped rules
+    pub errors: Vec<String>,
+    /// Modification time. RFC3339 format
+    pub updated: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleSetStatus {
+    pub enabled: bool,
+    pub dir: String,
+    pub rule_count: usize,
+    pub files: Vec<RuleFileStatus>,
+    /// RFC3339 format
+    pub loaded: String,
+    pub error: Option<String>,
+}
+
+/// Rules of all files in the rule directory
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// Indexes of TCP and UDP rules with few destination ports, by protocol and port
+    by_dst_port: HashMap<(RuleProtocol, u16), Vec<usize>>,
+    /// Indexes of the other rules
+    unindexed: Vec<usize>,
+    status: RuleSetStatus,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet {
+            rules: Vec::new(),
+            by_dst_port: HashMap::new(),
+            unindexed: Vec::new(),
+            status: RuleSetStatus {
+                enabled: false,
+                dir: String::new(),
+                rule_count: 0,
+                files: Vec::new(),
+                loaded: String::new(),
+                error: None,
+            },
+        }
+    }
+    /// Load the rule files. Rules that fail are reported in the status and skipped
+    pub fn load(config: &RuleConfig) -> RuleSet {
+        let mut rule_set = RuleSet::new();
+        rule_set.status.enabled = config.enabled;
+        rule_set.status.loaded = sys::get_sysdate();
+        let dir = match rule_dir_path() {
+            Some(dir) => dir,
+            None => {
+                rule_set.status.error = Some(String::from("No config directory"));
+                return rule_set;
+            }
+        };
+        rule_set.status.dir = dir.to_string_lossy().to_string();
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            rule_set.status.error = Some(format!("Failed to create {}: {}", dir.display(), e));
+            return rule_set;
+        }
+        if !config.enabled {
+            return rule_set;
+        }
+        let vars = config.vars();
+        let mut sids: HashSet<u32> = HashSet::new();
+        for path in rule_file_paths(&dir) {
+            let status = rule_set.add_file(&path, &vars, &mut sids);
+            rule_set.status.files.push(status);
+        }
+        rule_set.build_index();
+        rule_set.status.rule_count = rule_set.rules.len();
+        rule_set
+    }
+    fn build_index(&mut self) {
+        self.by_dst_port.clear();
+        self.unindexed.clear();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let ports: u32 = rule
+                .dst_ports
+                .include
+                .iter()
+                .map(|(start, end)| (*end - *start) as u32 + 1)
+                .sum();
+            let indexed = matches!(rule.protocol, RuleProtocol::Tcp | RuleProtocol::Udp)
+                && !rule.dst_ports.include.is_empty()
+                && ports <= MAX_INDEXED_PORTS;
+            if !indexed {
+                self.unindexed.push(index);
+                continue;
+            }
+            for (start, end) in &rule.dst_ports.include {
+                for port in *start..=*end {
+                    self.by_dst_port.entry((rule.protocol, port)).or_insert_with(Vec::new).push(index);
+                }
+            }
+        }
+    }
+    fn add_file(&mut self, path: &Path, vars: &HashMap<String, String>, sids: &mut HashSet<u32>) -> RuleFileStatus {
+        let mut status = RuleFileStatus {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            rule_count: 0,
+            skipped: 0,
+            errors: Vec::new(),
+            updated: String::new(),
+        };
+        if let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            status.updated = chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339();
+        }
+        let content = match std::fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(e) => {
+                status.errors.push(format!("Failed to read {}: {}", path.display(), e));
+                return status;
+            }
+        };
+        // Rules continue on the next line after a trailing backslash
+        let mut text = String::new();
+        let mut start_line: usize = 0;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if text.is_empty() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                start_line = index + 1;
+            }
+            match line.strip_suffix('\\') {
+                Some(part) => {
+                    text.push_str(part);
+                    continue;
+                }
+                None => text.push_str(line),
+            }
+            let result = parse_rule(&text, vars).and_then(|rule| {
+                if sids.insert(rule.sid) {
+                    Ok(rule)
+                } else {
+                    Err(format!("Duplicate sid {}", rule.sid))
+                }
+            });
+            match result {
+                Ok(rule) => {
+                    self.rules.push(rule);
+                    status.rule_count += 1;
+                }
+                Err(e) => {
+                    status.skipped += 1;
+                    if status.errors.len() < MAX_FILE_ERRORS {
+                        status.errors.push(format!("Line {}: {}", start_line, e));
+                    }
+                }
+            }
+            text.clear();
+        }
+        status
+    }
+    /// Rules matching the packet. Rules with content are skipped unless with_content is set
+    pub fn matches<'a>(&'a self, packet: &'a PacketFields, with_content: bool) -> impl Iterator<Item = &'a Rule> + 'a {
+        // A bidirectional rule may match the source port of the packet against its destination ports
+        let mut candidates: Vec<usize> = self.unindexed.clone();
+        for port in [packet.dst_port, packet.src_port].iter().flatten() {
+            if let Some(indexes) = self.by_dst_port.get(&(packet.protocol, *port)) {
+                candidates.extend(indexes);
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .map(move |index| &self.rules[index])
+            .filter(move |rule| (with_content || !rule.has_content()) && rule.matches(packet))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    pub fn status(&self) -> RuleSetStatus {
+        self.status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        RuleConfig::new().vars()
+    }
+
+    fn parse(text: &str) -> Result<Rule, String> {
+        parse_rule(text, &vars())
+    }
+
+    fn packet<'a>(src: &str, src_port: u16, dst: &str, dst_port: u16, payload: &'a [u8]) -> PacketFields<'a> {
+        PacketFields {
+            protocol: RuleProtocol::Tcp,
+            src_ip_addr: src.parse().unwrap(),
+            dst_ip_addr: dst.parse().unwrap(),
+            src_port: Some(src_port),
+            dst_port: Some(dst_port),
+            tcp_flags: Some(TCP_FLAG_ACK),
+            to_server: Some(true),
+            payload: payload,
+        }
+    }
+
+    fn rule_set(rules: &[&str]) -> RuleSet {
+        let mut rule_set = RuleSet::new();
+        rule_set.rules = rules.iter().map(|text| parse(text).unwrap()).collect();
+        rule_set.build_index();
+        rule_set
+    }
+
+    fn matched_sids(rule_set: &RuleSet, packet: &PacketFields, with_content: bool) -> Vec<u32> {
+        rule_set.matches(packet, with_content).map(|rule| rule.sid).collect()
+    }
+
+    #[test]
+    fn parse_header() {
+        let rule = parse(r#"alert tcp $EXTERNAL_NET any <> $HOME_NET 22 (msg:"SSH \"in\"\; test"; sid:1000001; rev:3;)"#).unwrap();
+        assert_eq!(rule.action, RuleAction::Alert);
+        assert_eq!(rule.protocol, RuleProtocol::Tcp);
+        assert!(rule.bidirectional);
+        assert_eq!(rule.sid, 1000001);
+        assert_eq!(rule.rev, 3);
+        assert_eq!(rule.msg, "SSH \"in\"; test");
+        assert_eq!(parse("drop udp any any -> any 53 (sid:2;)").unwrap().action, RuleAction::Drop);
+        assert_eq!(parse("rejectboth ip any any -> any any (sid:3;)").unwrap().action, RuleAction::Reject);
+        assert!(parse("alert tcp any any -> any (sid:4;)").unwrap_err().starts_with("Invalid rule header"));
+        assert!(parse("pass tcp any any -> any any (sid:5;)").unwrap_err().starts_with("Unsupported action"));
+        assert!(parse("alert http any any -> any any (sid:6;)").unwrap_err().starts_with("Unsupported protocol"));
+        assert!(parse("alert tcp any any <- any any (sid:7;)").unwrap_err().starts_with("Invalid direction"));
+        assert!(parse("alert tcp any any -> any any").unwrap_err().starts_with("Missing rule options"));
+        assert_eq!(parse("alert tcp any any -> any any (msg:\"no sid\";)").unwrap_err(), "Missing sid");
+        assert!(parse("alert tcp $NOPE any -> any any (sid:8;)").unwrap_err().starts_with("Unknown variable"));
+    }
+
+    #[test]
+    fn address_lists_with_negation() {
+        let set = parse_address("[192.168.0.0/16,!192.168.1.0/24]", &vars(), 0).unwrap();
+        assert!(set.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!set.contains(&"192.168.1.1".parse().unwrap()));
+        assert!(!set.contains(&"10.0.0.1".parse().unwrap()));
+        let external = parse_address("$EXTERNAL_NET", &vars(), 0).unwrap();
+        assert!(external.contains(&"8.8.8.8".parse().unwrap()));
+        assert!(!external.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!external.contains(&"fe80::1".parse().unwrap()));
+        let any = parse_address("[10.0.0.0/8,any]", &vars(), 0).unwrap();
+        assert!(any.contains(&"8.8.8.8".parse().unwrap()));
+        // Negating a negation is not supported
+        assert!(parse_address("!!10.0.0.0/8", &vars(), 0).is_err());
+        assert!(parse_address("[[[[[[[[[[10.0.0.1]]]]]]]]]]", &vars(), 0).is_err());
+    }
+
+    #[test]
+    fn port_lists_with_negation() {
+        let set = parse_ports("[80,443,8000:8080]", &vars(), 0).unwrap();
+        assert!(set.contains(Some(80)));
+        assert!(set.contains(Some(8042)));
+        assert!(!set.contains(Some(22)));
+        assert!(!set.contains(None));
+        let open_range = parse_ports("[1024:,!3389]", &vars(), 0).unwrap();
+        assert!(open_range.contains(Some(65535)));
+        assert!(!open_range.contains(Some(3389)));
+        assert!(!open_range.contains(Some(80)));
+        let negated = parse_ports("![80,443]", &vars(), 0).unwrap();
+        assert!(negated.contains(Some(22)));
+        assert!(!negated.contains(Some(443)));
+        let shellcode = parse_ports("$SHELLCODE_PORTS", &vars(), 0).unwrap();
+        assert!(!shellcode.contains(Some(80)));
+        assert!(parse_ports("any", &vars(), 0).unwrap().contains(None));
+        assert!(parse_ports("9000:8000", &vars(), 0).is_err());
+        assert!(parse_ports("70000", &vars(), 0).is_err());
+    }
+
+    #[test]
+    fn hex_content() {
+        assert_eq!(parse_content(r#""|47 45 54|""#).unwrap(), (b"GET".to_vec(), false));
+        assert_eq!(parse_content(r#""a|0d0A|b""#).unwrap(), (b"a\r\nb".to_vec(), false));
+        assert_eq!(parse_content(r#"!"x\"y""#).unwrap(), (b"x\"y".to_vec(), true));
+        assert!(parse_content(r#""|4|""#).is_err());
+        assert!(parse_content(r#""|47 zz|""#).is_err());
+        assert!(parse_content(r#""|47""#).is_err());
+        assert!(parse_content(r#""""#).is_err());
+        assert!(parse_content("GET").is_err());
+    }
+
+    #[test]
+    fn content_offset_depth_nocase() {
+        let rule = parse(r#"alert tcp any any -> any any (content:"get"; nocase; offset:2; depth:5; sid:1;)"#).unwrap();
+        assert_eq!(
+            rule.contents,
+            vec![ContentMatch {
+                pattern: b"get".to_vec(),
+                negated: false,
+                nocase: true,
+                offset: 2,
+                depth: Some(5),
+            }]
+        );
+        let content = &rule.contents[0];
+        assert!(content.matches(b"..GET /"));
+        assert!(content.matches(b"....GeT"));
+        // Starts before offset or ends after offset + depth
+        assert!(!content.matches(b"GET /"));
+        assert!(!content.matches(b".....GET"));
+        let negated = parse(r#"alert tcp any any -> any any (content:!"admin"; sid:2;)"#).unwrap();
+        assert!(negated.contents[0].matches(b"GET /index"));
+        assert!(!negated.contents[0].matches(b"GET /admin"));
+        assert!(parse("alert tcp any any -> any any (nocase; sid:3;)").unwrap_err().starts_with("nocase without content"));
+        assert!(parse(r#"alert tcp any any -> any any (content:"a"; depth:x; sid:4;)"#).is_err());
+    }
+
+    #[test]
+    fn unsupported_keywords_are_rejected() {
+        assert_eq!(
+            parse(r#"alert tcp any any -> any any (content:"a"; pcre:"/a/"; sid:1;)"#).unwrap_err(),
+            "Unsupported keyword: pcre"
+        );
+        assert!(parse("alert tcp any any -> any any (flow:to_server,only_stream; sid:2;)").is_err());
+        assert!(parse("alert tcp any any -> any any (classtype:trojan-activity; reference:url,example.com; sid:3;)").is_ok());
+    }
+
+    #[test]
+    fn flow_direction_needs_the_start_of_the_flow() {
+        let rule = parse("alert tcp any any -> any 80 (flow:to_server; sid:1;)").unwrap();
+        let mut to_server = packet("10.0.0.2", 40000, "10.0.0.1", 80, b"");
+        assert!(rule.matches(&to_server));
+        to_server.to_server = Some(false);
+        assert!(!rule.matches(&to_server));
+        // Unknown flows match no direction
+        to_server.to_server = None;
+        assert!(!rule.matches(&to_server));
+        // A SYN without ACK is always sent by the client
+        to_server.tcp_flags = Some(TCP_FLAG_SYN);
+        assert!(rule.matches(&to_server));
+    }
+
+    #[test]
+    fn established_skips_lone_probes() {
+        let rule = parse("alert tcp any any -> any 80 (flow:established; sid:1;)").unwrap();
+        let mut segment = packet("10.0.0.2", 40000, "10.0.0.1", 80, b"");
+        assert!(rule.matches(&segment));
+        // ACK or RST probe on a flow whose SYN was not seen
+        segment.to_server = None;
+        assert!(!rule.matches(&segment));
+        segment.to_server = Some(true);
+        segment.tcp_flags = Some(TCP_FLAG_RST | TCP_FLAG_ACK);
+        assert!(!rule.matches(&segment));
+        segment.tcp_flags = Some(TCP_FLAG_SYN);
+        assert!(!rule.matches(&segment));
+        let not_established = parse("alert tcp any any -> any 80 (flow:not_established; sid:2;)").unwrap();
+        assert!(not_established.matches(&segment));
+    }
+
+    #[test]
+    fn rule_set_index_by_port() {
+        let rule_set = rule_set(&[
+            "alert tcp any any -> any 80 (sid:1;)",
+            "alert tcp any any <> any 22 (sid:2;)",
+            "alert udp any any -> any 80 (sid:3;)",
+            "alert tcp any any -> any any (sid:4;)",
+            "alert ip any any -> any any (sid:5;)",
+            "alert tcp any any -> any ![80,443] (sid:6;)",
+            r#"alert tcp any any -> any 80 (content:"GET"; sid:7;)"#,
+        ]);
+        assert!(rule_set.unindexed.len() == 3);
+        let web = packet("10.0.0.2", 40000, "10.0.0.1", 80, b"GET /");
+        assert_eq!(matched_sids(&rule_set, &web, true), vec![1, 4, 5, 7]);
+        // Content rules are left to sampled frames
+        assert_eq!(matched_sids(&rule_set, &web, false), vec![1, 4, 5]);
+        let reply = packet("10.0.0.1", 22, "10.0.0.2", 40000, b"");
+        assert_eq!(matched_sids(&rule_set, &reply, true), vec![2, 4, 5, 6]);
+    }
+}
@@ -1,4 +1,7 @@
 use crate::db;
+use crate::db::bin;
+use crate::db::error::DbError;
+use crate::db::update::DatabaseKind;
 use std::collections::HashMap;
 
 /// In-memory service database with hash map
@@ -15,18 +18,28 @@ impl ServiceDatabase {
             udp_map: HashMap::new(),
         }
     }
-    pub fn load() -> Result<ServiceDatabase, Box<dyn std::error::Error>> {
+    /// Load both tables, falling back to the embedded copies. Returns the problems found
+    pub fn load() -> (ServiceDatabase, Vec<DbError>) {
         let mut service_db = ServiceDatabase::new();
-        service_db.load_tcp_map()?;
-        service_db.load_udp_map()?;
-        Ok(service_db)
+        let mut errors: Vec<DbError> = Vec::new();
+        service_db.load_tcp_map(&mut errors);
+        service_db.load_udp_map(&mut errors);
+        (service_db, errors)
     }
-    pub fn load_tcp_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.tcp_map = db::tcp_service::get_map();
-        Ok(())
+    pub fn load_tcp_map(&mut self, errors: &mut Vec<DbError>) {
+        self.tcp_map = bin::load_table(
+            DatabaseKind::TcpService,
+            db::tcp_service::get_map_from_file,
+            db::tcp_service::get_map,
+            errors,
+        );
     }
-    pub fn load_udp_map(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.udp_map = db::udp_service::get_map();
-        Ok(())
+    pub fn load_udp_map(&mut self, errors: &mut Vec<DbError>) {
+        self.udp_map = bin::load_table(
+            DatabaseKind::UdpService,
+            db::udp_service::get_map_from_file,
+            db::udp_service::get_map,
+            errors,
+        );
     }
 }
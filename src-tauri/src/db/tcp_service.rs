@@ -1,4 +1,6 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -23,28 +25,30 @@ impl TcpService {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> HashMap<u16, String> {
+impl DbRecord for TcpService {
+    const MIN_SIZE: usize = 10;
+}
+
+fn to_map(tcp_services: Vec<TcpService>) -> HashMap<u16, String> {
     let mut tcp_map: HashMap<u16, String> = HashMap::new();
-    let tcp_services: Vec<TcpService> = bincode::deserialize(TCP_SERVICE_BIN).unwrap();
     for tcp_service in tcp_services {
         tcp_map.insert(tcp_service.port, tcp_service.service_name);
     }
     tcp_map
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> HashMap<u16, String> {
-    let mut tcp_map: HashMap<u16, String> = HashMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let tcp_services: Vec<TcpService> = bincode::deserialize(&f).unwrap();
-            for tcp_service in tcp_services {
-                tcp_map.insert(tcp_service.port, tcp_service.service_name);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    tcp_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<HashMap<u16, String>, DbError> {
+    let tcp_services: Vec<TcpService> = bin::decode_records(TCP_SERVICE_BIN, TCP_SERVICE_BIN_NAME)?;
+    Ok(to_map(tcp_services))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<HashMap<u16, String>, DbError> {
+    Err(DbError::NotBundled { file_name: TCP_SERVICE_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<HashMap<u16, String>, DbError> {
+    let tcp_services: Vec<TcpService> = bin::read_records(&file_path, TCP_SERVICE_BIN_NAME)?;
+    Ok(to_map(tcp_services))
 }
@@ -0,0 +1,544 @@
+use crate::db::import::split_csv_line;
+use crate::db::label::parse_cidr;
+use crate::sys;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Downloaded feeds in the config directory
+pub const FEED_DIR_NAME: &str = "feeds";
+pub const DEFAULT_FEED_REFRESH_INTERVAL: u64 = 3600 * 6;
+/// Column names holding the indicator in CSV feeds
+const INDICATOR_COLUMNS: [&str; 10] = [
+    "indicator", "ioc", "ip", "ip_address", "dst_ip", "cidr", "network", "domain", "host", "value",
+];
+/// Sink addresses of hosts-file style blocklists
+const HOSTS_FILE_ADDRS: [&str; 4] = ["0.0.0.0", "127.0.0.1", "::", "::1"];
+/// STIX pattern object paths with an indicator value
+const STIX_PATTERN_PATHS: [&str; 3] = ["ipv4-addr:value", "ipv6-addr:value", "domain-name:value"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreatFeedFormat {
+    /// One IP, CIDR or domain per line. Hosts-file lines are accepted
+    PlainText,
+    Csv,
+    /// STIX 2 bundle with indicator patterns or cyber observables
+    StixJson,
+}
+
+impl ThreatFeedFormat {
+    pub fn detect(path: &Path, content: &str) -> ThreatFeedFormat {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "json" => return ThreatFeedFormat::StixJson,
+            "csv" => return ThreatFeedFormat::Csv,
+            _ => {}
+        }
+        match content.trim_start().chars().next() {
+            Some('{') | Some('[') => ThreatFeedFormat::StixJson,
+            _ => ThreatFeedFormat::PlainText,
+        }
+    }
+}
+
+/// A blocklist. The source is a local file path or an http(s) URL
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ThreatFeedConfig {
+    pub name: String,
+    pub source: String,
+    /// Detected from the file name and content if not set
+    #[serde(default)]
+    pub format: Option<ThreatFeedFormat>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl ThreatFeedConfig {
+    pub fn is_remote(&self) -> bool {
+        let source = self.source.trim().to_lowercase();
+        source.starts_with("http://") || source.starts_with("https://")
+    }
+    /// File the feed is read from. Remote feeds are read from their downloaded copy
+    pub fn file_path(&self) -> Option<PathBuf> {
+        if self.is_remote() {
+            feed_dir_path().map(|dir| dir.join(self.cache_file_name()))
+        } else if self.source.trim().is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(self.source.trim()))
+        }
+    }
+    fn cache_file_name(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        format!("{}.feed", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreatIntelConfig {
+    pub feeds: Vec<ThreatFeedConfig>,
+    /// Seconds between downloads of remote feeds
+    pub refresh_interval: u64,
+}
+
+impl ThreatIntelConfig {
+    pub fn new() -> ThreatIntelConfig {
+        ThreatIntelConfig {
+            feeds: Vec::new(),
+            refresh_interval: DEFAULT_FEED_REFRESH_INTERVAL,
+        }
+    }
+}
+
+impl Default for ThreatIntelConfig {
+    fn default() -> Self {
+        ThreatIntelConfig::new()
+    }
+}
+
+/// Result of loading a feed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreatFeedStatus {
+    pub name: String,
+    pub source: String,
+    pub ip_count: usize,
+    pub domain_count: usize,
+    /// Modification time of the file read. RFC3339 format
+    pub updated: String,
+    pub error: Option<String>,
+}
+
+pub fn feed_dir_path() -> Option<PathBuf> {
+    sys::get_config_dir_path().map(|path| path.join(FEED_DIR_NAME))
+}
+
+/// An indicator of a feed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Indicator {
+    Net(IpNet),
+    Domain(String),
+}
+
+/// Parse an IP, CIDR or domain. Returns None for anything else.
+/// URLs are skipped: a listed URL does not make its whole host malicious
+pub fn parse_indicator(value: &str) -> Option<Indicator> {
+    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+    if value.is_empty() || value.contains("://") {
+        return None;
+    }
+    if let Ok(net) = parse_cidr(value) {
+        return Some(Indicator::Net(net));
+    }
+    let domain = normalize_domain(value);
+    let valid = domain.contains('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+        && !domain.chars().all(|c| c.is_ascii_digit() || c == '.');
+    if valid {
+        Some(Indicator::Domain(domain))
+    } else {
+        None
+    }
+}
+
+/// Lower case without wildcard prefix and trailing dot
+pub fn normalize_domain(name: &str) -> String {
+    name.trim()
+        .trim_start_matches("*.")
+        .trim_matches('.')
+        .to_lowercase()
+}
+
+fn parse_plain_text(content: &str) -> Vec<Indicator> {
+    let mut indicators: Vec<Indicator> = Vec::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with("//") {
+            continue;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let token = if tokens.len() >= 2 && HOSTS_FILE_ADDRS.contains(&tokens[0]) {
+            tokens[1]
+        } else {
+            tokens[0]
+        };
+        if let Some(indicator) = parse_indicator(token) {
+            indicators.push(indicator);
+        }
+    }
+    indicators
+}
+
+fn parse_csv(content: &str) -> Vec<Indicator> {
+    let mut indicators: Vec<Indicator> = Vec::new();
+    let mut column: Option<usize> = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        // Some feeds put the header in a comment
+        let (is_comment, body) = match trimmed.strip_prefix('#') {
+            Some(body) => (true, body.trim()),
+            None => (false, trimmed),
+        };
+        let fields = split_csv_line(body, ',');
+        if column.is_none() {
+            let header = fields.iter().position(|field| {
+                INDICATOR_COLUMNS.contains(&field.trim().to_lowercase().as_str())
+            });
+            if let Some(index) = header {
+                column = Some(index);
+                continue;
+            }
+        }
+        if is_comment {
+            continue;
+        }
+        let field = match fields.get(column.unwrap_or(0)) {
+            Some(field) => field,
+            None => continue,
+        };
+        if let Some(indicator) = parse_indicator(field) {
+            indicators.push(indicator);
+        }
+    }
+    indicators
+}
+
+/// Values of the supported object paths in a STIX pattern,
+/// e.g. [ipv4-addr:value = '198.51.100.1' OR domain-name:value = 'example.com']
+fn stix_pattern_values(pattern: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for path in STIX_PATTERN_PATHS {
+        let mut rest = pattern;
+        while let Some(pos) = rest.find(path) {
+            rest = &rest[pos + path.len()..];
+            let start = match rest.find('\'') {
+                Some(start) => start + 1,
+                None => break,
+            };
+            let end = match rest[start..].find('\'') {
+                Some(end) => start + end,
+                None => break,
+            };
+            values.push(rest[start..end].to_string());
+            rest = &rest[end + 1..];
+        }
+    }
+    values
+}
+
+fn parse_stix_json(content: &str) -> Result<Vec<Indicator>, String> {
+    let json: serde_json::Value = match serde_json::from_str(content) {
+        Ok(json) => json,
+        Err(e) => return Err(format!("Invalid JSON: {}", e)),
+    };
+    let objects = match &json {
+        serde_json::Value::Array(objects) => objects,
+        serde_json::Value::Object(bundle) => match bundle.get("objects") {
+            Some(serde_json::Value::Array(objects)) => objects,
+            _ => return Err(String::from("No objects in STIX bundle")),
+        },
+        _ => return Err(String::from("Not a STIX bundle")),
+    };
+    let mut indicators: Vec<Indicator> = Vec::new();
+    for object in objects {
+        let object_type = object.get("type").and_then(|v| v.as_str()).unwrap_or("");
+        let values: Vec<String> = match object_type {
+            "indicator" => match object.get("pattern").and_then(|v| v.as_str()) {
+                Some(pattern) => stix_pattern_values(pattern),
+                None => Vec::new(),
+            },
+            "ipv4-addr" | "ipv6-addr" | "domain-name" => match object.get("value").and_then(|v| v.as_str()) {
+                Some(value) => vec![value.to_string()],
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        indicators.extend(values.iter().filter_map(|value| parse_indicator(value)));
+    }
+    Ok(indicators)
+}
+
+/// Read the indicators of a feed file
+pub fn read_feed(path: &Path, format: Option<ThreatFeedFormat>) -> Result<Vec<Indicator>, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let content = String::from_utf8_lossy(&bytes);
+    match format.unwrap_or_else(|| ThreatFeedFormat::detect(path, &content)) {
+        ThreatFeedFormat::PlainText => Ok(parse_plain_text(&content)),
+        ThreatFeedFormat::Csv => Ok(parse_csv(&content)),
+        ThreatFeedFormat::StixJson => parse_stix_json(&content),
+    }
+}
+
+/// A feed hit. indicator is the network or domain listed in the feeds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ThreatMatch {
+    pub indicator: String,
+    pub feeds: Vec<String>,
+}
+
+/// Indicators of all enabled feeds
+#[derive(Debug, Clone)]
+pub struct ThreatDatabase {
+    feed_names: Vec<String>,
+    /// Networks by prefix. Values index into feed_names
+    nets: HashMap<IpNet, Vec<u16>>,
+    /// Distinct prefix lengths in nets, longest first
+    ipv4_prefix_lens: Vec<u8>,
+    ipv6_prefix_lens: Vec<u8>,
+    domains: HashMap<String, Vec<u16>>,
+    status: Vec<ThreatFeedStatus>,
+}
+
+impl ThreatDatabase {
+    pub fn new() -> ThreatDatabase {
+        ThreatDatabase {
+            feed_names: Vec::new(),
+            nets: HashMap::new(),
+            ipv4_prefix_lens: Vec::new(),
+            ipv6_prefix_lens: Vec::new(),
+            domains: HashMap::new(),
+            status: Vec::new(),
+        }
+    }
+    /// Load the enabled feeds. Feeds that fail are reported in the status and skipped
+    pub fn load(config: &ThreatIntelConfig) -> ThreatDatabase {
+        let mut db = ThreatDatabase::new();
+        for feed in config.feeds.iter().filter(|feed| feed.enabled) {
+            let mut status = ThreatFeedStatus {
+                name: feed.name.clone(),
+                source: feed.source.clone(),
+                ip_count: 0,
+                domain_count: 0,
+                updated: String::new(),
+                error: None,
+            };
+            let path = match feed.file_path() {
+                Some(path) => path,
+                None => {
+                    status.error = Some(String::from("No source"));
+                    db.status.push(status);
+                    continue;
+                }
+            };
+            if let Ok(modified) = std::fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                status.updated = chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339();
+            }
+            match read_feed(&path, feed.format) {
+                Ok(indicators) => {
+                    let (ip_count, domain_count) = db.add_feed(&feed.name, indicators);
+                    status.ip_count = ip_count;
+                    status.domain_count = domain_count;
+                }
+                Err(e) => {
+                    status.error = Some(e);
+                }
+            }
+            db.status.push(status);
+        }
+        db
+    }
+    /// Returns the number of networks and domains added
+    fn add_feed(&mut self, name: &str, indicators: Vec<Indicator>) -> (usize, usize) {
+        let feed = match self.feed_names.iter().position(|feed_name| feed_name == name) {
+            Some(feed) => feed as u16,
+            None => {
+                self.feed_names.push(name.to_string());
+                (self.feed_names.len() - 1) as u16
+            }
+        };
+        let mut ip_count: usize = 0;
+        let mut domain_count: usize = 0;
+        for indicator in indicators {
+            let feeds = match indicator {
+                Indicator::Net(net) => {
+                    let prefix_lens = match net {
+                        IpNet::V4(_) => &mut self.ipv4_prefix_lens,
+                        IpNet::V6(_) => &mut self.ipv6_prefix_lens,
+                    };
+                    if !prefix_lens.contains(&net.prefix_len()) {
+                        prefix_lens.push(net.prefix_len());
+                        prefix_lens.sort_by(|a, b| b.cmp(a));
+                    }
+                    ip_count += 1;
+                    self.nets.entry(net).or_insert_with(Vec::new)
+                }
+                Indicator::Domain(domain) => {
+                    domain_count += 1;
+                    self.domains.entry(domain).or_insert_with(Vec::new)
+                }
+            };
+            if !feeds.contains(&feed) {
+                feeds.push(feed);
+            }
+        }
+        (ip_count, domain_count)
+    }
+    fn feed_names(&self, feeds: &[u16]) -> Vec<String> {
+        feeds.iter().map(|feed| self.feed_names[*feed as usize].clone()).collect()
+    }
+    /// Most specific network listing the address. Feeds of all listing networks are included
+    pub fn lookup_ip(&self, ip_addr: &IpAddr) -> Option<ThreatMatch> {
+        let prefix_lens = match ip_addr {
+            IpAddr::V4(_) => &self.ipv4_prefix_lens,
+            IpAddr::V6(_) => &self.ipv6_prefix_lens,
+        };
+        let mut result: Option<ThreatMatch> = None;
+        for prefix_len in prefix_lens {
+            let net = match IpNet::new(*ip_addr, *prefix_len) {
+                Ok(net) => net.trunc(),
+                Err(_) => continue,
+            };
+            if let Some(feeds) = self.nets.get(&net) {
+                let threat_match = result.get_or_insert_with(|| ThreatMatch {
+                    // Single addresses without the prefix length
+                    indicator: if net.prefix_len() == net.max_prefix_len() {
+                        net.addr().to_string()
+                    } else {
+                        net.to_string()
+                    },
+                    feeds: Vec::new(),
+                });
+                for feed in self.feed_names(feeds) {
+                    if !threat_match.feeds.contains(&feed) {
+                        threat_match.feeds.push(feed);
+                    }
+                }
+            }
+        }
+        result
+    }
+    /// The name or its closest listed parent domain
+    pub fn lookup_domain(&self, name: &str) -> Option<ThreatMatch> {
+        let name = normalize_domain(name);
+        let mut domain = name.as_str();
+        loop {
+            if let Some(feeds) = self.domains.get(domain) {
+                return Some(ThreatMatch {
+                    indicator: domain.to_string(),
+                    feeds: self.feed_names(feeds),
+                });
+            }
+            match domain.find('.') {
+                Some(pos) => domain = &domain[pos + 1..],
+                None => return None,
+            }
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nets.is_empty() && self.domains.is_empty()
+    }
+    pub fn status(&self) -> Vec<ThreatFeedStatus> {
+        self.status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(value: &str) -> Indicator {
+        Indicator::Net(parse_cidr(value).unwrap())
+    }
+
+    fn domain(value: &str) -> Indicator {
+        Indicator::Domain(value.to_string())
+    }
+
+    #[test]
+    fn indicators() {
+        assert_eq!(parse_indicator("198.51.100.7"), Some(net("198.51.100.7")));
+        assert_eq!(parse_indicator("'198.51.100.0/24'"), Some(net("198.51.100.0/24")));
+        assert_eq!(parse_indicator("2001:db8::/32"), Some(net("2001:db8::/32")));
+        assert_eq!(parse_indicator("*.Evil.Example."), Some(domain("evil.example")));
+        assert_eq!(parse_indicator("https://evil.example/payload.exe"), None);
+        assert_eq!(parse_indicator("hxxp://evil.example"), None);
+        assert_eq!(parse_indicator("localhost"), None);
+        assert_eq!(parse_indicator("1.2.3"), None);
+        assert_eq!(parse_indicator("evil.example/path"), None);
+        assert_eq!(parse_indicator(""), None);
+    }
+
+    #[test]
+    fn plain_text_feed() {
+        let content = "# comment\n\
+            198.51.100.7\n\
+            ; another comment\n\
+            0.0.0.0 ads.example # hosts file\n\
+            127.0.0.1 tracker.example\n\
+            203.0.113.0/24 listed 2024-01-01\n\
+            https://evil.example/payload.exe\n\
+            not-a-domain\n";
+        assert_eq!(
+            parse_plain_text(content),
+            vec![net("198.51.100.7"), domain("ads.example"), domain("tracker.example"), net("203.0.113.0/24")]
+        );
+    }
+
+    #[test]
+    fn csv_feed() {
+        let content = "# first_seen,dst_ip,dst_port\n\
+            2024-01-01,198.51.100.7,443\n\
+            2024-01-02,\"203.0.113.9\",8080\n\
+            2024-01-03,,80\n";
+        assert_eq!(parse_csv(content), vec![net("198.51.100.7"), net("203.0.113.9")]);
+        // Without a header the first column is used
+        assert_eq!(parse_csv("evil.example,malware\nhttps://evil.example/x,phishing\n"), vec![domain("evil.example")]);
+    }
+
+    #[test]
+    fn stix_feed() {
+        let content = r#"{"type": "bundle", "objects": [
+            {"type": "indicator", "pattern": "[ipv4-addr:value = '198.51.100.7' OR domain-name:value = 'evil.example']"},
+            {"type": "indicator", "pattern": "[url:value = 'https://good.example/bad']"},
+            {"type": "ipv6-addr", "value": "2001:db8::1"},
+            {"type": "url", "value": "https://good.example/other"},
+            {"type": "malware", "name": "x"}
+        ]}"#;
+        assert_eq!(
+            parse_stix_json(content).unwrap(),
+            vec![net("198.51.100.7"), domain("evil.example"), net("2001:db8::1")]
+        );
+        assert!(parse_stix_json(r#"[{"type": "domain-name", "value": "evil.example"}]"#).is_ok());
+        assert!(parse_stix_json(r#"{"type": "bundle"}"#).is_err());
+        assert!(parse_stix_json("not json").is_err());
+    }
+
+    #[test]
+    fn lookup_parent_domain_and_network() {
+        let mut db = ThreatDatabase::new();
+        db.add_feed("feed-a", vec![domain("evil.example"), net("198.51.100.0/24")]);
+        db.add_feed("feed-b", vec![domain("sub.evil.example"), net("198.51.100.7")]);
+        let hit = db.lookup_domain("a.b.evil.example.").unwrap();
+        assert_eq!(hit.indicator, "evil.example");
+        assert_eq!(hit.feeds, vec!["feed-a".to_string()]);
+        // Closest listed parent wins
+        assert_eq!(db.lookup_domain("x.sub.evil.example").unwrap().feeds, vec!["feed-b".to_string()]);
+        assert!(db.lookup_domain("notevil.example").is_none());
+        assert!(db.lookup_domain("example").is_none());
+        let hit = db.lookup_ip(&"198.51.100.7".parse().unwrap()).unwrap();
+        assert_eq!(hit.indicator, "198.51.100.7");
+        assert_eq!(hit.feeds, vec!["feed-b".to_string(), "feed-a".to_string()]);
+        assert_eq!(db.lookup_ip(&"198.51.100.8".parse().unwrap()).unwrap().indicator, "198.51.100.0/24");
+        assert!(db.lookup_ip(&"203.0.113.1".parse().unwrap()).is_none());
+    }
+}
@@ -1,4 +1,6 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -23,28 +25,30 @@ impl UdpService {
     }
 }
 
-#[cfg(feature = "bundle")]
-pub fn get_map() -> HashMap<u16, String> {
+impl DbRecord for UdpService {
+    const MIN_SIZE: usize = 10;
+}
+
+fn to_map(udp_services: Vec<UdpService>) -> HashMap<u16, String> {
     let mut udp_map: HashMap<u16, String> = HashMap::new();
-    let udp_services: Vec<UdpService> = bincode::deserialize(UDP_SERVICE_BIN).unwrap();
     for udp_service in udp_services {
         udp_map.insert(udp_service.port, udp_service.service_name);
     }
     udp_map
 }
 
-pub fn get_map_from_file(file_path: PathBuf) -> HashMap<u16, String> {
-    let mut udp_map: HashMap<u16, String> = HashMap::new();
-    match fs::read(file_path) {
-        Ok(f) => {
-            let udp_services: Vec<UdpService> = bincode::deserialize(&f).unwrap();
-            for udp_service in udp_services {
-                udp_map.insert(udp_service.port, udp_service.service_name);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-        }
-    }
-    udp_map
+#[cfg(feature = "bundle")]
+pub fn get_map() -> Result<HashMap<u16, String>, DbError> {
+    let udp_services: Vec<UdpService> = bin::decode_records(UDP_SERVICE_BIN, UDP_SERVICE_BIN_NAME)?;
+    Ok(to_map(udp_services))
+}
+
+#[cfg(not(feature = "bundle"))]
+pub fn get_map() -> Result<HashMap<u16, String>, DbError> {
+    Err(DbError::NotBundled { file_name: UDP_SERVICE_BIN_NAME.to_owned() })
+}
+
+pub fn get_map_from_file(file_path: PathBuf) -> Result<HashMap<u16, String>, DbError> {
+    let udp_services: Vec<UdpService> = bin::read_records(&file_path, UDP_SERVICE_BIN_NAME)?;
+    Ok(to_map(udp_services))
 }
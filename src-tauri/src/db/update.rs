@@ -0,0 +1,586 @@
+use crate::db;
+use crate::db::bin::{self, DbRecord};
+use crate::db::error::DbError;
+use crate::net::http::DownloadProgress;
+use crate::sys;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+pub const DEFAULT_DB_BASE_URL: &str = "https://r2.ntap.io";
+pub const DB_DIR_NAME: &str = "db";
+/// Remote manifest listing the available database files
+pub const DB_MANIFEST_FILE_NAME: &str = "manifest.json";
+/// Manifest of the installed database files in the db directory
+pub const INSTALLED_MANIFEST_FILE_NAME: &str = "installed.json";
+const DOWNLOAD_SUFFIX: &str = ".download";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DatabaseKind {
+    As,
+    Country,
+    Ipv4Asn,
+    Ipv6Asn,
+    Ipv4Country,
+    Ipv6Country,
+    Oui,
+    TcpService,
+    UdpService,
+}
+
+impl DatabaseKind {
+    pub fn all() -> Vec<DatabaseKind> {
+        vec![
+            DatabaseKind::As,
+            DatabaseKind::Country,
+            DatabaseKind::Ipv4Asn,
+            DatabaseKind::Ipv6Asn,
+            DatabaseKind::Ipv4Country,
+            DatabaseKind::Ipv6Country,
+            DatabaseKind::Oui,
+            DatabaseKind::TcpService,
+            DatabaseKind::UdpService,
+        ]
+    }
+    pub fn file_name(&self) -> String {
+        match self {
+            DatabaseKind::As => db::asn::AutonomousSystem::file_name(),
+            DatabaseKind::Country => db::country::Country::file_name(),
+            DatabaseKind::Ipv4Asn => db::ipv4_asn::Ipv4Asn::file_name(),
+            DatabaseKind::Ipv6Asn => db::ipv6_asn::Ipv6Asn::file_name(),
+            DatabaseKind::Ipv4Country => db::ipv4_country::Ipv4Country::file_name(),
+            DatabaseKind::Ipv6Country => db::ipv6_country::Ipv6Country::file_name(),
+            DatabaseKind::Oui => db::oui::Oui::file_name(),
+            DatabaseKind::TcpService => db::tcp_service::TcpService::file_name(),
+            DatabaseKind::UdpService => db::udp_service::UdpService::file_name(),
+        }
+    }
+    pub fn from_file_name(file_name: &str) -> Option<DatabaseKind> {
+        DatabaseKind::all().into_iter().find(|kind| kind.file_name() == file_name)
+    }
+    /// Run the integrity checks of the loaders on a file
+    pub fn verify_file(&self, path: &PathBuf) -> Result<(), DbError> {
+        match self {
+            DatabaseKind::As => verify::<db::asn::AutonomousSystem>(*self, path),
+            DatabaseKind::Country => verify::<db::country::Country>(*self, path),
+            DatabaseKind::Ipv4Asn => verify::<db::ipv4_asn::Ipv4Asn>(*self, path),
+            DatabaseKind::Ipv6Asn => verify::<db::ipv6_asn::Ipv6Asn>(*self, path),
+            DatabaseKind::Ipv4Country => verify::<db::ipv4_country::Ipv4Country>(*self, path),
+            DatabaseKind::Ipv6Country => verify::<db::ipv6_country::Ipv6Country>(*self, path),
+            DatabaseKind::Oui => verify::<db::oui::Oui>(*self, path),
+            DatabaseKind::TcpService => verify::<db::tcp_service::TcpService>(*self, path),
+            DatabaseKind::UdpService => verify::<db::udp_service::UdpService>(*self, path),
+        }
+    }
+    /// Part of IpDatabase. A change requires reloading it
+    pub fn is_ipdb(&self) -> bool {
+        match self {
+            DatabaseKind::Oui | DatabaseKind::TcpService | DatabaseKind::UdpService => false,
+            _ => true,
+        }
+    }
+}
+
+fn verify<T: DbRecord>(kind: DatabaseKind, path: &PathBuf) -> Result<(), DbError> {
+    bin::read_records::<T>(path, &kind.file_name()).map(|_| ())
+}
+
+/// A database file in a manifest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseManifestEntry {
+    pub file_name: String,
+    pub version: String,
+    pub build_date: String,
+    /// Hex encoded SHA-256 of the file
+    pub sha256: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseManifest {
+    pub entries: Vec<DatabaseManifestEntry>,
+}
+
+impl DatabaseManifest {
+    pub fn new() -> DatabaseManifest {
+        DatabaseManifest { entries: Vec::new() }
+    }
+    pub fn get(&self, file_name: &str) -> Option<&DatabaseManifestEntry> {
+        self.entries.iter().find(|entry| entry.file_name == file_name)
+    }
+    pub fn set(&mut self, entry: DatabaseManifestEntry) {
+        self.entries.retain(|e| e.file_name != entry.file_name);
+        self.entries.push(entry);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseSource {
+    /// Built into the binary
+    Embedded,
+    /// Downloaded to the db directory
+    File,
+}
+
+/// Database in use
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseStatus {
+    pub kind: DatabaseKind,
+    pub file_name: String,
+    pub source: DatabaseSource,
+    /// Empty for embedded databases
+    pub path: String,
+    pub version: String,
+    pub build_date: String,
+    pub sha256: String,
+}
+
+/// Result of comparing the installed files with the remote manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseUpdateInfo {
+    pub kind: DatabaseKind,
+    pub file_name: String,
+    pub installed_version: Option<String>,
+    pub available_version: String,
+    pub build_date: String,
+    pub size: u64,
+    pub update_available: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseUpdateProgress {
+    pub file_name: String,
+    pub progress: DownloadProgress,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseUpdateResult {
+    pub updated: Vec<DatabaseKind>,
+    /// File name and reason of failed updates
+    pub failed: Vec<(String, String)>,
+}
+
+impl DatabaseUpdateResult {
+    pub fn ipdb_changed(&self) -> bool {
+        self.updated.iter().any(|kind| kind.is_ipdb())
+    }
+}
+
+/// Directory of the downloaded databases in the config directory
+pub fn db_dir_path() -> Option<PathBuf> {
+    sys::get_config_dir_path().map(|path| path.join(DB_DIR_NAME))
+}
+
+/// Path of a downloaded database if it is installed
+pub fn installed_file_path(kind: DatabaseKind) -> Option<PathBuf> {
+    let dir = db_dir_path()?;
+    let manifest = load_installed_manifest(&dir);
+    manifest.get(&kind.file_name())?;
+    let path = dir.join(kind.file_name());
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+pub fn load_installed_manifest(dir: &PathBuf) -> DatabaseManifest {
+    match fs::read_to_string(dir.join(INSTALLED_MANIFEST_FILE_NAME)) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(manifest) => manifest,
+            Err(_) => DatabaseManifest::new(),
+        },
+        Err(_) => DatabaseManifest::new(),
+    }
+}
+
+fn save_installed_manifest(dir: &PathBuf, manifest: &DatabaseManifest) -> Result<(), String> {
+    let content = match serde_json::to_string_pretty(manifest) {
+        Ok(content) => content,
+        Err(e) => return Err(e.to_string()),
+    };
+    match fs::write(dir.join(INSTALLED_MANIFEST_FILE_NAME), content) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Hex encoded SHA-256 of a file
+pub fn file_sha256(path: &PathBuf) -> Result<String, String> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+    };
+    let mut hasher = Sha256::new();
+    match std::io::copy(&mut file, &mut hasher) {
+        Ok(_) => {}
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Status of every database, downloaded or embedded
+pub fn get_database_status() -> Vec<DatabaseStatus> {
+    let dir = db_dir_path();
+    let manifest = match &dir {
+        Some(dir) => load_installed_manifest(dir),
+        None => DatabaseManifest::new(),
+    };
+    DatabaseKind::all()
+        .into_iter()
+        .map(|kind| match (installed_file_path(kind), manifest.get(&kind.file_name())) {
+            (Some(path), Some(entry)) => DatabaseStatus {
+                kind: kind,
+                file_name: kind.file_name(),
+                source: DatabaseSource::File,
+                path: path.to_string_lossy().to_string(),
+                version: entry.version.clone(),
+                build_date: entry.build_date.clone(),
+                sha256: entry.sha256.clone(),
+            },
+            _ => DatabaseStatus {
+                kind: kind,
+                file_name: kind.file_name(),
+                source: DatabaseSource::Embedded,
+                path: String::new(),
+                version: String::new(),
+                build_date: String::new(),
+                sha256: String::new(),
+            },
+        })
+        .collect()
+}
+
+/// Downloads database files listed in the manifest at base_url to dir
+pub struct DatabaseUpdater {
+    base_url: String,
+    dir: PathBuf,
+}
+
+impl DatabaseUpdater {
+    pub fn new(base_url: String, dir: PathBuf) -> DatabaseUpdater {
+        DatabaseUpdater {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            dir: dir,
+        }
+    }
+    fn url(&self, file_name: &str) -> String {
+        format!("{}/{}", self.base_url, file_name)
+    }
+    pub async fn fetch_manifest(&self) -> Result<DatabaseManifest, String> {
+        let url = self.url(DB_MANIFEST_FILE_NAME);
+        let response = match reqwest::get(&url).await {
+            Ok(response) => response,
+            Err(e) => return Err(format!("Failed to fetch {}: {}", url, e)),
+        };
+        if !response.status().is_success() {
+            return Err(format!("Failed to fetch {}: {}", url, response.status()));
+        }
+        match response.json::<DatabaseManifest>().await {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => Err(format!("Invalid manifest {}: {}", url, e)),
+        }
+    }
+    /// Compare the installed files with the remote manifest
+    pub async fn check(&self) -> Result<Vec<DatabaseUpdateInfo>, String> {
+        let remote = self.fetch_manifest().await?;
+        Ok(self.compare(&remote))
+    }
+    fn compare(&self, remote: &DatabaseManifest) -> Vec<DatabaseUpdateInfo> {
+        let installed = load_installed_manifest(&self.dir);
+        let mut infos: Vec<DatabaseUpdateInfo> = Vec::new();
+        for kind in DatabaseKind::all() {
+            let file_name = kind.file_name();
+            let entry = match remote.get(&file_name) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let installed_entry = match installed.get(&file_name) {
+                Some(installed_entry) if self.dir.join(&file_name).exists() => Some(installed_entry),
+                _ => None,
+            };
+            infos.push(DatabaseUpdateInfo {
+                kind: kind,
+                file_name: file_name,
+                installed_version: installed_entry.map(|e| e.version.clone()),
+                available_version: entry.version.clone(),
+                build_date: entry.build_date.clone(),
+                size: entry.size,
+                update_available: match installed_entry {
+                    Some(installed_entry) => !installed_entry.sha256.eq_ignore_ascii_case(&entry.sha256),
+                    None => true,
+                },
+            });
+        }
+        infos
+    }
+    /// Download and verify the files with updates. A file is only replaced after its checksum matched
+    pub async fn update(
+        &self,
+        progress_tx: tokio::sync::mpsc::Sender<DatabaseUpdateProgress>,
+    ) -> Result<DatabaseUpdateResult, String> {
+        let remote = self.fetch_manifest().await?;
+        let infos = self.compare(&remote);
+        match fs::create_dir_all(&self.dir) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Failed to create {}: {}", self.dir.display(), e)),
+        }
+        let mut installed = load_installed_manifest(&self.dir);
+        let mut result = DatabaseUpdateResult {
+            updated: Vec::new(),
+            failed: Vec::new(),
+        };
+        for info in infos.iter().filter(|info| info.update_available) {
+            let entry = match remote.get(&info.file_name) {
+                Some(entry) => entry.clone(),
+                None => continue,
+            };
+            match self.download(&entry, progress_tx.clone()).await {
+                Ok(_) => {
+                    installed.set(entry);
+                    save_installed_manifest(&self.dir, &installed)?;
+                    result.updated.push(info.kind);
+                }
+                Err(e) => {
+                    result.failed.push((info.file_name.clone(), e));
+                }
+            }
+        }
+        Ok(result)
+    }
+    async fn download(
+        &self,
+        entry: &DatabaseManifestEntry,
+        progress_tx: tokio::sync::mpsc::Sender<DatabaseUpdateProgress>,
+    ) -> Result<(), String> {
+        let temp_path = self.dir.join(format!("{}{}", entry.file_name, DOWNLOAD_SUFFIX));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let url = self.url(&entry.file_name);
+        let save_path = temp_path.clone();
+        let handle = tokio::spawn(async move {
+            match crate::net::http::download_file_with_progress(url, save_path, tx).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            }
+        });
+        while let Some(progress) = rx.recv().await {
+            let _ = progress_tx
+                .send(DatabaseUpdateProgress {
+                    file_name: entry.file_name.clone(),
+                    progress: progress,
+                })
+                .await;
+        }
+        let downloaded = match handle.await {
+            Ok(result) => result,
+            Err(e) => Err(e.to_string()),
+        };
+        let verified = match downloaded {
+            Ok(_) => match file_sha256(&temp_path) {
+                Ok(sha256) if sha256.eq_ignore_ascii_case(&entry.sha256) => {
+                    // A matching checksum only proves the download, not the published file
+                    match DatabaseKind::from_file_name(&entry.file_name) {
+                        Some(kind) => kind.verify_file(&temp_path).map_err(|e| e.to_string()),
+                        None => Ok(()),
+                    }
+                }
+                Ok(sha256) => Err(format!("Checksum mismatch: expected {}, got {}", entry.sha256, sha256)),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = verified {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        match fs::rename(&temp_path, self.dir.join(&entry.file_name)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ipv4_asn::Ipv4Asn;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Serves fixed files over HTTP/1.1 and records the requested paths
+    struct TestServer {
+        base_url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestServer {
+        fn start(files: HashMap<String, Vec<u8>>) -> TestServer {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let server_requests = Arc::clone(&requests);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    server_requests.lock().unwrap().push(path.clone());
+                    let response = match files.get(path.trim_start_matches('/')) {
+                        Some(body) => {
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                body.len()
+                            )
+                            .into_bytes();
+                            response.extend_from_slice(body);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                    };
+                    let _ = stream.write_all(&response);
+                }
+            });
+            TestServer { base_url: base_url, requests: requests }
+        }
+        fn requested(&self, file_name: &str) -> bool {
+            let path = format!("/{}", file_name);
+            self.requests.lock().unwrap().contains(&path)
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("netpulsar-update-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ipv4_asn_file(asn: u32) -> Vec<u8> {
+        let records = vec![Ipv4Asn { ip_from: 16777216, ip_to: 16777471, asn: asn }];
+        bin::encode(&records, records.len() as u64, &Ipv4Asn::file_name()).unwrap()
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn manifest_entry(version: &str, sha256: String) -> DatabaseManifestEntry {
+        DatabaseManifestEntry {
+            file_name: Ipv4Asn::file_name(),
+            version: version.to_string(),
+            build_date: String::from("2026-10-01"),
+            sha256: sha256,
+            size: 0,
+        }
+    }
+
+    fn manifest_json(entry: DatabaseManifestEntry) -> Vec<u8> {
+        serde_json::to_vec(&DatabaseManifest { entries: vec![entry] }).unwrap()
+    }
+
+    /// Install a file as if downloaded before
+    fn install(dir: &PathBuf, content: &[u8], entry: DatabaseManifestEntry) {
+        fs::write(dir.join(&entry.file_name), content).unwrap();
+        let mut installed = DatabaseManifest::new();
+        installed.set(entry);
+        save_installed_manifest(dir, &installed).unwrap();
+    }
+
+    fn run_update(updater: &DatabaseUpdater) -> DatabaseUpdateResult {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(100);
+            let drain = tokio::spawn(async move { while progress_rx.recv().await.is_some() {} });
+            let result = updater.update(progress_tx).await.unwrap();
+            drain.await.unwrap();
+            result
+        })
+    }
+
+    #[test]
+    fn checksum_mismatch_keeps_installed_file() {
+        let dir = temp_dir("mismatch");
+        let old = ipv4_asn_file(1);
+        install(&dir, &old, manifest_entry("1", sha256(&old)));
+        let new = ipv4_asn_file(2);
+        let mut files = HashMap::new();
+        files.insert(DB_MANIFEST_FILE_NAME.to_string(), manifest_json(manifest_entry("2", sha256(b"something else"))));
+        files.insert(Ipv4Asn::file_name(), new);
+        let server = TestServer::start(files);
+        let updater = DatabaseUpdater::new(server.base_url.clone(), dir.clone());
+        let result = run_update(&updater);
+        assert!(result.updated.is_empty());
+        assert_eq!(result.failed.len(), 1);
+        assert!(result.failed[0].1.starts_with("Checksum mismatch"), "{}", result.failed[0].1);
+        assert_eq!(fs::read(dir.join(Ipv4Asn::file_name())).unwrap(), old);
+        assert!(!dir.join(format!("{}{}", Ipv4Asn::file_name(), DOWNLOAD_SUFFIX)).exists());
+        assert_eq!(load_installed_manifest(&dir).get(&Ipv4Asn::file_name()).unwrap().version, "1");
+    }
+
+    #[test]
+    fn unchanged_sha256_is_not_downloaded() {
+        let dir = temp_dir("unchanged");
+        let content = ipv4_asn_file(1);
+        install(&dir, &content, manifest_entry("1", sha256(&content)));
+        let mut files = HashMap::new();
+        // Version strings may differ, the checksum decides
+        files.insert(DB_MANIFEST_FILE_NAME.to_string(), manifest_json(manifest_entry("1b", sha256(&content).to_uppercase())));
+        files.insert(Ipv4Asn::file_name(), content.clone());
+        let server = TestServer::start(files);
+        let updater = DatabaseUpdater::new(format!("{}/", server.base_url), dir.clone());
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let infos = rt.block_on(updater.check()).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert!(!infos[0].update_available);
+        assert_eq!(infos[0].installed_version.as_deref(), Some("1"));
+        let result = run_update(&updater);
+        assert!(result.updated.is_empty());
+        assert!(result.failed.is_empty());
+        assert!(!server.requested(&Ipv4Asn::file_name()));
+    }
+
+    #[test]
+    fn verified_download_replaces_installed_file() {
+        let dir = temp_dir("swap");
+        let old = ipv4_asn_file(1);
+        install(&dir, &old, manifest_entry("1", sha256(&old)));
+        let new = ipv4_asn_file(2);
+        let mut files = HashMap::new();
+        files.insert(DB_MANIFEST_FILE_NAME.to_string(), manifest_json(manifest_entry("2", sha256(&new))));
+        files.insert(Ipv4Asn::file_name(), new.clone());
+        let server = TestServer::start(files);
+        let updater = DatabaseUpdater::new(server.base_url.clone(), dir.clone());
+        let result = run_update(&updater);
+        assert_eq!(result.updated, vec![DatabaseKind::Ipv4Asn]);
+        assert!(result.failed.is_empty());
+        assert!(result.ipdb_changed());
+        assert_eq!(fs::read(dir.join(Ipv4Asn::file_name())).unwrap(), new);
+        assert!(!dir.join(format!("{}{}", Ipv4Asn::file_name(), DOWNLOAD_SUFFIX)).exists());
+        let installed = load_installed_manifest(&dir);
+        let entry = installed.get(&Ipv4Asn::file_name()).unwrap();
+        assert_eq!(entry.version, "2");
+        assert_eq!(entry.sha256, sha256(&new));
+    }
+}
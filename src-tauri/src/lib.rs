@@ -0,0 +1,127 @@
+pub mod db;
+mod deps;
+pub mod net;
+mod sys;
+mod config;
+mod thread_log;
+mod notification;
+mod process;
+mod time;
+mod commands;
+mod route;
+mod task;
+mod app;
+
+use commands::{
+    start_background_task, get_deps_map, download_dep, run_dep_installer, get_default_interface, get_netstat, get_overview, get_process_info, get_interface_traffic, get_vlan_traffic, get_capture_stats, get_remote_hosts, get_countries, get_autonomous_systems, get_label_groups, get_labels, set_labels,
+    get_self_ip_info, get_self_ipv4_info, start_packet_capture, list_captures, stop_capture, remove_capture, get_packet_detail, get_capture_ring_config, set_capture_ring_config, dump_capture_ring, list_ring_dumps, start_port_scan,
+    start_follow_stream, stop_follow_stream, remove_follow_stream, get_follow_stream, save_follow_stream, get_app_info, get_app_config, 
+    save_app_config, get_interfaces, get_config_dir, get_database_config, check_database_updates, update_databases, import_ip_database, clear_imported_ip_database, get_ipdb_memory_report, benchmark_ipdb_lookup,
+    get_threat_feed_status, refresh_threat_feeds, get_threat_hits, clear_threat_hits,
+    get_security_config, set_security_config, get_security_events, clear_security_events, get_beacons, get_dns_domains,
+    get_rule_status, reload_rules, get_rule_matches, clear_rule_matches, get_routes
+};
+use crate::net::stat::{NetStatData, NetStatStrage};
+use crate::net::ring::CaptureRing;
+use crate::net::session::CaptureSessionStore;
+use crate::net::stream::FollowStreamStore;
+use std::sync::{Arc, Mutex};
+
+pub fn run() {
+    //let netstat_strage: Arc<Mutex<NetStatStrage>> = Arc::new(Mutex::new(NetStatStrage::new()));
+    let netstat_strage: Arc<NetStatStrage> = Arc::new(NetStatStrage::new());
+    let netstat_data: Arc<Mutex<NetStatData>> = Arc::new(Mutex::new(NetStatData::new()));
+    let thread_handles: Arc<Mutex<Vec<std::thread::JoinHandle<()>>>> = Arc::new(Mutex::new(Vec::new()));
+    let follow_streams: Arc<Mutex<FollowStreamStore>> = Arc::new(Mutex::new(FollowStreamStore::new()));
+    let capture_sessions: Arc<Mutex<CaptureSessionStore>> = Arc::new(Mutex::new(CaptureSessionStore::new()));
+    let capture_ring: Arc<CaptureRing> = Arc::new(CaptureRing::new(crate::config::AppConfig::load().capture_ring));
+    tauri::Builder::default()
+        .manage(netstat_strage)
+        .manage(netstat_data)
+        .manage(thread_handles)
+        .manage(follow_streams)
+        .manage(capture_sessions)
+        .manage(capture_ring)
+        .invoke_handler(tauri::generate_handler![
+            start_background_task,
+            get_deps_map,
+            download_dep,
+            run_dep_installer,
+            get_overview,
+            get_remote_hosts,
+            get_countries,
+            get_autonomous_systems,
+            get_label_groups,
+            get_labels,
+            set_labels,
+            get_netstat,
+            get_process_info,
+            get_interface_traffic,
+            get_vlan_traffic,
+            get_capture_stats,
+            start_packet_capture,
+            list_captures,
+            stop_capture,
+            remove_capture,
+            get_packet_detail,
+            get_capture_ring_config,
+            set_capture_ring_config,
+            dump_capture_ring,
+            list_ring_dumps,
+            start_port_scan,
+            start_follow_stream,
+            stop_follow_stream,
+            remove_follow_stream,
+            get_follow_stream,
+            save_follow_stream,
+            get_self_ip_info,
+            get_self_ipv4_info,
+            get_default_interface,
+            get_app_info,
+            get_app_config,
+            save_app_config,
+            get_interfaces,
+            get_config_dir,
+            get_database_config,
+            check_database_updates,
+            update_databases,
+            import_ip_database,
+            clear_imported_ip_database,
+            get_ipdb_memory_report,
+            benchmark_ipdb_lookup,
+            get_threat_feed_status,
+            refresh_threat_feeds,
+            get_threat_hits,
+            clear_threat_hits,
+            get_security_config,
+            set_security_config,
+            get_security_events,
+            clear_security_events,
+            get_beacons,
+            get_dns_domains,
+            get_rule_status,
+            reload_rules,
+            get_rule_matches,
+            clear_rule_matches,
+            get_routes
+        ])
+        .setup(|app| {
+            let app_handle = app.handle();
+            match sys::init(&app_handle) {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("Error: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+            Ok(())
+        })
+        .on_window_event(|event| match event.event() {
+            tauri::WindowEvent::Destroyed => {
+                sys::cleanup();
+            }
+            _ => {}
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
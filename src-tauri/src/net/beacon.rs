@@ -0,0 +1,490 @@
+//! Beaconing detection. Malware polling its command and control server shows up as
+//! small outbound connections to the same host and port at regular intervals.
+use crate::net::security::{SecurityEvent, SecurityEventKind, Severity};
+use crate::net::socket::{LocalSocket, SocketConnection, SocketProcess, SocketStatus, TransportProtocol};
+use crate::net::traffic::TrafficInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Connection starts kept per series, oldest dropped first
+const MAX_STARTS_PER_SERIES: usize = 256;
+/// Series tracked before the least recently active are dropped
+const MAX_SERIES: usize = 4096;
+/// A new connection waits this long for its process to be resolved
+const PROCESS_RESOLVE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Series without a new connection for this long are dropped
+const SERIES_TTL: Duration = Duration::from_secs(6 * 3600);
+/// Intervals listed in the evidence of an event
+const MAX_EVIDENCE_INTERVALS: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BeaconConfig {
+    pub enabled: bool,
+    /// Connections needed before a series is scored
+    pub min_connections: usize,
+    /// Shortest mean interval considered. Faster repeats are ordinary polling
+    pub min_interval_secs: u64,
+    /// Longest mean interval considered
+    pub max_interval_secs: u64,
+    /// Highest interval standard deviation relative to the mean
+    pub max_jitter: f64,
+    /// Highest mean bytes per connection. Beacons are small
+    pub max_bytes_per_connection: u64,
+    /// Lowest score that raises an event, 0.0 to 1.0
+    pub score_threshold: f64,
+    /// Process names, IP addresses or host names of known updaters
+    pub allowlist: Vec<String>,
+}
+
+impl BeaconConfig {
+    pub fn new() -> BeaconConfig {
+        BeaconConfig {
+            enabled: true,
+            min_connections: 8,
+            min_interval_secs: 10,
+            max_interval_secs: 3600,
+            max_jitter: 0.2,
+            max_bytes_per_connection: 16 * 1024,
+            score_threshold: 0.75,
+            allowlist: vec![
+                "GoogleUpdate.exe".to_string(),
+                "MicrosoftEdgeUpdate.exe".to_string(),
+                "OneDrive.exe".to_string(),
+                "softwareupdated".to_string(),
+                "packagekitd".to_string(),
+            ],
+        }
+    }
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        BeaconConfig::new()
+    }
+}
+
+/// Connections of one process to one remote host and port
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BeaconKey {
+    /// None when the process was not resolved
+    pub process_name: Option<String>,
+    pub local_ip_addr: IpAddr,
+    pub remote_ip_addr: IpAddr,
+    pub remote_port: u16,
+    pub protocol: TransportProtocol,
+}
+
+/// Inter-arrival statistics of connection starts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct IntervalStats {
+    /// Number of connections
+    pub count: usize,
+    pub mean_secs: f64,
+    /// Standard deviation of the intervals relative to the mean
+    pub jitter: f64,
+    pub min_secs: f64,
+    pub max_secs: f64,
+}
+
+/// Interval statistics of start times in seconds. None with fewer than three starts
+pub fn interval_stats(starts: &[f64]) -> Option<IntervalStats> {
+    if starts.len() < 3 {
+        return None;
+    }
+    let mut sorted: Vec<f64> = starts.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let intervals: Vec<f64> = sorted.windows(2).map(|w| w[1] - w[0]).collect();
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+    let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+    Some(IntervalStats {
+        count: starts.len(),
+        mean_secs: mean,
+        jitter: variance.sqrt() / mean,
+        min_secs: intervals.iter().cloned().fold(f64::INFINITY, f64::min),
+        max_secs: intervals.iter().cloned().fold(0.0, f64::max),
+    })
+}
+
+/// Score of a series from 0.0 to 1.0. Regular intervals weigh most,
+/// then the number of connections and their volume. None when the series
+/// is outside the configured limits
+pub fn beacon_score(stats: &IntervalStats, mean_bytes: f64, config: &BeaconConfig) -> Option<f64> {
+    if stats.count < config.min_connections.max(3)
+        || stats.mean_secs < config.min_interval_secs as f64
+        || stats.mean_secs > config.max_interval_secs as f64
+        || stats.jitter > config.max_jitter
+        || mean_bytes > config.max_bytes_per_connection as f64
+    {
+        return None;
+    }
+    let regularity = 1.0 - stats.jitter / config.max_jitter.max(f64::EPSILON);
+    let repetition = (stats.count as f64 / (config.min_connections.max(3) * 2) as f64).min(1.0);
+    let volume = 1.0 - mean_bytes / config.max_bytes_per_connection.max(1) as f64;
+    Some((0.6 * regularity + 0.2 * repetition + 0.2 * volume).clamp(0.0, 1.0))
+}
+
+/// Scored series for display
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BeaconInfo {
+    pub key: BeaconKey,
+    pub stats: IntervalStats,
+    pub mean_bytes: f64,
+    /// None when the series is outside the configured limits
+    pub score: Option<f64>,
+    pub allowlisted: bool,
+}
+
+#[derive(Debug, Clone)]
+struct BeaconSeries {
+    starts: Vec<Instant>,
+    bytes: u64,
+    connections: u64,
+    last_start: Instant,
+    /// A start was added since the last evaluation
+    changed: bool,
+}
+
+impl BeaconSeries {
+    fn new(start: Instant) -> BeaconSeries {
+        BeaconSeries {
+            starts: Vec::new(),
+            bytes: 0,
+            connections: 0,
+            last_start: start,
+            changed: false,
+        }
+    }
+    fn add_start(&mut self, start: Instant) {
+        let position = self.starts.partition_point(|s| *s <= start);
+        self.starts.insert(position, start);
+        if self.starts.len() > MAX_STARTS_PER_SERIES {
+            self.starts.remove(0);
+        }
+        self.connections += 1;
+        self.last_start = self.last_start.max(start);
+        self.changed = true;
+    }
+    fn mean_bytes(&self) -> f64 {
+        if self.connections == 0 {
+            return 0.0;
+        }
+        self.bytes as f64 / self.connections as f64
+    }
+    fn stats(&self) -> Option<IntervalStats> {
+        let origin = match self.starts.first() {
+            Some(origin) => *origin,
+            None => return None,
+        };
+        let starts: Vec<f64> = self.starts.iter().map(|s| s.duration_since(origin).as_secs_f64()).collect();
+        interval_stats(&starts)
+    }
+}
+
+/// Connection being counted towards a series
+#[derive(Debug, Clone)]
+struct TrackedConnection {
+    /// None while the process is being resolved
+    key: Option<BeaconKey>,
+    first_seen: Instant,
+    bytes: u64,
+    /// Open before monitoring started, or accepted by a listening socket
+    ignored: bool,
+}
+
+/// Tracks connection starts per process, remote host and port
+#[derive(Debug, Clone)]
+pub struct BeaconDetector {
+    config: BeaconConfig,
+    connections: HashMap<SocketConnection, TrackedConnection>,
+    series: HashMap<BeaconKey, BeaconSeries>,
+    /// Connections present at the first observation have no known start
+    initialized: bool,
+}
+
+impl BeaconDetector {
+    pub fn new(config: BeaconConfig) -> BeaconDetector {
+        BeaconDetector {
+            config: config,
+            connections: HashMap::new(),
+            series: HashMap::new(),
+            initialized: false,
+        }
+    }
+    pub fn set_config(&mut self, config: BeaconConfig) {
+        if !config.enabled {
+            self.connections.clear();
+            self.series.clear();
+            self.initialized = false;
+        }
+        self.config = config;
+    }
+    /// Record new outbound connections of the connection map and their traffic
+    pub fn observe(
+        &mut self,
+        connection_map: &HashMap<SocketConnection, TrafficInfo>,
+        local_socket_map: &HashMap<LocalSocket, SocketProcess>,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+        let now = Instant::now();
+        self.connections.retain(|conn, _| connection_map.contains_key(conn));
+        if !self.initialized {
+            // Skip the connections open before monitoring started
+            for (conn, traffic_info) in connection_map {
+                self.connections.insert(
+                    conn.clone(),
+                    TrackedConnection { key: None, first_seen: traffic_info.first_seen, bytes: 0, ignored: true },
+                );
+            }
+            self.initialized = true;
+            return;
+        }
+        for (conn, traffic_info) in connection_map {
+            let tracked = self.connections.entry(conn.clone()).or_insert(TrackedConnection {
+                key: None,
+                first_seen: traffic_info.first_seen,
+                bytes: 0,
+                ignored: false,
+            });
+            if tracked.ignored {
+                continue;
+            }
+            if tracked.key.is_none() {
+                let socket_process = get_socket_process(conn, local_socket_map);
+                if socket_process.map_or(false, |socket_process| socket_process.status == SocketStatus::Listen) {
+                    tracked.ignored = true;
+                    continue;
+                }
+                let process_name = socket_process
+                    .and_then(|socket_process| socket_process.process.as_ref())
+                    .map(|process| process.name.clone());
+                if process_name.is_none() && now.duration_since(tracked.first_seen) < PROCESS_RESOLVE_TIMEOUT {
+                    continue;
+                }
+                let key = BeaconKey {
+                    process_name: process_name,
+                    local_ip_addr: conn.local_ip_addr,
+                    remote_ip_addr: conn.remote_ip_addr,
+                    remote_port: conn.remote_port,
+                    protocol: conn.protocol,
+                };
+                self.series
+                    .entry(key.clone())
+                    .or_insert_with(|| BeaconSeries::new(tracked.first_seen))
+                    .add_start(tracked.first_seen);
+                tracked.key = Some(key);
+            }
+            let total_bytes = traffic_info.total_bytes();
+            if let Some(series) = tracked.key.as_ref().and_then(|key| self.series.get_mut(key)) {
+                series.bytes += total_bytes.saturating_sub(tracked.bytes);
+            }
+            tracked.bytes = total_bytes;
+        }
+        self.series.retain(|_, series| now.duration_since(series.last_start) < SERIES_TTL);
+        if self.series.len() > MAX_SERIES {
+            let mut last_starts: Vec<Instant> = self.series.values().map(|s| s.last_start).collect();
+            last_starts.sort();
+            let cutoff = last_starts[self.series.len() - MAX_SERIES];
+            self.series.retain(|_, series| series.last_start >= cutoff);
+        }
+    }
+    fn is_allowlisted(&self, key: &BeaconKey, host_name: Option<&String>) -> bool {
+        let remote_ip_addr = key.remote_ip_addr.to_string();
+        self.config.allowlist.iter().any(|entry| {
+            let entry = entry.trim();
+            key.process_name.as_ref().map_or(false, |name| name.eq_ignore_ascii_case(entry))
+                || remote_ip_addr == entry
+                || host_name.map_or(false, |host_name| {
+                    let host_name = host_name.trim_end_matches('.').to_lowercase();
+                    let entry = entry.trim_end_matches('.').to_lowercase();
+                    host_name == entry || host_name.ends_with(&format!(".{}", entry))
+                })
+        })
+    }
+    /// All series with enough connections for statistics, highest score first
+    pub fn get_beacons(&self, reverse_dns_map: &HashMap<IpAddr, String>) -> Vec<BeaconInfo> {
+        let mut beacons: Vec<BeaconInfo> = self
+            .series
+            .iter()
+            .filter_map(|(key, series)| {
+                series.stats().map(|stats| BeaconInfo {
+                    key: key.clone(),
+                    stats: stats,
+                    mean_bytes: series.mean_bytes(),
+                    score: beacon_score(&stats, series.mean_bytes(), &self.config),
+                    allowlisted: self.is_allowlisted(key, reverse_dns_map.get(&key.remote_ip_addr)),
+                })
+            })
+            .collect();
+        beacons.sort_by(|a, b| b.score.unwrap_or(-1.0).total_cmp(&a.score.unwrap_or(-1.0)));
+        beacons
+    }
+    /// Series scoring above the threshold. Only series with new connections are scored
+    pub fn evaluate(&mut self, reverse_dns_map: &HashMap<IpAddr, String>) -> Vec<SecurityEvent> {
+        let mut events: Vec<SecurityEvent> = Vec::new();
+        if !self.config.enabled {
+            return events;
+        }
+        for beacon in self.get_beacons(reverse_dns_map) {
+            if !self.series.get(&beacon.key).map_or(false, |series| series.changed) {
+                continue;
+            }
+            let score = match beacon.score {
+                Some(score) if score >= self.config.score_threshold && !beacon.allowlisted => score,
+                _ => continue,
+            };
+            let key = &beacon.key;
+            let remote = match reverse_dns_map.get(&key.remote_ip_addr) {
+                Some(host_name) => format!("{} ({})", key.remote_ip_addr, host_name),
+                None => key.remote_ip_addr.to_string(),
+            };
+            let mut event = SecurityEvent::new(
+                SecurityEventKind::Beaconing,
+                if score >= 0.9 { Severity::High } else { Severity::Medium },
+                format!(
+                    "{} connected to {} port {}/{} {} times every {:.0}s (jitter {:.1}%, score {:.2})",
+                    key.process_name.clone().unwrap_or("Unknown process".to_string()),
+                    remote,
+                    key.remote_port,
+                    key.protocol.as_str().to_lowercase(),
+                    beacon.stats.count,
+                    beacon.stats.mean_secs,
+                    beacon.stats.jitter * 100.0,
+                    score
+                ),
+            );
+            event.source_ip_addr = Some(key.remote_ip_addr);
+            event.target_ip_addr = Some(key.local_ip_addr);
+            event.evidence = vec![
+                format!("Mean interval: {:.1}s", beacon.stats.mean_secs),
+                format!("Interval range: {:.1}s - {:.1}s", beacon.stats.min_secs, beacon.stats.max_secs),
+                format!("Mean bytes per connection: {:.0}", beacon.mean_bytes),
+            ];
+            if let Some(series) = self.series.get(key) {
+                let recent: Vec<String> = series
+                    .starts
+                    .windows(2)
+                    .rev()
+                    .take(MAX_EVIDENCE_INTERVALS)
+                    .map(|w| format!("{:.1}s", w[1].duration_since(w[0]).as_secs_f64()))
+                    .collect();
+                event.evidence.push(format!("Recent intervals: {}", recent.join(", ")));
+            }
+            events.push(event);
+        }
+        self.series.values_mut().for_each(|series| series.changed = false);
+        events
+    }
+}
+
+fn get_socket_process<'a>(
+    conn: &SocketConnection,
+    local_socket_map: &'a HashMap<LocalSocket, SocketProcess>,
+) -> Option<&'a SocketProcess> {
+    let local_socket = LocalSocket::new(conn.interface_name.clone(), conn.local_port, conn.protocol);
+    local_socket_map.get(&local_socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn starts(intervals: &[f64]) -> Vec<f64> {
+        let mut starts = vec![0.0];
+        for interval in intervals {
+            starts.push(starts.last().unwrap() + interval);
+        }
+        starts
+    }
+
+    fn beacon_key(process_name: &str) -> BeaconKey {
+        BeaconKey {
+            process_name: Some(process_name.to_string()),
+            local_ip_addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+            remote_ip_addr: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)),
+            remote_port: 443,
+            protocol: TransportProtocol::TCP,
+        }
+    }
+
+    #[test]
+    fn periodic_series_scores_above_threshold() {
+        let config = BeaconConfig::new();
+        let stats = interval_stats(&starts(&[60.0; 11])).unwrap();
+        assert_eq!(stats.count, 12);
+        assert_eq!(stats.mean_secs, 60.0);
+        assert_eq!(stats.jitter, 0.0);
+        assert_eq!((stats.min_secs, stats.max_secs), (60.0, 60.0));
+        let score = beacon_score(&stats, 512.0, &config).unwrap();
+        assert!(score >= config.score_threshold, "score {}", score);
+    }
+
+    #[test]
+    fn unordered_starts_are_sorted() {
+        let stats = interval_stats(&[120.0, 0.0, 60.0, 180.0]).unwrap();
+        assert_eq!(stats.mean_secs, 60.0);
+        assert_eq!(stats.jitter, 0.0);
+    }
+
+    #[test]
+    fn jittered_series_scores_lower_or_not_at_all() {
+        let config = BeaconConfig::new();
+        let periodic = interval_stats(&starts(&[60.0; 11])).unwrap();
+        let slight = interval_stats(&starts(&[55.0, 65.0, 55.0, 65.0, 55.0, 65.0, 55.0, 65.0, 55.0, 65.0])).unwrap();
+        assert!(slight.jitter > 0.0 && slight.jitter < config.max_jitter);
+        let periodic_score = beacon_score(&periodic, 512.0, &config).unwrap();
+        let slight_score = beacon_score(&slight, 512.0, &config).unwrap();
+        assert!(slight_score < periodic_score);
+        let heavy = interval_stats(&starts(&[30.0, 90.0, 30.0, 90.0, 30.0, 90.0, 30.0, 90.0, 30.0, 90.0])).unwrap();
+        assert!((heavy.jitter - 0.5).abs() < 1e-9);
+        assert_eq!(beacon_score(&heavy, 512.0, &config), None);
+    }
+
+    #[test]
+    fn too_few_starts_are_not_scored() {
+        let config = BeaconConfig::new();
+        assert_eq!(interval_stats(&[]), None);
+        assert_eq!(interval_stats(&[0.0, 60.0]), None);
+        // Same start time has no interval
+        assert_eq!(interval_stats(&[10.0, 10.0, 10.0]), None);
+        let stats = interval_stats(&starts(&[60.0; 4])).unwrap();
+        assert!(stats.count < config.min_connections);
+        assert_eq!(beacon_score(&stats, 512.0, &config), None);
+    }
+
+    #[test]
+    fn series_outside_limits_are_not_scored() {
+        let config = BeaconConfig::new();
+        let fast = interval_stats(&starts(&[1.0; 11])).unwrap();
+        assert_eq!(beacon_score(&fast, 512.0, &config), None);
+        let periodic = interval_stats(&starts(&[60.0; 11])).unwrap();
+        assert_eq!(beacon_score(&periodic, 1024.0 * 1024.0, &config), None);
+    }
+
+    #[test]
+    fn allowlist_matches_process_address_and_domain() {
+        let mut config = BeaconConfig::new();
+        config.allowlist = vec![
+            String::from("OneDrive.exe"),
+            String::from("198.51.100.1"),
+            String::from("update.example.com."),
+        ];
+        let detector = BeaconDetector::new(config);
+        assert!(detector.is_allowlisted(&beacon_key("onedrive.exe"), None));
+        assert!(!detector.is_allowlisted(&beacon_key("implant.exe"), None));
+        let mut key = beacon_key("implant.exe");
+        key.remote_ip_addr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+        assert!(detector.is_allowlisted(&key, None));
+        let key = beacon_key("implant.exe");
+        assert!(detector.is_allowlisted(&key, Some(&String::from("cdn.update.example.com"))));
+        assert!(detector.is_allowlisted(&key, Some(&String::from("UPDATE.example.com."))));
+        assert!(!detector.is_allowlisted(&key, Some(&String::from("notupdate.example.com"))));
+    }
+}
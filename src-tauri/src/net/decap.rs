@@ -0,0 +1,544 @@
+use crate::net::fragment::{self, FragmentInfo};
+use crate::net::traffic::TrafficDisplayInfo;
+use nex::packet::frame::{Frame, ParseOption};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86DD;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+const ETHER_TYPE_QINQ: u16 = 0x88A8;
+const ETHER_TYPE_QINQ_OLD: u16 = 0x9100;
+/// Transparent Ethernet Bridging
+const ETHER_TYPE_TEB: u16 = 0x6558;
+const IP_PROTO_IPV4: u8 = 4;
+const IP_PROTO_UDP: u8 = 17;
+const IP_PROTO_IPV6: u8 = 41;
+const IP_PROTO_GRE: u8 = 47;
+const VXLAN_PORT: u16 = 4789;
+const GENEVE_PORT: u16 = 6081;
+const WIREGUARD_PORT: u16 = 51820;
+/// Maximum number of VLAN tags to strip
+const MAX_VLAN_TAGS: usize = 4;
+
+/// Tunnel encapsulation type
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TunnelType {
+    Gre,
+    Vxlan,
+    Geneve,
+    IpInIp,
+    /// Encrypted. Only detected, never decapsulated
+    WireGuard,
+}
+
+/// Which flow tunneled traffic is attributed to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelAttribution {
+    /// Account the tunnel endpoints (outer IP header)
+    Outer,
+    /// Account the encapsulated flows (inner IP header)
+    Inner,
+}
+
+impl Default for TunnelAttribution {
+    fn default() -> Self {
+        TunnelAttribution::Outer
+    }
+}
+
+/// Tunnel found in a captured frame
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TunnelInfo {
+    pub tunnel_type: TunnelType,
+    pub outer_src: IpAddr,
+    pub outer_dst: IpAddr,
+    /// VXLAN/GENEVE VNI or GRE key
+    pub vni: Option<u32>,
+    /// True if the IP and transport layers of the frame are the inner ones
+    pub decapsulated: bool,
+}
+
+/// Traffic of a VLAN
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VlanTrafficDisplayInfo {
+    pub vlan_id: u16,
+    pub traffic: TrafficDisplayInfo,
+}
+
+/// Inner packet location within the outer IP packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InnerPacket {
+    /// Offset from the start of the outer IP header
+    pub offset: usize,
+    /// Inner packet starts with an Ethernet header
+    pub ethernet: bool,
+}
+
+/// Captured frame after VLAN, tunnel and fragment decoding
+pub struct DecodedFrame {
+    /// Parsed frame. IP and transport layers are the inner ones if the tunnel was decapsulated
+    pub frame: Frame,
+    /// VLAN IDs, outermost first
+    pub vlan_ids: Vec<u16>,
+    pub tunnel: Option<TunnelInfo>,
+    /// Fragment fields of the accounted IP layer
+    pub fragment: Option<FragmentInfo>,
+}
+
+/// Parse a captured frame, stripping VLAN tags and decapsulating tunnels
+/// when attribution is Inner. packet_len is the length of the captured frame as given.
+pub fn decode_frame(
+    packet: &[u8],
+    parse_option: ParseOption,
+    attribution: TunnelAttribution,
+) -> DecodedFrame {
+    let mut vlan_ids: Vec<u16> = Vec::new();
+    let untagged: Vec<u8>;
+    let mut data: &[u8] = packet;
+    if !parse_option.from_ip_packet {
+        if let Some((buf, ids)) = strip_vlan(packet) {
+            untagged = buf;
+            data = &untagged;
+            vlan_ids = ids;
+        }
+    }
+    let ip_offset = if parse_option.from_ip_packet {
+        parse_option.offset
+    } else {
+        ETHERNET_HEADER_LEN
+    };
+    let mut frame: Frame = Frame::from_bytes(data, parse_option);
+    frame.packet_len = packet.len();
+    if frame.ip.is_none() || data.len() <= ip_offset {
+        return DecodedFrame {
+            frame: frame,
+            vlan_ids: vlan_ids,
+            tunnel: None,
+            fragment: None,
+        };
+    }
+    let ip_packet = &data[ip_offset..];
+    let tunnel = match find_tunnel(ip_packet) {
+        Some((mut tunnel, inner)) => {
+            if attribution == TunnelAttribution::Inner {
+                if let Some(inner) = inner {
+                    if inner.offset < ip_packet.len() {
+                        let inner_packet = &ip_packet[inner.offset..];
+                        let inner_untagged: Vec<u8>;
+                        let mut inner_data: &[u8] = inner_packet;
+                        let inner_frame = if inner.ethernet {
+                            // Overlay frames may carry their own VLAN tags
+                            if let Some((buf, ids)) = strip_vlan(inner_packet) {
+                                vlan_ids.extend(ids);
+                                inner_untagged = buf;
+                                inner_data = &inner_untagged;
+                            }
+                            Frame::from_bytes(inner_data, ParseOption::default())
+                        } else {
+                            let mut option = ParseOption::default();
+                            option.from_ip_packet = true;
+                            option.offset = 0;
+                            Frame::from_bytes(inner_data, option)
+                        };
+                        let inner_ip_offset = if inner.ethernet { ETHERNET_HEADER_LEN } else { 0 };
+                        if inner_frame.ip.is_some() && inner_data.len() > inner_ip_offset {
+                            // Keep the outer link layer when the inner packet has none
+                            if inner.ethernet {
+                                frame.datalink = inner_frame.datalink;
+                            }
+                            frame.ip = inner_frame.ip;
+                            frame.transport = inner_frame.transport;
+                            frame.payload = inner_frame.payload;
+                            tunnel.decapsulated = true;
+                            let fragment = check_fragment(&mut frame, &inner_data[inner_ip_offset..]);
+                            return DecodedFrame {
+                                frame: frame,
+                                vlan_ids: vlan_ids,
+                                tunnel: Some(tunnel),
+                                fragment: fragment,
+                            };
+                        }
+                    }
+                }
+            }
+            Some(tunnel)
+        }
+        None => None,
+    };
+    let fragment = check_fragment(&mut frame, ip_packet);
+    DecodedFrame {
+        frame: frame,
+        vlan_ids: vlan_ids,
+        tunnel: tunnel,
+        fragment: fragment,
+    }
+}
+
+/// Get the fragment fields and fix up the transport layer of a fragment
+fn check_fragment(frame: &mut Frame, ip_packet: &[u8]) -> Option<FragmentInfo> {
+    let info = fragment::parse_fragment(ip_packet)?;
+    fragment::fix_transport(frame, ip_packet, &info);
+    Some(info)
+}
+
+/// Remove 802.1Q/802.1ad tags from an Ethernet frame.
+/// Returns the untagged frame and the VLAN IDs (outermost first), or None if the frame has no tags.
+pub fn strip_vlan(packet: &[u8]) -> Option<(Vec<u8>, Vec<u16>)> {
+    if packet.len() < ETHERNET_HEADER_LEN {
+        return None;
+    }
+    let mut vlan_ids: Vec<u16> = Vec::new();
+    let mut offset = 12;
+    while vlan_ids.len() < MAX_VLAN_TAGS && offset + 6 <= packet.len() {
+        match read_u16(packet, offset) {
+            ETHER_TYPE_VLAN | ETHER_TYPE_QINQ | ETHER_TYPE_QINQ_OLD => {
+                vlan_ids.push(read_u16(packet, offset + 2) & 0x0FFF);
+                offset += 4;
+            }
+            _ => break,
+        }
+    }
+    if vlan_ids.is_empty() {
+        return None;
+    }
+    let mut untagged: Vec<u8> = Vec::with_capacity(packet.len() - (offset - 12));
+    untagged.extend_from_slice(&packet[..12]);
+    untagged.extend_from_slice(&packet[offset..]);
+    Some((untagged, vlan_ids))
+}
+
+/// Detect a tunnel in an IP packet.
+/// Returns the tunnel and the location of the inner packet if it can be decapsulated.
+pub fn find_tunnel(ip_packet: &[u8]) -> Option<(TunnelInfo, Option<InnerPacket>)> {
+    if ip_packet.is_empty() {
+        return None;
+    }
+    let (protocol, header_len, src, dst) = match ip_packet[0] >> 4 {
+        4 => {
+            if ip_packet.len() < 20 {
+                return None;
+            }
+            let header_len = ((ip_packet[0] & 0x0F) as usize) * 4;
+            // Non-first fragments do not carry the tunnel header
+            if read_u16(ip_packet, 6) & 0x1FFF != 0 || header_len < 20 {
+                return None;
+            }
+            let src = Ipv4Addr::new(ip_packet[12], ip_packet[13], ip_packet[14], ip_packet[15]);
+            let dst = Ipv4Addr::new(ip_packet[16], ip_packet[17], ip_packet[18], ip_packet[19]);
+            (ip_packet[9], header_len, IpAddr::V4(src), IpAddr::V4(dst))
+        }
+        6 => {
+            if ip_packet.len() < 40 {
+                return None;
+            }
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&ip_packet[8..24]);
+            dst.copy_from_slice(&ip_packet[24..40]);
+            (ip_packet[6], 40, IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)))
+        }
+        _ => return None,
+    };
+    if ip_packet.len() < header_len {
+        return None;
+    }
+    let mut tunnel = TunnelInfo {
+        tunnel_type: TunnelType::IpInIp,
+        outer_src: src,
+        outer_dst: dst,
+        vni: None,
+        decapsulated: false,
+    };
+    match protocol {
+        IP_PROTO_IPV4 | IP_PROTO_IPV6 => Some((
+            tunnel,
+            Some(InnerPacket {
+                offset: header_len,
+                ethernet: false,
+            }),
+        )),
+        IP_PROTO_GRE => {
+            tunnel.tunnel_type = TunnelType::Gre;
+            let inner = parse_gre(ip_packet, header_len, &mut tunnel);
+            Some((tunnel, inner))
+        }
+        IP_PROTO_UDP => parse_udp_tunnel(ip_packet, header_len, tunnel),
+        _ => None,
+    }
+}
+
+fn parse_gre(ip_packet: &[u8], offset: usize, tunnel: &mut TunnelInfo) -> Option<InnerPacket> {
+    if ip_packet.len() < offset + 4 {
+        return None;
+    }
+    let flags = read_u16(ip_packet, offset);
+    // Only GRE version 0 without routing is decapsulated (version 1 is PPTP)
+    if flags & 0x0007 != 0 || flags & 0x4000 != 0 {
+        return None;
+    }
+    let protocol = read_u16(ip_packet, offset + 2);
+    let mut header_len = 4;
+    // Checksum present
+    if flags & 0x8000 != 0 {
+        header_len += 4;
+    }
+    // Key present
+    if flags & 0x2000 != 0 {
+        if ip_packet.len() < offset + header_len + 4 {
+            return None;
+        }
+        tunnel.vni = Some(read_u32(ip_packet, offset + header_len));
+        header_len += 4;
+    }
+    // Sequence number present
+    if flags & 0x1000 != 0 {
+        header_len += 4;
+    }
+    inner_by_ether_type(protocol, offset + header_len)
+}
+
+fn parse_udp_tunnel(
+    ip_packet: &[u8],
+    offset: usize,
+    mut tunnel: TunnelInfo,
+) -> Option<(TunnelInfo, Option<InnerPacket>)> {
+    if ip_packet.len() < offset + 8 {
+        return None;
+    }
+    let src_port = read_u16(ip_packet, offset);
+    let dst_port = read_u16(ip_packet, offset + 2);
+    let payload = offset + 8;
+    if dst_port == VXLAN_PORT {
+        // flags(1) reserved(3) vni(3) reserved(1). I flag must be set
+        if ip_packet.len() < payload + 8 || ip_packet[payload] & 0x08 == 0 {
+            return None;
+        }
+        tunnel.tunnel_type = TunnelType::Vxlan;
+        tunnel.vni = Some(read_u24(ip_packet, payload + 4));
+        return Some((
+            tunnel,
+            Some(InnerPacket {
+                offset: payload + 8,
+                ethernet: true,
+            }),
+        ));
+    }
+    if dst_port == GENEVE_PORT {
+        // ver(2) opt_len(6) flags(8) protocol(16) vni(24) reserved(8) options
+        if ip_packet.len() < payload + 8 || ip_packet[payload] >> 6 != 0 {
+            return None;
+        }
+        let options_len = ((ip_packet[payload] & 0x3F) as usize) * 4;
+        let protocol = read_u16(ip_packet, payload + 2);
+        tunnel.tunnel_type = TunnelType::Geneve;
+        tunnel.vni = Some(read_u24(ip_packet, payload + 4));
+        let inner = inner_by_ether_type(protocol, payload + 8 + options_len);
+        return Some((tunnel, inner));
+    }
+    if src_port == WIREGUARD_PORT || dst_port == WIREGUARD_PORT {
+        // message type(1) reserved(3)
+        if ip_packet.len() >= payload + 4
+            && (1..=4).contains(&ip_packet[payload])
+            && ip_packet[payload + 1..payload + 4] == [0, 0, 0]
+        {
+            tunnel.tunnel_type = TunnelType::WireGuard;
+            return Some((tunnel, None));
+        }
+    }
+    None
+}
+
+fn inner_by_ether_type(ether_type: u16, offset: usize) -> Option<InnerPacket> {
+    match ether_type {
+        ETHER_TYPE_IPV4 | ETHER_TYPE_IPV6 => Some(InnerPacket {
+            offset: offset,
+            ethernet: false,
+        }),
+        ETHER_TYPE_TEB => Some(InnerPacket {
+            offset: offset,
+            ethernet: true,
+        }),
+        _ => None,
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u24(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([0, buf[offset], buf[offset + 1], buf[offset + 2]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTER_SRC: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    const OUTER_DST: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 2);
+    const INNER_SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const INNER_DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn ethernet(ether_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02];
+        data.extend_from_slice(&ether_type.to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn ipv4(protocol: u8, src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+        let total_len = (20 + payload.len()) as u16;
+        let mut data: Vec<u8> = vec![0x45, 0x00];
+        data.extend_from_slice(&total_len.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        data.extend_from_slice(&src.octets());
+        data.extend_from_slice(&dst.octets());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&src_port.to_be_bytes());
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// Inner UDP packet from INNER_SRC:5000 to INNER_DST:53
+    fn inner_packet() -> Vec<u8> {
+        ipv4(IP_PROTO_UDP, INNER_SRC, INNER_DST, &udp(5000, 53, b"query"))
+    }
+
+    fn decode(data: &[u8], attribution: TunnelAttribution) -> DecodedFrame {
+        decode_frame(data, ParseOption::default(), attribution)
+    }
+
+    fn ip_addrs(decoded: &DecodedFrame) -> (Ipv4Addr, Ipv4Addr) {
+        let ipv4 = decoded.frame.ip.as_ref().unwrap().ipv4.as_ref().unwrap();
+        (ipv4.source, ipv4.destination)
+    }
+
+    fn udp_dst_port(decoded: &DecodedFrame) -> u16 {
+        decoded.frame.transport.as_ref().unwrap().udp.as_ref().unwrap().destination
+    }
+
+    #[test]
+    fn vlan_tags_are_stripped() {
+        let mut tagged = ethernet(ETHER_TYPE_VLAN, &[0x20, 100]);
+        tagged.extend_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        tagged.extend_from_slice(&inner_packet());
+        let decoded = decode(&tagged, TunnelAttribution::Outer);
+        assert_eq!(decoded.vlan_ids, vec![100]);
+        assert_eq!(ip_addrs(&decoded), (INNER_SRC, INNER_DST));
+        assert_eq!(udp_dst_port(&decoded), 53);
+        assert_eq!(decoded.frame.packet_len, tagged.len());
+    }
+
+    #[test]
+    fn qinq_tags_are_stripped_outermost_first() {
+        let mut tagged = ethernet(ETHER_TYPE_QINQ, &[0, 10]);
+        tagged.extend_from_slice(&ETHER_TYPE_VLAN.to_be_bytes());
+        tagged.extend_from_slice(&[0, 20]);
+        tagged.extend_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        tagged.extend_from_slice(&inner_packet());
+        let (untagged, vlan_ids) = strip_vlan(&tagged).unwrap();
+        assert_eq!(vlan_ids, vec![10, 20]);
+        assert_eq!(untagged, ethernet(ETHER_TYPE_IPV4, &inner_packet()));
+        assert_eq!(ip_addrs(&decode(&tagged, TunnelAttribution::Outer)), (INNER_SRC, INNER_DST));
+        assert!(strip_vlan(&ethernet(ETHER_TYPE_IPV4, &inner_packet())).is_none());
+        // Truncated tag
+        assert!(strip_vlan(&ethernet(ETHER_TYPE_VLAN, &[0, 10])).is_none());
+    }
+
+    #[test]
+    fn vxlan_is_decapsulated_with_inner_vlan() {
+        let mut inner_frame = ethernet(ETHER_TYPE_VLAN, &[0, 30]);
+        inner_frame.extend_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        inner_frame.extend_from_slice(&inner_packet());
+        let mut vxlan: Vec<u8> = vec![0x08, 0, 0, 0, 0x00, 0x12, 0x34, 0];
+        vxlan.extend_from_slice(&inner_frame);
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_UDP, OUTER_SRC, OUTER_DST, &udp(40000, VXLAN_PORT, &vxlan)));
+
+        let outer = decode(&data, TunnelAttribution::Outer);
+        let tunnel = outer.tunnel.as_ref().unwrap();
+        assert_eq!(tunnel.tunnel_type, TunnelType::Vxlan);
+        assert_eq!(tunnel.vni, Some(0x1234));
+        assert!(!tunnel.decapsulated);
+        assert_eq!(ip_addrs(&outer), (OUTER_SRC, OUTER_DST));
+        assert_eq!(udp_dst_port(&outer), VXLAN_PORT);
+
+        let inner = decode(&data, TunnelAttribution::Inner);
+        assert!(inner.tunnel.as_ref().unwrap().decapsulated);
+        assert_eq!(inner.vlan_ids, vec![30]);
+        assert_eq!(ip_addrs(&inner), (INNER_SRC, INNER_DST));
+        assert_eq!(udp_dst_port(&inner), 53);
+
+        // I flag not set
+        vxlan[0] = 0;
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_UDP, OUTER_SRC, OUTER_DST, &udp(40000, VXLAN_PORT, &vxlan)));
+        assert!(decode(&data, TunnelAttribution::Inner).tunnel.is_none());
+    }
+
+    #[test]
+    fn gre_with_key_is_decapsulated() {
+        // Checksum and key present
+        let mut gre: Vec<u8> = vec![0xA0, 0x00];
+        gre.extend_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        gre.extend_from_slice(&[0, 0, 0, 0]);
+        gre.extend_from_slice(&7u32.to_be_bytes());
+        gre.extend_from_slice(&inner_packet());
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_GRE, OUTER_SRC, OUTER_DST, &gre));
+        let inner = decode(&data, TunnelAttribution::Inner);
+        let tunnel = inner.tunnel.as_ref().unwrap();
+        assert_eq!(tunnel.tunnel_type, TunnelType::Gre);
+        assert_eq!(tunnel.vni, Some(7));
+        assert!(tunnel.decapsulated);
+        assert_eq!(ip_addrs(&inner), (INNER_SRC, INNER_DST));
+
+        // Version 1 (PPTP) is detected but not decapsulated
+        let mut pptp = gre.clone();
+        pptp[1] = 0x01;
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_GRE, OUTER_SRC, OUTER_DST, &pptp));
+        let decoded = decode(&data, TunnelAttribution::Inner);
+        assert!(!decoded.tunnel.as_ref().unwrap().decapsulated);
+        assert_eq!(ip_addrs(&decoded), (OUTER_SRC, OUTER_DST));
+
+        // Key cut off
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_GRE, OUTER_SRC, OUTER_DST, &gre[..10]));
+        assert!(!decode(&data, TunnelAttribution::Inner).tunnel.unwrap().decapsulated);
+    }
+
+    #[test]
+    fn geneve_options_are_skipped() {
+        // One 4-byte option, protocol IPv4, VNI 0xABCDEF
+        let mut geneve: Vec<u8> = vec![0x01, 0x00];
+        geneve.extend_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+        geneve.extend_from_slice(&[0xAB, 0xCD, 0xEF, 0]);
+        geneve.extend_from_slice(&[0x01, 0x02, 0x03, 0x00]);
+        geneve.extend_from_slice(&inner_packet());
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_UDP, OUTER_SRC, OUTER_DST, &udp(40000, GENEVE_PORT, &geneve)));
+        let inner = decode(&data, TunnelAttribution::Inner);
+        let tunnel = inner.tunnel.as_ref().unwrap();
+        assert_eq!(tunnel.tunnel_type, TunnelType::Geneve);
+        assert_eq!(tunnel.vni, Some(0xABCDEF));
+        assert!(tunnel.decapsulated);
+        assert_eq!(ip_addrs(&inner), (INNER_SRC, INNER_DST));
+        assert_eq!(udp_dst_port(&inner), 53);
+
+        // Options longer than the packet leave the outer layers
+        geneve[0] = 0x3F;
+        let data = ethernet(ETHER_TYPE_IPV4, &ipv4(IP_PROTO_UDP, OUTER_SRC, OUTER_DST, &udp(40000, GENEVE_PORT, &geneve)));
+        let decoded = decode(&data, TunnelAttribution::Inner);
+        assert!(!decoded.tunnel.as_ref().unwrap().decapsulated);
+        assert_eq!(ip_addrs(&decoded), (OUTER_SRC, OUTER_DST));
+    }
+}
@@ -0,0 +1,1412 @@
+use crate::net::packet::PacketFrame;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Maximum number of raw frames kept for packet details
+pub const PACKET_STORE_MAX_PACKETS: usize = 10000;
+/// Maximum bytes of raw frames kept for packet details
+pub const PACKET_STORE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Maximum nesting of IP-in-IP layers
+const MAX_IP_DEPTH: usize = 4;
+/// Maximum DNS compression pointers followed for one name
+const MAX_DNS_POINTERS: usize = 16;
+/// Maximum records dissected per DNS section
+const MAX_DNS_RECORDS: usize = 64;
+const DNS_PORTS: [u16; 3] = [53, 5353, 5355];
+const DHCP_MAGIC_COOKIE: u32 = 0x63825363;
+
+/// Link layer of captured bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    /// IP packet starting at offset (tun interfaces, loopback on macOS)
+    Ip { offset: usize },
+}
+
+impl LinkType {
+    pub fn from_parse_option(parse_option: &nex::packet::frame::ParseOption) -> LinkType {
+        if parse_option.from_ip_packet {
+            LinkType::Ip {
+                offset: parse_option.offset,
+            }
+        } else {
+            LinkType::Ethernet
+        }
+    }
+}
+
+/// Field of a dissected packet. offset and len are the byte range in the raw packet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DissectNode {
+    pub name: String,
+    pub value: String,
+    pub offset: usize,
+    pub len: usize,
+    pub children: Vec<DissectNode>,
+}
+
+impl DissectNode {
+    pub fn new(name: &str, value: String, offset: usize, len: usize) -> DissectNode {
+        DissectNode {
+            name: name.to_string(),
+            value: value,
+            offset: offset,
+            len: len,
+            children: Vec::new(),
+        }
+    }
+    fn push(&mut self, name: &str, value: String, offset: usize, len: usize) -> &mut DissectNode {
+        self.children.push(DissectNode::new(name, value, offset, len));
+        self.children.last_mut().unwrap()
+    }
+}
+
+/// Decode tree and raw bytes of a captured frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PacketDetail {
+    pub capture_no: usize,
+    pub if_name: String,
+    pub timestamp: String,
+    pub raw: Vec<u8>,
+    pub tree: Vec<DissectNode>,
+}
+
+/// Raw bytes of a captured frame
+#[derive(Debug, Clone)]
+pub struct RawPacket {
+    pub capture_no: usize,
+    pub if_name: String,
+    pub timestamp: String,
+    pub link: LinkType,
+    pub data: Vec<u8>,
+}
+
+/// Recent raw frames of an on-demand capture. Oldest frames are dropped first
+#[derive(Debug)]
+pub struct PacketStore {
+    packets: VecDeque<RawPacket>,
+    bytes: usize,
+}
+
+impl PacketStore {
+    pub fn new() -> PacketStore {
+        PacketStore {
+            packets: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+    /// Keep the raw bytes of frame. Capture numbers must be increasing
+    pub fn push(&mut self, frame: &PacketFrame, link: LinkType) {
+        if frame.raw.is_empty() {
+            return;
+        }
+        self.bytes += frame.raw.len();
+        self.packets.push_back(RawPacket {
+            capture_no: frame.capture_no,
+            if_name: frame.if_name.clone(),
+            timestamp: frame.timestamp.clone(),
+            link: link,
+            data: frame.raw.clone(),
+        });
+        while self.packets.len() > PACKET_STORE_MAX_PACKETS || self.bytes > PACKET_STORE_MAX_BYTES {
+            match self.packets.pop_front() {
+                Some(packet) => self.bytes -= packet.data.len(),
+                None => break,
+            }
+        }
+    }
+    pub fn get(&self, capture_no: usize) -> Option<&RawPacket> {
+        match self.packets.binary_search_by_key(&capture_no, |p| p.capture_no) {
+            Ok(i) => self.packets.get(i),
+            Err(_) => None,
+        }
+    }
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.bytes = 0;
+    }
+    /// Decode tree and raw bytes of a stored frame
+    pub fn get_detail(&self, capture_no: usize) -> Option<PacketDetail> {
+        let packet = self.get(capture_no)?;
+        Some(PacketDetail {
+            capture_no: packet.capture_no,
+            if_name: packet.if_name.clone(),
+            timestamp: packet.timestamp.clone(),
+            raw: packet.data.clone(),
+            tree: dissect(&packet.data, packet.link),
+        })
+    }
+}
+
+/// Dissect captured bytes into a tree of fields with their byte ranges
+pub fn dissect(data: &[u8], link: LinkType) -> Vec<DissectNode> {
+    let mut tree: Vec<DissectNode> = Vec::new();
+    tree.push(DissectNode::new(
+        "Frame",
+        format!("{} bytes captured", data.len()),
+        0,
+        data.len(),
+    ));
+    match link {
+        LinkType::Ethernet => dissect_ethernet(data, &mut tree),
+        LinkType::Ip { offset } => {
+            if offset > 0 {
+                tree.push(DissectNode::new("Link Header", hex(&data[..offset.min(data.len())]), 0, offset.min(data.len())));
+            }
+            dissect_ip(data, offset, data.len(), 0, &mut tree);
+        }
+    }
+    tree
+}
+
+fn dissect_ethernet(data: &[u8], tree: &mut Vec<DissectNode>) {
+    if data.len() < 14 {
+        push_data(data, 0, data.len(), tree);
+        return;
+    }
+    let mut node = DissectNode::new(
+        "Ethernet II",
+        format!("{} -> {}", mac(&data[6..12]), mac(&data[0..6])),
+        0,
+        14,
+    );
+    node.push("Destination", mac(&data[0..6]), 0, 6);
+    node.push("Source", mac(&data[6..12]), 6, 6);
+    let mut offset = 12;
+    let mut ether_type = be16(data, offset).unwrap_or(0);
+    while matches!(ether_type, 0x8100 | 0x88A8 | 0x9100) && offset + 6 <= data.len() {
+        let tci = be16(data, offset + 2).unwrap_or(0);
+        let tag = node.push(
+            if ether_type == 0x8100 { "802.1Q VLAN" } else { "802.1ad Service VLAN" },
+            format!("ID {}", tci & 0x0FFF),
+            offset,
+            4,
+        );
+        tag.push("TPID", format!("0x{:04x}", ether_type), offset, 2);
+        tag.push("Priority", format!("{}", tci >> 13), offset + 2, 2);
+        tag.push("DEI", format!("{}", (tci >> 12) & 1), offset + 2, 2);
+        tag.push("ID", format!("{}", tci & 0x0FFF), offset + 2, 2);
+        offset += 4;
+        ether_type = be16(data, offset).unwrap_or(0);
+    }
+    node.push("Type", format!("{} (0x{:04x})", ether_type_name(ether_type), ether_type), offset, 2);
+    offset += 2;
+    node.len = offset;
+    tree.push(node);
+    match ether_type {
+        0x0800 | 0x86DD => dissect_ip(data, offset, data.len(), 0, tree),
+        0x0806 => dissect_arp(data, offset, tree),
+        _ => push_data(data, offset, data.len(), tree),
+    }
+}
+
+fn dissect_arp(data: &[u8], offset: usize, tree: &mut Vec<DissectNode>) {
+    let hlen = data.get(offset + 4).copied().unwrap_or(0) as usize;
+    let plen = data.get(offset + 5).copied().unwrap_or(0) as usize;
+    let len = 8 + 2 * (hlen + plen);
+    if data.len() < offset + len {
+        push_data(data, offset, data.len(), tree);
+        return;
+    }
+    let op = be16(data, offset + 6).unwrap_or(0);
+    let op_name = match op {
+        1 => "Request",
+        2 => "Reply",
+        3 => "RARP Request",
+        4 => "RARP Reply",
+        _ => "Unknown",
+    };
+    let mut node = DissectNode::new("Address Resolution Protocol", op_name.to_string(), offset, len);
+    node.push("Hardware Type", format!("{}", be16(data, offset).unwrap_or(0)), offset, 2);
+    node.push("Protocol Type", format!("0x{:04x}", be16(data, offset + 2).unwrap_or(0)), offset + 2, 2);
+    node.push("Hardware Size", format!("{}", hlen), offset + 4, 1);
+    node.push("Protocol Size", format!("{}", plen), offset + 5, 1);
+    node.push("Opcode", format!("{} ({})", op_name, op), offset + 6, 2);
+    let mut pos = offset + 8;
+    for name in ["Sender", "Target"] {
+        node.push(&format!("{} MAC Address", name), address(&data[pos..pos + hlen]), pos, hlen);
+        pos += hlen;
+        node.push(&format!("{} IP Address", name), address(&data[pos..pos + plen]), pos, plen);
+        pos += plen;
+    }
+    tree.push(node);
+    push_padding(data, offset + len, data.len(), tree);
+}
+
+/// Dissect an IPv4 or IPv6 packet between offset and end
+fn dissect_ip(data: &[u8], offset: usize, end: usize, depth: usize, tree: &mut Vec<DissectNode>) {
+    if depth >= MAX_IP_DEPTH {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    match data.get(offset).map(|b| b >> 4) {
+        Some(4) => dissect_ipv4(data, offset, end, depth, tree),
+        Some(6) => dissect_ipv6(data, offset, end, depth, tree),
+        _ => push_data(data, offset, end, tree),
+    }
+}
+
+fn dissect_ipv4(data: &[u8], offset: usize, end: usize, depth: usize, tree: &mut Vec<DissectNode>) {
+    let header_len = ((data[offset] & 0x0F) as usize) * 4;
+    if header_len < 20 || end < offset + header_len {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let total_len = be16(data, offset + 2).unwrap_or(0) as usize;
+    let packet_end = (offset + total_len.max(header_len)).min(end);
+    let flags_frag = be16(data, offset + 6).unwrap_or(0);
+    let protocol = data[offset + 9];
+    let src = Ipv4Addr::new(data[offset + 12], data[offset + 13], data[offset + 14], data[offset + 15]);
+    let dst = Ipv4Addr::new(data[offset + 16], data[offset + 17], data[offset + 18], data[offset + 19]);
+    let mut node = DissectNode::new("Internet Protocol Version 4", format!("{} -> {}", src, dst), offset, header_len);
+    node.push("Version", "4".to_string(), offset, 1);
+    node.push("Header Length", format!("{} bytes", header_len), offset, 1);
+    node.push("DSCP", format!("{}", data[offset + 1] >> 2), offset + 1, 1);
+    node.push("ECN", format!("{}", data[offset + 1] & 0x03), offset + 1, 1);
+    node.push("Total Length", format!("{}", total_len), offset + 2, 2);
+    node.push("Identification", format!("0x{:04x}", be16(data, offset + 4).unwrap_or(0)), offset + 4, 2);
+    let flags = node.push("Flags", format!("0x{:x}", flags_frag >> 13), offset + 6, 1);
+    flags.push("Don't Fragment", flag(flags_frag & 0x4000), offset + 6, 1);
+    flags.push("More Fragments", flag(flags_frag & 0x2000), offset + 6, 1);
+    let fragment_offset = ((flags_frag & 0x1FFF) as usize) * 8;
+    node.push("Fragment Offset", format!("{}", fragment_offset), offset + 6, 2);
+    node.push("Time to Live", format!("{}", data[offset + 8]), offset + 8, 1);
+    node.push("Protocol", format!("{} ({})", ip_protocol_name(protocol), protocol), offset + 9, 1);
+    node.push("Header Checksum", format!("0x{:04x}", be16(data, offset + 10).unwrap_or(0)), offset + 10, 2);
+    node.push("Source Address", src.to_string(), offset + 12, 4);
+    node.push("Destination Address", dst.to_string(), offset + 16, 4);
+    if header_len > 20 {
+        let options = node.push("Options", format!("{} bytes", header_len - 20), offset + 20, header_len - 20);
+        dissect_tlv_options(data, offset + 20, offset + header_len, true, options);
+    }
+    tree.push(node);
+    let payload = offset + header_len;
+    if fragment_offset > 0 {
+        tree.push(DissectNode::new(
+            "Fragment Data",
+            format!("{} bytes at offset {}", packet_end.saturating_sub(payload), fragment_offset),
+            payload,
+            packet_end.saturating_sub(payload),
+        ));
+    } else {
+        dissect_transport(data, protocol, payload, packet_end, depth, tree);
+    }
+    push_padding(data, packet_end, end, tree);
+}
+
+fn dissect_ipv6(data: &[u8], offset: usize, end: usize, depth: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 40 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let payload_len = be16(data, offset + 4).unwrap_or(0) as usize;
+    let packet_end = (offset + 40 + payload_len).min(end);
+    let src = ipv6(&data[offset + 8..offset + 24]);
+    let dst = ipv6(&data[offset + 24..offset + 40]);
+    let first = be32(data, offset).unwrap_or(0);
+    let mut node = DissectNode::new("Internet Protocol Version 6", format!("{} -> {}", src, dst), offset, 40);
+    node.push("Version", "6".to_string(), offset, 1);
+    node.push("Traffic Class", format!("0x{:02x}", (first >> 20) & 0xFF), offset, 2);
+    node.push("Flow Label", format!("0x{:05x}", first & 0xFFFFF), offset + 1, 3);
+    node.push("Payload Length", format!("{}", payload_len), offset + 4, 2);
+    let mut next_header = data[offset + 6];
+    node.push("Next Header", format!("{} ({})", ip_protocol_name(next_header), next_header), offset + 6, 1);
+    node.push("Hop Limit", format!("{}", data[offset + 7]), offset + 7, 1);
+    node.push("Source Address", src.to_string(), offset + 8, 16);
+    node.push("Destination Address", dst.to_string(), offset + 24, 16);
+    let mut pos = offset + 40;
+    let mut fragmented = false;
+    // Extension headers
+    loop {
+        let (name, len) = match next_header {
+            0 | 43 | 60 => {
+                let name = match next_header {
+                    0 => "Hop-by-Hop Options",
+                    43 => "Routing Header",
+                    _ => "Destination Options",
+                };
+                match data.get(pos + 1) {
+                    Some(l) => (name, (*l as usize + 1) * 8),
+                    None => break,
+                }
+            }
+            44 => ("Fragment Header", 8),
+            51 => match data.get(pos + 1) {
+                Some(l) => ("Authentication Header", (*l as usize + 2) * 4),
+                None => break,
+            },
+            _ => break,
+        };
+        if pos + len > packet_end {
+            break;
+        }
+        let ext = node.push(name, format!("{} bytes", len), pos, len);
+        let header_next = data[pos];
+        ext.push("Next Header", format!("{} ({})", ip_protocol_name(header_next), header_next), pos, 1);
+        match next_header {
+            0 | 60 => {
+                ext.push("Length", format!("{}", data[pos + 1]), pos + 1, 1);
+                dissect_tlv_options(data, pos + 2, pos + len, false, ext);
+            }
+            43 => {
+                ext.push("Length", format!("{}", data[pos + 1]), pos + 1, 1);
+                ext.push("Type", format!("{}", data[pos + 2]), pos + 2, 1);
+                ext.push("Segments Left", format!("{}", data[pos + 3]), pos + 3, 1);
+            }
+            44 => {
+                let frag = be16(data, pos + 2).unwrap_or(0);
+                ext.push("Offset", format!("{}", frag & 0xFFF8), pos + 2, 2);
+                ext.push("More Fragments", flag(frag & 1), pos + 3, 1);
+                ext.push("Identification", format!("0x{:08x}", be32(data, pos + 4).unwrap_or(0)), pos + 4, 4);
+                fragmented = frag & 0xFFF8 != 0;
+            }
+            _ => {
+                ext.push("Length", format!("{}", data[pos + 1]), pos + 1, 1);
+                ext.push("SPI", format!("0x{:08x}", be32(data, pos + 4).unwrap_or(0)), pos + 4, 4);
+                ext.push("Sequence", format!("{}", be32(data, pos + 8).unwrap_or(0)), pos + 8, 4);
+            }
+        }
+        next_header = header_next;
+        pos += len;
+    }
+    node.len = pos - offset;
+    tree.push(node);
+    if fragmented {
+        tree.push(DissectNode::new(
+            "Fragment Data",
+            format!("{} bytes", packet_end.saturating_sub(pos)),
+            pos,
+            packet_end.saturating_sub(pos),
+        ));
+    } else {
+        dissect_transport(data, next_header, pos, packet_end, depth, tree);
+    }
+    push_padding(data, packet_end, end, tree);
+}
+
+/// IPv4 options (one byte NOP/EOL) or IPv6 TLV options (Pad1)
+fn dissect_tlv_options(data: &[u8], start: usize, end: usize, ipv4: bool, node: &mut DissectNode) {
+    let mut pos = start;
+    while pos < end {
+        let kind = data[pos];
+        let single = if ipv4 { kind == 0 || kind == 1 } else { kind == 0 };
+        if single {
+            let name = if ipv4 && kind == 1 { "No-Operation" } else if ipv4 { "End of Options" } else { "Pad1" };
+            node.push(name, String::new(), pos, 1);
+            pos += 1;
+            continue;
+        }
+        let len = match data.get(pos + 1) {
+            Some(l) => {
+                if ipv4 {
+                    (*l as usize).max(2)
+                } else {
+                    *l as usize + 2
+                }
+            }
+            None => break,
+        };
+        let len = len.min(end - pos);
+        node.push("Option", format!("type {} length {}", kind, len), pos, len);
+        pos += len;
+    }
+}
+
+fn dissect_transport(data: &[u8], protocol: u8, offset: usize, end: usize, depth: usize, tree: &mut Vec<DissectNode>) {
+    match protocol {
+        1 => dissect_icmp(data, offset, end, tree),
+        6 => dissect_tcp(data, offset, end, tree),
+        17 => dissect_udp(data, offset, end, tree),
+        58 => dissect_icmpv6(data, offset, end, tree),
+        4 | 41 => dissect_ip(data, offset, end, depth + 1, tree),
+        _ => push_data(data, offset, end, tree),
+    }
+}
+
+fn dissect_tcp(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 20 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let header_len = ((data[offset + 12] >> 4) as usize) * 4;
+    let header_len = header_len.clamp(20, end - offset);
+    let src_port = be16(data, offset).unwrap_or(0);
+    let dst_port = be16(data, offset + 2).unwrap_or(0);
+    let flags = data[offset + 13];
+    let mut node = DissectNode::new(
+        "Transmission Control Protocol",
+        format!("{} -> {} [{}]", src_port, dst_port, tcp_flags(flags)),
+        offset,
+        header_len,
+    );
+    node.push("Source Port", format!("{}", src_port), offset, 2);
+    node.push("Destination Port", format!("{}", dst_port), offset + 2, 2);
+    node.push("Sequence Number", format!("{}", be32(data, offset + 4).unwrap_or(0)), offset + 4, 4);
+    node.push("Acknowledgment Number", format!("{}", be32(data, offset + 8).unwrap_or(0)), offset + 8, 4);
+    node.push("Header Length", format!("{} bytes", header_len), offset + 12, 1);
+    let flag_node = node.push("Flags", format!("0x{:02x} [{}]", flags, tcp_flags(flags)), offset + 13, 1);
+    for (bit, name) in [(0x80, "CWR"), (0x40, "ECE"), (0x20, "URG"), (0x10, "ACK"), (0x08, "PSH"), (0x04, "RST"), (0x02, "SYN"), (0x01, "FIN")] {
+        flag_node.push(name, flag((flags & bit) as u16), offset + 13, 1);
+    }
+    node.push("Window", format!("{}", be16(data, offset + 14).unwrap_or(0)), offset + 14, 2);
+    node.push("Checksum", format!("0x{:04x}", be16(data, offset + 16).unwrap_or(0)), offset + 16, 2);
+    node.push("Urgent Pointer", format!("{}", be16(data, offset + 18).unwrap_or(0)), offset + 18, 2);
+    if header_len > 20 {
+        let options = node.push("Options", format!("{} bytes", header_len - 20), offset + 20, header_len - 20);
+        dissect_tcp_options(data, offset + 20, offset + header_len, options);
+    }
+    tree.push(node);
+    let payload = offset + header_len;
+    if payload >= end {
+        return;
+    }
+    if DNS_PORTS.contains(&src_port) || DNS_PORTS.contains(&dst_port) {
+        // DNS over TCP has a two byte length prefix
+        if let Some(len) = be16(data, payload) {
+            tree.push(DissectNode::new("DNS Length", format!("{}", len), payload, 2));
+            dissect_dns(data, payload + 2, end, tree);
+            return;
+        }
+    }
+    if is_tls_record(data, payload) {
+        dissect_tls(data, payload, end, tree);
+        return;
+    }
+    push_data(data, payload, end, tree);
+}
+
+fn dissect_tcp_options(data: &[u8], start: usize, end: usize, node: &mut DissectNode) {
+    let mut pos = start;
+    while pos < end {
+        let kind = data[pos];
+        if kind == 0 || kind == 1 {
+            node.push(if kind == 0 { "End of Option List" } else { "No-Operation" }, String::new(), pos, 1);
+            pos += 1;
+            continue;
+        }
+        let len = match data.get(pos + 1) {
+            Some(l) if pos + 2 <= end => (*l as usize).max(2).min(end - pos),
+            _ => break,
+        };
+        let (name, value) = match kind {
+            2 => ("Maximum Segment Size", be16(data, pos + 2).map(|v| v.to_string()).unwrap_or_default()),
+            3 => ("Window Scale", data.get(pos + 2).map(|v| format!("{} (x{})", v, 1u32 << (*v).min(14))).unwrap_or_default()),
+            4 => ("SACK Permitted", String::new()),
+            5 => {
+                let edges: Vec<String> = data[pos + 2..pos + len]
+                    .chunks(8)
+                    .filter(|c| c.len() == 8)
+                    .map(|c| format!("{}-{}", be32(c, 0).unwrap_or(0), be32(c, 4).unwrap_or(0)))
+                    .collect();
+                ("SACK", edges.join(", "))
+            }
+            8 => (
+                "Timestamps",
+                format!("TSval {} TSecr {}", be32(data, pos + 2).unwrap_or(0), be32(data, pos + 6).unwrap_or(0)),
+            ),
+            _ => ("Option", format!("kind {} length {}", kind, len)),
+        };
+        node.push(name, value, pos, len);
+        pos += len;
+    }
+}
+
+fn dissect_udp(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 8 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let src_port = be16(data, offset).unwrap_or(0);
+    let dst_port = be16(data, offset + 2).unwrap_or(0);
+    let mut node = DissectNode::new("User Datagram Protocol", format!("{} -> {}", src_port, dst_port), offset, 8);
+    node.push("Source Port", format!("{}", src_port), offset, 2);
+    node.push("Destination Port", format!("{}", dst_port), offset + 2, 2);
+    node.push("Length", format!("{}", be16(data, offset + 4).unwrap_or(0)), offset + 4, 2);
+    node.push("Checksum", format!("0x{:04x}", be16(data, offset + 6).unwrap_or(0)), offset + 6, 2);
+    tree.push(node);
+    let payload = offset + 8;
+    if payload >= end {
+        return;
+    }
+    if DNS_PORTS.contains(&src_port) || DNS_PORTS.contains(&dst_port) {
+        dissect_dns(data, payload, end, tree);
+    } else if matches!(src_port, 67 | 68) && matches!(dst_port, 67 | 68) {
+        dissect_dhcp(data, payload, end, tree);
+    } else {
+        push_data(data, payload, end, tree);
+    }
+}
+
+fn dissect_icmp(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 4 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let icmp_type = data[offset];
+    let code = data[offset + 1];
+    let type_name = match icmp_type {
+        0 => "Echo Reply",
+        3 => "Destination Unreachable",
+        5 => "Redirect",
+        8 => "Echo Request",
+        11 => "Time Exceeded",
+        12 => "Parameter Problem",
+        13 => "Timestamp",
+        14 => "Timestamp Reply",
+        _ => "Unknown",
+    };
+    let mut node = DissectNode::new("Internet Control Message Protocol", type_name.to_string(), offset, end - offset);
+    node.push("Type", format!("{} ({})", icmp_type, type_name), offset, 1);
+    node.push("Code", format!("{}", code), offset + 1, 1);
+    node.push("Checksum", format!("0x{:04x}", be16(data, offset + 2).unwrap_or(0)), offset + 2, 2);
+    match icmp_type {
+        0 | 8 | 13 | 14 if end >= offset + 8 => {
+            node.push("Identifier", format!("{}", be16(data, offset + 4).unwrap_or(0)), offset + 4, 2);
+            node.push("Sequence Number", format!("{}", be16(data, offset + 6).unwrap_or(0)), offset + 6, 2);
+            if end > offset + 8 {
+                node.push("Data", format!("{} bytes", end - offset - 8), offset + 8, end - offset - 8);
+            }
+        }
+        3 | 5 | 11 | 12 if end >= offset + 8 => {
+            let mut inner: Vec<DissectNode> = Vec::new();
+            dissect_ip(data, offset + 8, end, MAX_IP_DEPTH - 1, &mut inner);
+            let original = node.push("Original Datagram", format!("{} bytes", end - offset - 8), offset + 8, end - offset - 8);
+            original.children = inner;
+        }
+        _ => {}
+    }
+    tree.push(node);
+}
+
+fn dissect_icmpv6(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 4 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let icmp_type = data[offset];
+    let type_name = match icmp_type {
+        1 => "Destination Unreachable",
+        2 => "Packet Too Big",
+        3 => "Time Exceeded",
+        4 => "Parameter Problem",
+        128 => "Echo Request",
+        129 => "Echo Reply",
+        133 => "Router Solicitation",
+        134 => "Router Advertisement",
+        135 => "Neighbor Solicitation",
+        136 => "Neighbor Advertisement",
+        137 => "Redirect",
+        _ => "Unknown",
+    };
+    let mut node = DissectNode::new("Internet Control Message Protocol v6", type_name.to_string(), offset, end - offset);
+    node.push("Type", format!("{} ({})", icmp_type, type_name), offset, 1);
+    node.push("Code", format!("{}", data[offset + 1]), offset + 1, 1);
+    node.push("Checksum", format!("0x{:04x}", be16(data, offset + 2).unwrap_or(0)), offset + 2, 2);
+    match icmp_type {
+        128 | 129 if end >= offset + 8 => {
+            node.push("Identifier", format!("{}", be16(data, offset + 4).unwrap_or(0)), offset + 4, 2);
+            node.push("Sequence Number", format!("{}", be16(data, offset + 6).unwrap_or(0)), offset + 6, 2);
+        }
+        135 | 136 | 137 if end >= offset + 24 => {
+            node.push("Target Address", ipv6(&data[offset + 8..offset + 24]).to_string(), offset + 8, 16);
+        }
+        _ => {}
+    }
+    tree.push(node);
+}
+
+fn dissect_dns(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 12 {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    // Names are relative to the start of the DNS message
+    let msg = &data[offset..end];
+    let flags = be16(msg, 2).unwrap_or(0);
+    let response = flags & 0x8000 != 0;
+    let counts: Vec<u16> = (0..4).map(|i| be16(msg, 4 + i * 2).unwrap_or(0)).collect();
+    let mut node = DissectNode::new(
+        "Domain Name System",
+        format!("{} 0x{:04x}", if response { "Response" } else { "Query" }, be16(msg, 0).unwrap_or(0)),
+        offset,
+        end - offset,
+    );
+    node.push("Transaction ID", format!("0x{:04x}", be16(msg, 0).unwrap_or(0)), offset, 2);
+    let flag_node = node.push("Flags", format!("0x{:04x}", flags), offset + 2, 2);
+    flag_node.push("Response", flag(flags & 0x8000), offset + 2, 1);
+    flag_node.push("Opcode", format!("{}", (flags >> 11) & 0x0F), offset + 2, 1);
+    flag_node.push("Authoritative", flag(flags & 0x0400), offset + 2, 1);
+    flag_node.push("Truncated", flag(flags & 0x0200), offset + 2, 1);
+    flag_node.push("Recursion Desired", flag(flags & 0x0100), offset + 2, 1);
+    flag_node.push("Recursion Available", flag(flags & 0x0080), offset + 3, 1);
+    flag_node.push("Reply Code", format!("{}", flags & 0x000F), offset + 3, 1);
+    for (i, name) in ["Questions", "Answer RRs", "Authority RRs", "Additional RRs"].iter().enumerate() {
+        node.push(name, format!("{}", counts[i]), offset + 4 + i * 2, 2);
+    }
+    let mut pos = 12;
+    if counts[0] > 0 {
+        let start = pos;
+        let mut questions = DissectNode::new("Queries", String::new(), offset + pos, 0);
+        for _ in 0..(counts[0] as usize).min(MAX_DNS_RECORDS) {
+            let q_start = pos;
+            let (qname, next) = match read_dns_name(msg, pos) {
+                Some(v) => v,
+                None => break,
+            };
+            let (qtype, qclass) = match (be16(msg, next), be16(msg, next + 2)) {
+                (Some(t), Some(c)) => (t, c),
+                _ => break,
+            };
+            pos = next + 4;
+            let q = questions.push(
+                &qname,
+                format!("type {}, class {}", dns_type_name(qtype), qclass),
+                offset + q_start,
+                pos - q_start,
+            );
+            q.push("Name", qname.clone(), offset + q_start, next - q_start);
+            q.push("Type", format!("{} ({})", dns_type_name(qtype), qtype), offset + next, 2);
+            q.push("Class", format!("{}", qclass), offset + next + 2, 2);
+        }
+        questions.len = pos - start;
+        node.children.push(questions);
+    }
+    for (i, section) in ["Answers", "Authoritative Nameservers", "Additional Records"].iter().enumerate() {
+        let count = counts[i + 1] as usize;
+        if count == 0 {
+            continue;
+        }
+        let start = pos;
+        let mut records = DissectNode::new(section, String::new(), offset + pos, 0);
+        for _ in 0..count.min(MAX_DNS_RECORDS) {
+            match dissect_dns_record(msg, pos, offset) {
+                Some((record, next)) => {
+                    records.children.push(record);
+                    pos = next;
+                }
+                None => break,
+            }
+        }
+        records.len = pos - start;
+        node.children.push(records);
+    }
+    tree.push(node);
+}
+
+/// Dissect a resource record at pos. base is the offset of the DNS message in the packet
+fn dissect_dns_record(msg: &[u8], pos: usize, base: usize) -> Option<(DissectNode, usize)> {
+    let (name, next) = read_dns_name(msg, pos)?;
+    let rtype = be16(msg, next)?;
+    let class = be16(msg, next + 2)?;
+    let ttl = be32(msg, next + 4)?;
+    let rdlen = be16(msg, next + 8)? as usize;
+    let rdata = next + 10;
+    if msg.len() < rdata + rdlen {
+        return None;
+    }
+    let value = match rtype {
+        1 if rdlen == 4 => Ipv4Addr::new(msg[rdata], msg[rdata + 1], msg[rdata + 2], msg[rdata + 3]).to_string(),
+        28 if rdlen == 16 => ipv6(&msg[rdata..rdata + 16]).to_string(),
+        2 | 5 | 12 => read_dns_name(msg, rdata).map(|(n, _)| n).unwrap_or_default(),
+        15 => match (be16(msg, rdata), read_dns_name(msg, rdata + 2)) {
+            (Some(pref), Some((n, _))) => format!("{} {}", pref, n),
+            _ => String::new(),
+        },
+        16 => {
+            let mut texts: Vec<String> = Vec::new();
+            let mut p = rdata;
+            while p < rdata + rdlen {
+                let l = msg[p] as usize;
+                let e = (p + 1 + l).min(rdata + rdlen);
+                texts.push(String::from_utf8_lossy(&msg[p + 1..e]).to_string());
+                p = e;
+            }
+            texts.join(" ")
+        }
+        _ => format!("{} bytes", rdlen),
+    };
+    let end = rdata + rdlen;
+    let mut node = DissectNode::new(
+        &name,
+        format!("type {}, {}", dns_type_name(rtype), value),
+        base + pos,
+        end - pos,
+    );
+    node.push("Name", name.clone(), base + pos, next - pos);
+    node.push("Type", format!("{} ({})", dns_type_name(rtype), rtype), base + next, 2);
+    node.push("Class", format!("{}", class), base + next + 2, 2);
+    node.push("Time to Live", format!("{}", ttl), base + next + 4, 4);
+    node.push("Data Length", format!("{}", rdlen), base + next + 8, 2);
+    node.push("Data", value, base + rdata, rdlen);
+    Some((node, end))
+}
+
+/// Read a possibly compressed DNS name. Returns the name and the position after it
+pub fn read_dns_name(msg: &[u8], pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut p = pos;
+    let mut end: Option<usize> = None;
+    let mut pointers = 0;
+    loop {
+        let len = *msg.get(p)? as usize;
+        if len == 0 {
+            p += 1;
+            break;
+        }
+        if len & 0xC0 == 0xC0 {
+            let target = (be16(msg, p)? & 0x3FFF) as usize;
+            if end.is_none() {
+                end = Some(p + 2);
+            }
+            pointers += 1;
+            if pointers > MAX_DNS_POINTERS {
+                return None;
+            }
+            p = target;
+            continue;
+        }
+        let label = msg.get(p + 1..p + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        p += 1 + len;
+    }
+    let name = if labels.is_empty() { "<Root>".to_string() } else { labels.join(".") };
+    Some((name, end.unwrap_or(p)))
+}
+
+fn dissect_dhcp(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    if end < offset + 240 || be32(data, offset + 236) != Some(DHCP_MAGIC_COOKIE) {
+        push_data(data, offset, end, tree);
+        return;
+    }
+    let ip = |p: usize| Ipv4Addr::new(data[p], data[p + 1], data[p + 2], data[p + 3]).to_string();
+    let mut node = DissectNode::new("Dynamic Host Configuration Protocol", String::new(), offset, end - offset);
+    node.push("Message Type", format!("{}", if data[offset] == 1 { "Boot Request" } else { "Boot Reply" }), offset, 1);
+    node.push("Hardware Type", format!("{}", data[offset + 1]), offset + 1, 1);
+    node.push("Hardware Address Length", format!("{}", data[offset + 2]), offset + 2, 1);
+    node.push("Hops", format!("{}", data[offset + 3]), offset + 3, 1);
+    node.push("Transaction ID", format!("0x{:08x}", be32(data, offset + 4).unwrap_or(0)), offset + 4, 4);
+    node.push("Seconds Elapsed", format!("{}", be16(data, offset + 8).unwrap_or(0)), offset + 8, 2);
+    node.push("Flags", format!("0x{:04x}", be16(data, offset + 10).unwrap_or(0)), offset + 10, 2);
+    node.push("Client IP Address", ip(offset + 12), offset + 12, 4);
+    node.push("Your IP Address", ip(offset + 16), offset + 16, 4);
+    node.push("Next Server IP Address", ip(offset + 20), offset + 20, 4);
+    node.push("Relay Agent IP Address", ip(offset + 24), offset + 24, 4);
+    let hlen = (data[offset + 2] as usize).min(16);
+    node.push("Client Hardware Address", mac(&data[offset + 28..offset + 28 + hlen]), offset + 28, 16);
+    node.push("Server Host Name", c_string(&data[offset + 44..offset + 108]), offset + 44, 64);
+    node.push("Boot File Name", c_string(&data[offset + 108..offset + 236]), offset + 108, 128);
+    node.push("Magic Cookie", "DHCP".to_string(), offset + 236, 4);
+    let mut pos = offset + 240;
+    while pos < end {
+        let code = data[pos];
+        if code == 0 {
+            pos += 1;
+            continue;
+        }
+        if code == 255 {
+            node.push("Option", "(255) End".to_string(), pos, 1);
+            break;
+        }
+        let len = match data.get(pos + 1) {
+            Some(l) => *l as usize,
+            None => break,
+        };
+        if pos + 2 + len > end {
+            break;
+        }
+        let value_bytes = &data[pos + 2..pos + 2 + len];
+        let (name, value) = dhcp_option(code, value_bytes);
+        node.push("Option", format!("({}) {}: {}", code, name, value), pos, len + 2);
+        pos += len + 2;
+    }
+    tree.push(node);
+}
+
+fn dhcp_option(code: u8, value: &[u8]) -> (&'static str, String) {
+    let ips = || {
+        value
+            .chunks(4)
+            .filter(|c| c.len() == 4)
+            .map(|c| Ipv4Addr::new(c[0], c[1], c[2], c[3]).to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    match code {
+        1 => ("Subnet Mask", ips()),
+        3 => ("Router", ips()),
+        6 => ("Domain Name Server", ips()),
+        12 => ("Host Name", String::from_utf8_lossy(value).to_string()),
+        15 => ("Domain Name", String::from_utf8_lossy(value).to_string()),
+        50 => ("Requested IP Address", ips()),
+        51 => ("IP Address Lease Time", be32(value, 0).map(|v| format!("{}s", v)).unwrap_or_default()),
+        53 => {
+            let name = match value.first() {
+                Some(1) => "Discover",
+                Some(2) => "Offer",
+                Some(3) => "Request",
+                Some(4) => "Decline",
+                Some(5) => "ACK",
+                Some(6) => "NAK",
+                Some(7) => "Release",
+                Some(8) => "Inform",
+                _ => "Unknown",
+            };
+            ("DHCP Message Type", name.to_string())
+        }
+        54 => ("DHCP Server Identifier", ips()),
+        55 => (
+            "Parameter Request List",
+            value.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", "),
+        ),
+        61 => ("Client Identifier", hex(value)),
+        _ => ("Unknown", hex(value)),
+    }
+}
+
+fn is_tls_record(data: &[u8], offset: usize) -> bool {
+    match (data.get(offset), data.get(offset + 1), data.get(offset + 2)) {
+        (Some(t), Some(3), Some(minor)) => (20..=23).contains(t) && *minor <= 4,
+        _ => false,
+    }
+}
+
+fn dissect_tls(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    let mut pos = offset;
+    while pos + 5 <= end && is_tls_record(data, pos) {
+        let content_type = data[pos];
+        let version = be16(data, pos + 1).unwrap_or(0);
+        let len = be16(data, pos + 3).unwrap_or(0) as usize;
+        let record_end = (pos + 5 + len).min(end);
+        let type_name = match content_type {
+            20 => "Change Cipher Spec",
+            21 => "Alert",
+            22 => "Handshake",
+            _ => "Application Data",
+        };
+        let mut node = DissectNode::new("TLS Record", format!("{}, {}", type_name, tls_version(version)), pos, record_end - pos);
+        node.push("Content Type", format!("{} ({})", type_name, content_type), pos, 1);
+        node.push("Version", tls_version(version), pos + 1, 2);
+        node.push("Length", format!("{}", len), pos + 3, 2);
+        if content_type == 22 && record_end >= pos + 9 {
+            dissect_tls_handshake(data, pos + 5, record_end, &mut node);
+        }
+        tree.push(node);
+        pos += 5 + len;
+    }
+    if pos < end {
+        push_data(data, pos, end, tree);
+    }
+}
+
+fn dissect_tls_handshake(data: &[u8], offset: usize, end: usize, record: &mut DissectNode) {
+    let hs_type = data[offset];
+    let len = be32(data, offset).unwrap_or(0) & 0x00FF_FFFF;
+    let type_name = match hs_type {
+        0 => "Hello Request",
+        1 => "Client Hello",
+        2 => "Server Hello",
+        4 => "New Session Ticket",
+        8 => "Encrypted Extensions",
+        11 => "Certificate",
+        12 => "Server Key Exchange",
+        13 => "Certificate Request",
+        14 => "Server Hello Done",
+        15 => "Certificate Verify",
+        16 => "Client Key Exchange",
+        20 => "Finished",
+        // Encrypted handshake messages look like random types
+        _ => "Encrypted Handshake Message",
+    };
+    let node = record.push("Handshake Protocol", type_name.to_string(), offset, end - offset);
+    node.push("Handshake Type", format!("{} ({})", type_name, hs_type), offset, 1);
+    node.push("Length", format!("{}", len), offset + 1, 3);
+    if hs_type != 1 && hs_type != 2 {
+        return;
+    }
+    // version(2) random(32) session_id
+    let mut pos = offset + 4;
+    match be16(data, pos) {
+        Some(v) if pos + 34 < end => {
+            node.push("Version", tls_version(v), pos, 2);
+            node.push("Random", hex(&data[pos + 2..pos + 34]), pos + 2, 32);
+        }
+        _ => return,
+    }
+    pos += 34;
+    let sid_len = data[pos] as usize;
+    node.push("Session ID Length", format!("{}", sid_len), pos, 1);
+    pos += 1 + sid_len;
+    if hs_type == 1 {
+        let suites_len = match be16(data, pos) {
+            Some(l) if pos + 2 + l as usize <= end => l as usize,
+            _ => return,
+        };
+        node.push("Cipher Suites", format!("{} suites", suites_len / 2), pos, 2 + suites_len);
+        pos += 2 + suites_len;
+        let comp_len = match data.get(pos) {
+            Some(l) if pos + 1 + *l as usize <= end => *l as usize,
+            _ => return,
+        };
+        node.push("Compression Methods", format!("{}", comp_len), pos, 1 + comp_len);
+        pos += 1 + comp_len;
+    } else {
+        if pos + 3 > end {
+            return;
+        }
+        node.push("Cipher Suite", format!("0x{:04x}", be16(data, pos).unwrap_or(0)), pos, 2);
+        node.push("Compression Method", format!("{}", data[pos + 2]), pos + 2, 1);
+        pos += 3;
+    }
+    if pos + 2 > end {
+        return;
+    }
+    let ext_len = match be16(data, pos) {
+        Some(l) => l as usize,
+        None => return,
+    };
+    let ext_end = (pos + 2 + ext_len).min(end);
+    let extensions = node.push("Extensions", format!("{} bytes", ext_len), pos, ext_end - pos);
+    pos += 2;
+    while pos + 4 <= ext_end {
+        let ext_type = be16(data, pos).unwrap_or(0);
+        let len = be16(data, pos + 2).unwrap_or(0) as usize;
+        let value_end = (pos + 4 + len).min(ext_end);
+        let (name, value) = match ext_type {
+            0 => ("server_name", tls_server_name(&data[pos + 4..value_end]).unwrap_or_default()),
+            10 => ("supported_groups", String::new()),
+            13 => ("signature_algorithms", String::new()),
+            16 => ("application_layer_protocol_negotiation", tls_alpn(&data[pos + 4..value_end])),
+            43 => ("supported_versions", String::new()),
+            51 => ("key_share", String::new()),
+            _ => ("extension", format!("type {}", ext_type)),
+        };
+        extensions.push(name, value, pos, value_end - pos);
+        pos += 4 + len;
+    }
+}
+
+/// Host name from a server_name extension body
+pub fn tls_server_name(ext: &[u8]) -> Option<String> {
+    // list length(2) type(1) name length(2) name
+    let name_len = be16(ext, 3)? as usize;
+    if *ext.get(2)? != 0 {
+        return None;
+    }
+    let name = ext.get(5..5 + name_len)?;
+    Some(String::from_utf8_lossy(name).to_string())
+}
+
+fn tls_alpn(ext: &[u8]) -> String {
+    let mut protocols: Vec<String> = Vec::new();
+    let mut pos = 2;
+    while pos < ext.len() {
+        let len = ext[pos] as usize;
+        match ext.get(pos + 1..pos + 1 + len) {
+            Some(p) => protocols.push(String::from_utf8_lossy(p).to_string()),
+            None => break,
+        }
+        pos += 1 + len;
+    }
+    protocols.join(", ")
+}
+
+fn push_data(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    let end = end.min(data.len());
+    if offset < end {
+        tree.push(DissectNode::new("Data", format!("{} bytes", end - offset), offset, end - offset));
+    }
+}
+
+/// Bytes after the end of an IP packet (Ethernet padding)
+fn push_padding(data: &[u8], offset: usize, end: usize, tree: &mut Vec<DissectNode>) {
+    let end = end.min(data.len());
+    if offset < end {
+        tree.push(DissectNode::new("Padding", format!("{} bytes", end - offset), offset, end - offset));
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn mac(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(":")
+}
+
+fn ipv6(bytes: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&bytes[..16]);
+    Ipv6Addr::from(octets)
+}
+
+/// Format an address by its length (MAC, IPv4 or IPv6)
+fn address(bytes: &[u8]) -> String {
+    match bytes.len() {
+        4 => Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).to_string(),
+        16 => ipv6(bytes).to_string(),
+        _ => mac(bytes),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn flag(value: u16) -> String {
+    if value != 0 { "Set".to_string() } else { "Not set".to_string() }
+}
+
+fn tcp_flags(flags: u8) -> String {
+    let names: Vec<&str> = [(0x02, "SYN"), (0x10, "ACK"), (0x08, "PSH"), (0x01, "FIN"), (0x04, "RST"), (0x20, "URG"), (0x40, "ECE"), (0x80, "CWR")]
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    names.join(", ")
+}
+
+fn ether_type_name(ether_type: u16) -> &'static str {
+    match ether_type {
+        0x0800 => "IPv4",
+        0x0806 => "ARP",
+        0x86DD => "IPv6",
+        0x8100 => "802.1Q",
+        0x88A8 => "802.1ad",
+        0x88CC => "LLDP",
+        0x888E => "EAPOL",
+        _ => "Unknown",
+    }
+}
+
+fn ip_protocol_name(protocol: u8) -> &'static str {
+    match protocol {
+        0 => "Hop-by-Hop",
+        1 => "ICMP",
+        2 => "IGMP",
+        4 => "IPv4",
+        6 => "TCP",
+        17 => "UDP",
+        41 => "IPv6",
+        43 => "IPv6-Route",
+        44 => "IPv6-Frag",
+        47 => "GRE",
+        50 => "ESP",
+        51 => "AH",
+        58 => "ICMPv6",
+        59 => "No Next Header",
+        60 => "IPv6-Opts",
+        132 => "SCTP",
+        _ => "Unknown",
+    }
+}
+
+fn dns_type_name(rtype: u16) -> &'static str {
+    match rtype {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        10 => "NULL",
+        12 => "PTR",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        33 => "SRV",
+        41 => "OPT",
+        64 => "SVCB",
+        65 => "HTTPS",
+        255 => "ANY",
+        _ => "Unknown",
+    }
+}
+
+fn tls_version(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301 => "TLS 1.0".to_string(),
+        0x0302 => "TLS 1.1".to_string(),
+        0x0303 => "TLS 1.2".to_string(),
+        0x0304 => "TLS 1.3".to_string(),
+        _ => format!("0x{:04x}", version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: LinkType = LinkType::Ip { offset: 0 };
+
+    /// IPv4 packet from 10.0.0.1 to 10.0.0.2
+    fn ipv4(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 1, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        packet[2..4].copy_from_slice(&((20 + transport.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram: Vec<u8> = Vec::new();
+        datagram.extend_from_slice(&src_port.to_be_bytes());
+        datagram.extend_from_slice(&dst_port.to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        ipv4(17, &datagram)
+    }
+
+    fn tcp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut segment: Vec<u8> = Vec::new();
+        segment.extend_from_slice(&src_port.to_be_bytes());
+        segment.extend_from_slice(&dst_port.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ipv4(6, &segment)
+    }
+
+    fn find<'a>(nodes: &'a [DissectNode], name: &str) -> Option<&'a DissectNode> {
+        for node in nodes {
+            if node.name == name {
+                return Some(node);
+            }
+            if let Some(found) = find(&node.children, name) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Every node lies within the captured bytes
+    fn assert_in_bounds(nodes: &[DissectNode], len: usize) {
+        for node in nodes {
+            assert!(node.offset + node.len <= len, "{} at {}+{} beyond {}", node.name, node.offset, node.len, len);
+            assert_in_bounds(&node.children, len);
+        }
+    }
+
+    /// Dissect every truncation of packet. The IP total length still claims the full packet
+    fn dissect_prefixes(packet: &[u8]) {
+        for len in 0..packet.len() {
+            let tree = dissect(&packet[..len], LINK);
+            assert_in_bounds(&tree, len);
+        }
+    }
+
+    /// Response for example.com with one A record whose name points at the question
+    fn dns_response() -> Vec<u8> {
+        let mut msg: Vec<u8> = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x07example\x03com\x00");
+        msg.extend_from_slice(&[0, 1, 0, 1]);
+        msg.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 93, 184, 216, 34]);
+        msg
+    }
+
+    #[test]
+    fn dns_response_with_compressed_name() {
+        let tree = dissect(&udp(53, 40000, &dns_response()), LINK);
+        let answers = find(&tree, "Answers").unwrap();
+        assert_eq!(answers.children.len(), 1);
+        assert_eq!(answers.children[0].name, "example.com");
+        assert_eq!(find(&answers.children, "Data").unwrap().value, "93.184.216.34");
+        assert_eq!(find(&tree, "Queries").unwrap().children[0].name, "example.com");
+    }
+
+    #[test]
+    fn dns_compression_loops_are_rejected() {
+        // Pointer to itself
+        let mut msg: Vec<u8> = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12, 0, 1, 0, 1];
+        assert_eq!(read_dns_name(&msg, 12), None);
+        let tree = dissect(&udp(40000, 53, &msg), LINK);
+        assert!(find(&tree, "Queries").unwrap().children.is_empty());
+        // Two names pointing at each other
+        msg.truncate(12);
+        msg.extend_from_slice(&[0x01, b'a', 0xC0, 16, 0x01, b'b', 0xC0, 12]);
+        assert_eq!(read_dns_name(&msg, 12), None);
+        assert_eq!(read_dns_name(&msg, 16), None);
+        // Pointer beyond the message and a label running past it
+        assert_eq!(read_dns_name(&[0xC0, 0xFF], 0), None);
+        assert_eq!(read_dns_name(b"\x3fabc", 0), None);
+        assert_eq!(read_dns_name(&[], 0), None);
+        // A chain of pointers within the limit resolves
+        let mut chain: Vec<u8> = b"\x01a\x00".to_vec();
+        for i in 0..MAX_DNS_POINTERS {
+            let target = if i == 0 { 0 } else { 3 + (i - 1) * 2 };
+            chain.extend_from_slice(&[0xC0, target as u8]);
+        }
+        assert_eq!(read_dns_name(&chain, chain.len() - 2), Some((String::from("a"), chain.len())));
+    }
+
+    #[test]
+    fn malformed_dns_records_stop_the_section() {
+        let mut msg = dns_response();
+        // Answer count larger than the records present
+        msg[7] = 3;
+        let tree = dissect(&udp(53, 40000, &msg), LINK);
+        assert_eq!(find(&tree, "Answers").unwrap().children.len(), 1);
+        // Data length past the end of the message
+        let mut msg = dns_response();
+        let rdlen = msg.len() - 6;
+        msg[rdlen] = 0x10;
+        let tree = dissect(&udp(53, 40000, &msg), LINK);
+        assert!(find(&tree, "Answers").unwrap().children.is_empty());
+        // TXT string longer than its record
+        let mut msg: Vec<u8> = vec![0, 1, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+        msg.extend_from_slice(&[0, 0, 16, 0, 1, 0, 0, 0, 60, 0, 3, 0x20, b'h', b'i']);
+        let tree = dissect(&udp(53, 40000, &msg), LINK);
+        assert_eq!(find(&find(&tree, "Answers").unwrap().children, "Data").unwrap().value, "hi");
+        // Shorter than a header
+        let tree = dissect(&udp(53, 40000, &msg[..8]), LINK);
+        assert!(find(&tree, "Domain Name System").is_none());
+        assert_eq!(find(&tree, "Data").unwrap().len, 8);
+    }
+
+    #[test]
+    fn truncated_dns_is_dissected_within_bounds() {
+        dissect_prefixes(&udp(53, 40000, &dns_response()));
+        let mut tcp_msg: Vec<u8> = (dns_response().len() as u16).to_be_bytes().to_vec();
+        tcp_msg.extend_from_slice(&dns_response());
+        dissect_prefixes(&tcp(53, 40000, &tcp_msg));
+    }
+
+    fn dhcp_discover(options: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0u8; 240];
+        msg[0] = 1;
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        msg[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(options);
+        msg
+    }
+
+    fn dhcp_options(tree: &[DissectNode]) -> Vec<String> {
+        find(tree, "Dynamic Host Configuration Protocol")
+            .unwrap()
+            .children
+            .iter()
+            .filter(|node| node.name == "Option")
+            .map(|node| node.value.clone())
+            .collect()
+    }
+
+    #[test]
+    fn dhcp_options_are_dissected() {
+        let msg = dhcp_discover(&[53, 1, 1, 0, 12, 4, b'h', b'o', b's', b't', 255]);
+        let tree = dissect(&udp(68, 67, &msg), LINK);
+        assert_eq!(
+            dhcp_options(&tree),
+            vec!["(53) DHCP Message Type: Discover", "(12) Host Name: host", "(255) End"]
+        );
+        assert_eq!(find(&tree, "Client Hardware Address").unwrap().value, "02:00:00:00:00:01");
+    }
+
+    #[test]
+    fn malformed_dhcp() {
+        // Option length past the end of the datagram
+        let tree = dissect(&udp(68, 67, &dhcp_discover(&[53, 1, 1, 12, 40, b'h'])), LINK);
+        assert_eq!(dhcp_options(&tree), vec!["(53) DHCP Message Type: Discover"]);
+        // Option code without a length
+        let tree = dissect(&udp(68, 67, &dhcp_discover(&[53])), LINK);
+        assert!(dhcp_options(&tree).is_empty());
+        // Wrong magic cookie and a truncated fixed part are shown as data
+        let mut msg = dhcp_discover(&[255]);
+        msg[239] = 0;
+        let tree = dissect(&udp(68, 67, &msg), LINK);
+        assert!(find(&tree, "Dynamic Host Configuration Protocol").is_none());
+        let tree = dissect(&udp(68, 67, &dhcp_discover(&[])[..200]), LINK);
+        assert!(find(&tree, "Dynamic Host Configuration Protocol").is_none());
+        dissect_prefixes(&udp(68, 67, &dhcp_discover(&[53, 1, 1, 12, 4, b'h', b'o', b's', b't', 255])));
+    }
+
+    /// TLS record with a ClientHello carrying a server_name extension
+    fn client_hello(server_name_ext: &[u8]) -> Vec<u8> {
+        let mut body: Vec<u8> = vec![3, 3];
+        body.extend_from_slice(&[0xAA; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0, 2, 0x13, 0x01]);
+        body.extend_from_slice(&[1, 0]);
+        let mut extensions: Vec<u8> = vec![0, 0];
+        extensions.extend_from_slice(&(server_name_ext.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(server_name_ext);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+        let mut handshake: Vec<u8> = vec![1];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&body);
+        let mut record: Vec<u8> = vec![22, 3, 1];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    fn server_name_ext(name: &[u8], name_len: u16) -> Vec<u8> {
+        let mut ext: Vec<u8> = Vec::new();
+        ext.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+        ext.push(0);
+        ext.extend_from_slice(&name_len.to_be_bytes());
+        ext.extend_from_slice(name);
+        ext
+    }
+
+    #[test]
+    fn tls_client_hello_server_name() {
+        let tree = dissect(&tcp(50000, 443, &client_hello(&server_name_ext(b"example.com", 11))), LINK);
+        assert_eq!(find(&tree, "Handshake Protocol").unwrap().value, "Client Hello");
+        assert_eq!(find(&tree, "Cipher Suites").unwrap().value, "1 suites");
+        assert_eq!(find(&tree, "server_name").unwrap().value, "example.com");
+    }
+
+    #[test]
+    fn tls_server_name_overrunning_its_length() {
+        // Name length larger than the extension
+        let ext = server_name_ext(b"example.com", 200);
+        assert_eq!(tls_server_name(&ext), None);
+        let tree = dissect(&tcp(50000, 443, &client_hello(&ext)), LINK);
+        assert_eq!(find(&tree, "server_name").unwrap().value, "");
+        // Name type other than host_name and an extension too short for a header
+        assert_eq!(tls_server_name(&[0, 4, 1, 0, 1, b'a']), None);
+        assert_eq!(tls_server_name(&[0, 1, 0]), None);
+        // Extension length past the extensions block is clamped
+        let mut record = client_hello(&server_name_ext(b"example.com", 11));
+        let ext_len_pos = record.len() - server_name_ext(b"example.com", 11).len() - 2;
+        record[ext_len_pos..ext_len_pos + 2].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        let packet = tcp(50000, 443, &record);
+        let tree = dissect(&packet, LINK);
+        let server_name = find(&tree, "server_name").unwrap();
+        assert_eq!(server_name.offset + server_name.len, packet.len());
+        assert_eq!(server_name.value, "example.com");
+    }
+
+    #[test]
+    fn malformed_tls_client_hello() {
+        // Session ID length past the record
+        let mut record = client_hello(&server_name_ext(b"example.com", 11));
+        record[5 + 4 + 34] = 0xFF;
+        let tree = dissect(&tcp(50000, 443, &record), LINK);
+        assert!(find(&tree, "Session ID Length").is_some());
+        assert!(find(&tree, "Cipher Suites").is_none());
+        // Cipher suite length past the record
+        let mut record = client_hello(&server_name_ext(b"example.com", 11));
+        record[5 + 4 + 35] = 0xFF;
+        let tree = dissect(&tcp(50000, 443, &record), LINK);
+        assert!(find(&tree, "Cipher Suites").is_none());
+        // Record length larger than the segment
+        let mut record = client_hello(&server_name_ext(b"example.com", 11));
+        record[3..5].copy_from_slice(&0x4000u16.to_be_bytes());
+        let packet = tcp(50000, 443, &record);
+        assert_in_bounds(&dissect(&packet, LINK), packet.len());
+        dissect_prefixes(&tcp(50000, 443, &client_hello(&server_name_ext(b"example.com", 11))));
+    }
+}
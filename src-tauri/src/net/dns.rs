@@ -295,57 +295,39 @@ pub fn lookup_addr(addr: IpAddr) -> Vec<String> {
 pub fn start_dns_map_update(netstat_strage: &mut Arc<NetStatStrage>) {
     loop {
         let mut lookup_target_ips: Vec<IpAddr> = vec![];
-        // Lock the remote_hosts
-        let remote_hosts_inner = match netstat_strage.remote_hosts.try_lock() {
-            Ok(remote_hosts) => remote_hosts,
-            Err(e) => {
-                thread_log!(error, "[dns_map_update] lock error: {}", e);
-                continue;
-            }
-        };
+        // Remote hosts are collected by NetStatStrage::flush_shards
+        let known_remote_ips = netstat_strage.get_known_remote_ips();
         // Lock the reverse_dns_map
-        let reverse_dns_map_inner = match netstat_strage.reverse_dns_map.try_lock() {
+        let reverse_dns_map_inner = match netstat_strage.reverse_dns_map.lock() {
             Ok(reverse_dns_map) => reverse_dns_map,
             Err(e) => {
                 thread_log!(error, "[dns_map_update] lock error: {}", e);
+                std::thread::sleep(std::time::Duration::from_secs(8));
                 continue;
             }
         };
-        for (ip_addr, _remote_host) in remote_hosts_inner.iter() {
-            if !reverse_dns_map_inner.contains_key(ip_addr) {
-                lookup_target_ips.push(*ip_addr);
+        for ip_addr in known_remote_ips {
+            if !reverse_dns_map_inner.contains_key(&ip_addr) {
+                lookup_target_ips.push(ip_addr);
             }
         }
         // Drop the lock before calling lookup_ips
-        drop(remote_hosts_inner);
         drop(reverse_dns_map_inner);
-        let mut resolver = DnsResolver::new();
-        let dns_map = resolver.lookup_ips(lookup_target_ips);
-        // Lock the remote_hosts
-        let mut remote_hosts_inner = match netstat_strage.remote_hosts.try_lock() {
-            Ok(remote_hosts) => remote_hosts,
-            Err(e) => {
-                thread_log!(error, "[dns_map_update] lock error: {}", e);
-                continue;
-            }
-        };
-        // Lock the reverse_dns_map
-        let mut reverse_dns_map_inner = match netstat_strage.reverse_dns_map.try_lock() {
-            Ok(reverse_dns_map) => reverse_dns_map,
-            Err(e) => {
-                thread_log!(error, "[dns_map_update] lock error: {}", e);
-                continue;
-            }
-        };
-        for (ip_addr, hostname) in dns_map {
-            if let Some(remote_host) = remote_hosts_inner.get_mut(&ip_addr) {
-                remote_host.hostname = hostname.clone();
+        if lookup_target_ips.len() > 0 {
+            let mut resolver = DnsResolver::new();
+            let dns_map = resolver.lookup_ips(lookup_target_ips);
+            // Lock the reverse_dns_map
+            match netstat_strage.reverse_dns_map.lock() {
+                Ok(mut reverse_dns_map) => {
+                    for (ip_addr, hostname) in dns_map {
+                        reverse_dns_map.insert(ip_addr, hostname);
+                    }
+                }
+                Err(e) => {
+                    thread_log!(error, "[dns_map_update] lock error: {}", e);
+                }
             }
-            reverse_dns_map_inner.insert(ip_addr, hostname);
         }
-        // Drop the lock
-        drop(remote_hosts_inner);
-        drop(reverse_dns_map_inner);
         std::thread::sleep(std::time::Duration::from_secs(8));
     }
 }
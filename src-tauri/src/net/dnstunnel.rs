@@ -0,0 +1,629 @@
+//! DNS tunneling and exfiltration heuristics per querying process and base domain.
+use crate::net::names::{self, DNS_PORT};
+use crate::net::security::{SecurityEvent, SecurityEventKind, Severity};
+use crate::net::socket::{LocalSocket, SocketProcess, TransportProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+pub const DNS_TYPE_NULL: u16 = 10;
+pub const DNS_TYPE_TXT: u16 = 16;
+/// A query waits this long for its process to be resolved
+const PROCESS_RESOLVE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Observations waiting for their process, oldest dropped first
+const MAX_PENDING: usize = 65536;
+/// Queries kept per domain in the window, oldest dropped first
+const MAX_QUERIES_PER_DOMAIN: usize = 4096;
+/// Domains tracked before the least recently queried are dropped
+const MAX_DOMAINS: usize = 4096;
+/// Sample queries listed in the evidence of an event
+const MAX_SAMPLE_QUERIES: usize = 5;
+/// Subdomains shorter than this are not scored for entropy
+const MIN_ENTROPY_LENGTH: usize = 16;
+/// Second level labels registered under a country code, as in example.co.uk
+const COUNTRY_SECOND_LEVELS: [&str; 9] = ["co", "com", "net", "org", "ac", "gov", "edu", "ne", "or"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DnsTunnelConfig {
+    pub enabled: bool,
+    /// Length of the sliding window
+    pub window_secs: u64,
+    /// Labels longer than this are suspicious. DNS allows up to 63
+    pub max_label_length: usize,
+    /// Shannon entropy in bits per character above which a subdomain looks encoded
+    pub entropy_threshold: f64,
+    /// Distinct subdomains of one base domain within the window
+    pub unique_subdomain_threshold: usize,
+    /// TXT and NULL queries for one base domain within the window
+    pub txt_null_threshold: usize,
+    /// Response bytes for one base domain within the window
+    pub response_bytes_threshold: u64,
+    /// Indicators needed to flag a domain
+    pub min_indicators: usize,
+    /// Base domains never flagged
+    pub allowlist: Vec<String>,
+}
+
+impl DnsTunnelConfig {
+    pub fn new() -> DnsTunnelConfig {
+        DnsTunnelConfig {
+            enabled: true,
+            window_secs: 300,
+            max_label_length: 40,
+            entropy_threshold: 3.8,
+            unique_subdomain_threshold: 60,
+            txt_null_threshold: 20,
+            response_bytes_threshold: 256 * 1024,
+            min_indicators: 2,
+            allowlist: vec![
+                "in-addr.arpa".to_string(),
+                "ip6.arpa".to_string(),
+                "local".to_string(),
+                "akamaiedge.net".to_string(),
+                "cloudfront.net".to_string(),
+                "amazonaws.com".to_string(),
+            ],
+        }
+    }
+}
+
+impl Default for DnsTunnelConfig {
+    fn default() -> Self {
+        DnsTunnelConfig::new()
+    }
+}
+
+/// A DNS query or response of a local client
+#[derive(Debug, Clone)]
+pub struct DnsObservation {
+    pub name: String,
+    pub qtype: u16,
+    pub response: bool,
+    /// DNS message size
+    pub bytes: u64,
+    pub interface_name: String,
+    pub local_ip_addr: IpAddr,
+    pub local_port: u16,
+    /// DNS server
+    pub remote_ip_addr: IpAddr,
+    pub protocol: TransportProtocol,
+    pub time: Instant,
+}
+
+/// DNS messages sent to or received from a DNS server by a local client
+pub fn observe_dns(
+    payload: &[u8],
+    protocol: TransportProtocol,
+    interface_name: &str,
+    local_ip_addr: IpAddr,
+    local_port: u16,
+    remote_ip_addr: IpAddr,
+    remote_port: u16,
+) -> Vec<DnsObservation> {
+    if remote_port != DNS_PORT || payload.is_empty() {
+        return Vec::new();
+    }
+    // DNS over TCP has a length prefix
+    let msg = match protocol {
+        TransportProtocol::TCP => payload.get(2..).unwrap_or(&[]),
+        TransportProtocol::UDP => payload,
+    };
+    let message = match names::parse_dns_message(msg) {
+        Some(message) => message,
+        None => return Vec::new(),
+    };
+    let now = Instant::now();
+    let question_count = message.questions.len().max(1) as u64;
+    message
+        .questions
+        .into_iter()
+        .map(|question| DnsObservation {
+            name: question.name.trim_end_matches('.').to_lowercase(),
+            qtype: question.qtype,
+            response: message.response,
+            bytes: msg.len() as u64 / question_count,
+            interface_name: interface_name.to_string(),
+            local_ip_addr: local_ip_addr,
+            local_port: local_port,
+            remote_ip_addr: remote_ip_addr,
+            protocol: protocol,
+            time: now,
+        })
+        .collect()
+}
+
+/// Registrable part of a name, as example.com for a.b.example.com
+pub fn base_domain(name: &str) -> String {
+    let labels: Vec<&str> = name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()).collect();
+    let count = if labels.len() >= 3
+        && labels[labels.len() - 1].len() == 2
+        && COUNTRY_SECOND_LEVELS.contains(&labels[labels.len() - 2])
+    {
+        3
+    } else {
+        2
+    };
+    labels[labels.len().saturating_sub(count)..].join(".")
+}
+
+/// Shannon entropy in bits per character
+pub fn shannon_entropy(text: &str) -> f64 {
+    if text.is_empty() {
+        return 0.0;
+    }
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_insert(0) += 1;
+    }
+    let len = text.chars().count() as f64;
+    counts
+        .values()
+        .map(|count| {
+            let p = *count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// Process and base domain of a group of queries
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DnsDomainKey {
+    /// None when the process was not resolved
+    pub process_name: Option<String>,
+    pub base_domain: String,
+}
+
+/// Heuristics of one process and base domain over the window
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DnsDomainInfo {
+    pub key: DnsDomainKey,
+    pub queries: usize,
+    pub unique_subdomains: usize,
+    /// Unique subdomains per minute
+    pub unique_subdomain_rate: f64,
+    pub longest_label: usize,
+    /// Highest entropy of a subdomain long enough to be scored
+    pub max_entropy: f64,
+    pub txt_null_queries: usize,
+    pub response_bytes: u64,
+    /// Names of the triggered heuristics
+    pub indicators: Vec<String>,
+    /// Queries with the longest or most random subdomains
+    pub sample_queries: Vec<String>,
+    pub allowlisted: bool,
+}
+
+#[derive(Debug, Clone)]
+struct DnsQuery {
+    time: Instant,
+    subdomain: String,
+    qtype: u16,
+}
+
+#[derive(Debug, Clone)]
+struct DnsDomainStats {
+    local_ip_addr: IpAddr,
+    queries: VecDeque<DnsQuery>,
+    responses: VecDeque<(Instant, u64)>,
+    last_seen: Instant,
+    /// A query was added since the last evaluation
+    changed: bool,
+}
+
+/// Groups DNS queries per process and base domain
+#[derive(Debug, Clone)]
+pub struct DnsTunnelDetector {
+    config: DnsTunnelConfig,
+    /// Observations waiting for their process
+    pending: VecDeque<DnsObservation>,
+    domains: HashMap<DnsDomainKey, DnsDomainStats>,
+}
+
+impl DnsTunnelDetector {
+    pub fn new(config: DnsTunnelConfig) -> DnsTunnelDetector {
+        DnsTunnelDetector {
+            config: config,
+            pending: VecDeque::new(),
+            domains: HashMap::new(),
+        }
+    }
+    pub fn set_config(&mut self, config: DnsTunnelConfig) {
+        if !config.enabled {
+            self.pending.clear();
+            self.domains.clear();
+        }
+        self.config = config;
+    }
+    pub fn add(&mut self, observations: Vec<DnsObservation>) {
+        if !self.config.enabled {
+            return;
+        }
+        for observation in observations {
+            if self.pending.len() >= MAX_PENDING {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(observation);
+        }
+    }
+    /// Attribute the waiting observations to their processes
+    pub fn resolve(&mut self, local_socket_map: &HashMap<LocalSocket, SocketProcess>) {
+        let now = Instant::now();
+        let mut waiting: VecDeque<DnsObservation> = VecDeque::new();
+        while let Some(observation) = self.pending.pop_front() {
+            let local_socket = LocalSocket::new(
+                observation.interface_name.clone(),
+                observation.local_port,
+                observation.protocol,
+            );
+            let process_name = local_socket_map
+                .get(&local_socket)
+                .and_then(|socket_process| socket_process.process.as_ref())
+                .map(|process| process.name.clone());
+            if process_name.is_none() && now.duration_since(observation.time) < PROCESS_RESOLVE_TIMEOUT {
+                waiting.push_back(observation);
+                continue;
+            }
+            self.record(process_name, observation);
+        }
+        self.pending = waiting;
+        let window = Duration::from_secs(self.config.window_secs.max(1));
+        self.domains.retain(|_, stats| {
+            while stats.queries.front().map_or(false, |q| now.duration_since(q.time) >= window) {
+                stats.queries.pop_front();
+            }
+            while stats.responses.front().map_or(false, |(time, _)| now.duration_since(*time) >= window) {
+                stats.responses.pop_front();
+            }
+            !stats.queries.is_empty() || !stats.responses.is_empty()
+        });
+        if self.domains.len() > MAX_DOMAINS {
+            let mut last_seen: Vec<Instant> = self.domains.values().map(|stats| stats.last_seen).collect();
+            last_seen.sort();
+            let cutoff = last_seen[self.domains.len() - MAX_DOMAINS];
+            self.domains.retain(|_, stats| stats.last_seen >= cutoff);
+        }
+    }
+    fn record(&mut self, process_name: Option<String>, observation: DnsObservation) {
+        let base_domain = base_domain(&observation.name);
+        if base_domain.is_empty() {
+            return;
+        }
+        let subdomain = observation
+            .name
+            .strip_suffix(&base_domain)
+            .unwrap_or("")
+            .trim_end_matches('.')
+            .to_string();
+        let key = DnsDomainKey {
+            process_name: process_name,
+            base_domain: base_domain,
+        };
+        let stats = self.domains.entry(key).or_insert_with(|| DnsDomainStats {
+            local_ip_addr: observation.local_ip_addr,
+            queries: VecDeque::new(),
+            responses: VecDeque::new(),
+            last_seen: observation.time,
+            changed: false,
+        });
+        stats.last_seen = stats.last_seen.max(observation.time);
+        if observation.response {
+            stats.responses.push_back((observation.time, observation.bytes));
+            return;
+        }
+        if stats.queries.len() >= MAX_QUERIES_PER_DOMAIN {
+            stats.queries.pop_front();
+        }
+        stats.queries.push_back(DnsQuery {
+            time: observation.time,
+            subdomain: subdomain,
+            qtype: observation.qtype,
+        });
+        stats.changed = true;
+    }
+    fn is_allowlisted(&self, base_domain: &str) -> bool {
+        self.config.allowlist.iter().any(|entry| {
+            let entry = entry.trim().trim_end_matches('.').to_lowercase();
+            base_domain == entry || base_domain.ends_with(&format!(".{}", entry))
+        })
+    }
+    fn domain_info(&self, key: &DnsDomainKey, stats: &DnsDomainStats) -> DnsDomainInfo {
+        let config = &self.config;
+        let unique: HashSet<&str> = stats.queries.iter().map(|q| q.subdomain.as_str()).collect();
+        let longest_label = stats
+            .queries
+            .iter()
+            .flat_map(|q| q.subdomain.split('.'))
+            .map(|label| label.len())
+            .max()
+            .unwrap_or(0);
+        // Entropy of the subdomain without dots, scored once per distinct subdomain
+        let mut scored: Vec<(f64, &str)> = unique
+            .iter()
+            .filter(|subdomain| subdomain.len() >= MIN_ENTROPY_LENGTH)
+            .map(|subdomain| (shannon_entropy(&subdomain.replace('.', "")), *subdomain))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let max_entropy = scored.first().map_or(0.0, |(entropy, _)| *entropy);
+        let txt_null_queries = stats
+            .queries
+            .iter()
+            .filter(|q| q.qtype == DNS_TYPE_TXT || q.qtype == DNS_TYPE_NULL)
+            .count();
+        let response_bytes: u64 = stats.responses.iter().map(|(_, bytes)| bytes).sum();
+        // Rate over the observed span, at least one minute
+        let span_secs = match (stats.queries.front(), stats.queries.back()) {
+            (Some(first), Some(last)) => last.time.duration_since(first.time).as_secs_f64(),
+            _ => 0.0,
+        };
+        let unique_subdomain_rate = unique.len() as f64 / (span_secs / 60.0).max(1.0);
+        let mut indicators: Vec<String> = Vec::new();
+        if longest_label > config.max_label_length {
+            indicators.push(format!("Long label ({} characters)", longest_label));
+        }
+        if max_entropy > config.entropy_threshold {
+            indicators.push(format!("High entropy ({:.2} bits/char)", max_entropy));
+        }
+        if unique.len() >= config.unique_subdomain_threshold.max(1) {
+            indicators.push(format!("{} unique subdomains ({:.1}/min)", unique.len(), unique_subdomain_rate));
+        }
+        if txt_null_queries >= config.txt_null_threshold.max(1) {
+            indicators.push(format!("{} TXT/NULL queries", txt_null_queries));
+        }
+        if response_bytes >= config.response_bytes_threshold.max(1) {
+            indicators.push(format!("{} response bytes", response_bytes));
+        }
+        // Most random subdomains first, then the longest
+        let mut sample_queries: Vec<String> = scored
+            .iter()
+            .map(|(_, subdomain)| format!("{}.{}", subdomain, key.base_domain))
+            .take(MAX_SAMPLE_QUERIES)
+            .collect();
+        if sample_queries.is_empty() {
+            let mut by_length: Vec<&str> = unique.iter().filter(|s| !s.is_empty()).cloned().collect();
+            by_length.sort_by(|a, b| b.len().cmp(&a.len()));
+            sample_queries = by_length
+                .iter()
+                .take(MAX_SAMPLE_QUERIES)
+                .map(|subdomain| format!("{}.{}", subdomain, key.base_domain))
+                .collect();
+        }
+        DnsDomainInfo {
+            key: key.clone(),
+            queries: stats.queries.len(),
+            unique_subdomains: unique.len(),
+            unique_subdomain_rate: unique_subdomain_rate,
+            longest_label: longest_label,
+            max_entropy: max_entropy,
+            txt_null_queries: txt_null_queries,
+            response_bytes: response_bytes,
+            indicators: indicators,
+            sample_queries: sample_queries,
+            allowlisted: self.is_allowlisted(&key.base_domain),
+        }
+    }
+    /// Domains in the window, most indicators first
+    pub fn get_domains(&self) -> Vec<DnsDomainInfo> {
+        let mut domains: Vec<DnsDomainInfo> = self
+            .domains
+            .iter()
+            .map(|(key, stats)| self.domain_info(key, stats))
+            .collect();
+        domains.sort_by(|a, b| {
+            b.indicators
+                .len()
+                .cmp(&a.indicators.len())
+                .then(b.unique_subdomains.cmp(&a.unique_subdomains))
+        });
+        domains
+    }
+    /// Domains with enough indicators. Only domains with new queries are checked
+    pub fn evaluate(&mut self) -> Vec<SecurityEvent> {
+        let mut events: Vec<SecurityEvent> = Vec::new();
+        if !self.config.enabled {
+            return events;
+        }
+        for (key, stats) in self.domains.iter() {
+            if !stats.changed {
+                continue;
+            }
+            let info = self.domain_info(key, stats);
+            if info.allowlisted || info.indicators.len() < self.config.min_indicators.max(1) {
+                continue;
+            }
+            let mut event = SecurityEvent::new(
+                SecurityEventKind::DnsTunneling,
+                if info.indicators.len() >= 3 { Severity::High } else { Severity::Medium },
+                format!(
+                    "{} sent {} queries for {} subdomains of {} in {}s: {}",
+                    key.process_name.clone().unwrap_or("Unknown process".to_string()),
+                    info.queries,
+                    info.unique_subdomains,
+                    key.base_domain,
+                    self.config.window_secs,
+                    info.indicators.join(", ")
+                ),
+            );
+            event.target_ip_addr = Some(stats.local_ip_addr);
+            event.domain = Some(key.base_domain.clone());
+            event.evidence = vec![
+                format!("Queries: {}", info.queries),
+                format!("Unique subdomains: {} ({:.1}/min)", info.unique_subdomains, info.unique_subdomain_rate),
+                format!("Longest label: {}", info.longest_label),
+                format!("Highest entropy: {:.2} bits/char", info.max_entropy),
+                format!("TXT/NULL queries: {}", info.txt_null_queries),
+                format!("Response bytes: {}", info.response_bytes),
+            ];
+            event
+                .evidence
+                .extend(info.sample_queries.iter().map(|query| format!("Sample: {}", query)));
+            events.push(event);
+        }
+        self.domains.values_mut().for_each(|stats| stats.changed = false);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const ENCODED_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    fn observation(name: &str, qtype: u16, response: bool, bytes: u64) -> DnsObservation {
+        DnsObservation {
+            name: name.to_string(),
+            qtype: qtype,
+            response: response,
+            bytes: bytes,
+            interface_name: String::from("eth0"),
+            local_ip_addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+            local_port: 53000,
+            remote_ip_addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            protocol: TransportProtocol::UDP,
+            time: Instant::now(),
+        }
+    }
+
+    /// Base32 looking labels from a fixed seed, as an exfiltration tool would send
+    fn encoded_labels(count: usize, length: usize) -> Vec<String> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..count)
+            .map(|_| {
+                (0..length)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ENCODED_ALPHABET[(state >> 59) as usize] as char
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn detector_with(queries: Vec<DnsObservation>) -> DnsTunnelDetector {
+        let mut detector = DnsTunnelDetector::new(DnsTunnelConfig::new());
+        for query in queries {
+            detector.record(Some(String::from("exfil")), query);
+        }
+        detector
+    }
+
+    #[test]
+    fn base_domain_keeps_country_second_level() {
+        assert_eq!(base_domain("a.b.example.com"), "example.com");
+        assert_eq!(base_domain("www.example.co.uk"), "example.co.uk");
+        assert_eq!(base_domain("x.y.example.com.au."), "example.com.au");
+        assert_eq!(base_domain("mail.example.ne.jp"), "example.ne.jp");
+        assert_eq!(base_domain("example.co.uk"), "example.co.uk");
+        // Only two letter top level domains have registered second levels
+        assert_eq!(base_domain("a.example.co.com"), "co.com");
+        assert_eq!(base_domain("a.example.io"), "example.io");
+        assert_eq!(base_domain("co.uk"), "co.uk");
+        assert_eq!(base_domain("localhost"), "localhost");
+        assert_eq!(base_domain("a..example.com"), "example.com");
+        assert_eq!(base_domain(""), "");
+    }
+
+    #[test]
+    fn shannon_entropy_of_known_strings() {
+        assert_eq!(shannon_entropy(""), 0.0);
+        assert_eq!(shannon_entropy("aaaaaaaa"), 0.0);
+        assert_eq!(shannon_entropy("abab"), 1.0);
+        assert_eq!(shannon_entropy("abcd"), 2.0);
+        assert_eq!(shannon_entropy("0123456789abcdef"), 4.0);
+        assert!((shannon_entropy("aab") - 0.9182958340544896).abs() < 1e-12);
+        // Characters, not bytes
+        assert_eq!(shannon_entropy("éé"), 0.0);
+        let encoded = encoded_labels(1, 50).remove(0);
+        assert!(shannon_entropy(&encoded) > DnsTunnelConfig::new().entropy_threshold);
+        assert!(shannon_entropy("mailserverbackup") < DnsTunnelConfig::new().entropy_threshold);
+    }
+
+    #[test]
+    fn encoded_subdomains_are_flagged() {
+        let queries = encoded_labels(60, 50)
+            .iter()
+            .map(|label| observation(&format!("{}.t.example.com", label), 1, false, 120))
+            .collect();
+        let mut detector = detector_with(queries);
+        let domains = detector.get_domains();
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].key.base_domain, "example.com");
+        assert_eq!(domains[0].unique_subdomains, 60);
+        assert_eq!(domains[0].longest_label, 50);
+        assert_eq!(domains[0].indicators.len(), 3, "{:?}", domains[0].indicators);
+        assert_eq!(domains[0].sample_queries.len(), MAX_SAMPLE_QUERIES);
+        let events = detector.evaluate();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].severity, Severity::High);
+        assert_eq!(events[0].domain, Some(String::from("example.com")));
+        // Nothing new since the last evaluation
+        assert!(detector.evaluate().is_empty());
+    }
+
+    #[test]
+    fn ordinary_lookups_are_not_flagged() {
+        let queries = (0..200)
+            .map(|i| observation(if i % 2 == 0 { "www.example.org" } else { "mail.example.org" }, 1, false, 60))
+            .collect();
+        let mut detector = detector_with(queries);
+        let domains = detector.get_domains();
+        assert_eq!(domains[0].unique_subdomains, 2);
+        assert!(domains[0].indicators.is_empty());
+        assert_eq!(domains[0].max_entropy, 0.0);
+        assert!(detector.evaluate().is_empty());
+    }
+
+    #[test]
+    fn thresholds_and_minimum_indicators() {
+        let config = DnsTunnelConfig::new();
+        // One short of the TXT threshold, then reaching it
+        let mut queries: Vec<DnsObservation> = (0..config.txt_null_threshold - 1)
+            .map(|i| observation(&format!("k{}.example.net", i), DNS_TYPE_TXT, false, 80))
+            .collect();
+        let mut detector = detector_with(queries.clone());
+        assert!(detector.get_domains()[0].indicators.is_empty());
+        queries.push(observation("k.example.net", DNS_TYPE_NULL, false, 80));
+        let mut detector = detector_with(queries.clone());
+        assert_eq!(detector.get_domains()[0].indicators, vec![format!("{} TXT/NULL queries", config.txt_null_threshold)]);
+        // A single indicator is below min_indicators
+        assert!(detector.evaluate().is_empty());
+        // Large responses add the second one
+        queries.push(observation("k.example.net", DNS_TYPE_TXT, true, config.response_bytes_threshold));
+        let mut detector = detector_with(queries);
+        let events = detector.evaluate();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].severity, Severity::Medium);
+        // A label at the limit is not long
+        let label = "a".repeat(config.max_label_length);
+        let detector = detector_with(vec![observation(&format!("{}.example.net", label), 1, false, 80)]);
+        assert!(detector.get_domains()[0].indicators.is_empty());
+    }
+
+    #[test]
+    fn short_subdomains_are_not_scored_for_entropy() {
+        let queries = encoded_labels(10, MIN_ENTROPY_LENGTH - 1)
+            .iter()
+            .map(|label| observation(&format!("{}.example.com", label), 1, false, 60))
+            .collect();
+        let detector = detector_with(queries);
+        let domains = detector.get_domains();
+        assert_eq!(domains[0].max_entropy, 0.0);
+        // Longest subdomains are sampled instead
+        assert_eq!(domains[0].sample_queries.len(), MAX_SAMPLE_QUERIES);
+    }
+
+    #[test]
+    fn allowlisted_domains_are_not_flagged() {
+        let queries = encoded_labels(60, 50)
+            .iter()
+            .map(|label| observation(&format!("{}.d111111abcdef8.cloudfront.net", label), 1, false, 120))
+            .collect();
+        let mut detector = detector_with(queries);
+        let domains = detector.get_domains();
+        assert!(domains[0].allowlisted);
+        assert!(domains[0].indicators.len() >= 2);
+        assert!(detector.evaluate().is_empty());
+    }
+}
@@ -0,0 +1,523 @@
+use crate::net::packet::PacketFrame;
+use nex::packet::frame::{Frame, ParseOption, TransportLayer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+/// Incomplete datagrams are dropped after this duration (same as Linux ipfrag_time)
+pub const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of datagrams tracked at the same time
+const MAX_DATAGRAMS: usize = 1024;
+/// Maximum number of fragments accepted for one datagram
+const MAX_FRAGMENTS_PER_DATAGRAM: usize = 64;
+/// Maximum number of frames held while waiting for a first fragment
+const MAX_PENDING_FRAMES: usize = 4096;
+/// Maximum reassembled payload length
+const MAX_DATAGRAM_LEN: u32 = 65535;
+
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+/// Maximum number of IPv6 extension headers walked before the fragment header
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// Fragment of an IPv4 or IPv6 datagram
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FragmentInfo {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// IPv4 identification or IPv6 fragment identification
+    pub id: u32,
+    /// Upper layer protocol of the datagram
+    pub protocol: u8,
+    /// Offset of this fragment in the datagram payload, in bytes
+    pub offset: u32,
+    /// Length of the fragment payload, in bytes
+    pub length: u32,
+    pub more_fragments: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src: IpAddr,
+    dst: IpAddr,
+    id: u32,
+    protocol: u8,
+}
+
+impl FragmentKey {
+    fn from_info(info: &FragmentInfo) -> FragmentKey {
+        FragmentKey {
+            src: info.src,
+            dst: info.dst,
+            id: info.id,
+            protocol: info.protocol,
+        }
+    }
+}
+
+/// Parse the fragment fields of an IP packet. Returns None if the packet is not fragmented
+pub fn parse_fragment(ip_packet: &[u8]) -> Option<FragmentInfo> {
+    match ip_packet.first()? >> 4 {
+        4 => {
+            if ip_packet.len() < 20 {
+                return None;
+            }
+            let flags_offset = read_u16(ip_packet, 6);
+            let more_fragments = flags_offset & 0x2000 != 0;
+            let offset = ((flags_offset & 0x1FFF) as u32) * 8;
+            if !more_fragments && offset == 0 {
+                return None;
+            }
+            let header_len = ((ip_packet[0] & 0x0F) as u32) * 4;
+            let total_len = read_u16(ip_packet, 2) as u32;
+            Some(FragmentInfo {
+                src: IpAddr::V4(Ipv4Addr::new(ip_packet[12], ip_packet[13], ip_packet[14], ip_packet[15])),
+                dst: IpAddr::V4(Ipv4Addr::new(ip_packet[16], ip_packet[17], ip_packet[18], ip_packet[19])),
+                id: read_u16(ip_packet, 4) as u32,
+                protocol: ip_packet[9],
+                offset: offset,
+                length: total_len.saturating_sub(header_len),
+                more_fragments: more_fragments,
+            })
+        }
+        6 => {
+            let (fragment_offset, _) = find_ipv6_fragment_header(ip_packet)?;
+            let flags_offset = read_u16(ip_packet, fragment_offset + 2);
+            let payload_len = read_u16(ip_packet, 4) as u32;
+            // Bytes between the end of the fixed header and the end of the fragment header
+            let headers_len = (fragment_offset + 8 - 40) as u32;
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&ip_packet[8..24]);
+            dst.copy_from_slice(&ip_packet[24..40]);
+            Some(FragmentInfo {
+                src: IpAddr::V6(Ipv6Addr::from(src)),
+                dst: IpAddr::V6(Ipv6Addr::from(dst)),
+                id: read_u32(ip_packet, fragment_offset + 4),
+                protocol: ip_packet[fragment_offset],
+                offset: (flags_offset & 0xFFF8) as u32,
+                length: payload_len.saturating_sub(headers_len),
+                more_fragments: flags_offset & 0x0001 != 0,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Find the IPv6 fragment header.
+/// Returns its offset and the offset of the next header field that points to it
+fn find_ipv6_fragment_header(ip_packet: &[u8]) -> Option<(usize, usize)> {
+    if ip_packet.len() < 40 {
+        return None;
+    }
+    let mut next_header = ip_packet[6];
+    let mut next_header_pos = 6;
+    let mut offset = 40;
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        match next_header {
+            IPV6_FRAGMENT => {
+                if ip_packet.len() < offset + 8 {
+                    return None;
+                }
+                return Some((offset, next_header_pos));
+            }
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS => {
+                if ip_packet.len() < offset + 8 {
+                    return None;
+                }
+                next_header = ip_packet[offset];
+                next_header_pos = offset;
+                offset += (ip_packet[offset + 1] as usize + 1) * 8;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Fix up the transport layer of a fragment parsed by nex.
+/// Non-first fragments have no transport header, and the transport of a first IPv6 fragment
+/// is parsed again without the fragment header.
+pub fn fix_transport(frame: &mut Frame, ip_packet: &[u8], info: &FragmentInfo) {
+    if info.offset != 0 {
+        frame.transport = None;
+        return;
+    }
+    if ip_packet[0] >> 4 != 6 {
+        return;
+    }
+    let (fragment_offset, next_header_pos) = match find_ipv6_fragment_header(ip_packet) {
+        Some(v) => v,
+        None => return,
+    };
+    let mut unfragmented: Vec<u8> = Vec::with_capacity(ip_packet.len() - 8);
+    unfragmented.extend_from_slice(&ip_packet[..fragment_offset]);
+    unfragmented.extend_from_slice(&ip_packet[fragment_offset + 8..]);
+    unfragmented[next_header_pos] = info.protocol;
+    let payload_len = read_u16(ip_packet, 4).saturating_sub(8);
+    unfragmented[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    let mut option = ParseOption::default();
+    option.from_ip_packet = true;
+    option.offset = 0;
+    frame.transport = Frame::from_bytes(&unfragmented, option).transport;
+}
+
+/// Fragment table counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FragmentStats {
+    /// Datagrams whose fragments were all seen
+    pub reassembled: u64,
+    /// Datagrams dropped from the table before all fragments were seen
+    pub timeouts: u64,
+    /// Datagrams discarded because of overlapping or oversized fragments
+    pub overlaps: u64,
+    /// Fragments accounted without ports because a table limit was reached
+    pub over_limit: u64,
+}
+
+struct FragmentEntry {
+    first_seen: Instant,
+    /// Transport layer of the first fragment
+    transport: Option<TransportLayer>,
+    /// Received payload ranges, sorted by start
+    ranges: Vec<(u32, u32)>,
+    /// Payload length, known once the last fragment is seen
+    total_len: Option<u32>,
+    fragments: usize,
+    /// Frames waiting for the first fragment
+    pending: Vec<PacketFrame>,
+    /// Overlapping or oversized. Remaining fragments are accounted without ports
+    discarded: bool,
+}
+
+impl FragmentEntry {
+    fn new(now: Instant) -> FragmentEntry {
+        FragmentEntry {
+            first_seen: now,
+            transport: None,
+            ranges: Vec::new(),
+            total_len: None,
+            fragments: 0,
+            pending: Vec::new(),
+            discarded: false,
+        }
+    }
+    /// Record a fragment range. Returns false if it overlaps a different range
+    fn add_range(&mut self, start: u32, end: u32) -> bool {
+        for (s, e) in &self.ranges {
+            if *s == start && *e == end {
+                // Retransmitted duplicate
+                return true;
+            }
+            if start < *e && *s < end {
+                return false;
+            }
+        }
+        let pos = self.ranges.partition_point(|(s, _)| *s < start);
+        self.ranges.insert(pos, (start, end));
+        true
+    }
+    fn is_complete(&self) -> bool {
+        let total_len = match self.total_len {
+            Some(len) => len,
+            None => return false,
+        };
+        let mut covered = 0;
+        for (s, e) in &self.ranges {
+            if *s != covered {
+                return false;
+            }
+            covered = *e;
+        }
+        covered == total_len
+    }
+}
+
+/// Bounded table that credits every fragment of a datagram to the 5-tuple of its first fragment.
+/// Owned by one capture thread. Fragments received before the first one are held until it arrives.
+pub struct FragmentTable {
+    entries: HashMap<FragmentKey, FragmentEntry>,
+    pending_frames: usize,
+    stats: FragmentStats,
+}
+
+impl FragmentTable {
+    pub fn new() -> FragmentTable {
+        FragmentTable {
+            entries: HashMap::new(),
+            pending_frames: 0,
+            stats: FragmentStats::default(),
+        }
+    }
+    /// Pass the frame to f once its transport layer is known.
+    /// Unfragmented frames are passed through immediately.
+    pub fn process<F: FnMut(PacketFrame)>(&mut self, mut frame: PacketFrame, mut f: F) {
+        let info = match &frame.fragment {
+            Some(info) => info.clone(),
+            None => {
+                f(frame);
+                return;
+            }
+        };
+        let key = FragmentKey::from_info(&info);
+        if !self.entries.contains_key(&key) && self.entries.len() >= MAX_DATAGRAMS {
+            self.expire(Instant::now(), &mut f);
+            if self.entries.len() >= MAX_DATAGRAMS {
+                self.stats.over_limit += 1;
+                frame.transport = None;
+                f(frame);
+                return;
+            }
+        }
+        let entry = self
+            .entries
+            .entry(key)
+            .or_insert_with(|| FragmentEntry::new(Instant::now()));
+        if entry.discarded {
+            frame.transport = None;
+            f(frame);
+            return;
+        }
+        let start = info.offset;
+        let end = info.offset.saturating_add(info.length);
+        entry.fragments += 1;
+        let mut valid = end <= MAX_DATAGRAM_LEN
+            && entry.fragments <= MAX_FRAGMENTS_PER_DATAGRAM
+            && entry.add_range(start, end);
+        if valid && !info.more_fragments {
+            if entry.total_len.is_some() && entry.total_len != Some(end) {
+                valid = false;
+            }
+            entry.total_len = Some(end);
+        }
+        if !valid {
+            // Do not trust any transport header of this datagram
+            entry.discarded = true;
+            entry.transport = None;
+            self.pending_frames -= entry.pending.len();
+            for pending in entry.pending.drain(..) {
+                f(pending);
+            }
+            self.stats.overlaps += 1;
+            frame.transport = None;
+            f(frame);
+            return;
+        }
+        if start == 0 {
+            entry.transport = frame.transport.clone();
+        }
+        match &entry.transport {
+            Some(transport) => {
+                frame.transport = Some(transport.clone());
+                f(frame);
+                self.pending_frames -= entry.pending.len();
+                for mut pending in entry.pending.drain(..) {
+                    pending.transport = Some(transport.clone());
+                    f(pending);
+                }
+            }
+            None => {
+                if start == 0 {
+                    // First fragment without a parsable transport header
+                    f(frame);
+                } else if self.pending_frames < MAX_PENDING_FRAMES {
+                    entry.pending.push(frame);
+                    self.pending_frames += 1;
+                } else {
+                    self.stats.over_limit += 1;
+                    f(frame);
+                }
+            }
+        }
+        if entry.is_complete() {
+            self.stats.reassembled += 1;
+            let entry = self.entries.remove(&key);
+            if let Some(mut entry) = entry {
+                self.pending_frames -= entry.pending.len();
+                for pending in entry.pending.drain(..) {
+                    f(pending);
+                }
+            }
+        }
+    }
+    /// Drop datagrams older than FRAGMENT_TIMEOUT.
+    /// Held frames are passed to f without ports and are counted at the IP layer only
+    pub fn expire<F: FnMut(PacketFrame)>(&mut self, now: Instant, mut f: F) {
+        let expired: Vec<FragmentKey> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.first_seen) >= FRAGMENT_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            if let Some(entry) = self.entries.remove(&key) {
+                if !entry.discarded {
+                    self.stats.timeouts += 1;
+                }
+                self.pending_frames -= entry.pending.len();
+                for pending in entry.pending {
+                    f(pending);
+                }
+            }
+        }
+    }
+    /// Take the counters and reset them
+    pub fn take_stats(&mut self) -> FragmentStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::decap::{self, TunnelAttribution};
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+    const DST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+
+    /// IPv4 fragment of a UDP datagram from SRC:5353 to DST:4000. offset is in bytes
+    fn fragment(id: u16, offset: u16, more: bool, payload_len: usize) -> PacketFrame {
+        let mut payload: Vec<u8> = Vec::new();
+        if offset == 0 {
+            payload.extend_from_slice(&5353u16.to_be_bytes());
+            payload.extend_from_slice(&4000u16.to_be_bytes());
+            payload.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        payload.resize(payload_len, 0xAA);
+        let flags_offset = offset / 8 | if more { 0x2000 } else { 0 };
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00, 0x45, 0x00];
+        data.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&id.to_be_bytes());
+        data.extend_from_slice(&flags_offset.to_be_bytes());
+        data.extend_from_slice(&[64, 17, 0, 0]);
+        data.extend_from_slice(&SRC.octets());
+        data.extend_from_slice(&DST.octets());
+        data.extend_from_slice(&payload);
+        let decoded = decap::decode_frame(&data, ParseOption::default(), TunnelAttribution::Outer);
+        PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded)
+    }
+
+    fn udp_ports(frame: &PacketFrame) -> Option<(u16, u16)> {
+        let udp = frame.transport.as_ref()?.udp.as_ref()?;
+        Some((udp.source, udp.destination))
+    }
+
+    /// Process frames and return the ports of the frames passed on, in order
+    fn process_all(table: &mut FragmentTable, frames: Vec<PacketFrame>) -> Vec<Option<(u16, u16)>> {
+        let mut out: Vec<Option<(u16, u16)>> = Vec::new();
+        for frame in frames {
+            table.process(frame, |frame| out.push(udp_ports(&frame)));
+        }
+        out
+    }
+
+    #[test]
+    fn parse_fragment_fields() {
+        let frame = fragment(7, 16, false, 8);
+        let info = frame.fragment.as_ref().unwrap();
+        assert_eq!(info.id, 7);
+        assert_eq!(info.protocol, 17);
+        assert_eq!(info.offset, 16);
+        assert_eq!(info.length, 8);
+        assert!(!info.more_fragments);
+        assert!(frame.transport.is_none());
+        assert_eq!(udp_ports(&fragment(7, 0, true, 16)), Some((5353, 4000)));
+    }
+
+    #[test]
+    fn reassembly_credits_later_fragments_to_first() {
+        let mut table = FragmentTable::new();
+        let out = process_all(&mut table, vec![fragment(1, 0, true, 16), fragment(1, 16, false, 8)]);
+        assert_eq!(out, vec![Some((5353, 4000)), Some((5353, 4000))]);
+        assert_eq!(table.take_stats().reassembled, 1);
+        assert!(table.entries.is_empty());
+    }
+
+    #[test]
+    fn out_of_order_fragments_wait_for_first() {
+        let mut table = FragmentTable::new();
+        let out = process_all(
+            &mut table,
+            vec![fragment(2, 24, false, 8), fragment(2, 16, true, 8)],
+        );
+        assert!(out.is_empty());
+        assert_eq!(table.pending_frames, 2);
+        let out = process_all(&mut table, vec![fragment(2, 0, true, 16)]);
+        assert_eq!(out, vec![Some((5353, 4000)); 3]);
+        assert_eq!(table.pending_frames, 0);
+        assert_eq!(table.take_stats().reassembled, 1);
+    }
+
+    #[test]
+    fn overlap_discards_datagram() {
+        let mut table = FragmentTable::new();
+        let out = process_all(
+            &mut table,
+            vec![
+                fragment(3, 16, true, 16),
+                // Overlaps 16..32
+                fragment(3, 24, false, 16),
+                fragment(3, 0, true, 16),
+            ],
+        );
+        // Held and overlapping fragments pass without ports, and so does the first one
+        assert_eq!(out, vec![None, None, None]);
+        let stats = table.take_stats();
+        assert_eq!(stats.overlaps, 1);
+        assert_eq!(stats.reassembled, 0);
+        // A retransmitted fragment is not an overlap
+        let out = process_all(&mut table, vec![fragment(4, 0, true, 16), fragment(4, 0, true, 16), fragment(4, 16, false, 8)]);
+        assert_eq!(out, vec![Some((5353, 4000)); 3]);
+        assert_eq!(table.take_stats().overlaps, 0);
+    }
+
+    #[test]
+    fn fragment_limits() {
+        let mut table = FragmentTable::new();
+        let frames: Vec<PacketFrame> = (0..=MAX_FRAGMENTS_PER_DATAGRAM as u16)
+            .map(|i| fragment(5, i * 8, true, 8))
+            .collect();
+        let out = process_all(&mut table, frames);
+        assert_eq!(out.len(), MAX_FRAGMENTS_PER_DATAGRAM + 1);
+        assert_eq!(out.last(), Some(&None));
+        assert_eq!(table.take_stats().overlaps, 1);
+        // Longer than the largest datagram
+        let out = process_all(&mut table, vec![fragment(6, 65528, false, 16)]);
+        assert_eq!(out, vec![None]);
+        assert_eq!(table.take_stats().overlaps, 1);
+
+        let mut table = FragmentTable::new();
+        let frames: Vec<PacketFrame> = (0..=MAX_DATAGRAMS as u16).map(|id| fragment(id, 0, true, 16)).collect();
+        process_all(&mut table, frames);
+        assert_eq!(table.entries.len(), MAX_DATAGRAMS);
+        assert_eq!(table.take_stats().over_limit, 1);
+    }
+
+    #[test]
+    fn expiry_releases_held_frames_without_ports() {
+        let mut table = FragmentTable::new();
+        let out = process_all(&mut table, vec![fragment(8, 16, false, 8)]);
+        assert!(out.is_empty());
+        let mut expired: Vec<PacketFrame> = Vec::new();
+        table.expire(Instant::now(), |frame| expired.push(frame));
+        assert!(expired.is_empty());
+        table.expire(Instant::now() + FRAGMENT_TIMEOUT, |frame| expired.push(frame));
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].transport.is_none());
+        assert!(expired[0].fragment.is_some());
+        assert!(table.entries.is_empty());
+        assert_eq!(table.pending_frames, 0);
+        assert_eq!(table.take_stats().timeouts, 1);
+    }
+}
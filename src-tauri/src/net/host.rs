@@ -1,4 +1,6 @@
-use super::traffic::{TrafficDisplayInfo, TrafficInfo};
+use super::traffic::{RateWindows, TrafficDisplayInfo, TrafficInfo};
+use crate::db::ip::IpInfo;
+use crate::db::label::IpLabel;
 use serde::{Deserialize, Serialize};
 use std::{net::IpAddr, time::Duration};
 
@@ -11,7 +13,13 @@ pub struct RemoteHostInfo {
     pub country_name: String,
     pub asn: u32,
     pub as_name: String,
+    pub city: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub organization: String,
     pub traffic_info: TrafficInfo,
+    /// Threat feeds listing the address or host name
+    pub threat_feeds: Vec<String>,
 }
 
 impl RemoteHostInfo {
@@ -24,13 +32,29 @@ impl RemoteHostInfo {
             country_name: String::new(),
             asn: 0,
             as_name: String::new(),
+            city: String::new(),
+            latitude: None,
+            longitude: None,
+            organization: String::new(),
             traffic_info: TrafficInfo::new(),
+            threat_feeds: Vec::new(),
         }
     }
-    pub fn merge(&mut self, other: &RemoteHostInfo, duration: Duration) {
+    /// Set the country, AS and location fields from an IP database lookup
+    pub fn set_ip_info(&mut self, ip_info: IpInfo) {
+        self.country_code = ip_info.country_code;
+        self.country_name = ip_info.country_name;
+        self.asn = ip_info.asn;
+        self.as_name = ip_info.as_name;
+        self.city = ip_info.city;
+        self.latitude = ip_info.latitude;
+        self.longitude = ip_info.longitude;
+        self.organization = ip_info.organization;
+    }
+    pub fn merge(&mut self, other: &RemoteHostInfo, duration: Duration, windows: &RateWindows) {
         // Update traffic_info
         self.traffic_info
-            .update_bytes_per_sec(&other.traffic_info, duration);
+            .update_bytes_per_sec(&other.traffic_info, duration, windows);
         self.traffic_info.add_traffic(&other.traffic_info);
         // Update other fields
         if self.hostname.is_empty() {
@@ -48,6 +72,16 @@ impl RemoteHostInfo {
         if self.as_name.is_empty() {
             self.as_name = other.as_name.clone();
         }
+        if self.city.is_empty() {
+            self.city = other.city.clone();
+            self.latitude = other.latitude;
+            self.longitude = other.longitude;
+        }
+        if self.organization.is_empty() {
+            self.organization = other.organization.clone();
+        }
+        // Feeds are reloaded at runtime. The latest match wins
+        self.threat_feeds = other.threat_feeds.clone();
     }
 }
 
@@ -59,5 +93,78 @@ pub struct HostDisplayInfo {
     pub country_name: String,
     pub asn: u32,
     pub as_name: String,
+    pub city: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub organization: String,
+    pub traffic: TrafficDisplayInfo,
+    /// User-defined label of the address
+    pub label: Option<IpLabel>,
+    pub threat_feeds: Vec<String>,
+}
+
+impl HostDisplayInfo {
+    pub fn from_remote_host(host: &RemoteHostInfo, label: Option<&IpLabel>) -> Self {
+        let mut info = HostDisplayInfo {
+            ip_addr: host.ip_addr,
+            host_name: host.hostname.clone(),
+            country_code: host.country_code.clone(),
+            country_name: host.country_name.clone(),
+            asn: host.asn,
+            as_name: host.as_name.clone(),
+            city: host.city.clone(),
+            latitude: host.latitude,
+            longitude: host.longitude,
+            organization: host.organization.clone(),
+            traffic: host.traffic_info.to_display_info(),
+            label: label.cloned(),
+            threat_feeds: host.threat_feeds.clone(),
+        };
+        // Private ranges have no country or AS. Show the label instead
+        if let Some(label) = label {
+            if !crate::net::ip::is_global_addr(host.ip_addr) {
+                info.country_name = label.name.clone();
+                info.as_name = label.name.clone();
+            }
+        }
+        info
+    }
+}
+
+/// Traffic of the remote hosts in a country
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CountryTrafficDisplayInfo {
+    /// Empty if the country is unknown
+    pub country_code: String,
+    pub country_name: String,
+    pub host_count: usize,
+    pub traffic: TrafficDisplayInfo,
+    /// Hosts with the most traffic, sorted by total bytes
+    pub top_hosts: Vec<HostDisplayInfo>,
+}
+
+/// Traffic of the remote hosts with the same label name
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelTrafficDisplayInfo {
+    /// Empty for hosts without a label
+    pub name: String,
+    /// Networks labeled with this name
+    pub cidrs: Vec<String>,
+    pub tags: Vec<String>,
+    pub host_count: usize,
+    pub traffic: TrafficDisplayInfo,
+    /// Hosts with the most traffic, sorted by total bytes
+    pub top_hosts: Vec<HostDisplayInfo>,
+}
+
+/// Traffic of the remote hosts in an autonomous system
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AsTrafficDisplayInfo {
+    /// 0 if the AS is unknown
+    pub asn: u32,
+    pub as_name: String,
+    pub host_count: usize,
     pub traffic: TrafficDisplayInfo,
-}
\ No newline at end of file
+    /// Hosts with the most traffic, sorted by total bytes
+    pub top_hosts: Vec<HostDisplayInfo>,
+}
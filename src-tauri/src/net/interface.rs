@@ -1,8 +1,13 @@
+use crate::net::link::GatewayInfo;
+use crate::net::traffic::TrafficDisplayInfo;
+use crate::thread_log;
 use netdev::mac::MacAddr;
 use nex::net::interface::Interface;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
 
 pub fn get_interface_by_ip(ip_addr: IpAddr) -> Option<Interface> {
@@ -121,8 +126,12 @@ pub fn get_interface_local_ips(iface: &Interface) -> HashSet<IpAddr> {
 }
 
 pub fn get_local_ip_map() -> HashMap<IpAddr, String> {
+    build_local_ip_map(&nex::net::interface::get_interfaces())
+}
+
+pub fn build_local_ip_map(interfaces: &[Interface]) -> HashMap<IpAddr, String> {
     let mut ip_map: HashMap<IpAddr, String> = HashMap::new();
-    for iface in nex::net::interface::get_interfaces() {
+    for iface in interfaces {
         for ip in iface.ipv4.clone() {
             ip_map.insert(IpAddr::V4(ip.addr), iface.name.clone());
         }
@@ -133,17 +142,40 @@ pub fn get_local_ip_map() -> HashMap<IpAddr, String> {
     ip_map
 }
 
+/// IPv4 gateway of each interface, keyed by interface name
+pub fn build_gateway_map(interfaces: &[Interface]) -> HashMap<String, GatewayInfo> {
+    let mut gateway_map: HashMap<String, GatewayInfo> = HashMap::new();
+    for iface in interfaces {
+        if let Some(gateway) = &iface.gateway {
+            if let Some(ip_addr) = gateway.ipv4.first() {
+                gateway_map.insert(
+                    iface.name.clone(),
+                    GatewayInfo {
+                        ip_addr: *ip_addr,
+                        mac_addr: get_gateway_macaddr(iface),
+                    },
+                );
+            }
+        }
+    }
+    gateway_map
+}
+
 // get usable interface list
 pub fn get_usable_interfaces() -> Vec<Interface> {
     let mut usable_interfaces: Vec<Interface> = Vec::new();
     for iface in nex::net::interface::get_interfaces() {
-        if iface.is_up() && (iface.ipv4.len() > 0 || iface.ipv6.len() > 0) {
+        if is_usable_interface(&iface) {
             usable_interfaces.push(iface);
         }
     }
     usable_interfaces
 }
 
+pub fn is_usable_interface(iface: &Interface) -> bool {
+    iface.is_up() && (iface.ipv4.len() > 0 || iface.ipv6.len() > 0)
+}
+
 pub fn get_interface_macaddr(iface: &Interface) -> MacAddr {
     match &iface.mac_addr {
         Some(mac_addr) => mac_addr.clone(),
@@ -156,4 +188,148 @@ pub fn get_gateway_macaddr(iface: &Interface) -> MacAddr {
         Some(gateway) => gateway.mac_addr.clone(),
         None => MacAddr::zero(),
     }
-}
\ No newline at end of file
+}
+/// Kernel interface counters
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub rx_multicast: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+impl InterfaceCounters {
+    pub fn new() -> InterfaceCounters {
+        InterfaceCounters {
+            rx_bytes: 0,
+            rx_packets: 0,
+            rx_errors: 0,
+            rx_dropped: 0,
+            rx_multicast: 0,
+            tx_bytes: 0,
+            tx_packets: 0,
+            tx_errors: 0,
+            tx_dropped: 0,
+        }
+    }
+}
+
+/// Kernel interface counters with rates calculated from the previous sample
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterfaceCounterInfo {
+    pub counters: InterfaceCounters,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    pub rx_packets_per_sec: u64,
+    pub tx_packets_per_sec: u64,
+}
+
+impl InterfaceCounterInfo {
+    pub fn new(counters: InterfaceCounters) -> InterfaceCounterInfo {
+        InterfaceCounterInfo {
+            counters: counters,
+            rx_bytes_per_sec: 0,
+            tx_bytes_per_sec: 0,
+            rx_packets_per_sec: 0,
+            tx_packets_per_sec: 0,
+        }
+    }
+    /// Update counters and rates. Counter resets (e.g. interface re-created) yield zero rates.
+    pub fn update(&mut self, counters: InterfaceCounters, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if secs > 0.0 {
+            let rate = |new: u64, old: u64| -> u64 {
+                if new >= old {
+                    ((new - old) as f64 / secs) as u64
+                } else {
+                    0
+                }
+            };
+            self.rx_bytes_per_sec = rate(counters.rx_bytes, self.counters.rx_bytes);
+            self.tx_bytes_per_sec = rate(counters.tx_bytes, self.counters.tx_bytes);
+            self.rx_packets_per_sec = rate(counters.rx_packets, self.counters.rx_packets);
+            self.tx_packets_per_sec = rate(counters.tx_packets, self.counters.tx_packets);
+        }
+        self.counters = counters;
+    }
+}
+
+/// Capture-derived traffic of an interface, with the kernel counters for comparison
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterfaceTrafficDisplayInfo {
+    pub name: String,
+    pub traffic: TrafficDisplayInfo,
+    pub counters: Option<InterfaceCounterInfo>,
+}
+
+/// Get kernel interface counters (Interface Name -> InterfaceCounters)
+#[cfg(target_os = "linux")]
+pub fn get_interface_counters() -> HashMap<String, InterfaceCounters> {
+    match std::fs::read_to_string("/proc/net/dev") {
+        Ok(content) => parse_proc_net_dev(&content),
+        Err(e) => {
+            thread_log!(error, "get_interface_counters error: {:?}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Get kernel interface counters (Interface Name -> InterfaceCounters)
+/// currently only implemented for linux
+#[cfg(not(target_os = "linux"))]
+pub fn get_interface_counters() -> HashMap<String, InterfaceCounters> {
+    HashMap::new()
+}
+
+/// Whether get_interface_counters can read kernel counters on this platform
+#[cfg(target_os = "linux")]
+pub fn interface_counters_available() -> bool {
+    true
+}
+
+/// Whether get_interface_counters can read kernel counters on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn interface_counters_available() -> bool {
+    false
+}
+
+/// Parse /proc/net/dev
+/// Receive: bytes packets errs drop fifo frame compressed multicast
+/// Transmit: bytes packets errs drop fifo colls carrier compressed
+pub fn parse_proc_net_dev(content: &str) -> HashMap<String, InterfaceCounters> {
+    let mut counter_map: HashMap<String, InterfaceCounters> = HashMap::new();
+    // Skip the 2 header lines
+    for line in content.lines().skip(2) {
+        let (name, values) = match line.split_once(':') {
+            Some((name, values)) => (name.trim(), values),
+            None => continue,
+        };
+        let values: Vec<u64> = values
+            .split_whitespace()
+            .map(|v| v.parse::<u64>().unwrap_or(0))
+            .collect();
+        if values.len() < 16 {
+            continue;
+        }
+        counter_map.insert(
+            name.to_string(),
+            InterfaceCounters {
+                rx_bytes: values[0],
+                rx_packets: values[1],
+                rx_errors: values[2],
+                rx_dropped: values[3],
+                rx_multicast: values[7],
+                tx_bytes: values[8],
+                tx_packets: values[9],
+                tx_errors: values[10],
+                tx_dropped: values[11],
+            },
+        );
+    }
+    counter_map
+}
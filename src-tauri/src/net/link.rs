@@ -0,0 +1,550 @@
+//! ARP spoofing, gateway MAC change and rogue DHCP server detection.
+use crate::net::packet::PacketFrame;
+use crate::net::security::{SecurityEvent, SecurityEventKind, Severity};
+use netdev::mac::MacAddr;
+use nex::packet::arp::ArpOperation;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+/// ARP observations kept in the window, oldest dropped first
+const MAX_ARP_OBSERVATIONS: usize = 65536;
+/// DHCP servers remembered per interface
+const MAX_DHCP_SERVERS: usize = 64;
+/// Addresses or senders listed in the evidence of an event
+const MAX_EVIDENCE_ITEMS: usize = 20;
+
+const BOOTP_REPLY: u8 = 2;
+const BOOTP_OPTIONS_OFFSET: usize = 240;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_ROUTER: u8 = 3;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_END: u8 = 255;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkConfig {
+    pub enabled: bool,
+    /// Length of the sliding window
+    pub window_secs: u64,
+    /// Distinct IP addresses claimed by one MAC address within the window
+    pub mac_ip_threshold: usize,
+    /// Gratuitous ARP packets on one interface within the window
+    pub gratuitous_arp_threshold: usize,
+    /// IP or MAC addresses of the DHCP servers allowed to answer.
+    /// When empty, the first server seen on an interface is trusted
+    pub dhcp_server_allowlist: Vec<String>,
+}
+
+impl LinkConfig {
+    pub fn new() -> LinkConfig {
+        LinkConfig {
+            enabled: true,
+            window_secs: 60,
+            mac_ip_threshold: 8,
+            gratuitous_arp_threshold: 30,
+            dhcp_server_allowlist: Vec::new(),
+        }
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig::new()
+    }
+}
+
+/// Sender binding announced by an ARP reply or a gratuitous ARP
+#[derive(Debug, Clone)]
+pub struct ArpObservation {
+    pub if_name: String,
+    pub sender_mac_addr: MacAddr,
+    pub sender_ip_addr: Ipv4Addr,
+    pub target_ip_addr: Ipv4Addr,
+    pub time: Instant,
+}
+
+impl ArpObservation {
+    /// Announces the sender's own address unasked
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip_addr == self.target_ip_addr
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Offer,
+    Ack,
+    Nak,
+}
+
+/// Fields of a DHCP server reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpReply {
+    pub message_type: DhcpMessageType,
+    /// yiaddr
+    pub offered_ip_addr: Ipv4Addr,
+    /// Option 54
+    pub server_id: Option<Ipv4Addr>,
+    /// First address of option 3
+    pub router: Option<Ipv4Addr>,
+}
+
+/// DHCP server reply seen on an interface
+#[derive(Debug, Clone)]
+pub struct DhcpObservation {
+    pub if_name: String,
+    pub server_mac_addr: MacAddr,
+    pub server_ip_addr: Ipv4Addr,
+    pub reply: DhcpReply,
+    pub time: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub enum LinkObservation {
+    Arp(ArpObservation),
+    Dhcp(DhcpObservation),
+}
+
+/// Parse a BOOTP/DHCP server reply
+pub fn parse_dhcp_reply(payload: &[u8]) -> Option<DhcpReply> {
+    if payload.len() < BOOTP_OPTIONS_OFFSET || payload[0] != BOOTP_REPLY {
+        return None;
+    }
+    if payload[236..240] != DHCP_MAGIC_COOKIE {
+        return None;
+    }
+    let offered_ip_addr = Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]);
+    let mut message_type: Option<DhcpMessageType> = None;
+    let mut server_id: Option<Ipv4Addr> = None;
+    let mut router: Option<Ipv4Addr> = None;
+    let mut pos = BOOTP_OPTIONS_OFFSET;
+    while pos < payload.len() {
+        let code = payload[pos];
+        if code == DHCP_OPTION_PAD {
+            pos += 1;
+            continue;
+        }
+        if code == DHCP_OPTION_END {
+            break;
+        }
+        let len = *payload.get(pos + 1)? as usize;
+        let data = payload.get(pos + 2..pos + 2 + len)?;
+        match code {
+            DHCP_OPTION_MESSAGE_TYPE if len == 1 => {
+                message_type = match data[0] {
+                    2 => Some(DhcpMessageType::Offer),
+                    5 => Some(DhcpMessageType::Ack),
+                    6 => Some(DhcpMessageType::Nak),
+                    _ => None,
+                };
+            }
+            DHCP_OPTION_SERVER_ID if len == 4 => {
+                server_id = Some(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+            }
+            DHCP_OPTION_ROUTER if len >= 4 => {
+                router = Some(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+            }
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+    Some(DhcpReply {
+        message_type: message_type?,
+        offered_ip_addr: offered_ip_addr,
+        server_id: server_id,
+        router: router,
+    })
+}
+
+/// ARP reply, gratuitous ARP or DHCP server reply in a frame
+pub fn observe_frame(frame: &PacketFrame) -> Option<LinkObservation> {
+    let datalink = frame.datalink.as_ref()?;
+    if let Some(arp) = &datalink.arp {
+        let observation = ArpObservation {
+            if_name: frame.if_name.clone(),
+            sender_mac_addr: arp.sender_hw_addr,
+            sender_ip_addr: arp.sender_proto_addr,
+            target_ip_addr: arp.target_proto_addr,
+            time: Instant::now(),
+        };
+        // ARP probes have no sender address and claim nothing
+        if observation.sender_ip_addr.is_unspecified() {
+            return None;
+        }
+        if arp.operation == ArpOperation::Reply || observation.is_gratuitous() {
+            return Some(LinkObservation::Arp(observation));
+        }
+        return None;
+    }
+    let udp = frame.transport.as_ref()?.udp.as_ref()?;
+    if udp.source != DHCP_SERVER_PORT || udp.destination != DHCP_CLIENT_PORT {
+        return None;
+    }
+    let ipv4 = frame.ip.as_ref()?.ipv4.as_ref()?;
+    let reply = parse_dhcp_reply(&frame.payload)?;
+    Some(LinkObservation::Dhcp(DhcpObservation {
+        if_name: frame.if_name.clone(),
+        server_mac_addr: datalink.ethernet.as_ref().map_or(MacAddr::zero(), |ethernet| ethernet.source),
+        server_ip_addr: reply.server_id.unwrap_or(ipv4.source),
+        reply: reply,
+        time: Instant::now(),
+    }))
+}
+
+/// Gateway of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewayInfo {
+    pub ip_addr: Ipv4Addr,
+    /// Zero when unknown. Learned from the first ARP reply then
+    pub mac_addr: MacAddr,
+}
+
+#[derive(Debug, Clone)]
+struct DhcpServer {
+    server_ip_addr: Ipv4Addr,
+    server_mac_addr: MacAddr,
+    trusted: bool,
+}
+
+/// Sliding window of ARP bindings and the DHCP servers seen per interface
+#[derive(Debug, Clone)]
+pub struct LinkDetector {
+    config: LinkConfig,
+    /// Expected gateway per interface name
+    gateways: HashMap<String, GatewayInfo>,
+    arp: VecDeque<ArpObservation>,
+    dhcp_servers: HashMap<String, Vec<DhcpServer>>,
+    /// Detections made when the observations were added
+    pending: Vec<SecurityEvent>,
+}
+
+impl LinkDetector {
+    pub fn new(config: LinkConfig) -> LinkDetector {
+        LinkDetector {
+            config: config,
+            gateways: HashMap::new(),
+            arp: VecDeque::new(),
+            dhcp_servers: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+    pub fn set_config(&mut self, config: LinkConfig) {
+        if !config.enabled {
+            self.arp.clear();
+            self.pending.clear();
+        }
+        // Trust is decided again under the new allowlist
+        self.dhcp_servers.clear();
+        self.config = config;
+    }
+    /// Set the gateways of the captured interfaces. A known gateway MAC address is kept
+    /// while the gateway address stays the same, since the system ARP cache it comes
+    /// from may itself be poisoned
+    pub fn set_gateways(&mut self, gateways: HashMap<String, GatewayInfo>) {
+        for (if_name, gateway) in gateways.iter() {
+            if let Some(known) = self.gateways.get(if_name) {
+                if known.ip_addr == gateway.ip_addr && known.mac_addr != MacAddr::zero() {
+                    continue;
+                }
+            }
+            self.gateways.insert(if_name.clone(), gateway.clone());
+        }
+        self.gateways.retain(|if_name, _| gateways.contains_key(if_name));
+    }
+    pub fn add(&mut self, observations: Vec<LinkObservation>) {
+        if !self.config.enabled {
+            return;
+        }
+        for observation in observations {
+            match observation {
+                LinkObservation::Arp(arp) => {
+                    self.check_gateway(&arp);
+                    if self.arp.len() >= MAX_ARP_OBSERVATIONS {
+                        self.arp.pop_front();
+                    }
+                    self.arp.push_back(arp);
+                }
+                LinkObservation::Dhcp(dhcp) => {
+                    self.check_dhcp_server(&dhcp);
+                }
+            }
+        }
+    }
+    fn check_gateway(&mut self, arp: &ArpObservation) {
+        let gateway = match self.gateways.get_mut(&arp.if_name) {
+            Some(gateway) if gateway.ip_addr == arp.sender_ip_addr => gateway,
+            _ => return,
+        };
+        if gateway.mac_addr == MacAddr::zero() {
+            gateway.mac_addr = arp.sender_mac_addr;
+            return;
+        }
+        if gateway.mac_addr == arp.sender_mac_addr {
+            return;
+        }
+        let mut event = SecurityEvent::new(
+            SecurityEventKind::GatewayMacChange,
+            Severity::High,
+            format!(
+                "Gateway {} on {} announced from {}, expected {}",
+                gateway.ip_addr, arp.if_name, arp.sender_mac_addr, gateway.mac_addr
+            ),
+        );
+        event.target_ip_addr = Some(IpAddr::V4(gateway.ip_addr));
+        event.source_mac_addr = Some(arp.sender_mac_addr.to_string());
+        event.if_name = Some(arp.if_name.clone());
+        event.evidence = vec![
+            format!("Expected MAC: {}", gateway.mac_addr),
+            format!("Announced MAC: {}", arp.sender_mac_addr),
+        ];
+        if arp.is_gratuitous() {
+            event.evidence.push("Gratuitous ARP".to_string());
+        }
+        self.pending.push(event);
+    }
+    fn is_allowed_dhcp_server(&self, dhcp: &DhcpObservation) -> bool {
+        let server_ip_addr = dhcp.server_ip_addr.to_string();
+        let server_mac_addr = dhcp.server_mac_addr.to_string();
+        self.config.dhcp_server_allowlist.iter().any(|entry| {
+            let entry = entry.trim();
+            entry == server_ip_addr || entry.eq_ignore_ascii_case(&server_mac_addr)
+        })
+    }
+    fn check_dhcp_server(&mut self, dhcp: &DhcpObservation) {
+        let allowlisted = self.is_allowed_dhcp_server(dhcp);
+        let use_allowlist = !self.config.dhcp_server_allowlist.is_empty();
+        let servers = self.dhcp_servers.entry(dhcp.if_name.clone()).or_insert_with(Vec::new);
+        let trusted = match servers
+            .iter()
+            .find(|s| s.server_ip_addr == dhcp.server_ip_addr && s.server_mac_addr == dhcp.server_mac_addr)
+        {
+            Some(server) => server.trusted,
+            None => {
+                let trusted = if use_allowlist { allowlisted } else { servers.is_empty() };
+                if servers.len() < MAX_DHCP_SERVERS {
+                    servers.push(DhcpServer {
+                        server_ip_addr: dhcp.server_ip_addr,
+                        server_mac_addr: dhcp.server_mac_addr,
+                        trusted: trusted,
+                    });
+                }
+                trusted
+            }
+        };
+        if trusted {
+            return;
+        }
+        let description = if use_allowlist {
+            format!(
+                "DHCP server {} ({}) not on the allowlist answered on {}",
+                dhcp.server_ip_addr, dhcp.server_mac_addr, dhcp.if_name
+            )
+        } else {
+            let known: Vec<String> = servers
+                .iter()
+                .filter(|s| s.trusted)
+                .map(|s| s.server_ip_addr.to_string())
+                .collect();
+            format!(
+                "Second DHCP server {} ({}) answered on {}, known server {}",
+                dhcp.server_ip_addr,
+                dhcp.server_mac_addr,
+                dhcp.if_name,
+                known.join(", ")
+            )
+        };
+        let mut event = SecurityEvent::new(SecurityEventKind::RogueDhcpServer, Severity::High, description);
+        event.source_ip_addr = Some(IpAddr::V4(dhcp.server_ip_addr));
+        event.source_mac_addr = Some(dhcp.server_mac_addr.to_string());
+        event.if_name = Some(dhcp.if_name.clone());
+        event.evidence = vec![
+            format!("Message: {:?}", dhcp.reply.message_type),
+            format!("Offered address: {}", dhcp.reply.offered_ip_addr),
+        ];
+        if let Some(router) = dhcp.reply.router {
+            event.evidence.push(format!("Router: {}", router));
+        }
+        self.pending.push(event);
+    }
+    /// Drop observations outside the window and return the detections
+    pub fn evaluate(&mut self) -> Vec<SecurityEvent> {
+        let mut events: Vec<SecurityEvent> = std::mem::take(&mut self.pending);
+        if !self.config.enabled {
+            return events;
+        }
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_secs.max(1));
+        while self.arp.front().map_or(false, |arp| now.duration_since(arp.time) >= window) {
+            self.arp.pop_front();
+        }
+        // Addresses claimed per MAC, gratuitous senders per interface
+        let mut claims: HashMap<(&str, MacAddr), HashSet<Ipv4Addr>> = HashMap::new();
+        let mut gratuitous: HashMap<&str, HashMap<MacAddr, usize>> = HashMap::new();
+        for arp in self.arp.iter() {
+            claims
+                .entry((arp.if_name.as_str(), arp.sender_mac_addr))
+                .or_insert_with(HashSet::new)
+                .insert(arp.sender_ip_addr);
+            if arp.is_gratuitous() {
+                *gratuitous
+                    .entry(arp.if_name.as_str())
+                    .or_insert_with(HashMap::new)
+                    .entry(arp.sender_mac_addr)
+                    .or_insert(0) += 1;
+            }
+        }
+        for ((if_name, mac_addr), ip_addrs) in claims {
+            if ip_addrs.len() < self.config.mac_ip_threshold.max(2) {
+                continue;
+            }
+            let gateway_ip_addr = self
+                .gateways
+                .get(if_name)
+                .map(|gateway| gateway.ip_addr)
+                .filter(|ip_addr| ip_addrs.contains(ip_addr));
+            let mut ip_addrs: Vec<Ipv4Addr> = ip_addrs.into_iter().collect();
+            ip_addrs.sort();
+            let mut event = SecurityEvent::new(
+                SecurityEventKind::ArpSpoofing,
+                if gateway_ip_addr.is_some() { Severity::High } else { Severity::Medium },
+                format!(
+                    "{} claimed {} addresses on {} in {}s{}",
+                    mac_addr,
+                    ip_addrs.len(),
+                    if_name,
+                    window.as_secs(),
+                    if gateway_ip_addr.is_some() { " including the gateway" } else { "" }
+                ),
+            );
+            event.target_ip_addr = gateway_ip_addr.map(IpAddr::V4);
+            event.source_mac_addr = Some(mac_addr.to_string());
+            event.if_name = Some(if_name.to_string());
+            event.evidence = ip_addrs
+                .iter()
+                .take(MAX_EVIDENCE_ITEMS)
+                .map(|ip_addr| ip_addr.to_string())
+                .collect();
+            events.push(event);
+        }
+        for (if_name, senders) in gratuitous {
+            let count: usize = senders.values().sum();
+            if count < self.config.gratuitous_arp_threshold.max(1) {
+                continue;
+            }
+            let mut senders: Vec<(MacAddr, usize)> = senders.into_iter().collect();
+            senders.sort_by(|a, b| b.1.cmp(&a.1));
+            let mut event = SecurityEvent::new(
+                SecurityEventKind::GratuitousArpFlood,
+                Severity::Medium,
+                format!(
+                    "{} gratuitous ARP packets from {} senders on {} in {}s",
+                    count,
+                    senders.len(),
+                    if_name,
+                    window.as_secs()
+                ),
+            );
+            event.if_name = Some(if_name.to_string());
+            event.evidence = senders
+                .iter()
+                .take(MAX_EVIDENCE_ITEMS)
+                .map(|(mac_addr, count)| format!("{}: {}", mac_addr, count))
+                .collect();
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::decap::{self, TunnelAttribution};
+    use nex::packet::frame::ParseOption;
+
+    const SERVER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const SERVER_IP: [u8; 4] = [192, 168, 1, 1];
+    const OFFERED_IP: [u8; 4] = [192, 168, 1, 100];
+
+    /// Ethernet frame of a broadcast DHCPOFFER as sent by a typical home router
+    fn dhcp_offer_frame() -> Vec<u8> {
+        let mut bootp = vec![0u8; BOOTP_OPTIONS_OFFSET];
+        bootp[0] = BOOTP_REPLY;
+        bootp[1] = 1; // htype ethernet
+        bootp[2] = 6; // hlen
+        bootp[4..8].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]); // xid
+        bootp[16..20].copy_from_slice(&OFFERED_IP);
+        bootp[20..24].copy_from_slice(&SERVER_IP);
+        bootp[28..34].copy_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x02]); // chaddr
+        bootp[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        bootp.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, 2]);
+        bootp.extend_from_slice(&[DHCP_OPTION_SERVER_ID, 4]);
+        bootp.extend_from_slice(&SERVER_IP);
+        bootp.extend_from_slice(&[51, 4, 0, 1, 0x51, 0x80]); // lease time
+        bootp.extend_from_slice(&[1, 4, 255, 255, 255, 0]); // subnet mask
+        bootp.extend_from_slice(&[DHCP_OPTION_ROUTER, 4]);
+        bootp.extend_from_slice(&SERVER_IP);
+        bootp.push(DHCP_OPTION_END);
+        bootp.resize(300, DHCP_OPTION_PAD);
+
+        let udp_len = (8 + bootp.len()) as u16;
+        let mut udp = Vec::new();
+        udp.extend_from_slice(&DHCP_SERVER_PORT.to_be_bytes());
+        udp.extend_from_slice(&DHCP_CLIENT_PORT.to_be_bytes());
+        udp.extend_from_slice(&udp_len.to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(&bootp);
+
+        let ip_len = (20 + udp.len()) as u16;
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&[0xff; 6]);
+        frame.extend_from_slice(&SERVER_MAC);
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0x00]);
+        frame.extend_from_slice(&ip_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&SERVER_IP);
+        frame.extend_from_slice(&[255, 255, 255, 255]);
+        frame.extend_from_slice(&udp);
+        frame
+    }
+
+    fn packet_frame(data: &[u8]) -> PacketFrame {
+        let decoded = decap::decode_frame(data, ParseOption::default(), TunnelAttribution::Outer);
+        PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded)
+    }
+
+    #[test]
+    fn observe_dhcp_offer_frame() {
+        let data = dhcp_offer_frame();
+        assert_eq!(data.len(), 342);
+        let observation = match observe_frame(&packet_frame(&data)) {
+            Some(LinkObservation::Dhcp(observation)) => observation,
+            other => panic!("expected a DHCP observation, got {:?}", other),
+        };
+        assert_eq!(observation.if_name, "eth0");
+        assert_eq!(observation.server_mac_addr, MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01));
+        assert_eq!(observation.server_ip_addr, Ipv4Addr::from(SERVER_IP));
+        assert_eq!(
+            observation.reply,
+            DhcpReply {
+                message_type: DhcpMessageType::Offer,
+                offered_ip_addr: Ipv4Addr::from(OFFERED_IP),
+                server_id: Some(Ipv4Addr::from(SERVER_IP)),
+                router: Some(Ipv4Addr::from(SERVER_IP)),
+            }
+        );
+    }
+
+    #[test]
+    fn truncated_dhcp_offer_is_not_observed() {
+        let data = dhcp_offer_frame();
+        // The options start past a 256 byte snaplen
+        assert!(observe_frame(&packet_frame(&data[..256])).is_none());
+    }
+}
@@ -1,14 +1,35 @@
 #![allow(unused)]
 
+pub mod beacon;
+pub mod decap;
+pub mod dissect;
+pub mod dnstunnel;
 pub mod dns;
+pub mod fragment;
 pub mod host;
 pub mod http;
 pub mod interface;
 pub mod ip;
+pub mod link;
+pub mod names;
 pub mod packet;
 pub mod pcap;
+pub mod pcapng;
+pub mod probe;
 pub mod protocol;
+pub mod ring;
+pub mod sampling;
+pub mod scan;
+pub mod security;
 pub mod service;
+pub mod session;
+pub mod shard;
+pub mod signature;
 pub mod socket;
 pub mod stat;
+pub mod stream;
+pub mod threat;
+#[cfg(target_os = "linux")]
+pub mod tpacket;
 pub mod traffic;
+pub mod watcher;
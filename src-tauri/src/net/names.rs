@@ -0,0 +1,159 @@
+//! Host names seen in captured packets: DNS questions and TLS server names.
+use crate::net::dissect::{read_dns_name, tls_server_name};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+pub const DNS_PORT: u16 = 53;
+/// Questions read from one DNS message
+const MAX_DNS_QUESTIONS: usize = 8;
+const DNS_HEADER_SIZE: usize = 12;
+const TLS_HANDSHAKE: u8 = 22;
+const TLS_CLIENT_HELLO: u8 = 1;
+const TLS_EXT_SERVER_NAME: u16 = 0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameSource {
+    /// Question of a DNS query or response
+    Dns,
+    /// server_name of a TLS ClientHello
+    Sni,
+    /// PTR lookup of a remote address
+    ReverseDns,
+}
+
+impl NameSource {
+    pub fn name(&self) -> String {
+        match self {
+            NameSource::Dns => "DNS".to_string(),
+            NameSource::Sni => "SNI".to_string(),
+            NameSource::ReverseDns => "Reverse DNS".to_string(),
+        }
+    }
+}
+
+/// A name seen in traffic with the local and remote endpoints of the packet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObservedName {
+    pub name: String,
+    pub source: NameSource,
+    pub local_ip_addr: IpAddr,
+    /// DNS server or TLS server
+    pub remote_ip_addr: IpAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+}
+
+/// Header fields and questions of a DNS message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub response: bool,
+    pub questions: Vec<DnsQuestion>,
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+/// Parse the header and questions of a DNS message. Records are not read
+pub fn parse_dns_message(msg: &[u8]) -> Option<DnsMessage> {
+    if msg.len() < DNS_HEADER_SIZE {
+        return None;
+    }
+    let flags = be16(msg, 2)?;
+    // Standard queries only
+    if (flags >> 11) & 0x0F != 0 {
+        return None;
+    }
+    let mut message = DnsMessage {
+        id: be16(msg, 0)?,
+        response: flags & 0x8000 != 0,
+        questions: Vec::new(),
+    };
+    let qdcount = be16(msg, 4)? as usize;
+    let mut pos = DNS_HEADER_SIZE;
+    for _ in 0..qdcount.min(MAX_DNS_QUESTIONS) {
+        let (name, next) = read_dns_name(msg, pos)?;
+        let qtype = be16(msg, next)?;
+        pos = next + 4;
+        if pos > msg.len() {
+            return None;
+        }
+        message.questions.push(DnsQuestion { name: name, qtype: qtype });
+    }
+    Some(message)
+}
+
+/// server_name of a TLS ClientHello at the start of a TCP payload
+pub fn parse_tls_sni(payload: &[u8]) -> Option<String> {
+    // Record header: type(1) version(2) length(2)
+    if *payload.first()? != TLS_HANDSHAKE || *payload.get(1)? != 3 {
+        return None;
+    }
+    let record_end = (5 + be16(payload, 3)? as usize).min(payload.len());
+    // Handshake header: type(1) length(3)
+    if *payload.get(5)? != TLS_CLIENT_HELLO {
+        return None;
+    }
+    // version(2) random(32)
+    let mut pos = 5 + 4 + 2 + 32;
+    let session_id_len = *payload.get(pos)? as usize;
+    pos += 1 + session_id_len;
+    let suites_len = be16(payload, pos)? as usize;
+    pos += 2 + suites_len;
+    let compression_len = *payload.get(pos)? as usize;
+    pos += 1 + compression_len;
+    let ext_end = (pos + 2 + be16(payload, pos)? as usize).min(record_end);
+    pos += 2;
+    while pos + 4 <= ext_end {
+        let ext_type = be16(payload, pos)?;
+        let len = be16(payload, pos + 2)? as usize;
+        if ext_type == TLS_EXT_SERVER_NAME {
+            return payload
+                .get(pos + 4..(pos + 4 + len).min(ext_end))
+                .and_then(tls_server_name)
+                .filter(|name| !name.is_empty());
+        }
+        pos += 4 + len;
+    }
+    None
+}
+
+/// Names in the payload of a packet between local_port and remote_port
+pub fn extract_names(
+    payload: &[u8],
+    is_tcp: bool,
+    local_ip_addr: IpAddr,
+    local_port: u16,
+    remote_ip_addr: IpAddr,
+    remote_port: u16,
+) -> Vec<ObservedName> {
+    let mut names: Vec<ObservedName> = Vec::new();
+    if payload.is_empty() {
+        return names;
+    }
+    let observed = |name: String, source: NameSource| ObservedName {
+        name: name,
+        source: source,
+        local_ip_addr: local_ip_addr,
+        remote_ip_addr: remote_ip_addr,
+    };
+    if local_port == DNS_PORT || remote_port == DNS_PORT {
+        // DNS over TCP has a length prefix
+        let msg = if is_tcp { payload.get(2..).unwrap_or(&[]) } else { payload };
+        if let Some(message) = parse_dns_message(msg) {
+            for question in message.questions {
+                names.push(observed(question.name, NameSource::Dns));
+            }
+        }
+    } else if is_tcp {
+        if let Some(name) = parse_tls_sni(payload) {
+            names.push(observed(name, NameSource::Sni));
+        }
+    }
+    names
+}
@@ -1,3 +1,5 @@
+use crate::net::decap::{DecodedFrame, TunnelInfo};
+use crate::net::fragment::FragmentInfo;
 use crate::sys;
 use nex::packet::frame::{DatalinkLayer, IpLayer, TransportLayer};
 use serde::{Deserialize, Serialize};
@@ -17,9 +19,23 @@ pub struct PacketFrame {
     /// The transport layer.
     pub transport: Option<TransportLayer>,
     /// Rest of the packet that could not be parsed as a header. (Usually payload)
-    //pub payload: Vec<u8>,
+    /// Not sent to the frontend.
+    #[serde(skip)]
+    pub payload: Vec<u8>,
+    /// Captured bytes. Only kept by on-demand capture for packet details.
+    #[serde(skip)]
+    pub raw: Vec<u8>,
     /// Packet length.
     pub packet_len: usize,
+    /// Number of frames this frame stands for when sampling. 1 without sampling.
+    /// 0 for frames left out of the sample, which are only seen by the security detectors.
+    pub sample_weight: u32,
+    /// VLAN IDs, outermost first.
+    pub vlan_ids: Vec<u16>,
+    /// Tunnel the packet was carried in.
+    pub tunnel: Option<TunnelInfo>,
+    /// Fragment fields if the packet is an IP fragment.
+    pub fragment: Option<FragmentInfo>,
     /// Packet arrival time. RFC3339 format.
     pub timestamp: String,
 }
@@ -33,8 +49,13 @@ impl PacketFrame {
             datalink: None,
             ip: None,
             transport: None,
-            //payload: Vec::new(),
+            payload: Vec::new(),
+            raw: Vec::new(),
             packet_len: 0,
+            sample_weight: 1,
+            vlan_ids: Vec::new(),
+            tunnel: None,
+            fragment: None,
             timestamp: String::new(),
         }
     }
@@ -51,9 +72,26 @@ impl PacketFrame {
             datalink: frame.datalink,
             ip: frame.ip,
             transport: frame.transport,
-            //payload: frame.payload,
+            payload: frame.payload,
+            raw: Vec::new(),
             packet_len: frame.packet_len,
+            sample_weight: 1,
+            vlan_ids: Vec::new(),
+            tunnel: None,
+            fragment: None,
             timestamp: sys::get_sysdate(),
         }
     }
+    pub fn from_decoded_frame(
+        capture_no: usize,
+        if_index: u32,
+        if_name: String,
+        decoded: DecodedFrame,
+    ) -> PacketFrame {
+        let mut packet_frame = PacketFrame::from_nex_frame(capture_no, if_index, if_name, decoded.frame);
+        packet_frame.vlan_ids = decoded.vlan_ids;
+        packet_frame.tunnel = decoded.tunnel;
+        packet_frame.fragment = decoded.fragment;
+        packet_frame
+    }
 }
@@ -1,5 +1,12 @@
+use crate::net::decap::{self, DecodedFrame, TunnelAttribution};
+use crate::net::dissect::LinkType;
+use crate::net::fragment::FragmentTable;
 use crate::net::interface;
 use crate::net::packet::PacketFrame;
+use crate::net::ring::CaptureRing;
+use crate::net::sampling::{self, Sampler, SamplingConfig};
+use crate::net::shard::StatShard;
+use crate::net::socket::{SocketConnection, TransportProtocol};
 use crate::net::stat::NetStatStrage;
 use crate::sys;
 use crate::thread_log;
@@ -37,6 +44,50 @@ impl CaptureReport {
     }
 }
 
+/// Packet capture backend
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureBackend {
+    /// nex datalink channel. Reads one frame per call
+    Datalink,
+    /// Linux memory-mapped TPACKET_V3 ring with PACKET_FANOUT. Falls back to Datalink elsewhere
+    PacketMmap,
+}
+
+impl CaptureBackend {
+    /// Preferred backend for background capture on this platform
+    pub fn preferred() -> CaptureBackend {
+        if cfg!(target_os = "linux") {
+            CaptureBackend::PacketMmap
+        } else {
+            CaptureBackend::Datalink
+        }
+    }
+}
+
+/// Kernel capture statistics of an interface
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureStats {
+    pub if_name: String,
+    pub backend: CaptureBackend,
+    /// Packets received by the capture sockets, including dropped ones
+    pub packets: u64,
+    /// Packets dropped by the kernel because the ring was full
+    pub drops: u64,
+    pub freeze_q_cnt: u64,
+}
+
+impl CaptureStats {
+    pub fn new(if_name: String, backend: CaptureBackend) -> CaptureStats {
+        CaptureStats {
+            if_name: if_name,
+            backend: backend,
+            packets: 0,
+            drops: 0,
+            freeze_q_cnt: 0,
+        }
+    }
+}
+
 /// Packet capture options
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PacketCaptureOptions {
@@ -53,6 +104,8 @@ pub struct PacketCaptureOptions {
     pub src_ports: HashSet<u16>,
     /// Destination ports to filter. If empty, all destination ports will be captured
     pub dst_ports: HashSet<u16>,
+    /// Only capture the packets of this connection, in both directions
+    pub connection: Option<SocketConnection>,
     /// Ether types to filter. If empty, all ether types will be captured
     pub ether_types: HashSet<EtherType>,
     /// IP protocols to filter. If empty, all IP protocols will be captured
@@ -69,6 +122,17 @@ pub struct PacketCaptureOptions {
     pub tunnel: bool,
     /// Loopback interface
     pub loopback: bool,
+    /// Capture backend
+    pub backend: CaptureBackend,
+    /// Maximum bytes captured per frame. 0 captures whole frames.
+    /// Limits the ring frame size for PacketMmap and the kept raw bytes for on-demand capture
+    pub snaplen: usize,
+    /// Number of PACKET_FANOUT worker threads (PacketMmap only)
+    pub fanout_workers: usize,
+    /// Account tunneled traffic to the inner or the outer flow
+    pub tunnel_attribution: TunnelAttribution,
+    /// Frame sampling for background capture
+    pub sampling: SamplingConfig,
 }
 
 impl PacketCaptureOptions {
@@ -81,6 +145,7 @@ impl PacketCaptureOptions {
             dst_ips: HashSet::new(),
             src_ports: HashSet::new(),
             dst_ports: HashSet::new(),
+            connection: None,
             ether_types: HashSet::new(),
             ip_protocols: HashSet::new(),
             capture_timeout: Duration::MAX,
@@ -89,6 +154,11 @@ impl PacketCaptureOptions {
             receive_undefined: true,
             tunnel: iface.is_tun(),
             loopback: iface.is_loopback(),
+            backend: CaptureBackend::Datalink,
+            snaplen: 0,
+            fanout_workers: 1,
+            tunnel_attribution: TunnelAttribution::default(),
+            sampling: SamplingConfig::new(),
         };
         Ok(options)
     }
@@ -101,6 +171,7 @@ impl PacketCaptureOptions {
             dst_ips: HashSet::new(),
             src_ports: HashSet::new(),
             dst_ports: HashSet::new(),
+            connection: None,
             ether_types: HashSet::new(),
             ip_protocols: HashSet::new(),
             capture_timeout: Duration::MAX,
@@ -109,6 +180,11 @@ impl PacketCaptureOptions {
             receive_undefined: true,
             tunnel: iface.is_tun(),
             loopback: iface.is_loopback(),
+            backend: CaptureBackend::Datalink,
+            snaplen: 0,
+            fanout_workers: 1,
+            tunnel_attribution: TunnelAttribution::default(),
+            sampling: SamplingConfig::new(),
         };
         Some(options)
     }
@@ -121,6 +197,7 @@ impl PacketCaptureOptions {
             dst_ips: HashSet::new(),
             src_ports: HashSet::new(),
             dst_ports: HashSet::new(),
+            connection: None,
             ether_types: HashSet::new(),
             ip_protocols: HashSet::new(),
             capture_timeout: Duration::MAX,
@@ -129,6 +206,11 @@ impl PacketCaptureOptions {
             receive_undefined: true,
             tunnel: iface.is_tun(),
             loopback: iface.is_loopback(),
+            backend: CaptureBackend::Datalink,
+            snaplen: 0,
+            fanout_workers: 1,
+            tunnel_attribution: TunnelAttribution::default(),
+            sampling: SamplingConfig::new(),
         };
         options
     }
@@ -140,6 +222,7 @@ impl PacketCaptureOptions {
             dst_ips: HashSet::new(),
             src_ports: HashSet::new(),
             dst_ports: HashSet::new(),
+            connection: None,
             ether_types: HashSet::new(),
             ip_protocols: HashSet::new(),
             capture_timeout: Duration::MAX,
@@ -148,6 +231,11 @@ impl PacketCaptureOptions {
             receive_undefined: true,
             tunnel: iface.is_tun(),
             loopback: iface.is_loopback(),
+            backend: CaptureBackend::Datalink,
+            snaplen: 0,
+            fanout_workers: 1,
+            tunnel_attribution: TunnelAttribution::default(),
+            sampling: SamplingConfig::new(),
         };
         options
     }
@@ -163,7 +251,7 @@ pub fn start_capture(
     let mut report = CaptureReport::new();
     let config = nex::datalink::Config {
         write_buffer_size: 4096,
-        read_buffer_size: 4096,
+        read_buffer_size: 65536,
         read_timeout: Some(capture_options.read_timeout),
         write_timeout: None,
         channel_type: nex::datalink::ChannelType::Layer2,
@@ -187,30 +275,25 @@ pub fn start_capture(
     loop {
         match rx.next() {
             Ok(packet) => {
-                let mut parse_option: ParseOption = ParseOption::default();
-                if interface.is_tun()
-                    || (cfg!(any(target_os = "macos", target_os = "ios"))
-                        && interface.is_loopback())
-                {
-                    let payload_offset;
-                    if interface.is_loopback() {
-                        payload_offset = 14;
-                    } else {
-                        payload_offset = 0;
-                    }
-                    parse_option.from_ip_packet = true;
-                    parse_option.offset = payload_offset;
-                }
+                let parse_option: ParseOption = get_parse_option(&interface);
                 report.bytes = report.bytes.saturating_add(packet.len());
                 report.packets = report.packets.saturating_add(1);
-                let frame: Frame = Frame::from_bytes(&packet, parse_option);
-                if filter_packet(&frame, &capture_options) {
-                    let packet_frame = PacketFrame::from_nex_frame(
+                let decoded: DecodedFrame = decap::decode_frame(
+                    &packet,
+                    parse_option,
+                    capture_options.tunnel_attribution,
+                );
+                if filter_packet(&decoded.frame, &capture_options) {
+                    let mut packet_frame = PacketFrame::from_decoded_frame(
                         report.packets,
                         interface.index,
                         interface.name.clone(),
-                        frame,
+                        decoded,
                     );
+                    packet_frame.raw = packet.to_vec();
+                    if capture_options.snaplen > 0 {
+                        packet_frame.raw.truncate(capture_options.snaplen);
+                    }
                     match msg_tx.send(packet_frame) {
                         Ok(_) => {}
                         Err(_) => {}
@@ -236,11 +319,45 @@ pub fn start_capture(
     report
 }
 
+/// Snaplen for the kernel ring covering both frame analysis and the capture ring. 0 keeps whole frames
+fn kernel_snaplen(snaplen: usize, ring_snaplen: usize) -> usize {
+    if snaplen == 0 || ring_snaplen == 0 {
+        0
+    } else {
+        snaplen.max(ring_snaplen)
+    }
+}
+
+/// Leading snaplen bytes of a frame. 0 keeps the whole frame
+fn truncate_frame(data: &[u8], snaplen: usize) -> &[u8] {
+    if snaplen > 0 && data.len() > snaplen {
+        &data[..snaplen]
+    } else {
+        data
+    }
+}
+
 pub fn start_background_capture(
     capture_options: PacketCaptureOptions,
     netstat_strage: &mut Arc<NetStatStrage>,
+    capture_ring: &Arc<CaptureRing>,
     interface: Interface,
+    stop: &Arc<Mutex<bool>>,
 ) {
+    #[cfg(target_os = "linux")]
+    if capture_options.backend == CaptureBackend::PacketMmap {
+        match start_background_capture_mmap(&capture_options, netstat_strage, capture_ring, &interface, stop) {
+            Ok(_) => return,
+            Err(e) => {
+                thread_log!(
+                    warn,
+                    "[{}] TPACKET_V3 capture unavailable, fallback to datalink: {}",
+                    interface.name,
+                    e
+                );
+            }
+        }
+    }
     let config = nex::datalink::Config {
         write_buffer_size: 4096,
         read_buffer_size: 4096,
@@ -262,33 +379,62 @@ pub fn start_background_capture(
             return;
         }
     };
+    let shard = netstat_strage.new_shard();
+    let link = LinkType::from_parse_option(&get_parse_option(&interface));
+    let packet_ring = capture_ring.register(&interface.name, link);
+    let mut sampler = Sampler::new(capture_options.sampling.clone());
+    let mut fragments = FragmentTable::new();
+    let mut last_expire = Instant::now();
     let start_time = Instant::now();
     loop {
         match rx.next() {
             Ok(packet) => {
-                let mut parse_option: ParseOption = ParseOption::default();
-                if interface.is_tun()
-                    || (cfg!(any(target_os = "macos", target_os = "ios"))
-                        && interface.is_loopback())
-                {
-                    let payload_offset;
-                    if interface.is_loopback() {
-                        payload_offset = 14;
-                    } else {
-                        payload_offset = 0;
-                    }
-                    parse_option.from_ip_packet = true;
-                    parse_option.offset = payload_offset;
+                match packet_ring.lock() {
+                    Ok(mut packet_ring) => packet_ring.push(&packet, packet.len()),
+                    Err(_) => {}
                 }
-                let frame: Frame = Frame::from_bytes(&packet, parse_option);
-                if filter_packet(&frame, &capture_options) {
-                    let packet_frame = PacketFrame::from_nex_frame(
-                        0,
-                        interface.index,
-                        interface.name.clone(),
-                        frame,
+                let len = packet.len();
+                let packet = truncate_frame(packet, capture_options.snaplen);
+                // Frames left out of the sample are decoded only if the security detectors may need them
+                let sample_weight: u32 = sampler.sample(&packet, link).unwrap_or(0);
+                if sample_weight > 0 || sampling::detector_check(&packet, link) {
+                    let parse_option: ParseOption = get_parse_option(&interface);
+                    let mut decoded: DecodedFrame = decap::decode_frame(
+                        &packet,
+                        parse_option,
+                        capture_options.tunnel_attribution,
                     );
-                    netstat_strage.update(packet_frame);
+                    decoded.frame.packet_len = len;
+                    if filter_packet(&decoded.frame, &capture_options) {
+                        let mut packet_frame = PacketFrame::from_decoded_frame(
+                            0,
+                            interface.index,
+                            interface.name.clone(),
+                            decoded,
+                        );
+                        packet_frame.sample_weight = sample_weight;
+                        match shard.lock() {
+                            Ok(mut shard) => {
+                                fragments.process(packet_frame, |frame| shard.update(frame));
+                            }
+                            Err(e) => {
+                                thread_log!(error, "Failed to lock shard: {:?}", e);
+                            }
+                        }
+                    }
+                }
+            }
+            Err(_) => {}
+        }
+        if last_expire.elapsed() >= Duration::from_secs(1) {
+            expire_fragments(&mut fragments, &shard, &interface.name);
+            add_sampling_stats(&mut sampler, &shard);
+            last_expire = Instant::now();
+        }
+        match stop.lock() {
+            Ok(stop) => {
+                if *stop {
+                    break;
                 }
             }
             Err(_) => {}
@@ -299,6 +445,187 @@ pub fn start_background_capture(
     }
 }
 
+/// Background capture with TPACKET_V3 rings.
+/// One ring per fanout worker, all joined to the same fanout group.
+#[cfg(target_os = "linux")]
+fn start_background_capture_mmap(
+    capture_options: &PacketCaptureOptions,
+    netstat_strage: &Arc<NetStatStrage>,
+    capture_ring: &Arc<CaptureRing>,
+    interface: &Interface,
+    stop: &Arc<Mutex<bool>>,
+) -> Result<(), String> {
+    use crate::net::tpacket::{RingConfig, TpacketRing};
+    let workers = capture_options.fanout_workers.max(1);
+    let mut ring_config = RingConfig::new();
+    // The capture ring may keep more of each frame than the analysis needs
+    ring_config.snaplen = kernel_snaplen(capture_options.snaplen, capture_ring.get_config().snaplen);
+    ring_config.promiscuous = capture_options.promiscuous;
+    ring_config.retire_timeout_ms = capture_options.read_timeout.as_millis().min(1000) as u32;
+    // Open all rings first so that any failure can fall back to datalink
+    let rings: Vec<TpacketRing> = if workers > 1 {
+        TpacketRing::open_fanout(interface.index, &ring_config, workers).map_err(|e| e.to_string())?
+    } else {
+        vec![TpacketRing::open(interface.index, &ring_config).map_err(|e| e.to_string())?]
+    };
+    thread_log!(
+        info,
+        "[{}] TPACKET_V3 capture with {} worker(s), snaplen {}",
+        interface.name,
+        workers,
+        ring_config.snaplen
+    );
+    netstat_strage.reset_capture_stats(&interface.name, CaptureBackend::PacketMmap);
+    let link = LinkType::from_parse_option(&get_parse_option(interface));
+    let packet_ring = capture_ring.register(&interface.name, link);
+    let start_time = Instant::now();
+    let mut handles = Vec::new();
+    for (i, mut ring) in rings.into_iter().enumerate() {
+        let capture_options = capture_options.clone();
+        let netstat_strage = Arc::clone(netstat_strage);
+        let interface = interface.clone();
+        let stop = Arc::clone(stop);
+        let packet_ring = Arc::clone(&packet_ring);
+        let handle = std::thread::Builder::new()
+            .name(format!("pcap-thread-{}-{}", interface.name, i))
+            .spawn(move || {
+                let shard = netstat_strage.new_shard();
+                let mut fragments = FragmentTable::new();
+                let mut sampler = Sampler::new(capture_options.sampling.clone());
+                let mut last_stats = Instant::now();
+                loop {
+                    ring.read_block(capture_options.read_timeout, |data, len| {
+                        match packet_ring.lock() {
+                            Ok(mut packet_ring) => packet_ring.push(data, len),
+                            Err(_) => {}
+                        }
+                        let data = truncate_frame(data, capture_options.snaplen);
+                        // Frames left out of the sample are decoded only if the security detectors may need them
+                        let sample_weight: u32 = sampler.sample(data, link).unwrap_or(0);
+                        if sample_weight == 0 && !sampling::detector_check(data, link) {
+                            return;
+                        }
+                        let mut decoded: DecodedFrame = decap::decode_frame(
+                            data,
+                            get_parse_option(&interface),
+                            capture_options.tunnel_attribution,
+                        );
+                        // Frames may be truncated by snaplen
+                        decoded.frame.packet_len = len;
+                        if filter_packet(&decoded.frame, &capture_options) {
+                            let mut packet_frame = PacketFrame::from_decoded_frame(
+                                0,
+                                interface.index,
+                                interface.name.clone(),
+                                decoded,
+                            );
+                            packet_frame.sample_weight = sample_weight;
+                            match shard.lock() {
+                                Ok(mut shard) => {
+                                    fragments.process(packet_frame, |frame| shard.update(frame));
+                                }
+                                Err(e) => {
+                                    thread_log!(error, "Failed to lock shard: {:?}", e);
+                                }
+                            }
+                        }
+                    });
+                    if last_stats.elapsed() >= Duration::from_secs(1) {
+                        match ring.statistics() {
+                            Ok(stats) => {
+                                netstat_strage.add_capture_stats(
+                                    &interface.name,
+                                    stats.packets,
+                                    stats.drops,
+                                    stats.freeze_q_cnt,
+                                );
+                            }
+                            Err(e) => {
+                                thread_log!(error, "PACKET_STATISTICS error: {}", e);
+                            }
+                        }
+                        expire_fragments(&mut fragments, &shard, &interface.name);
+                        add_sampling_stats(&mut sampler, &shard);
+                        last_stats = Instant::now();
+                    }
+                    match stop.lock() {
+                        Ok(stop) => {
+                            if *stop {
+                                break;
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                    if Instant::now().duration_since(start_time) > capture_options.capture_timeout {
+                        break;
+                    }
+                }
+            });
+        match handle {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                thread_log!(error, "Error: {:?}", e);
+            }
+        }
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+/// Release timed out fragments to the shard and report fragment anomalies
+fn expire_fragments(fragments: &mut FragmentTable, shard: &Arc<Mutex<StatShard>>, if_name: &str) {
+    match shard.lock() {
+        Ok(mut shard) => {
+            fragments.expire(Instant::now(), |frame| shard.update(frame));
+        }
+        Err(e) => {
+            thread_log!(error, "Failed to lock shard: {:?}", e);
+        }
+    }
+    let stats = fragments.take_stats();
+    if stats.overlaps > 0 || stats.over_limit > 0 {
+        thread_log!(
+            warn,
+            "[{}] fragments: {} overlapping datagram(s) discarded, {} fragment(s) over table limits",
+            if_name,
+            stats.overlaps,
+            stats.over_limit
+        );
+    }
+}
+
+/// Move the sampler counters to the shard
+fn add_sampling_stats(sampler: &mut Sampler, shard: &Arc<Mutex<StatShard>>) {
+    let stats = sampler.take_stats();
+    match shard.lock() {
+        Ok(mut shard) => {
+            shard.sampling.add(&stats);
+        }
+        Err(e) => {
+            thread_log!(error, "Failed to lock shard: {:?}", e);
+        }
+    }
+}
+
+pub fn get_parse_option(interface: &Interface) -> ParseOption {
+    let mut parse_option: ParseOption = ParseOption::default();
+    if interface.is_tun()
+        || (cfg!(any(target_os = "macos", target_os = "ios")) && interface.is_loopback())
+    {
+        let payload_offset;
+        if interface.is_loopback() {
+            payload_offset = 14;
+        } else {
+            payload_offset = 0;
+        }
+        parse_option.from_ip_packet = true;
+        parse_option.offset = payload_offset;
+    }
+    parse_option
+}
+
 fn filter_packet(frame: &Frame, capture_options: &PacketCaptureOptions) -> bool {
     if let Some(datalink) = &frame.datalink {
         if let Some(ethernet_header) = &datalink.ethernet {
@@ -354,7 +681,41 @@ fn filter_packet(frame: &Frame, capture_options: &PacketCaptureOptions) -> bool
             }
         }
     }
-    true
+    match &capture_options.connection {
+        Some(connection) => filter_connection(frame, connection),
+        None => true,
+    }
+}
+
+/// Match the addresses, ports and protocol of the connection in either direction
+fn filter_connection(frame: &Frame, connection: &SocketConnection) -> bool {
+    let (src_ip, dst_ip) = match &frame.ip {
+        Some(ip) => {
+            if let Some(ipv4) = &ip.ipv4 {
+                (IpAddr::V4(ipv4.source), IpAddr::V4(ipv4.destination))
+            } else if let Some(ipv6) = &ip.ipv6 {
+                (IpAddr::V6(ipv6.source), IpAddr::V6(ipv6.destination))
+            } else {
+                return false;
+            }
+        }
+        None => return false,
+    };
+    let (src_port, dst_port) = match (&frame.transport, connection.protocol) {
+        (Some(transport), TransportProtocol::TCP) => match &transport.tcp {
+            Some(tcp) => (tcp.source, tcp.destination),
+            None => return false,
+        },
+        (Some(transport), TransportProtocol::UDP) => match &transport.udp {
+            Some(udp) => (udp.source, udp.destination),
+            None => return false,
+        },
+        (None, _) => return false,
+    };
+    let local = (connection.local_ip_addr, connection.local_port);
+    let remote = (connection.remote_ip_addr, connection.remote_port);
+    let (src, dst) = ((src_ip, src_port), (dst_ip, dst_port));
+    (src == local && dst == remote) || (src == remote && dst == local)
 }
 
 fn filter_host(src_ip: IpAddr, dst_ip: IpAddr, capture_options: &PacketCaptureOptions) -> bool {
@@ -399,3 +760,45 @@ fn filter_ip_protocol(
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn tcp_frame(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16) -> Frame {
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00];
+        data.extend_from_slice(&[0x45, 0x00, 0x00, 40, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        data.extend_from_slice(&src.octets());
+        data.extend_from_slice(&dst.octets());
+        data.extend_from_slice(&src_port.to_be_bytes());
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x10, 0xff, 0xff, 0, 0, 0, 0]);
+        Frame::from_bytes(&data, ParseOption::default())
+    }
+
+    #[test]
+    fn connection_filter_matches_only_the_connection() {
+        let local = Ipv4Addr::new(192, 168, 1, 10);
+        let remote = Ipv4Addr::new(203, 0, 113, 5);
+        let other = Ipv4Addr::new(203, 0, 113, 6);
+        let connection = SocketConnection {
+            interface_name: String::from("eth0"),
+            local_ip_addr: IpAddr::V4(local),
+            local_port: 40000,
+            remote_ip_addr: IpAddr::V4(remote),
+            remote_port: 443,
+            protocol: TransportProtocol::TCP,
+        };
+        assert!(filter_connection(&tcp_frame(local, 40000, remote, 443), &connection));
+        assert!(filter_connection(&tcp_frame(remote, 443, local, 40000), &connection));
+        // Other flows of the same hosts and ports
+        assert!(!filter_connection(&tcp_frame(local, 40001, remote, 443), &connection));
+        assert!(!filter_connection(&tcp_frame(local, 443, remote, 40000), &connection));
+        assert!(!filter_connection(&tcp_frame(local, 40000, other, 443), &connection));
+        assert!(!filter_connection(&tcp_frame(remote, 40000, local, 443), &connection));
+        let mut udp = connection.clone();
+        udp.protocol = TransportProtocol::UDP;
+        assert!(!filter_connection(&tcp_frame(local, 40000, remote, 443), &udp));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::net::dissect::LinkType;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+
+/// pcapng link type for captured bytes
+pub fn link_type_code(link: LinkType) -> u16 {
+    match link {
+        LinkType::Ethernet => LINKTYPE_ETHERNET,
+        LinkType::Ip { offset: 0 } => LINKTYPE_RAW,
+        LinkType::Ip { offset: 4 } => LINKTYPE_NULL,
+        // Pseudo Ethernet header in front of the IP packet
+        LinkType::Ip { offset: _ } => LINKTYPE_ETHERNET,
+    }
+}
+
+/// Microseconds since the UNIX epoch
+pub fn timestamp_micros(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_micros() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// pcapng writer for frames of a single interface
+pub struct PcapngWriter {
+    writer: BufWriter<File>,
+    pub packets: usize,
+    pub bytes: usize,
+}
+
+impl PcapngWriter {
+    /// Create the file and write the section header and interface description
+    pub fn create(path: &str, if_name: &str, link: LinkType, snaplen: usize) -> Result<PcapngWriter, String> {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Failed to create {}: {}", path, e)),
+        };
+        let mut writer = PcapngWriter {
+            writer: BufWriter::new(file),
+            packets: 0,
+            bytes: 0,
+        };
+        writer.write_section_header()?;
+        writer.write_interface(if_name, link, snaplen)?;
+        Ok(writer)
+    }
+    fn write_section_header(&mut self) -> Result<(), String> {
+        let mut block: Vec<u8> = Vec::with_capacity(28);
+        block.extend_from_slice(&BLOCK_SECTION_HEADER.to_le_bytes());
+        block.extend_from_slice(&28u32.to_le_bytes());
+        block.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        block.extend_from_slice(&1u16.to_le_bytes());
+        block.extend_from_slice(&0u16.to_le_bytes());
+        // Section length is not specified
+        block.extend_from_slice(&(-1i64).to_le_bytes());
+        block.extend_from_slice(&28u32.to_le_bytes());
+        self.write_all(&block)
+    }
+    fn write_interface(&mut self, if_name: &str, link: LinkType, snaplen: usize) -> Result<(), String> {
+        let mut body: Vec<u8> = Vec::new();
+        body.extend_from_slice(&link_type_code(link).to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(snaplen as u32).to_le_bytes());
+        if !if_name.is_empty() {
+            body.extend_from_slice(&OPT_IF_NAME.to_le_bytes());
+            body.extend_from_slice(&(if_name.len() as u16).to_le_bytes());
+            body.extend_from_slice(if_name.as_bytes());
+            pad(&mut body);
+            body.extend_from_slice(&OPT_END.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+        }
+        self.write_block(BLOCK_INTERFACE_DESCRIPTION, &body)
+    }
+    /// Write a frame. orig_len is the length on the wire before snaplen truncation
+    pub fn write_packet(&mut self, timestamp_us: u64, data: &[u8], orig_len: usize) -> Result<(), String> {
+        let mut body: Vec<u8> = Vec::with_capacity(20 + data.len() + 3);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((timestamp_us >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp_us as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(orig_len.max(data.len()) as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+        self.write_block(BLOCK_ENHANCED_PACKET, &body)?;
+        self.packets += 1;
+        self.bytes += data.len();
+        Ok(())
+    }
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<(), String> {
+        let total_len = (body.len() + 12) as u32;
+        let mut block: Vec<u8> = Vec::with_capacity(total_len as usize);
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&total_len.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&total_len.to_le_bytes());
+        self.write_all(&block)
+    }
+    fn write_all(&mut self, data: &[u8]) -> Result<(), String> {
+        match self.writer.write_all(data) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    pub fn flush(&mut self) -> Result<(), String> {
+        match self.writer.flush() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// Pad to a 32-bit boundary
+fn pad(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
@@ -0,0 +1,458 @@
+//! Port scan, port sweep and SYN flood detection on inbound traffic.
+use crate::net::security::{SecurityEvent, SecurityEventKind, Severity};
+use crate::net::socket::TransportProtocol;
+use crate::net::traffic::Direction;
+use nex::packet::tcp::TcpFlags;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Flows remembered by a ProbeTracker before the oldest are pruned
+const MAX_TRACKED_FLOWS: usize = 65536;
+/// Tracked flows older than this are pruned
+const FLOW_TTL: Duration = Duration::from_secs(120);
+/// A SYN not acknowledged within this time is counted as half-open
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Probes kept per source, oldest dropped first
+const MAX_PROBES_PER_SOURCE: usize = 4096;
+/// Ports or hosts listed in the evidence of an event
+const MAX_EVIDENCE_ITEMS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortScanConfig {
+    pub enabled: bool,
+    /// Length of the sliding window
+    pub window_secs: u64,
+    /// Distinct ports of one local host probed by one source
+    pub vertical_port_threshold: usize,
+    /// Distinct local hosts probed on the same port by one source
+    pub horizontal_host_threshold: usize,
+    /// Half-open SYNs to one local port from any source
+    pub syn_flood_threshold: usize,
+}
+
+impl PortScanConfig {
+    pub fn new() -> PortScanConfig {
+        PortScanConfig {
+            enabled: true,
+            window_secs: 60,
+            vertical_port_threshold: 20,
+            horizontal_host_threshold: 10,
+            syn_flood_threshold: 200,
+        }
+    }
+}
+
+impl Default for PortScanConfig {
+    fn default() -> Self {
+        PortScanConfig::new()
+    }
+}
+
+/// Remote and local endpoints of a flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub remote_ip_addr: IpAddr,
+    pub remote_port: u16,
+    pub local_ip_addr: IpAddr,
+    pub local_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeKind {
+    /// Inbound SYN without ACK
+    Syn,
+    /// Inbound ACK completing the handshake of an earlier SYN
+    HandshakeCompleted,
+    /// Inbound UDP that is not a reply to outbound traffic
+    Udp,
+}
+
+/// An inbound connection attempt
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    pub flow: FlowKey,
+    pub kind: ProbeKind,
+    pub time: Instant,
+}
+
+/// Turns packets into probes. Owned by a capture shard
+#[derive(Debug, Clone)]
+pub struct ProbeTracker {
+    /// Inbound SYNs waiting for the final ACK
+    syn_seen: HashMap<FlowKey, Instant>,
+    /// Outbound UDP flows. Inbound datagrams on them are replies
+    udp_outbound: HashMap<FlowKey, Instant>,
+    /// Inbound UDP flows already reported
+    udp_inbound: HashMap<FlowKey, Instant>,
+    /// Direction of the SYN that opened a TCP flow. The sender of the SYN is the client
+    tcp_clients: HashMap<FlowKey, (Direction, Instant)>,
+}
+
+impl ProbeTracker {
+    pub fn new() -> ProbeTracker {
+        ProbeTracker {
+            syn_seen: HashMap::new(),
+            udp_outbound: HashMap::new(),
+            udp_inbound: HashMap::new(),
+            tcp_clients: HashMap::new(),
+        }
+    }
+    /// Probe of a TCP segment, if any. flags are the TCP header flags
+    pub fn observe_tcp(&mut self, direction: Direction, flow: FlowKey, flags: u8) -> Option<Probe> {
+        let now = Instant::now();
+        let syn = flags & TcpFlags::SYN != 0;
+        let ack = flags & TcpFlags::ACK != 0;
+        if syn && !ack {
+            if self.tcp_clients.len() >= MAX_TRACKED_FLOWS {
+                self.tcp_clients.retain(|_, (_, time)| now.duration_since(*time) < FLOW_TTL);
+                if self.tcp_clients.len() >= MAX_TRACKED_FLOWS {
+                    self.tcp_clients.clear();
+                }
+            }
+            self.tcp_clients.insert(flow, (direction, now));
+        }
+        if direction != Direction::Ingress {
+            return None;
+        }
+        if syn && !ack {
+            insert_flow(&mut self.syn_seen, flow, now);
+            return Some(Probe { flow: flow, kind: ProbeKind::Syn, time: now });
+        }
+        if ack && !syn && self.syn_seen.remove(&flow).is_some() {
+            return Some(Probe { flow: flow, kind: ProbeKind::HandshakeCompleted, time: now });
+        }
+        None
+    }
+    /// Direction of the packets sent by the client of a flow.
+    /// None if the start of the flow was not seen, or was forgotten after FLOW_TTL under load
+    pub fn client_direction(&self, flow: &FlowKey, protocol: TransportProtocol) -> Option<Direction> {
+        match protocol {
+            TransportProtocol::TCP => self.tcp_clients.get(flow).map(|(direction, _)| *direction),
+            TransportProtocol::UDP => {
+                if self.udp_outbound.contains_key(flow) {
+                    Some(Direction::Egress)
+                } else if self.udp_inbound.contains_key(flow) {
+                    Some(Direction::Ingress)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+    /// Probe of a UDP datagram, if any
+    pub fn observe_udp(&mut self, direction: Direction, flow: FlowKey) -> Option<Probe> {
+        let now = Instant::now();
+        match direction {
+            Direction::Egress => {
+                insert_flow(&mut self.udp_outbound, flow, now);
+                None
+            }
+            Direction::Ingress => {
+                if self.udp_outbound.contains_key(&flow) || self.udp_inbound.contains_key(&flow) {
+                    return None;
+                }
+                insert_flow(&mut self.udp_inbound, flow, now);
+                Some(Probe { flow: flow, kind: ProbeKind::Udp, time: now })
+            }
+        }
+    }
+}
+
+fn insert_flow(flows: &mut HashMap<FlowKey, Instant>, flow: FlowKey, now: Instant) {
+    if flows.len() >= MAX_TRACKED_FLOWS {
+        flows.retain(|_, time| now.duration_since(*time) < FLOW_TTL);
+        if flows.len() >= MAX_TRACKED_FLOWS {
+            flows.clear();
+        }
+    }
+    flows.insert(flow, now);
+}
+
+/// Sliding window of probes per remote source
+#[derive(Debug, Clone)]
+pub struct ScanDetector {
+    config: PortScanConfig,
+    probes: HashMap<IpAddr, VecDeque<(Instant, IpAddr, u16, TransportProtocol)>>,
+    /// SYNs without a completed handshake
+    pending_syns: HashMap<FlowKey, Instant>,
+}
+
+impl ScanDetector {
+    pub fn new(config: PortScanConfig) -> ScanDetector {
+        ScanDetector {
+            config: config,
+            probes: HashMap::new(),
+            pending_syns: HashMap::new(),
+        }
+    }
+    pub fn set_config(&mut self, config: PortScanConfig) {
+        if !config.enabled {
+            self.probes.clear();
+            self.pending_syns.clear();
+        }
+        self.config = config;
+    }
+    pub fn add(&mut self, probes: Vec<Probe>) {
+        if !self.config.enabled {
+            return;
+        }
+        for probe in probes {
+            let protocol = match probe.kind {
+                ProbeKind::Syn => {
+                    insert_flow(&mut self.pending_syns, probe.flow, probe.time);
+                    TransportProtocol::TCP
+                }
+                ProbeKind::HandshakeCompleted => {
+                    self.pending_syns.remove(&probe.flow);
+                    continue;
+                }
+                ProbeKind::Udp => TransportProtocol::UDP,
+            };
+            let source = self.probes.entry(probe.flow.remote_ip_addr).or_insert_with(VecDeque::new);
+            if source.len() >= MAX_PROBES_PER_SOURCE {
+                source.pop_front();
+            }
+            source.push_back((probe.time, probe.flow.local_ip_addr, probe.flow.local_port, protocol));
+        }
+    }
+    /// Drop probes outside the window and return the detections
+    pub fn evaluate(&mut self) -> Vec<SecurityEvent> {
+        self.evaluate_at(Instant::now())
+    }
+    fn evaluate_at(&mut self, now: Instant) -> Vec<SecurityEvent> {
+        let mut events: Vec<SecurityEvent> = Vec::new();
+        if !self.config.enabled {
+            return events;
+        }
+        let window = Duration::from_secs(self.config.window_secs.max(1));
+        self.probes.retain(|_, probes| {
+            while probes.front().map_or(false, |(time, _, _, _)| now.duration_since(*time) >= window) {
+                probes.pop_front();
+            }
+            !probes.is_empty()
+        });
+        self.pending_syns.retain(|_, time| now.duration_since(*time) < window);
+        for (source, probes) in self.probes.iter() {
+            // Distinct ports per local host, distinct local hosts per port
+            let mut ports_by_host: HashMap<IpAddr, HashSet<(u16, TransportProtocol)>> = HashMap::new();
+            let mut hosts_by_port: HashMap<(u16, TransportProtocol), HashSet<IpAddr>> = HashMap::new();
+            for (_, local_ip_addr, local_port, protocol) in probes.iter() {
+                ports_by_host
+                    .entry(*local_ip_addr)
+                    .or_insert_with(HashSet::new)
+                    .insert((*local_port, *protocol));
+                hosts_by_port
+                    .entry((*local_port, *protocol))
+                    .or_insert_with(HashSet::new)
+                    .insert(*local_ip_addr);
+            }
+            for (target, ports) in ports_by_host {
+                if ports.len() < self.config.vertical_port_threshold.max(1) {
+                    continue;
+                }
+                let mut ports: Vec<(u16, TransportProtocol)> = ports.into_iter().collect();
+                ports.sort_by_key(|(port, _)| *port);
+                let mut event = SecurityEvent::new(
+                    SecurityEventKind::VerticalScan,
+                    if ports.len() >= self.config.vertical_port_threshold * 5 { Severity::High } else { Severity::Medium },
+                    format!("{} probed {} ports of {} in {}s", source, ports.len(), target, window.as_secs()),
+                );
+                event.source_ip_addr = Some(*source);
+                event.target_ip_addr = Some(target);
+                event.evidence = ports
+                    .iter()
+                    .take(MAX_EVIDENCE_ITEMS)
+                    .map(|(port, protocol)| format!("{}/{}", port, protocol.as_str().to_lowercase()))
+                    .collect();
+                events.push(event);
+            }
+            for ((port, protocol), hosts) in hosts_by_port {
+                if hosts.len() < self.config.horizontal_host_threshold.max(1) {
+                    continue;
+                }
+                let mut hosts: Vec<IpAddr> = hosts.into_iter().collect();
+                hosts.sort();
+                let mut event = SecurityEvent::new(
+                    SecurityEventKind::HorizontalSweep,
+                    Severity::Medium,
+                    format!(
+                        "{} probed {}/{} on {} hosts in {}s",
+                        source,
+                        port,
+                        protocol.as_str().to_lowercase(),
+                        hosts.len(),
+                        window.as_secs()
+                    ),
+                );
+                event.source_ip_addr = Some(*source);
+                event.evidence = hosts.iter().take(MAX_EVIDENCE_ITEMS).map(|host| host.to_string()).collect();
+                events.push(event);
+            }
+        }
+        // Half-open SYNs per local port. Flood sources are often spoofed, so sources are not keyed
+        let mut half_open: HashMap<(IpAddr, u16), HashMap<IpAddr, usize>> = HashMap::new();
+        for (flow, time) in self.pending_syns.iter() {
+            if now.duration_since(*time) < HANDSHAKE_TIMEOUT {
+                continue;
+            }
+            *half_open
+                .entry((flow.local_ip_addr, flow.local_port))
+                .or_insert_with(HashMap::new)
+                .entry(flow.remote_ip_addr)
+                .or_insert(0) += 1;
+        }
+        for ((target, port), sources) in half_open {
+            let count: usize = sources.values().sum();
+            if count < self.config.syn_flood_threshold.max(1) {
+                continue;
+            }
+            let mut sources: Vec<(IpAddr, usize)> = sources.into_iter().collect();
+            sources.sort_by(|a, b| b.1.cmp(&a.1));
+            let mut event = SecurityEvent::new(
+                SecurityEventKind::SynFlood,
+                Severity::High,
+                format!(
+                    "{} half-open connections to {} port {} from {} sources in {}s",
+                    count,
+                    target,
+                    port,
+                    sources.len(),
+                    window.as_secs()
+                ),
+            );
+            // A single source is named, many sources are likely spoofed
+            if sources.len() == 1 {
+                event.source_ip_addr = Some(sources[0].0);
+            }
+            event.target_ip_addr = Some(target);
+            event.evidence = sources
+                .iter()
+                .take(MAX_EVIDENCE_ITEMS)
+                .map(|(source, count)| format!("{}: {} SYN", source, count))
+                .collect();
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const SCANNER: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5));
+
+    fn local(host: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, host))
+    }
+
+    fn probe(remote: IpAddr, local_ip_addr: IpAddr, local_port: u16, kind: ProbeKind, time: Instant) -> Probe {
+        Probe {
+            flow: FlowKey {
+                remote_ip_addr: remote,
+                remote_port: 40000,
+                local_ip_addr: local_ip_addr,
+                local_port: local_port,
+            },
+            kind: kind,
+            time: time,
+        }
+    }
+
+    fn kinds(events: &[SecurityEvent]) -> Vec<SecurityEventKind> {
+        events.iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn tracker_reports_inbound_syn_and_handshake() {
+        let mut tracker = ProbeTracker::new();
+        let flow = probe(SCANNER, local(10), 22, ProbeKind::Syn, Instant::now()).flow;
+        assert!(tracker.observe_tcp(Direction::Egress, flow, TcpFlags::SYN).is_none());
+        let syn = tracker.observe_tcp(Direction::Ingress, flow, TcpFlags::SYN).unwrap();
+        assert_eq!(syn.kind, ProbeKind::Syn);
+        assert!(tracker.observe_tcp(Direction::Ingress, flow, TcpFlags::SYN | TcpFlags::ACK).is_none());
+        let ack = tracker.observe_tcp(Direction::Ingress, flow, TcpFlags::ACK).unwrap();
+        assert_eq!(ack.kind, ProbeKind::HandshakeCompleted);
+        // Only the first ACK completes the handshake
+        assert!(tracker.observe_tcp(Direction::Ingress, flow, TcpFlags::ACK).is_none());
+    }
+
+    #[test]
+    fn tracker_ignores_udp_replies() {
+        let mut tracker = ProbeTracker::new();
+        let reply = probe(SCANNER, local(10), 53000, ProbeKind::Udp, Instant::now()).flow;
+        assert!(tracker.observe_udp(Direction::Egress, reply).is_none());
+        assert!(tracker.observe_udp(Direction::Ingress, reply).is_none());
+        let unsolicited = probe(SCANNER, local(10), 161, ProbeKind::Udp, Instant::now()).flow;
+        assert_eq!(tracker.observe_udp(Direction::Ingress, unsolicited).unwrap().kind, ProbeKind::Udp);
+        // Reported once per flow
+        assert!(tracker.observe_udp(Direction::Ingress, unsolicited).is_none());
+    }
+
+    #[test]
+    fn vertical_scan_fires_at_threshold_and_expires() {
+        let config = PortScanConfig::new();
+        let mut detector = ScanDetector::new(config.clone());
+        let base = Instant::now();
+        let probes: Vec<Probe> = (0..config.vertical_port_threshold as u16 - 1)
+            .map(|i| probe(SCANNER, local(10), 1000 + i, ProbeKind::Udp, base))
+            .collect();
+        detector.add(probes);
+        assert!(detector.evaluate_at(base).is_empty());
+        detector.add(vec![probe(SCANNER, local(10), 2000, ProbeKind::Udp, base)]);
+        let events = detector.evaluate_at(base);
+        assert_eq!(kinds(&events), vec![SecurityEventKind::VerticalScan]);
+        assert_eq!(events[0].source_ip_addr, Some(SCANNER));
+        assert_eq!(events[0].target_ip_addr, Some(local(10)));
+        let window = Duration::from_secs(config.window_secs);
+        assert!(detector.evaluate_at(base + window).is_empty());
+    }
+
+    #[test]
+    fn horizontal_sweep_fires_at_threshold() {
+        let config = PortScanConfig::new();
+        let mut detector = ScanDetector::new(config.clone());
+        let base = Instant::now();
+        let probes: Vec<Probe> = (0..config.horizontal_host_threshold as u8)
+            .map(|host| probe(SCANNER, local(host + 1), 445, ProbeKind::Syn, base))
+            .collect();
+        detector.add(probes);
+        let events = detector.evaluate_at(base);
+        assert_eq!(kinds(&events), vec![SecurityEventKind::HorizontalSweep]);
+        assert_eq!(events[0].evidence.len(), config.horizontal_host_threshold);
+    }
+
+    #[test]
+    fn syn_flood_counts_half_open_connections_only() {
+        let mut config = PortScanConfig::new();
+        config.syn_flood_threshold = 50;
+        let mut detector = ScanDetector::new(config.clone());
+        let base = Instant::now();
+        let sources: Vec<IpAddr> = (0..50u32).map(|i| IpAddr::V4(Ipv4Addr::from(0x0A00_0000 + i))).collect();
+        detector.add(sources.iter().map(|source| probe(*source, local(10), 80, ProbeKind::Syn, base)).collect());
+        // Not half-open until the handshake timeout
+        assert!(detector.evaluate_at(base).is_empty());
+        let events = detector.evaluate_at(base + HANDSHAKE_TIMEOUT);
+        assert_eq!(kinds(&events), vec![SecurityEventKind::SynFlood]);
+        assert_eq!(events[0].source_ip_addr, None);
+        assert_eq!(events[0].target_ip_addr, Some(local(10)));
+        // A completed handshake drops the flow below the threshold
+        detector.add(vec![probe(sources[0], local(10), 80, ProbeKind::HandshakeCompleted, base)]);
+        assert!(detector.evaluate_at(base + HANDSHAKE_TIMEOUT).is_empty());
+        let window = Duration::from_secs(config.window_secs);
+        assert!(detector.evaluate_at(base + window).is_empty());
+    }
+
+    #[test]
+    fn disabled_detector_reports_nothing() {
+        let mut config = PortScanConfig::new();
+        config.enabled = false;
+        let mut detector = ScanDetector::new(config);
+        let base = Instant::now();
+        detector.add((0..100u16).map(|port| probe(SCANNER, local(10), port, ProbeKind::Syn, base)).collect());
+        assert!(detector.evaluate_at(base).is_empty());
+    }
+}
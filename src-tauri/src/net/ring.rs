@@ -0,0 +1,716 @@
+use crate::net::dissect::LinkType;
+use crate::net::pcapng::{self, PcapngWriter};
+use crate::net::socket::{SocketConnection, TransportProtocol};
+use crate::net::stat::NetStatData;
+use crate::sys;
+use crate::thread_log;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Directory under the config directory for ring dumps
+pub const RING_DUMP_DIR_NAME: &str = "captures";
+
+/// Connection that fires a dump when it appears. Unset fields match anything
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConnectionWatch {
+    pub ip_addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub protocol: Option<TransportProtocol>,
+}
+
+impl ConnectionWatch {
+    pub fn matches(&self, connection: &SocketConnection) -> bool {
+        if let Some(ip_addr) = self.ip_addr {
+            if connection.local_ip_addr != ip_addr && connection.remote_ip_addr != ip_addr {
+                return false;
+            }
+        }
+        if let Some(port) = self.port {
+            if connection.local_port != port && connection.remote_port != port {
+                return false;
+            }
+        }
+        if let Some(protocol) = self.protocol {
+            if connection.protocol != protocol {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Pre-trigger capture ring configuration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureRingConfig {
+    pub enabled: bool,
+    /// Maximum bytes of frames kept per interface
+    pub max_bytes: usize,
+    /// Frames older than this are dropped
+    pub window_secs: u64,
+    /// Keep recording this long after a trigger
+    pub post_trigger_secs: u64,
+    /// Dump directory. The captures directory in the config directory if not set
+    pub output_dir: Option<String>,
+    /// Dump when a host exceeds this rate in bytes per second. 0 disables
+    pub host_bandwidth_threshold: u64,
+    /// Dump when a matching connection appears
+    pub watch_connections: Vec<ConnectionWatch>,
+    /// Minimum interval between automatic dumps for the same host or connection
+    pub trigger_cooldown_secs: u64,
+    /// Bytes kept of each frame. 0 keeps whole frames.
+    /// Background capture keeps at least this many bytes even with a smaller network snaplen
+    #[serde(default)]
+    pub snaplen: usize,
+}
+
+impl CaptureRingConfig {
+    pub fn new() -> CaptureRingConfig {
+        CaptureRingConfig {
+            enabled: true,
+            max_bytes: 32 * 1024 * 1024,
+            window_secs: 60,
+            post_trigger_secs: 10,
+            output_dir: None,
+            host_bandwidth_threshold: 0,
+            watch_connections: Vec::new(),
+            trigger_cooldown_secs: 300,
+            snaplen: 0,
+        }
+    }
+}
+
+impl Default for CaptureRingConfig {
+    fn default() -> Self {
+        CaptureRingConfig::new()
+    }
+}
+
+/// What fired a dump
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RingTrigger {
+    Manual,
+    HostBandwidth { ip_addr: IpAddr, bytes_per_sec: u64 },
+    Connection { connection: SocketConnection },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingDumpState {
+    /// Writing frames after the trigger
+    Recording,
+    Completed,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RingDumpInfo {
+    pub id: u32,
+    pub if_name: String,
+    pub trigger: RingTrigger,
+    pub path: String,
+    pub state: RingDumpState,
+    pub error: Option<String>,
+    /// Frames from before the trigger
+    pub pre_trigger_packets: usize,
+    pub packets: usize,
+    pub bytes: usize,
+    pub trigger_time: String,
+    pub end_time: String,
+}
+
+#[derive(Clone)]
+struct RingFrame {
+    timestamp_us: u64,
+    received: Instant,
+    data: Vec<u8>,
+    orig_len: usize,
+}
+
+/// Dump still recording frames after its trigger. Frames go to its writer thread
+struct ActiveDump {
+    sender: mpsc::Sender<RingFrame>,
+    deadline: Instant,
+}
+
+/// Write the pre-trigger frames and then frames received until the sender is dropped
+fn write_dump(
+    mut writer: PcapngWriter,
+    frames: Vec<RingFrame>,
+    receiver: mpsc::Receiver<RingFrame>,
+    info: Arc<Mutex<RingDumpInfo>>,
+) {
+    for frame in frames.iter() {
+        match writer.write_packet(frame.timestamp_us, &frame.data, frame.orig_len) {
+            Ok(_) => {}
+            Err(e) => {
+                fail_dump(&info, e);
+                return;
+            }
+        }
+    }
+    drop(frames);
+    match info.lock() {
+        Ok(mut info) => {
+            info.pre_trigger_packets = writer.packets;
+            info.packets = writer.packets;
+            info.bytes = writer.bytes;
+        }
+        Err(_) => {}
+    }
+    for frame in receiver.iter() {
+        match writer.write_packet(frame.timestamp_us, &frame.data, frame.orig_len) {
+            Ok(_) => {}
+            Err(e) => {
+                fail_dump(&info, e);
+                return;
+            }
+        }
+    }
+    let error = writer.flush().err();
+    match info.lock() {
+        Ok(mut info) => {
+            info.packets = writer.packets;
+            info.bytes = writer.bytes;
+            info.state = if error.is_some() {
+                RingDumpState::Failed
+            } else {
+                RingDumpState::Completed
+            };
+            info.error = error;
+            info.end_time = sys::get_sysdate();
+            thread_log!(
+                info,
+                "[{}] ring dump {} finished: {} packets to {}",
+                info.if_name,
+                info.id,
+                info.packets,
+                info.path
+            );
+        }
+        Err(e) => {
+            thread_log!(error, "ring dump lock error: {:?}", e);
+        }
+    }
+}
+
+/// Recent raw frames of an interface
+pub struct PacketRing {
+    if_name: String,
+    link: LinkType,
+    enabled: bool,
+    max_bytes: usize,
+    window: Duration,
+    snaplen: usize,
+    frames: VecDeque<RingFrame>,
+    bytes: usize,
+    dumps: Vec<ActiveDump>,
+}
+
+impl PacketRing {
+    fn new(if_name: String, link: LinkType, config: &CaptureRingConfig) -> PacketRing {
+        let mut ring = PacketRing {
+            if_name: if_name,
+            link: link,
+            enabled: false,
+            max_bytes: 0,
+            window: Duration::ZERO,
+            snaplen: 0,
+            frames: VecDeque::new(),
+            bytes: 0,
+            dumps: Vec::new(),
+        };
+        ring.apply_config(config);
+        ring
+    }
+    fn apply_config(&mut self, config: &CaptureRingConfig) {
+        self.enabled = config.enabled;
+        self.max_bytes = config.max_bytes;
+        self.window = Duration::from_secs(config.window_secs);
+        self.snaplen = config.snaplen;
+        if !self.enabled {
+            self.frames.clear();
+            self.bytes = 0;
+        }
+        self.evict(Instant::now());
+    }
+    /// Add a captured frame. orig_len is the length on the wire
+    pub fn push(&mut self, data: &[u8], orig_len: usize) {
+        self.push_at(data, orig_len, Instant::now());
+    }
+    fn push_at(&mut self, data: &[u8], orig_len: usize, now: Instant) {
+        self.finish_dumps(now);
+        if !self.enabled && self.dumps.is_empty() {
+            return;
+        }
+        let kept = if self.snaplen > 0 { data.len().min(self.snaplen) } else { data.len() };
+        let frame = RingFrame {
+            timestamp_us: pcapng::timestamp_micros(SystemTime::now()),
+            received: now,
+            data: data[..kept].to_vec(),
+            orig_len: orig_len,
+        };
+        // A dump whose writer thread has failed no longer receives
+        self.dumps.retain(|dump| dump.sender.send(frame.clone()).is_ok());
+        if self.enabled {
+            self.bytes += frame.data.len();
+            self.frames.push_back(frame);
+            self.evict(now);
+        }
+    }
+    fn evict(&mut self, now: Instant) {
+        while let Some(frame) = self.frames.front() {
+            if self.bytes <= self.max_bytes && now.duration_since(frame.received) <= self.window {
+                break;
+            }
+            self.bytes -= frame.data.len();
+            self.frames.pop_front();
+        }
+    }
+    /// Close dumps whose post-trigger time has passed
+    fn finish_dumps(&mut self, now: Instant) {
+        // Dropping the sender lets the writer thread finish the file
+        self.dumps.retain(|dump| now < dump.deadline);
+    }
+    /// Copy the ring to a new dump written on its own thread and keep recording until post_trigger has passed
+    fn start_dump(
+        &mut self,
+        path: PathBuf,
+        post_trigger: Duration,
+        info: Arc<Mutex<RingDumpInfo>>,
+    ) -> Option<thread::JoinHandle<()>> {
+        let path_str = path.to_string_lossy().to_string();
+        let writer = match PcapngWriter::create(&path_str, &self.if_name, self.link, self.snaplen) {
+            Ok(writer) => writer,
+            Err(e) => {
+                fail_dump(&info, e);
+                return None;
+            }
+        };
+        let frames: Vec<RingFrame> = self.frames.iter().cloned().collect();
+        let (sender, receiver) = mpsc::channel();
+        let thread_info = Arc::clone(&info);
+        let handle = match thread::Builder::new()
+            .name(format!("ring-dump-{}", self.if_name))
+            .spawn(move || write_dump(writer, frames, receiver, thread_info))
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                fail_dump(&info, e.to_string());
+                return None;
+            }
+        };
+        if !post_trigger.is_zero() {
+            self.dumps.push(ActiveDump {
+                sender: sender,
+                deadline: Instant::now() + post_trigger,
+            });
+        }
+        Some(handle)
+    }
+}
+
+fn fail_dump(info: &Arc<Mutex<RingDumpInfo>>, error: String) {
+    match info.lock() {
+        Ok(mut info) => {
+            thread_log!(error, "[{}] ring dump {} failed: {}", info.if_name, info.id, error);
+            info.state = RingDumpState::Failed;
+            info.error = Some(error);
+            info.end_time = sys::get_sysdate();
+        }
+        Err(_) => {}
+    }
+}
+
+/// State of automatic triggers
+struct TriggerState {
+    /// Last dump time per host or connection
+    last_fired: HashMap<String, Instant>,
+    /// Watched connections already seen
+    seen_connections: HashSet<SocketConnection>,
+}
+
+/// Pre-trigger capture rings of all interfaces and their dumps
+pub struct CaptureRing {
+    config: Mutex<CaptureRingConfig>,
+    rings: Mutex<HashMap<String, Arc<Mutex<PacketRing>>>>,
+    dumps: Mutex<Vec<Arc<Mutex<RingDumpInfo>>>>,
+    next_dump_id: Mutex<u32>,
+    trigger_state: Mutex<TriggerState>,
+}
+
+impl CaptureRing {
+    pub fn new(config: CaptureRingConfig) -> CaptureRing {
+        CaptureRing {
+            config: Mutex::new(config),
+            rings: Mutex::new(HashMap::new()),
+            dumps: Mutex::new(Vec::new()),
+            next_dump_id: Mutex::new(1),
+            trigger_state: Mutex::new(TriggerState {
+                last_fired: HashMap::new(),
+                seen_connections: HashSet::new(),
+            }),
+        }
+    }
+    pub fn get_config(&self) -> CaptureRingConfig {
+        match self.config.lock() {
+            Ok(config) => config.clone(),
+            Err(_) => CaptureRingConfig::new(),
+        }
+    }
+    pub fn set_config(&self, new_config: CaptureRingConfig) {
+        match self.rings.lock() {
+            Ok(rings) => {
+                for ring in rings.values() {
+                    match ring.lock() {
+                        Ok(mut ring) => ring.apply_config(&new_config),
+                        Err(_) => {}
+                    }
+                }
+            }
+            Err(e) => {
+                thread_log!(error, "capture ring lock error: {:?}", e);
+            }
+        }
+        match self.config.lock() {
+            Ok(mut config) => {
+                *config = new_config;
+            }
+            Err(e) => {
+                thread_log!(error, "capture ring lock error: {:?}", e);
+            }
+        }
+    }
+    /// Ring of an interface for its capture threads
+    pub fn register(&self, if_name: &str, link: LinkType) -> Arc<Mutex<PacketRing>> {
+        let config = self.get_config();
+        match self.rings.lock() {
+            Ok(mut rings) => {
+                let ring = rings
+                    .entry(if_name.to_string())
+                    .or_insert_with(|| Arc::new(Mutex::new(PacketRing::new(if_name.to_string(), link, &config))));
+                match ring.lock() {
+                    Ok(mut r) => r.link = link,
+                    Err(_) => {}
+                }
+                Arc::clone(ring)
+            }
+            Err(_) => Arc::new(Mutex::new(PacketRing::new(if_name.to_string(), link, &config))),
+        }
+    }
+    /// Dump the rings of if_names (all interfaces if empty)
+    pub fn trigger(&self, if_names: &[String], trigger: RingTrigger) -> Vec<RingDumpInfo> {
+        let config = self.get_config();
+        let dir = match &config.output_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => match sys::get_user_file_path(RING_DUMP_DIR_NAME) {
+                Some(path) => path,
+                None => {
+                    thread_log!(error, "ring dump: no config directory");
+                    return Vec::new();
+                }
+            },
+        };
+        match std::fs::create_dir_all(&dir) {
+            Ok(_) => {}
+            Err(e) => {
+                thread_log!(error, "ring dump: failed to create {:?}: {}", dir, e);
+                return Vec::new();
+            }
+        }
+        let rings: Vec<Arc<Mutex<PacketRing>>> = match self.rings.lock() {
+            Ok(rings) => rings
+                .iter()
+                .filter(|(if_name, _)| if_names.is_empty() || if_names.contains(if_name))
+                .map(|(_, ring)| Arc::clone(ring))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut started: Vec<RingDumpInfo> = Vec::new();
+        for ring in rings {
+            let mut ring = match ring.lock() {
+                Ok(ring) => ring,
+                Err(_) => continue,
+            };
+            let id = self.next_id();
+            let file_name = format!(
+                "netpulsar-{}-{}-{}.pcapng",
+                ring.if_name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_"),
+                chrono::Local::now().format("%Y%m%d-%H%M%S"),
+                id
+            );
+            let path = dir.join(file_name);
+            let info = Arc::new(Mutex::new(RingDumpInfo {
+                id: id,
+                if_name: ring.if_name.clone(),
+                trigger: trigger.clone(),
+                path: path.to_string_lossy().to_string(),
+                state: RingDumpState::Recording,
+                error: None,
+                pre_trigger_packets: 0,
+                packets: 0,
+                bytes: 0,
+                trigger_time: sys::get_sysdate(),
+                end_time: String::new(),
+            }));
+            thread_log!(info, "[{}] ring dump {} triggered: {:?}", ring.if_name, id, trigger);
+            ring.start_dump(path, Duration::from_secs(config.post_trigger_secs), Arc::clone(&info));
+            match info.lock() {
+                Ok(info) => started.push(info.clone()),
+                Err(_) => {}
+            }
+            match self.dumps.lock() {
+                Ok(mut dumps) => dumps.push(info),
+                Err(_) => {}
+            }
+        }
+        started
+    }
+    fn next_id(&self) -> u32 {
+        match self.next_dump_id.lock() {
+            Ok(mut next_id) => {
+                let id = *next_id;
+                *next_id = next_id.wrapping_add(1).max(1);
+                id
+            }
+            Err(_) => 0,
+        }
+    }
+    /// Dumps ordered by ID
+    pub fn list_dumps(&self) -> Vec<RingDumpInfo> {
+        match self.dumps.lock() {
+            Ok(dumps) => dumps
+                .iter()
+                .filter_map(|info| match info.lock() {
+                    Ok(info) => Some(info.clone()),
+                    Err(_) => None,
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+    /// Close dumps on interfaces without traffic after their post-trigger time
+    pub fn finish_expired(&self) {
+        let rings: Vec<Arc<Mutex<PacketRing>>> = match self.rings.lock() {
+            Ok(rings) => rings.values().cloned().collect(),
+            Err(_) => return,
+        };
+        let now = Instant::now();
+        for ring in rings {
+            match ring.lock() {
+                Ok(mut ring) => ring.finish_dumps(now),
+                Err(_) => {}
+            }
+        }
+    }
+    /// Fire automatic triggers from the latest statistics
+    pub fn check_triggers(&self, data: &NetStatData) {
+        let config = self.get_config();
+        if !config.enabled {
+            return;
+        }
+        let cooldown = Duration::from_secs(config.trigger_cooldown_secs);
+        let mut fired: Vec<(Vec<String>, RingTrigger)> = Vec::new();
+        match self.trigger_state.lock() {
+            Ok(mut state) => {
+                state.last_fired.retain(|_, time| time.elapsed() < cooldown);
+                if config.host_bandwidth_threshold > 0 {
+                    for (ip_addr, host) in data.remote_hosts.iter() {
+                        let bytes_per_sec = host.traffic_info.ingress_bytes_per_sec
+                            + host.traffic_info.egress_bytes_per_sec;
+                        if bytes_per_sec < config.host_bandwidth_threshold {
+                            continue;
+                        }
+                        let key = format!("host:{}", ip_addr);
+                        if state.last_fired.contains_key(&key) {
+                            continue;
+                        }
+                        // Interfaces the host was seen on
+                        let if_names: HashSet<String> = data
+                            .connection_map
+                            .keys()
+                            .filter(|conn| conn.remote_ip_addr == *ip_addr)
+                            .map(|conn| conn.interface_name.clone())
+                            .collect();
+                        // No connections to tell the interface. An empty list would dump every interface
+                        if if_names.is_empty() {
+                            continue;
+                        }
+                        state.last_fired.insert(key, Instant::now());
+                        fired.push((
+                            if_names.into_iter().collect(),
+                            RingTrigger::HostBandwidth {
+                                ip_addr: *ip_addr,
+                                bytes_per_sec: bytes_per_sec,
+                            },
+                        ));
+                    }
+                }
+                if config.watch_connections.is_empty() {
+                    state.seen_connections.clear();
+                } else {
+                    state
+                        .seen_connections
+                        .retain(|conn| data.connection_map.contains_key(conn));
+                    for conn in data.connection_map.keys() {
+                        if state.seen_connections.contains(conn)
+                            || !config.watch_connections.iter().any(|w| w.matches(conn))
+                        {
+                            continue;
+                        }
+                        state.seen_connections.insert(conn.clone());
+                        let key = format!("conn:{:?}", conn);
+                        if state.last_fired.contains_key(&key) {
+                            continue;
+                        }
+                        state.last_fired.insert(key, Instant::now());
+                        fired.push((
+                            vec![conn.interface_name.clone()],
+                            RingTrigger::Connection {
+                                connection: conn.clone(),
+                            },
+                        ));
+                    }
+                }
+            }
+            Err(e) => {
+                thread_log!(error, "capture ring lock error: {:?}", e);
+                return;
+            }
+        }
+        for (if_names, trigger) in fired {
+            self.trigger(&if_names, trigger);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::host::RemoteHostInfo;
+
+    fn test_config(max_bytes: usize, window_secs: u64) -> CaptureRingConfig {
+        let mut config = CaptureRingConfig::new();
+        config.max_bytes = max_bytes;
+        config.window_secs = window_secs;
+        config
+    }
+
+    fn test_info(path: &PathBuf) -> Arc<Mutex<RingDumpInfo>> {
+        Arc::new(Mutex::new(RingDumpInfo {
+            id: 1,
+            if_name: "eth0".to_string(),
+            trigger: RingTrigger::Manual,
+            path: path.to_string_lossy().to_string(),
+            state: RingDumpState::Recording,
+            error: None,
+            pre_trigger_packets: 0,
+            packets: 0,
+            bytes: 0,
+            trigger_time: String::new(),
+            end_time: String::new(),
+        }))
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("netpulsar-ring-{}-{}.pcapng", name, std::process::id()))
+    }
+
+    #[test]
+    fn byte_limit_evicts_oldest_frames() {
+        let mut ring = PacketRing::new("eth0".to_string(), LinkType::Ethernet, &test_config(250, 60));
+        let base = Instant::now();
+        for i in 0..3u8 {
+            ring.push_at(&[i; 100], 100, base);
+        }
+        assert_eq!(ring.frames.len(), 2);
+        assert_eq!(ring.bytes, 200);
+        assert_eq!(ring.frames[0].data[0], 1);
+    }
+
+    #[test]
+    fn window_evicts_old_frames() {
+        let mut ring = PacketRing::new("eth0".to_string(), LinkType::Ethernet, &test_config(1024, 60));
+        let base = Instant::now();
+        ring.push_at(&[0; 10], 10, base);
+        ring.push_at(&[1; 10], 10, base + Duration::from_secs(30));
+        ring.push_at(&[2; 10], 10, base + Duration::from_secs(90));
+        assert_eq!(ring.frames.len(), 2);
+        assert_eq!(ring.bytes, 20);
+        assert_eq!(ring.frames[0].data[0], 1);
+    }
+
+    #[test]
+    fn snaplen_and_disabled_ring() {
+        let mut config = test_config(1024, 60);
+        config.snaplen = 4;
+        let mut ring = PacketRing::new("eth0".to_string(), LinkType::Ethernet, &config);
+        ring.push(&[0; 10], 10);
+        assert_eq!(ring.frames[0].data.len(), 4);
+        assert_eq!(ring.frames[0].orig_len, 10);
+        config.enabled = false;
+        ring.apply_config(&config);
+        ring.push(&[0; 10], 10);
+        assert!(ring.frames.is_empty());
+        assert_eq!(ring.bytes, 0);
+    }
+
+    #[test]
+    fn dump_records_until_post_trigger_deadline() {
+        let mut ring = PacketRing::new("eth0".to_string(), LinkType::Ethernet, &test_config(1024, 60));
+        ring.push(&[0; 60], 60);
+        ring.push(&[1; 60], 60);
+        let path = test_path("post");
+        let info = test_info(&path);
+        let handle = ring.start_dump(path.clone(), Duration::from_secs(10), Arc::clone(&info)).unwrap();
+        ring.push(&[2; 60], 60);
+        // Past the deadline: closes the dump before the frame is written
+        ring.push_at(&[3; 60], 60, Instant::now() + Duration::from_secs(20));
+        assert!(ring.dumps.is_empty());
+        handle.join().unwrap();
+        let info = info.lock().unwrap().clone();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(info.state, RingDumpState::Completed);
+        assert_eq!(info.pre_trigger_packets, 2);
+        assert_eq!(info.packets, 3);
+    }
+
+    #[test]
+    fn dump_without_post_trigger_writes_snapshot() {
+        let mut ring = PacketRing::new("eth0".to_string(), LinkType::Ethernet, &test_config(1024, 60));
+        ring.push(&[0; 60], 60);
+        let path = test_path("snapshot");
+        let info = test_info(&path);
+        let handle = ring.start_dump(path.clone(), Duration::ZERO, Arc::clone(&info)).unwrap();
+        assert!(ring.dumps.is_empty());
+        handle.join().unwrap();
+        let info = info.lock().unwrap().clone();
+        let written = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(info.state, RingDumpState::Completed);
+        assert_eq!(info.packets, 1);
+        assert!(written > 60);
+    }
+
+    #[test]
+    fn host_trigger_without_connections_is_skipped() {
+        let dir = std::env::temp_dir().join(format!("netpulsar-ring-trigger-{}", std::process::id()));
+        let mut config = test_config(1024, 60);
+        config.host_bandwidth_threshold = 100;
+        config.post_trigger_secs = 0;
+        config.output_dir = Some(dir.to_string_lossy().to_string());
+        let capture_ring = CaptureRing::new(config);
+        capture_ring.register("eth0", LinkType::Ethernet);
+        let ip_addr: IpAddr = "203.0.113.1".parse().unwrap();
+        let mut host = RemoteHostInfo::new(String::new(), ip_addr);
+        host.traffic_info.ingress_bytes_per_sec = 1000;
+        let mut data = NetStatData::new();
+        data.remote_hosts.insert(ip_addr, host);
+        capture_ring.check_triggers(&data);
+        assert!(capture_ring.list_dumps().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::net::dissect::LinkType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Window for measuring per-flow packet rates
+const FLOW_WINDOW: Duration = Duration::from_secs(1);
+/// Flows idle for this long are forgotten
+const FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// TCP header flags read by detector_check
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+/// Maximum flows tracked by adaptive sampling. New flows beyond this are sampled at the maximum rate
+const MAX_FLOWS: usize = 65536;
+
+/// Packet sampling mode for background capture
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingMode {
+    /// Every frame is counted
+    Off,
+    /// Every N-th frame
+    OneInN,
+    /// Each frame with a fixed probability
+    Probabilistic,
+    /// Light flows are counted exactly, heavy flows are sampled in proportion to their rate
+    AdaptiveFlow,
+}
+
+impl Default for SamplingMode {
+    fn default() -> Self {
+        SamplingMode::Off
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplingConfig {
+    pub mode: SamplingMode,
+    /// N for OneInN
+    pub rate: u32,
+    /// Probability for Probabilistic. Rounded to the nearest 1/N so that sampled frames weigh exactly N
+    pub probability: f64,
+    /// Packets per second a flow may reach before AdaptiveFlow samples it
+    pub flow_exact_pps: u32,
+    /// Maximum N applied to a single flow by AdaptiveFlow
+    pub flow_max_rate: u32,
+}
+
+impl SamplingConfig {
+    pub fn new() -> SamplingConfig {
+        SamplingConfig {
+            mode: SamplingMode::Off,
+            rate: 10,
+            probability: 0.1,
+            flow_exact_pps: 100,
+            flow_max_rate: 1000,
+        }
+    }
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig::new()
+    }
+}
+
+/// Frames seen and frames sampled by a capture thread
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplingStats {
+    /// Frames read from the interface
+    pub seen_packets: u64,
+    /// Frames counted in the traffic statistics
+    pub sampled_packets: u64,
+}
+
+impl SamplingStats {
+    pub fn new() -> SamplingStats {
+        SamplingStats {
+            seen_packets: 0,
+            sampled_packets: 0,
+        }
+    }
+    pub fn add(&mut self, other: &SamplingStats) {
+        self.seen_packets += other.seen_packets;
+        self.sampled_packets += other.sampled_packets;
+    }
+}
+
+/// Sampling state shown with the statistics
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplingInfo {
+    pub mode: SamplingMode,
+    /// True if counters are extrapolated from samples
+    pub estimated: bool,
+    /// Frames seen per frame sampled in the last interval. 1.0 without sampling
+    pub effective_rate: f64,
+    pub seen_packets: u64,
+    pub sampled_packets: u64,
+}
+
+impl SamplingInfo {
+    pub fn new() -> SamplingInfo {
+        SamplingInfo {
+            mode: SamplingMode::Off,
+            estimated: false,
+            effective_rate: 1.0,
+            seen_packets: 0,
+            sampled_packets: 0,
+        }
+    }
+    pub fn from_stats(mode: SamplingMode, stats: &SamplingStats) -> SamplingInfo {
+        let effective_rate = if stats.sampled_packets > 0 {
+            stats.seen_packets as f64 / stats.sampled_packets as f64
+        } else {
+            1.0
+        };
+        SamplingInfo {
+            mode: mode,
+            estimated: mode != SamplingMode::Off,
+            effective_rate: effective_rate,
+            seen_packets: stats.seen_packets,
+            sampled_packets: stats.sampled_packets,
+        }
+    }
+}
+
+struct FlowState {
+    window_start: Instant,
+    window_packets: u32,
+    /// N applied in the current window
+    rate: u32,
+    counter: u32,
+    last_seen: Instant,
+}
+
+/// Decides which frames are parsed. Each capture thread owns one
+pub struct Sampler {
+    config: SamplingConfig,
+    counter: u32,
+    rng_state: u64,
+    flows: HashMap<u64, FlowState>,
+    last_prune: Instant,
+    stats: SamplingStats,
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Sampler {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as u64,
+            Err(_) => 0,
+        };
+        Sampler {
+            config: config,
+            counter: 0,
+            rng_state: seed | 1,
+            flows: HashMap::new(),
+            last_prune: Instant::now(),
+            stats: SamplingStats::new(),
+        }
+    }
+    pub fn mode(&self) -> SamplingMode {
+        self.config.mode
+    }
+    /// Returns the weight of the frame if it is sampled.
+    /// Counters of a sampled frame are multiplied by the weight to estimate totals
+    pub fn sample(&mut self, data: &[u8], link: LinkType) -> Option<u32> {
+        self.stats.seen_packets += 1;
+        let weight = match self.config.mode {
+            SamplingMode::Off => Some(1),
+            SamplingMode::OneInN => {
+                let rate = self.config.rate.max(1);
+                self.counter += 1;
+                if self.counter >= rate {
+                    self.counter = 0;
+                    Some(rate)
+                } else {
+                    None
+                }
+            }
+            SamplingMode::Probabilistic => {
+                let rate = probability_rate(self.config.probability);
+                if self.next_random() < 1.0 / rate as f64 {
+                    Some(rate)
+                } else {
+                    None
+                }
+            }
+            SamplingMode::AdaptiveFlow => self.sample_flow(data, link),
+        };
+        if weight.is_some() {
+            self.stats.sampled_packets += 1;
+        }
+        weight
+    }
+    fn sample_flow(&mut self, data: &[u8], link: LinkType) -> Option<u32> {
+        let key = match flow_key(data, link) {
+            Some(key) => key,
+            // Non-IP frames are rare and counted exactly
+            None => return Some(1),
+        };
+        let now = Instant::now();
+        if now.duration_since(self.last_prune) >= FLOW_IDLE_TIMEOUT {
+            self.flows
+                .retain(|_, flow| now.duration_since(flow.last_seen) < FLOW_IDLE_TIMEOUT);
+            self.last_prune = now;
+        }
+        let exact_pps = self.config.flow_exact_pps.max(1);
+        let max_rate = self.config.flow_max_rate.max(1);
+        if !self.flows.contains_key(&key) && self.flows.len() >= MAX_FLOWS {
+            self.counter += 1;
+            if self.counter >= max_rate {
+                self.counter = 0;
+                return Some(max_rate);
+            }
+            return None;
+        }
+        let flow = self.flows.entry(key).or_insert(FlowState {
+            window_start: now,
+            window_packets: 0,
+            rate: 1,
+            counter: 0,
+            last_seen: now,
+        });
+        flow.last_seen = now;
+        let elapsed = now.duration_since(flow.window_start);
+        if elapsed >= FLOW_WINDOW {
+            // Rate for the next window from the packets of the last one
+            let pps = flow.window_packets as f64 / elapsed.as_secs_f64();
+            flow.rate = ((pps / exact_pps as f64).ceil() as u32).clamp(1, max_rate);
+            flow.window_start = now;
+            flow.window_packets = 0;
+        }
+        flow.window_packets += 1;
+        if flow.rate <= 1 {
+            // A burst within the first window is sampled once over the exact limit
+            if flow.window_packets <= exact_pps {
+                return Some(1);
+            }
+            flow.rate = 2;
+        }
+        flow.counter += 1;
+        if flow.counter >= flow.rate {
+            flow.counter = 0;
+            Some(flow.rate)
+        } else {
+            None
+        }
+    }
+    /// xorshift64. Uniform in [0, 1)
+    fn next_random(&mut self) -> f64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// Take the counters accumulated since the last call
+    pub fn take_stats(&mut self) -> SamplingStats {
+        std::mem::replace(&mut self.stats, SamplingStats::new())
+    }
+}
+
+/// N of the probability 1/N nearest to probability.
+/// Sampling with the probability itself and weighting by a rounded 1/probability would bias the estimate
+fn probability_rate(probability: f64) -> u32 {
+    if !(probability > 0.0) {
+        return u32::MAX;
+    }
+    (1.0 / probability.min(1.0)).round().min(u32::MAX as f64) as u32
+}
+
+/// EtherType and offset of the network header. Raw IP links have no EtherType
+fn network_header(data: &[u8], link: LinkType) -> Option<(Option<u16>, usize)> {
+    match link {
+        LinkType::Ethernet => {
+            let mut offset = 12;
+            let mut ether_type = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            while matches!(ether_type, 0x8100 | 0x88A8 | 0x9100) {
+                offset += 4;
+                ether_type = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            Some((Some(ether_type), offset + 2))
+        }
+        LinkType::Ip { offset } => Some((None, offset)),
+    }
+}
+
+/// Addresses, protocol and transport offset of an IP packet.
+/// The offset is None for non-first fragments, which have no transport header
+fn ip_header(data: &[u8], offset: usize) -> Option<(&[u8], &[u8], u8, Option<usize>)> {
+    match *data.get(offset)? >> 4 {
+        4 => {
+            let header_len = ((data[offset] & 0x0F) as usize) * 4;
+            let src = data.get(offset + 12..offset + 16)?;
+            let dst = data.get(offset + 16..offset + 20)?;
+            let protocol = *data.get(offset + 9)?;
+            let flags_frag = u16::from_be_bytes([*data.get(offset + 6)?, *data.get(offset + 7)?]);
+            if flags_frag & 0x1FFF != 0 {
+                return Some((src, dst, protocol, None));
+            }
+            Some((src, dst, protocol, Some(offset + header_len)))
+        }
+        6 => {
+            let src = data.get(offset + 8..offset + 24)?;
+            let dst = data.get(offset + 24..offset + 40)?;
+            let protocol = *data.get(offset + 6)?;
+            Some((src, dst, protocol, Some(offset + 40)))
+        }
+        _ => None,
+    }
+}
+
+/// Direction independent hash of the 5-tuple read from raw bytes
+fn flow_key(data: &[u8], link: LinkType) -> Option<u64> {
+    let offset = match network_header(data, link)? {
+        (Some(ether_type), _) if ether_type != 0x0800 && ether_type != 0x86DD => return None,
+        (_, offset) => offset,
+    };
+    let (src, dst, protocol, transport_offset) = ip_header(data, offset)?;
+    let (src_port, dst_port) = match (protocol, transport_offset) {
+        (6 | 17 | 132, Some(offset)) => (
+            u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]),
+            u16::from_be_bytes([*data.get(offset + 2)?, *data.get(offset + 3)?]),
+        ),
+        // Non-first fragments have no ports
+        _ => (0, 0),
+    };
+    Some(hash_flow(src, dst, src_port, dst_port, protocol))
+}
+
+/// Whether a frame left out of the sample may matter to the security detectors:
+/// ARP, TCP SYN or RST, and DHCP or DNS over UDP.
+/// Read from the raw headers so that other unsampled frames are not parsed
+pub fn detector_check(data: &[u8], link: LinkType) -> bool {
+    detector_check_headers(data, link).unwrap_or(false)
+}
+
+fn detector_check_headers(data: &[u8], link: LinkType) -> Option<bool> {
+    let offset = match network_header(data, link)? {
+        (Some(0x0806), _) => return Some(true),
+        (Some(ether_type), _) if ether_type != 0x0800 && ether_type != 0x86DD => return Some(false),
+        (_, offset) => offset,
+    };
+    let (_, _, protocol, transport_offset) = ip_header(data, offset)?;
+    let offset = transport_offset?;
+    match protocol {
+        6 => {
+            let flags = *data.get(offset + 13)?;
+            Some(flags & (TCP_SYN | TCP_RST) != 0)
+        }
+        17 => {
+            let src_port = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            let dst_port = u16::from_be_bytes([*data.get(offset + 2)?, *data.get(offset + 3)?]);
+            Some([src_port, dst_port].iter().any(|port| matches!(port, 53 | 67 | 68)))
+        }
+        _ => Some(false),
+    }
+}
+
+fn hash_flow(src: &[u8], dst: &[u8], src_port: u16, dst_port: u16, protocol: u8) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    if (src, src_port) <= (dst, dst_port) {
+        (src, src_port, dst, dst_port, protocol).hash(&mut hasher);
+    } else {
+        (dst, dst_port, src, src_port, protocol).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probability_is_rounded_to_one_in_n() {
+        assert_eq!(probability_rate(1.0), 1);
+        assert_eq!(probability_rate(0.5), 2);
+        assert_eq!(probability_rate(0.3), 3);
+        assert_eq!(probability_rate(0.4), 3);
+        assert_eq!(probability_rate(0.0001), 10000);
+        assert_eq!(probability_rate(2.0), 1);
+        assert_eq!(probability_rate(0.0), u32::MAX);
+    }
+
+    /// Ethernet and IPv4 headers followed by the transport header
+    fn ipv4_frame(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut frame: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0x00, 0x00, 40, 0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&[203, 0, 113, 5, 192, 168, 1, 10]);
+        frame.extend_from_slice(transport);
+        frame
+    }
+
+    fn tcp_header(dst_port: u16, flags: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = vec![0x9c, 0x40];
+        header.extend_from_slice(&dst_port.to_be_bytes());
+        header.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        header
+    }
+
+    fn udp_header(src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut header: Vec<u8> = src_port.to_be_bytes().to_vec();
+        header.extend_from_slice(&dst_port.to_be_bytes());
+        header.extend_from_slice(&[0, 8, 0, 0]);
+        header
+    }
+
+    #[test]
+    fn detector_check_selects_control_frames() {
+        let link = LinkType::Ethernet;
+        assert!(detector_check(&ipv4_frame(6, &tcp_header(22, TCP_SYN)), link));
+        assert!(detector_check(&ipv4_frame(6, &tcp_header(22, TCP_RST | 0x10)), link));
+        assert!(!detector_check(&ipv4_frame(6, &tcp_header(443, 0x10)), link));
+        assert!(detector_check(&ipv4_frame(17, &udp_header(40000, 53)), link));
+        assert!(detector_check(&ipv4_frame(17, &udp_header(67, 68)), link));
+        assert!(!detector_check(&ipv4_frame(17, &udp_header(40000, 443)), link));
+        let mut arp: Vec<u8> = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x06];
+        arp.resize(42, 0);
+        assert!(detector_check(&arp, link));
+        // Truncated headers
+        assert!(!detector_check(&ipv4_frame(6, &[0x9c, 0x40]), link));
+    }
+
+    #[test]
+    fn detector_check_skips_non_first_fragments() {
+        let mut frame = ipv4_frame(17, &udp_header(40000, 53));
+        // Fragment offset 8
+        frame[21] = 1;
+        assert!(!detector_check(&frame, LinkType::Ethernet));
+    }
+
+    #[test]
+    fn probabilistic_estimate_is_unbiased() {
+        let mut config = SamplingConfig::new();
+        config.mode = SamplingMode::Probabilistic;
+        // 1/0.4 rounds to 3. Weighting 2.5 frames by 3 overestimated by 20%
+        config.probability = 0.4;
+        let mut sampler = Sampler::new(config);
+        let frames: u64 = 300_000;
+        let mut estimate: u64 = 0;
+        for _ in 0..frames {
+            if let Some(weight) = sampler.sample(&[], LinkType::Ethernet) {
+                assert_eq!(weight, 3);
+                estimate += weight as u64;
+            }
+        }
+        let error = (estimate as f64 - frames as f64).abs() / frames as f64;
+        assert!(error < 0.02, "estimate {} of {} frames", estimate, frames);
+    }
+}
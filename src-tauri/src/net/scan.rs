@@ -0,0 +1,1141 @@
+use crate::db::service::ServiceDatabase;
+use crate::net::interface;
+use crate::net::socket::TransportProtocol;
+use crate::sys;
+use crate::thread_log;
+use futures::stream::{self, StreamExt};
+use ipnet::IpNet;
+use netdev::mac::MacAddr;
+use nex::net::interface::Interface;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Upper bound of hosts expanded from CIDR targets.
+pub const MAX_SCAN_HOSTS: usize = 65536;
+/// Default ports when no port list is given.
+pub const DEFAULT_SCAN_PORT_RANGE: (u16, u16) = (1, 1024);
+/// Maximum banner length in bytes.
+pub const MAX_BANNER_LEN: usize = 256;
+
+/// Scan ids handed out by this process
+static NEXT_SCAN_ID: AtomicU32 = AtomicU32::new(1);
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const ARP_PACKET_LEN: usize = 28;
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAG_RST: u8 = 0x04;
+const TCP_FLAG_ACK: u8 = 0x10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortScanType {
+    TcpSyn,
+    TcpConnect,
+    Udp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortStatus {
+    Open,
+    Closed,
+    Filtered,
+    OpenOrFiltered,
+}
+
+/// Port scan options
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortScanOption {
+    /// Scan targets. Each entry is an IP address, a CIDR block or a host name.
+    pub targets: Vec<String>,
+    /// Comma separated ports and port ranges. e.g. "22,80,8000-8100".
+    /// If empty, 1-1024 will be scanned.
+    pub ports: String,
+    /// Scan type
+    pub scan_type: PortScanType,
+    /// Maximum number of probes in flight
+    pub concurrency: usize,
+    /// Probe timeout in milliseconds
+    pub timeout: u64,
+    /// Read a banner from open TCP ports
+    pub banner_grab: bool,
+}
+
+impl PortScanOption {
+    pub fn new() -> PortScanOption {
+        PortScanOption {
+            targets: Vec::new(),
+            ports: String::new(),
+            scan_type: PortScanType::TcpConnect,
+            concurrency: 100,
+            timeout: 1000,
+            banner_grab: false,
+        }
+    }
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout.max(1))
+    }
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.max(1)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScannedPort {
+    pub port: u16,
+    pub protocol: TransportProtocol,
+    pub status: PortStatus,
+    pub service_name: String,
+    pub banner: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostScanResult {
+    pub ip_addr: IpAddr,
+    pub hostname: String,
+    pub ports: Vec<ScannedPort>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortScanResult {
+    /// ID passed to stop_port_scan. Set by the command that started the scan
+    pub scan_id: u32,
+    pub hosts: Vec<HostScanResult>,
+    pub probes: usize,
+    pub cancelled: bool,
+    pub start_time: String,
+    pub end_time: String,
+    pub duration: Duration,
+}
+
+impl PortScanResult {
+    pub fn new() -> PortScanResult {
+        PortScanResult {
+            scan_id: 0,
+            hosts: Vec::new(),
+            probes: 0,
+            cancelled: false,
+            start_time: String::new(),
+            end_time: String::new(),
+            duration: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Port scan progress message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortScanProgress {
+    /// ID passed to stop_port_scan. Set by the command that started the scan
+    pub scan_id: u32,
+    pub completed: usize,
+    pub total: usize,
+    pub ip_addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub status: Option<PortStatus>,
+}
+
+#[derive(Debug, Clone)]
+struct ScanTarget {
+    ip_addr: IpAddr,
+    hostname: String,
+}
+
+#[derive(Debug, Clone)]
+struct ProbeResult {
+    ip_addr: IpAddr,
+    port: u16,
+    status: PortStatus,
+    banner: Option<String>,
+}
+
+/// Parse port list. e.g. "22,80,8000-8100"
+pub fn parse_ports(ports: &str) -> Result<Vec<u16>, String> {
+    let mut port_set: HashSet<u16> = HashSet::new();
+    for token in ports.split(|c: char| c == ',' || c.is_whitespace()) {
+        let token = token.trim();
+        if token.is_empty() {
+            continue;
+        }
+        match token.split_once('-') {
+            Some((start, end)) => {
+                let start: u16 = start
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid port range: {}", token))?;
+                let end: u16 = end
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid port range: {}", token))?;
+                if start == 0 || start > end {
+                    return Err(format!("Invalid port range: {}", token));
+                }
+                for port in start..=end {
+                    port_set.insert(port);
+                }
+            }
+            None => {
+                let port: u16 = token
+                    .parse()
+                    .map_err(|_| format!("Invalid port: {}", token))?;
+                if port == 0 {
+                    return Err(format!("Invalid port: {}", token));
+                }
+                port_set.insert(port);
+            }
+        }
+    }
+    if port_set.is_empty() {
+        for port in DEFAULT_SCAN_PORT_RANGE.0..=DEFAULT_SCAN_PORT_RANGE.1 {
+            port_set.insert(port);
+        }
+    }
+    let mut port_list: Vec<u16> = port_set.into_iter().collect();
+    port_list.sort();
+    Ok(port_list)
+}
+
+/// Expand targets (IP address, CIDR or host name) into a unique host list
+fn parse_targets(targets: &Vec<String>) -> Result<Vec<ScanTarget>, String> {
+    let mut scan_targets: Vec<ScanTarget> = Vec::new();
+    let mut seen: HashSet<IpAddr> = HashSet::new();
+    for target in targets
+        .iter()
+        .flat_map(|t| t.split(|c: char| c == ',' || c.is_whitespace()))
+    {
+        let target = target.trim();
+        if target.is_empty() {
+            continue;
+        }
+        if let Ok(ip_addr) = IpAddr::from_str(target) {
+            if seen.insert(ip_addr) {
+                scan_targets.push(ScanTarget {
+                    ip_addr: ip_addr,
+                    hostname: String::new(),
+                });
+            }
+        } else if let Ok(net) = IpNet::from_str(target) {
+            for ip_addr in net.hosts().take(MAX_SCAN_HOSTS + 1) {
+                if seen.insert(ip_addr) {
+                    scan_targets.push(ScanTarget {
+                        ip_addr: ip_addr,
+                        hostname: String::new(),
+                    });
+                }
+            }
+        } else {
+            match crate::net::dns::lookup_host_name(target.to_string()) {
+                Some(ip_addr) => {
+                    if seen.insert(ip_addr) {
+                        scan_targets.push(ScanTarget {
+                            ip_addr: ip_addr,
+                            hostname: target.to_string(),
+                        });
+                    }
+                }
+                None => {
+                    return Err(format!("Could not resolve target: {}", target));
+                }
+            }
+        }
+        if scan_targets.len() > MAX_SCAN_HOSTS {
+            return Err(format!("Too many scan targets. Limit is {}", MAX_SCAN_HOSTS));
+        }
+    }
+    if scan_targets.is_empty() {
+        return Err("No scan target".to_string());
+    }
+    Ok(scan_targets)
+}
+
+fn is_stopped(stop: &Arc<Mutex<bool>>) -> bool {
+    match stop.lock() {
+        Ok(stop) => *stop,
+        Err(_) => false,
+    }
+}
+
+/// ID of a new scan. Never 0
+pub fn next_scan_id() -> u32 {
+    match NEXT_SCAN_ID.fetch_add(1, Ordering::Relaxed) {
+        0 => NEXT_SCAN_ID.fetch_add(1, Ordering::Relaxed),
+        id => id,
+    }
+}
+
+/// Start port scan. Blocks until the scan completes or `stop` is set.
+pub fn start_port_scan(
+    scan_option: PortScanOption,
+    progress_tx: Sender<PortScanProgress>,
+    stop: &Arc<Mutex<bool>>,
+) -> Result<PortScanResult, String> {
+    let mut result = PortScanResult::new();
+    let targets = parse_targets(&scan_option.targets)?;
+    let ports = parse_ports(&scan_option.ports)?;
+    let start_time = Instant::now();
+    result.start_time = sys::get_sysdate();
+    let probe_results: Vec<ProbeResult> = match scan_option.scan_type {
+        PortScanType::TcpSyn => {
+            // SYN scan is IPv4 only. Other targets fall back to connect scan.
+            let (syn_targets, mut connect_targets): (Vec<ScanTarget>, Vec<ScanTarget>) =
+                targets.iter().cloned().partition(|t| syn_scan_supported(t.ip_addr));
+            let total = (syn_targets.len() + connect_targets.len()) * ports.len();
+            let mut probe_results: Vec<ProbeResult> = Vec::new();
+            if !syn_targets.is_empty() {
+                let (syn_results, unscanned) = run_syn_scan(
+                    &syn_targets,
+                    &ports,
+                    &scan_option,
+                    total,
+                    &progress_tx,
+                    stop,
+                );
+                probe_results.extend(syn_results);
+                connect_targets.extend(syn_targets.into_iter().filter(|t| unscanned.contains(&t.ip_addr)));
+            }
+            if !connect_targets.is_empty() {
+                let completed = probe_results.len();
+                probe_results.extend(run_async_scan(
+                    &connect_targets,
+                    &ports,
+                    &scan_option,
+                    TransportProtocol::TCP,
+                    completed,
+                    total,
+                    &progress_tx,
+                    stop,
+                ));
+            }
+            if scan_option.banner_grab {
+                grab_banners(&mut probe_results, &scan_option, stop);
+            }
+            probe_results
+        }
+        PortScanType::TcpConnect => run_async_scan(
+            &targets,
+            &ports,
+            &scan_option,
+            TransportProtocol::TCP,
+            0,
+            targets.len() * ports.len(),
+            &progress_tx,
+            stop,
+        ),
+        PortScanType::Udp => run_async_scan(
+            &targets,
+            &ports,
+            &scan_option,
+            TransportProtocol::UDP,
+            0,
+            targets.len() * ports.len(),
+            &progress_tx,
+            stop,
+        ),
+    };
+    result.probes = probe_results.len();
+    result.cancelled = is_stopped(stop);
+    result.hosts = build_host_results(&targets, probe_results, scan_option.scan_type);
+    result.end_time = sys::get_sysdate();
+    result.duration = Instant::now().duration_since(start_time);
+    Ok(result)
+}
+
+fn build_host_results(
+    targets: &Vec<ScanTarget>,
+    probe_results: Vec<ProbeResult>,
+    scan_type: PortScanType,
+) -> Vec<HostScanResult> {
+    let (service_db, errors) = ServiceDatabase::load();
+    for e in errors {
+        thread_log!(error, "port scan load service db error: {}", e);
+    }
+    let protocol = match scan_type {
+        PortScanType::Udp => TransportProtocol::UDP,
+        _ => TransportProtocol::TCP,
+    };
+    let mut port_map: HashMap<IpAddr, Vec<ScannedPort>> = HashMap::new();
+    for probe in probe_results {
+        // Only report ports that answered or may be open
+        if probe.status == PortStatus::Closed || probe.status == PortStatus::Filtered {
+            continue;
+        }
+        let service_name = match protocol {
+            TransportProtocol::TCP => service_db.tcp_map.get(&probe.port),
+            TransportProtocol::UDP => service_db.udp_map.get(&probe.port),
+        }
+        .cloned()
+        .unwrap_or(String::from("unknown"));
+        port_map.entry(probe.ip_addr).or_insert(Vec::new()).push(ScannedPort {
+            port: probe.port,
+            protocol: protocol,
+            status: probe.status,
+            service_name: service_name,
+            banner: probe.banner,
+        });
+    }
+    let mut hosts: Vec<HostScanResult> = Vec::new();
+    for target in targets {
+        if let Some(mut ports) = port_map.remove(&target.ip_addr) {
+            ports.sort_by(|a, b| a.port.cmp(&b.port));
+            hosts.push(HostScanResult {
+                ip_addr: target.ip_addr,
+                hostname: target.hostname.clone(),
+                ports: ports,
+            });
+        }
+    }
+    hosts
+}
+
+fn send_progress(
+    progress_tx: &Sender<PortScanProgress>,
+    completed: usize,
+    total: usize,
+    probe: Option<&ProbeResult>,
+) {
+    let progress = PortScanProgress {
+        scan_id: 0,
+        completed: completed,
+        total: total,
+        ip_addr: probe.map(|p| p.ip_addr),
+        port: probe.map(|p| p.port),
+        status: probe.map(|p| p.status),
+    };
+    match progress_tx.send(progress) {
+        Ok(_) => {}
+        Err(_) => {}
+    }
+}
+
+/// Number of probes between two progress messages
+fn progress_step(total: usize) -> usize {
+    (total / 100).max(1)
+}
+
+/// TCP connect and UDP scan on a tokio runtime
+fn run_async_scan(
+    targets: &Vec<ScanTarget>,
+    ports: &Vec<u16>,
+    scan_option: &PortScanOption,
+    protocol: TransportProtocol,
+    completed_offset: usize,
+    total: usize,
+    progress_tx: &Sender<PortScanProgress>,
+    stop: &Arc<Mutex<bool>>,
+) -> Vec<ProbeResult> {
+    // Generated as the probes are sent. targets x ports can be billions of addresses
+    let socket_addrs = targets
+        .iter()
+        .flat_map(|target| ports.iter().map(move |port| SocketAddr::new(target.ip_addr, *port)));
+    let timeout = scan_option.probe_timeout();
+    let concurrency = scan_option.concurrency();
+    let banner_grab = scan_option.banner_grab;
+    let progress_tx = progress_tx.clone();
+    let stop = stop.clone();
+    let rt: tokio::runtime::Runtime = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            thread_log!(error, "port scan runtime error: {:?}", e);
+            return Vec::new();
+        }
+    };
+    rt.block_on(async move {
+        let stop_ref = &stop;
+        let mut tasks = stream::iter(socket_addrs)
+            .map(|addr| async move {
+                if is_stopped(stop_ref) {
+                    return None;
+                }
+                let (status, banner) = match protocol {
+                    TransportProtocol::TCP => probe_tcp_connect(addr, timeout, banner_grab).await,
+                    TransportProtocol::UDP => (probe_udp(addr, timeout).await, None),
+                };
+                Some(ProbeResult {
+                    ip_addr: addr.ip(),
+                    port: addr.port(),
+                    status: status,
+                    banner: banner,
+                })
+            })
+            .buffer_unordered(concurrency);
+        let mut results: Vec<ProbeResult> = Vec::new();
+        let step = progress_step(total);
+        let mut completed = completed_offset;
+        while let Some(result) = tasks.next().await {
+            completed += 1;
+            if let Some(result) = result {
+                if result.status == PortStatus::Open {
+                    send_progress(&progress_tx, completed, total, Some(&result));
+                } else if completed % step == 0 {
+                    send_progress(&progress_tx, completed, total, None);
+                }
+                results.push(result);
+            }
+        }
+        send_progress(&progress_tx, completed, total, None);
+        results
+    })
+}
+
+async fn probe_tcp_connect(
+    addr: SocketAddr,
+    timeout: Duration,
+    banner_grab: bool,
+) -> (PortStatus, Option<String>) {
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        Ok(Ok(mut stream)) => {
+            let banner = if banner_grab {
+                read_banner(&mut stream, addr.port(), timeout).await
+            } else {
+                None
+            };
+            (PortStatus::Open, banner)
+        }
+        Ok(Err(e)) => {
+            if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                (PortStatus::Closed, None)
+            } else {
+                (PortStatus::Filtered, None)
+            }
+        }
+        Err(_) => (PortStatus::Filtered, None),
+    }
+}
+
+/// Read the service greeting. If the service waits for the client, send a minimal request first.
+async fn read_banner(
+    stream: &mut tokio::net::TcpStream,
+    port: u16,
+    timeout: Duration,
+) -> Option<String> {
+    let mut buf: Vec<u8> = vec![0; MAX_BANNER_LEN];
+    match tokio::time::timeout(timeout, stream.read(&mut buf)).await {
+        Ok(Ok(n)) if n > 0 => return Some(sanitize_banner(&buf[..n])),
+        Ok(_) => return None,
+        Err(_) => {}
+    }
+    let request: &[u8] = match port {
+        80 | 8000 | 8008 | 8080 | 8888 => b"HEAD / HTTP/1.0\r\n\r\n",
+        _ => b"\r\n",
+    };
+    if stream.write_all(request).await.is_err() {
+        return None;
+    }
+    match tokio::time::timeout(timeout, stream.read(&mut buf)).await {
+        Ok(Ok(n)) if n > 0 => Some(sanitize_banner(&buf[..n])),
+        _ => None,
+    }
+}
+
+fn sanitize_banner(data: &[u8]) -> String {
+    let banner: String = String::from_utf8_lossy(data)
+        .chars()
+        .map(|c| if c.is_control() && c != '\n' { ' ' } else { c })
+        .collect();
+    banner.trim().to_string()
+}
+
+/// UDP probe. Uses a connected socket so that ICMP port unreachable is reported as ConnectionRefused.
+async fn probe_udp(addr: SocketAddr, timeout: Duration) -> PortStatus {
+    let bind_addr: SocketAddr = match addr {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let socket = match tokio::net::UdpSocket::bind(bind_addr).await {
+        Ok(socket) => socket,
+        Err(_) => return PortStatus::Filtered,
+    };
+    if socket.connect(addr).await.is_err() {
+        return PortStatus::Filtered;
+    }
+    if socket.send(&udp_probe_payload(addr.port())).await.is_err() {
+        return PortStatus::Filtered;
+    }
+    let mut buf: Vec<u8> = vec![0; 1500];
+    match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => PortStatus::Open,
+        Ok(Err(e)) => {
+            if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                PortStatus::Closed
+            } else {
+                PortStatus::Filtered
+            }
+        }
+        Err(_) => PortStatus::OpenOrFiltered,
+    }
+}
+
+/// Protocol specific payload for well known UDP services. Empty datagrams are ignored by most of them.
+fn udp_probe_payload(port: u16) -> Vec<u8> {
+    match port {
+        // DNS: standard query for "." NS
+        53 => vec![
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x02, 0x00, 0x01,
+        ],
+        // NTP: version 3 client request
+        123 => {
+            let mut payload = vec![0u8; 48];
+            payload[0] = 0x1b;
+            payload
+        }
+        // SNMP: get-request public sysDescr.0
+        161 => vec![
+            0x30, 0x26, 0x02, 0x01, 0x01, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa0,
+            0x19, 0x02, 0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c,
+            0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// Grab banners from ports found open by SYN scan
+fn grab_banners(
+    probe_results: &mut Vec<ProbeResult>,
+    scan_option: &PortScanOption,
+    stop: &Arc<Mutex<bool>>,
+) {
+    let open_addrs: Vec<SocketAddr> = probe_results
+        .iter()
+        .filter(|p| p.status == PortStatus::Open && p.banner.is_none())
+        .map(|p| SocketAddr::new(p.ip_addr, p.port))
+        .collect();
+    if open_addrs.is_empty() {
+        return;
+    }
+    let timeout = scan_option.probe_timeout();
+    let concurrency = scan_option.concurrency();
+    let stop = stop.clone();
+    let rt: tokio::runtime::Runtime = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            thread_log!(error, "port scan runtime error: {:?}", e);
+            return;
+        }
+    };
+    let banners: HashMap<SocketAddr, String> = rt.block_on(async move {
+        let stop_ref = &stop;
+        let mut tasks = stream::iter(open_addrs)
+            .map(|addr| async move {
+                if is_stopped(stop_ref) {
+                    return (addr, None);
+                }
+                let (_status, banner) = probe_tcp_connect(addr, timeout, true).await;
+                (addr, banner)
+            })
+            .buffer_unordered(concurrency);
+        let mut banners: HashMap<SocketAddr, String> = HashMap::new();
+        while let Some((addr, banner)) = tasks.next().await {
+            if let Some(banner) = banner {
+                banners.insert(addr, banner);
+            }
+        }
+        banners
+    });
+    for probe in probe_results.iter_mut() {
+        if let Some(banner) = banners.get(&SocketAddr::new(probe.ip_addr, probe.port)) {
+            probe.banner = Some(banner.clone());
+        }
+    }
+}
+
+fn syn_scan_supported(ip_addr: IpAddr) -> bool {
+    match ip_addr {
+        IpAddr::V4(ipv4) => {
+            // Loopback frames are only Ethernet framed on Linux
+            !ipv4.is_loopback() || cfg!(target_os = "linux")
+        }
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Find the interface used to reach the target
+fn get_route_interface(ip_addr: Ipv4Addr) -> Option<Interface> {
+    let interfaces = nex::net::interface::get_interfaces();
+    if ip_addr.is_loopback() {
+        return interfaces.into_iter().find(|iface| iface.is_loopback());
+    }
+    for iface in &interfaces {
+        if !iface.is_up() {
+            continue;
+        }
+        for ipv4 in &iface.ipv4 {
+            if in_subnet(ip_addr, ipv4.addr, ipv4.netmask()) {
+                return Some(iface.clone());
+            }
+        }
+    }
+    match netdev::get_default_interface() {
+        Ok(iface) => Some(iface),
+        Err(_) => None,
+    }
+}
+
+fn in_subnet(ip_addr: Ipv4Addr, if_addr: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+    let mask = u32::from(netmask);
+    mask != 0 && (u32::from(ip_addr) & mask) == (u32::from(if_addr) & mask)
+}
+
+/// Internet checksum (RFC 1071)
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let Some(last) = chunks.remainder().first() {
+        sum += (*last as u32) << 8;
+    }
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Checksum over the IPv4 pseudo header and the transport segment
+pub fn ipv4_transport_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut data: Vec<u8> = Vec::with_capacity(12 + segment.len());
+    data.extend_from_slice(&src.octets());
+    data.extend_from_slice(&dst.octets());
+    data.push(0);
+    data.push(protocol);
+    data.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    data.extend_from_slice(segment);
+    checksum(&data)
+}
+
+fn build_syn_packet(
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    layer2: bool,
+) -> Vec<u8> {
+    let mut packet: Vec<u8> = Vec::with_capacity(ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + TCP_HEADER_LEN);
+    if layer2 {
+        packet.extend_from_slice(&dst_mac.octets());
+        packet.extend_from_slice(&src_mac.octets());
+        packet.extend_from_slice(&0x0800u16.to_be_bytes());
+    }
+    let ip_offset = packet.len();
+    let mut ipv4: [u8; IPV4_HEADER_LEN] = [0; IPV4_HEADER_LEN];
+    ipv4[0] = 0x45;
+    ipv4[2..4].copy_from_slice(&((IPV4_HEADER_LEN + TCP_HEADER_LEN) as u16).to_be_bytes());
+    ipv4[4..6].copy_from_slice(&dst_port.to_be_bytes());
+    // Don't fragment
+    ipv4[6] = 0x40;
+    ipv4[8] = 64;
+    ipv4[9] = 6;
+    ipv4[12..16].copy_from_slice(&src_ip.octets());
+    ipv4[16..20].copy_from_slice(&dst_ip.octets());
+    let ip_checksum = checksum(&ipv4);
+    ipv4[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+    packet.extend_from_slice(&ipv4);
+    let mut tcp: [u8; TCP_HEADER_LEN] = [0; TCP_HEADER_LEN];
+    tcp[0..2].copy_from_slice(&src_port.to_be_bytes());
+    tcp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    tcp[4..8].copy_from_slice(&syn_sequence(dst_ip, dst_port).to_be_bytes());
+    tcp[12] = (TCP_HEADER_LEN as u8 / 4) << 4;
+    tcp[13] = TCP_FLAG_SYN;
+    tcp[14..16].copy_from_slice(&1024u16.to_be_bytes());
+    let tcp_checksum = ipv4_transport_checksum(src_ip, dst_ip, 6, &tcp);
+    tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+    packet.extend_from_slice(&tcp);
+    debug_assert_eq!(packet.len() - ip_offset, IPV4_HEADER_LEN + TCP_HEADER_LEN);
+    packet
+}
+
+/// Sequence number derived from the target so that replies can be validated without state
+fn syn_sequence(dst_ip: Ipv4Addr, dst_port: u16) -> u32 {
+    u32::from(dst_ip).rotate_left(16) ^ ((dst_port as u32) << 8) ^ 0x5a5a_0000
+}
+
+fn build_arp_request(src_mac: MacAddr, src_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Vec<u8> {
+    let mut packet: Vec<u8> = Vec::with_capacity(ETHERNET_HEADER_LEN + ARP_PACKET_LEN);
+    packet.extend_from_slice(&MacAddr::broadcast().octets());
+    packet.extend_from_slice(&src_mac.octets());
+    packet.extend_from_slice(&0x0806u16.to_be_bytes());
+    // Hardware type: Ethernet, Protocol type: IPv4
+    packet.extend_from_slice(&[0x00, 0x01, 0x08, 0x00, 6, 4]);
+    // Operation: Request
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&src_mac.octets());
+    packet.extend_from_slice(&src_ip.octets());
+    packet.extend_from_slice(&MacAddr::zero().octets());
+    packet.extend_from_slice(&target_ip.octets());
+    packet
+}
+
+/// Parse an ARP reply from `target_ip` and return the sender MAC address
+fn parse_arp_reply(frame: &[u8], target_ip: Ipv4Addr) -> Option<MacAddr> {
+    if frame.len() < ETHERNET_HEADER_LEN + ARP_PACKET_LEN {
+        return None;
+    }
+    if u16::from_be_bytes([frame[12], frame[13]]) != 0x0806 {
+        return None;
+    }
+    let arp = &frame[ETHERNET_HEADER_LEN..];
+    if u16::from_be_bytes([arp[6], arp[7]]) != 2 {
+        return None;
+    }
+    if Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]) != target_ip {
+        return None;
+    }
+    Some(MacAddr::new(arp[8], arp[9], arp[10], arp[11], arp[12], arp[13]))
+}
+
+/// Parse a TCP reply to our probe. Returns (remote ip, remote port, status)
+fn parse_syn_reply(
+    frame: &[u8],
+    ip_offset: usize,
+    src_port: u16,
+) -> Option<(IpAddr, u16, PortStatus)> {
+    if frame.len() < ip_offset + IPV4_HEADER_LEN {
+        return None;
+    }
+    let ip = &frame[ip_offset..];
+    if ip[0] >> 4 != 4 || ip[9] != 6 {
+        return None;
+    }
+    let ihl = ((ip[0] & 0x0f) as usize) * 4;
+    if ip.len() < ihl + TCP_HEADER_LEN {
+        return None;
+    }
+    let tcp = &ip[ihl..];
+    if u16::from_be_bytes([tcp[2], tcp[3]]) != src_port {
+        return None;
+    }
+    let remote_ip = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let remote_port = u16::from_be_bytes([tcp[0], tcp[1]]);
+    let flags = tcp[13];
+    let ack = u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]);
+    if ack != syn_sequence(remote_ip, remote_port).wrapping_add(1) {
+        return None;
+    }
+    let status = if flags & (TCP_FLAG_SYN | TCP_FLAG_ACK) == (TCP_FLAG_SYN | TCP_FLAG_ACK) {
+        PortStatus::Open
+    } else if flags & TCP_FLAG_RST != 0 {
+        PortStatus::Closed
+    } else {
+        return None;
+    };
+    Some((IpAddr::V4(remote_ip), remote_port, status))
+}
+
+/// Resolve the next hop MAC address for the target
+fn resolve_next_hop(
+    iface: &Interface,
+    src_ip: Ipv4Addr,
+    target_ip: Ipv4Addr,
+    tx: &mut Box<dyn nex::datalink::DataLinkSender>,
+    rx: &mut Box<dyn nex::datalink::DataLinkReceiver>,
+    timeout: Duration,
+) -> Option<MacAddr> {
+    if iface.is_loopback() {
+        return Some(MacAddr::zero());
+    }
+    let on_link = iface
+        .ipv4
+        .iter()
+        .any(|ipv4| in_subnet(target_ip, ipv4.addr, ipv4.netmask()));
+    if !on_link {
+        let gateway_mac = interface::get_gateway_macaddr(iface);
+        if gateway_mac == MacAddr::zero() {
+            return None;
+        }
+        return Some(gateway_mac);
+    }
+    let src_mac = interface::get_interface_macaddr(iface);
+    let request = build_arp_request(src_mac, src_ip, target_ip);
+    match tx.send_to(&request, None) {
+        Some(Ok(_)) => {}
+        _ => return None,
+    }
+    let start_time = Instant::now();
+    while start_time.elapsed() < timeout {
+        match rx.next() {
+            Ok(frame) => {
+                if let Some(mac_addr) = parse_arp_reply(frame, target_ip) {
+                    return Some(mac_addr);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+    None
+}
+
+/// TCP SYN scan. Crafts SYN frames and sends them via datalink channel.
+/// Also returns the targets left for a connect scan because no channel could be opened on their interface, e.g. without capture privileges
+fn run_syn_scan(
+    targets: &Vec<ScanTarget>,
+    ports: &Vec<u16>,
+    scan_option: &PortScanOption,
+    total: usize,
+    progress_tx: &Sender<PortScanProgress>,
+    stop: &Arc<Mutex<bool>>,
+) -> (Vec<ProbeResult>, HashSet<IpAddr>) {
+    let timeout = scan_option.probe_timeout();
+    let mut unscanned: HashSet<IpAddr> = HashSet::new();
+    // Group targets by outgoing interface
+    let mut iface_targets: HashMap<u32, (Interface, Vec<Ipv4Addr>)> = HashMap::new();
+    for target in targets {
+        if let IpAddr::V4(ipv4) = target.ip_addr {
+            match get_route_interface(ipv4) {
+                Some(iface) => {
+                    iface_targets
+                        .entry(iface.index)
+                        .or_insert((iface, Vec::new()))
+                        .1
+                        .push(ipv4);
+                }
+                None => {
+                    thread_log!(warn, "port scan: no route to {}, fallback to connect scan", ipv4);
+                    unscanned.insert(target.ip_addr);
+                }
+            }
+        }
+    }
+    let src_port: u16 = 40000 + (std::process::id() % 20000) as u16;
+    let mut results: Vec<ProbeResult> = Vec::new();
+    let mut completed: usize = 0;
+    let step = progress_step(total);
+    for (_index, (iface, target_ips)) in iface_targets {
+        let src_ip = match interface::get_interface_ipv4(&iface) {
+            Some(IpAddr::V4(ipv4)) => ipv4,
+            _ => {
+                thread_log!(warn, "port scan: {} has no IPv4 address, fallback to connect scan", iface.name);
+                unscanned.extend(target_ips.iter().map(|ip| IpAddr::V4(*ip)));
+                continue;
+            }
+        };
+        let layer2 = !iface.is_tun();
+        let ip_offset = if layer2 { ETHERNET_HEADER_LEN } else { 0 };
+        let config = nex::datalink::Config {
+            write_buffer_size: 4096,
+            read_buffer_size: 4096,
+            read_timeout: Some(Duration::from_millis(100)),
+            write_timeout: None,
+            channel_type: nex::datalink::ChannelType::Layer2,
+            bpf_fd_attempts: 1000,
+            linux_fanout: None,
+            promiscuous: false,
+        };
+        let (mut tx, mut rx) = match nex::datalink::channel(&iface, config) {
+            Ok(nex::datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
+            Ok(_) => {
+                thread_log!(warn, "port scan: unknown channel type on {}, fallback to connect scan", iface.name);
+                unscanned.extend(target_ips.iter().map(|ip| IpAddr::V4(*ip)));
+                continue;
+            }
+            Err(e) => {
+                thread_log!(warn, "port scan: channel error on {}, fallback to connect scan: {}", iface.name, e);
+                unscanned.extend(target_ips.iter().map(|ip| IpAddr::V4(*ip)));
+                continue;
+            }
+        };
+        let src_mac = interface::get_interface_macaddr(&iface);
+        let mut next_hops: Vec<(Ipv4Addr, MacAddr)> = Vec::new();
+        for target_ip in &target_ips {
+            if !layer2 {
+                next_hops.push((*target_ip, MacAddr::zero()));
+                continue;
+            }
+            match resolve_next_hop(&iface, src_ip, *target_ip, &mut tx, &mut rx, timeout) {
+                Some(mac_addr) => next_hops.push((*target_ip, mac_addr)),
+                None => {
+                    // Unreachable host. Count its probes as filtered.
+                    for port in ports {
+                        results.push(ProbeResult {
+                            ip_addr: IpAddr::V4(*target_ip),
+                            port: *port,
+                            status: PortStatus::Filtered,
+                            banner: None,
+                        });
+                    }
+                    completed += ports.len();
+                }
+            }
+        }
+        // Receive replies in a separate thread
+        let replies: Arc<Mutex<HashMap<(IpAddr, u16), PortStatus>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let replies_rx = replies.clone();
+        let recv_done = Arc::new(Mutex::new(false));
+        let recv_done_rx = recv_done.clone();
+        let recv_handler = thread::spawn(move || loop {
+            match rx.next() {
+                Ok(frame) => {
+                    if let Some((ip_addr, port, status)) = parse_syn_reply(frame, ip_offset, src_port) {
+                        if let Ok(mut replies) = replies_rx.lock() {
+                            replies.entry((ip_addr, port)).or_insert(status);
+                        }
+                    }
+                }
+                Err(_) => {}
+            }
+            if is_stopped(&recv_done_rx) {
+                break;
+            }
+        });
+        // Send probes. Concurrency is the number of probes sent per timeout slice.
+        let send_interval = timeout / scan_option.concurrency() as u32;
+        for port in ports {
+            for (target_ip, dst_mac) in &next_hops {
+                if is_stopped(stop) {
+                    break;
+                }
+                let packet = build_syn_packet(
+                    src_mac, *dst_mac, src_ip, *target_ip, src_port, *port, layer2,
+                );
+                match tx.send_to(&packet, None) {
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        thread_log!(error, "port scan send error: {}", e);
+                    }
+                    None => {}
+                }
+                completed += 1;
+                if completed % step == 0 {
+                    send_progress(progress_tx, completed, total, None);
+                }
+                thread::sleep(send_interval);
+            }
+        }
+        // Wait for late replies
+        thread::sleep(timeout);
+        match recv_done.lock() {
+            Ok(mut done) => *done = true,
+            Err(_) => {}
+        }
+        match recv_handler.join() {
+            Ok(_) => {}
+            Err(e) => {
+                thread_log!(error, "Error: {:?}", e);
+            }
+        }
+        let replies = match replies.lock() {
+            Ok(replies) => replies.clone(),
+            Err(_) => HashMap::new(),
+        };
+        for (target_ip, _) in &next_hops {
+            for port in ports {
+                let ip_addr = IpAddr::V4(*target_ip);
+                let status = replies
+                    .get(&(ip_addr, *port))
+                    .cloned()
+                    .unwrap_or(PortStatus::Filtered);
+                let result = ProbeResult {
+                    ip_addr: ip_addr,
+                    port: *port,
+                    status: status,
+                    banner: None,
+                };
+                if status == PortStatus::Open {
+                    send_progress(progress_tx, completed, total, Some(&result));
+                }
+                results.push(result);
+            }
+        }
+    }
+    send_progress(progress_tx, completed, total, None);
+    (results, unscanned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::mpsc;
+
+    /// A loopback port nothing listens on
+    fn closed_tcp_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn closed_udp_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn scan_loopback(scan_type: PortScanType, ports: &[u16]) -> PortScanResult {
+        let mut scan_option = PortScanOption::new();
+        scan_option.targets = vec![String::from("127.0.0.1")];
+        scan_option.ports = ports.iter().map(|port| port.to_string()).collect::<Vec<String>>().join(",");
+        scan_option.scan_type = scan_type;
+        scan_option.concurrency = 10;
+        scan_option.timeout = 500;
+        let (progress_tx, _progress_rx) = mpsc::channel();
+        let stop = Arc::new(Mutex::new(false));
+        start_port_scan(scan_option, progress_tx, &stop).unwrap()
+    }
+
+    fn open_ports(result: &PortScanResult) -> Vec<(u16, PortStatus)> {
+        result
+            .hosts
+            .iter()
+            .flat_map(|host| host.ports.iter().map(|port| (port.port, port.status)))
+            .collect()
+    }
+
+    #[test]
+    fn tcp_connect_scan_finds_loopback_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = closed_tcp_port();
+        let result = scan_loopback(PortScanType::TcpConnect, &[open_port, closed_port]);
+        assert_eq!(result.probes, 2);
+        assert!(!result.cancelled);
+        assert_eq!(open_ports(&result), vec![(open_port, PortStatus::Open)]);
+        assert_eq!(result.hosts[0].ip_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn udp_scan_finds_loopback_responder() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let open_port = socket.local_addr().unwrap().port();
+        let responder = thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            if let Ok((_, peer)) = socket.recv_from(&mut buf) {
+                let _ = socket.send_to(b"pong", peer);
+            }
+        });
+        let closed_port = closed_udp_port();
+        let result = scan_loopback(PortScanType::Udp, &[open_port, closed_port]);
+        responder.join().unwrap();
+        assert_eq!(result.probes, 2);
+        // The closed port is refused with ICMP port unreachable
+        assert_eq!(open_ports(&result), vec![(open_port, PortStatus::Open)]);
+    }
+
+    #[test]
+    fn syn_scan_finds_loopback_listener() {
+        // Uses a connect scan when the datalink channel cannot be opened
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let result = scan_loopback(PortScanType::TcpSyn, &[open_port, closed_tcp_port()]);
+        assert_eq!(result.probes, 2);
+        assert_eq!(open_ports(&result), vec![(open_port, PortStatus::Open)]);
+    }
+
+    #[test]
+    fn stopped_scan_sends_no_probes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut scan_option = PortScanOption::new();
+        scan_option.targets = vec![String::from("127.0.0.1")];
+        scan_option.ports = listener.local_addr().unwrap().port().to_string();
+        let (progress_tx, _progress_rx) = mpsc::channel();
+        let stop = Arc::new(Mutex::new(true));
+        let result = start_port_scan(scan_option, progress_tx, &stop).unwrap();
+        assert!(result.cancelled);
+        assert!(result.hosts.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::net::beacon::{BeaconConfig, BeaconDetector, BeaconInfo};
+use crate::net::dnstunnel::{DnsDomainInfo, DnsObservation, DnsTunnelConfig, DnsTunnelDetector};
+use crate::net::link::{GatewayInfo, LinkConfig, LinkDetector, LinkObservation};
+use crate::net::probe::{PortScanConfig, Probe, ScanDetector};
+use crate::net::socket::{LocalSocket, SocketConnection, SocketProcess};
+use crate::net::traffic::TrafficInfo;
+use crate::notification::{Notification, NotificationType};
+use crate::sys;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Events kept in the log. The oldest events are dropped first
+pub const SECURITY_EVENT_LIMIT: usize = 1000;
+/// Events shown as notifications in the overview
+pub const OVERVIEW_NOTIFICATIONS: usize = 10;
+/// A detection repeating within this interval updates the existing event
+pub const DEFAULT_EVENT_COOLDOWN_SECS: u64 = 300;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecurityEventKind {
+    /// Many ports of one host probed by one source
+    VerticalScan,
+    /// The same port probed on many hosts by one source
+    HorizontalSweep,
+    /// Many SYNs to a local port without a completed handshake
+    SynFlood,
+    /// Small outbound connections repeating at regular intervals
+    Beaconing,
+    /// The gateway address announced from an unexpected MAC address
+    GatewayMacChange,
+    /// One MAC address claiming many IP addresses
+    ArpSpoofing,
+    /// Many gratuitous ARP packets on one interface
+    GratuitousArpFlood,
+    /// DHCP replies from a server that is not trusted
+    RogueDhcpServer,
+    /// DNS queries that look like data carried in names or records
+    DnsTunneling,
+}
+
+impl SecurityEventKind {
+    pub fn name(&self) -> String {
+        match self {
+            SecurityEventKind::VerticalScan => "Port Scan".to_string(),
+            SecurityEventKind::HorizontalSweep => "Port Sweep".to_string(),
+            SecurityEventKind::SynFlood => "SYN Flood".to_string(),
+            SecurityEventKind::Beaconing => "Beaconing".to_string(),
+            SecurityEventKind::GatewayMacChange => "Gateway MAC Change".to_string(),
+            SecurityEventKind::ArpSpoofing => "ARP Spoofing".to_string(),
+            SecurityEventKind::GratuitousArpFlood => "Gratuitous ARP Flood".to_string(),
+            SecurityEventKind::RogueDhcpServer => "Rogue DHCP Server".to_string(),
+            SecurityEventKind::DnsTunneling => "DNS Tunneling".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecurityEvent {
+    /// Assigned by the log
+    pub id: u64,
+    pub kind: SecurityEventKind,
+    pub severity: Severity,
+    pub title: String,
+    pub description: String,
+    /// Remote address the activity came from
+    pub source_ip_addr: Option<IpAddr>,
+    /// Local address the activity was aimed at
+    pub target_ip_addr: Option<IpAddr>,
+    /// Link layer address the activity came from
+    pub source_mac_addr: Option<String>,
+    /// Interface the activity was seen on, for link layer events
+    pub if_name: Option<String>,
+    /// Domain the activity involved
+    pub domain: Option<String>,
+    /// Observations that caused the detection
+    pub evidence: Vec<String>,
+    /// RFC3339 format
+    pub first_seen: String,
+    pub last_seen: String,
+    /// Number of times the detection fired
+    pub count: u64,
+    #[serde(skip)]
+    last_seen_at: Option<Instant>,
+}
+
+impl SecurityEvent {
+    pub fn new(kind: SecurityEventKind, severity: Severity, description: String) -> SecurityEvent {
+        let now = sys::get_sysdate();
+        SecurityEvent {
+            id: 0,
+            kind: kind,
+            severity: severity,
+            title: kind.name(),
+            description: description,
+            source_ip_addr: None,
+            target_ip_addr: None,
+            source_mac_addr: None,
+            if_name: None,
+            domain: None,
+            evidence: Vec::new(),
+            first_seen: now.clone(),
+            last_seen: now,
+            count: 1,
+            last_seen_at: Some(Instant::now()),
+        }
+    }
+    fn same_subject(&self, other: &SecurityEvent) -> bool {
+        self.kind == other.kind
+            && self.source_ip_addr == other.source_ip_addr
+            && self.target_ip_addr == other.target_ip_addr
+            && self.source_mac_addr == other.source_mac_addr
+            && self.if_name == other.if_name
+            && self.domain == other.domain
+    }
+    pub fn to_notification(&self) -> Notification {
+        Notification {
+            title: self.title.clone(),
+            body: self.description.clone(),
+            notification_type: NotificationType::Security,
+            timestamp: self.last_seen.clone(),
+        }
+    }
+}
+
+/// Detected events in the order they were last seen
+#[derive(Debug, Clone)]
+pub struct SecurityEventLog {
+    events: Vec<SecurityEvent>,
+    next_id: u64,
+    cooldown: Duration,
+}
+
+impl SecurityEventLog {
+    pub fn new() -> SecurityEventLog {
+        SecurityEventLog {
+            events: Vec::new(),
+            next_id: 1,
+            cooldown: Duration::from_secs(DEFAULT_EVENT_COOLDOWN_SECS),
+        }
+    }
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+    /// Add an event, or update the event with the same kind, source and target
+    /// seen within the cooldown. Returns true for a new event
+    pub fn record(&mut self, mut event: SecurityEvent) -> bool {
+        let cooldown = self.cooldown;
+        let position = self.events.iter().rposition(|e| {
+            e.same_subject(&event)
+                && e.last_seen_at.map_or(false, |last_seen_at| last_seen_at.elapsed() < cooldown)
+        });
+        match position {
+            Some(index) => {
+                let mut existing = self.events.remove(index);
+                existing.severity = existing.severity.max(event.severity);
+                existing.description = event.description;
+                existing.evidence = event.evidence;
+                existing.last_seen = event.last_seen;
+                existing.last_seen_at = event.last_seen_at;
+                existing.count += 1;
+                self.events.push(existing);
+                false
+            }
+            None => {
+                event.id = self.next_id;
+                self.next_id += 1;
+                self.events.push(event);
+                if self.events.len() > SECURITY_EVENT_LIMIT {
+                    self.events.remove(0);
+                }
+                true
+            }
+        }
+    }
+    /// Most recent first
+    pub fn recent(&self, limit: Option<usize>) -> Vec<SecurityEvent> {
+        self.events
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+/// Detector thresholds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecurityConfig {
+    /// A repeating detection updates its event within this interval
+    #[serde(default = "default_event_cooldown_secs")]
+    pub event_cooldown_secs: u64,
+    #[serde(default)]
+    pub port_scan: PortScanConfig,
+    #[serde(default)]
+    pub beacon: BeaconConfig,
+    #[serde(default)]
+    pub link: LinkConfig,
+    #[serde(default)]
+    pub dns_tunnel: DnsTunnelConfig,
+}
+
+fn default_event_cooldown_secs() -> u64 {
+    DEFAULT_EVENT_COOLDOWN_SECS
+}
+
+impl SecurityConfig {
+    pub fn new() -> SecurityConfig {
+        SecurityConfig {
+            event_cooldown_secs: DEFAULT_EVENT_COOLDOWN_SECS,
+            port_scan: PortScanConfig::new(),
+            beacon: BeaconConfig::new(),
+            link: LinkConfig::new(),
+            dns_tunnel: DnsTunnelConfig::new(),
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        SecurityConfig::new()
+    }
+}
+
+/// Detectors and the events they raised
+#[derive(Debug, Clone)]
+pub struct SecurityMonitor {
+    config: SecurityConfig,
+    events: SecurityEventLog,
+    scan_detector: ScanDetector,
+    beacon_detector: BeaconDetector,
+    link_detector: LinkDetector,
+    dns_tunnel_detector: DnsTunnelDetector,
+}
+
+impl SecurityMonitor {
+    pub fn new(config: SecurityConfig) -> SecurityMonitor {
+        let mut events = SecurityEventLog::new();
+        events.set_cooldown(Duration::from_secs(config.event_cooldown_secs));
+        SecurityMonitor {
+            scan_detector: ScanDetector::new(config.port_scan.clone()),
+            beacon_detector: BeaconDetector::new(config.beacon.clone()),
+            link_detector: LinkDetector::new(config.link.clone()),
+            dns_tunnel_detector: DnsTunnelDetector::new(config.dns_tunnel.clone()),
+            events: events,
+            config: config,
+        }
+    }
+    pub fn get_config(&self) -> SecurityConfig {
+        self.config.clone()
+    }
+    pub fn set_config(&mut self, config: SecurityConfig) {
+        self.events.set_cooldown(Duration::from_secs(config.event_cooldown_secs));
+        self.scan_detector.set_config(config.port_scan.clone());
+        self.beacon_detector.set_config(config.beacon.clone());
+        self.link_detector.set_config(config.link.clone());
+        self.dns_tunnel_detector.set_config(config.dns_tunnel.clone());
+        self.config = config;
+    }
+    pub fn add_probes(&mut self, probes: Vec<Probe>) {
+        self.scan_detector.add(probes);
+    }
+    pub fn add_link_observations(&mut self, observations: Vec<LinkObservation>) {
+        self.link_detector.add(observations);
+    }
+    /// Queue DNS messages until their processes are resolved
+    pub fn add_dns_observations(&mut self, observations: Vec<DnsObservation>) {
+        self.dns_tunnel_detector.add(observations);
+    }
+    /// Gateways of the captured interfaces, keyed by interface name
+    pub fn set_gateways(&mut self, gateways: HashMap<String, GatewayInfo>) {
+        self.link_detector.set_gateways(gateways);
+    }
+    /// Run the detectors and record their events. Returns the new events
+    pub fn evaluate(&mut self) -> Vec<SecurityEvent> {
+        let mut new_events: Vec<SecurityEvent> = Vec::new();
+        for event in self.scan_detector.evaluate() {
+            self.record(event, &mut new_events);
+        }
+        for event in self.link_detector.evaluate() {
+            self.record(event, &mut new_events);
+        }
+        new_events
+    }
+    /// Record new connections for beaconing detection and score them. Returns the new events
+    pub fn update_beacons(
+        &mut self,
+        connection_map: &HashMap<SocketConnection, TrafficInfo>,
+        local_socket_map: &HashMap<LocalSocket, SocketProcess>,
+        reverse_dns_map: &HashMap<IpAddr, String>,
+    ) -> Vec<SecurityEvent> {
+        let mut new_events: Vec<SecurityEvent> = Vec::new();
+        self.beacon_detector.observe(connection_map, local_socket_map);
+        for event in self.beacon_detector.evaluate(reverse_dns_map) {
+            self.record(event, &mut new_events);
+        }
+        new_events
+    }
+    /// Attribute queued DNS messages to processes and check their domains. Returns the new events
+    pub fn update_dns_tunneling(&mut self, local_socket_map: &HashMap<LocalSocket, SocketProcess>) -> Vec<SecurityEvent> {
+        let mut new_events: Vec<SecurityEvent> = Vec::new();
+        self.dns_tunnel_detector.resolve(local_socket_map);
+        for event in self.dns_tunnel_detector.evaluate() {
+            self.record(event, &mut new_events);
+        }
+        new_events
+    }
+    pub fn get_dns_domains(&self) -> Vec<DnsDomainInfo> {
+        self.dns_tunnel_detector.get_domains()
+    }
+    pub fn get_beacons(&self, reverse_dns_map: &HashMap<IpAddr, String>) -> Vec<BeaconInfo> {
+        self.beacon_detector.get_beacons(reverse_dns_map)
+    }
+    fn record(&mut self, event: SecurityEvent, new_events: &mut Vec<SecurityEvent>) {
+        if self.events.record(event) {
+            new_events.extend(self.events.recent(Some(1)));
+        }
+    }
+    /// Most recent first
+    pub fn get_events(&self, limit: Option<usize>) -> Vec<SecurityEvent> {
+        self.events.recent(limit)
+    }
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+}
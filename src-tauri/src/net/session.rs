@@ -0,0 +1,444 @@
+use crate::config::AppConfig;
+use crate::net::dissect::{LinkType, PacketDetail, PacketStore};
+use crate::net::interface;
+use crate::net::packet::PacketFrame;
+use crate::net::pcap::{self, CaptureReport, PacketCaptureOptions};
+use crate::net::pcapng::{self, PcapngWriter};
+use crate::sys;
+use crate::thread_log;
+use nex::net::interface::Interface;
+use nex::packet::{ethernet::EtherType, ip::IpNextLevelProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Where captured frames go
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CaptureSink {
+    /// Send frames to the frontend and keep raw bytes for packet details
+    Events,
+    /// Write frames to a pcapng file
+    Pcapng { path: String },
+    /// Both of the above
+    EventsAndPcapng { path: String },
+}
+
+impl CaptureSink {
+    pub fn events(&self) -> bool {
+        match self {
+            CaptureSink::Events | CaptureSink::EventsAndPcapng { .. } => true,
+            CaptureSink::Pcapng { .. } => false,
+        }
+    }
+    pub fn pcapng_path(&self) -> Option<&str> {
+        match self {
+            CaptureSink::Pcapng { path } | CaptureSink::EventsAndPcapng { path } => Some(path),
+            CaptureSink::Events => None,
+        }
+    }
+}
+
+/// Options of an on-demand capture session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureSessionOptions {
+    /// Interface name. The default interface is used if not set
+    pub interface_name: Option<String>,
+    /// Source IP addresses to filter. If empty, all source IP addresses will be captured
+    pub src_ips: Vec<IpAddr>,
+    /// Destination IP addresses to filter. If empty, all destination IP addresses will be captured
+    pub dst_ips: Vec<IpAddr>,
+    /// Source ports to filter. If empty, all source ports will be captured
+    pub src_ports: Vec<u16>,
+    /// Destination ports to filter. If empty, all destination ports will be captured
+    pub dst_ports: Vec<u16>,
+    /// Ether types to filter. If empty, all ether types will be captured
+    pub ether_types: Vec<EtherType>,
+    /// IP protocols to filter. If empty, all IP protocols will be captured
+    pub ip_protocols: Vec<IpNextLevelProtocol>,
+    /// Capture in promiscuous mode
+    pub promiscuous: bool,
+    /// Maximum bytes kept per frame. 0 keeps whole frames
+    pub snaplen: usize,
+    /// Stop after this many captured packets
+    pub max_packets: Option<usize>,
+    /// Stop after this many captured bytes
+    pub max_bytes: Option<usize>,
+    /// Stop after this many seconds
+    pub duration_secs: Option<u64>,
+    pub sink: CaptureSink,
+}
+
+impl CaptureSessionOptions {
+    pub fn new() -> CaptureSessionOptions {
+        CaptureSessionOptions {
+            interface_name: None,
+            src_ips: Vec::new(),
+            dst_ips: Vec::new(),
+            src_ports: Vec::new(),
+            dst_ports: Vec::new(),
+            ether_types: Vec::new(),
+            ip_protocols: Vec::new(),
+            promiscuous: false,
+            snaplen: 0,
+            max_packets: None,
+            max_bytes: None,
+            duration_secs: None,
+            sink: CaptureSink::Events,
+        }
+    }
+    /// Resolve the interface and build capture options
+    pub fn to_capture_options(&self) -> Result<(PacketCaptureOptions, Interface), String> {
+        let iface = match &self.interface_name {
+            Some(name) => match interface::get_interface_by_name(name.clone()) {
+                Some(iface) => iface,
+                None => return Err(format!("Interface {} not found", name)),
+            },
+            None => match netdev::get_default_interface() {
+                Ok(iface) => iface,
+                Err(e) => return Err(format!("No default interface: {}", e)),
+            },
+        };
+        let mut options = PacketCaptureOptions::from_interface(&iface);
+        options.src_ips = self.src_ips.iter().copied().collect();
+        options.dst_ips = self.dst_ips.iter().copied().collect();
+        options.src_ports = self.src_ports.iter().copied().collect();
+        options.dst_ports = self.dst_ports.iter().copied().collect();
+        options.ether_types = self.ether_types.iter().copied().collect();
+        options.ip_protocols = self.ip_protocols.iter().copied().collect();
+        options.promiscuous = self.promiscuous;
+        options.snaplen = self.snaplen;
+        if let Some(secs) = self.duration_secs {
+            options.capture_timeout = Duration::from_secs(secs);
+        }
+        options.tunnel_attribution = AppConfig::load().network.tunnel_attribution;
+        Ok((options, iface))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureSessionState {
+    Running,
+    /// Stopped by the user
+    Stopped,
+    /// A packet, byte or time limit was reached
+    Completed,
+    Failed,
+}
+
+/// Per-session statistics
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureSessionStats {
+    /// Frames read from the interface. Updated when the capture ends
+    pub packets_received: usize,
+    /// Bytes read from the interface. Updated when the capture ends
+    pub bytes_received: usize,
+    /// Frames that matched the filter
+    pub packets_captured: usize,
+    /// Wire bytes of frames that matched the filter
+    pub bytes_captured: usize,
+    /// Frames cut to snaplen
+    pub packets_truncated: usize,
+    /// Frames written to the pcapng file
+    pub packets_written: usize,
+    pub bytes_written: usize,
+}
+
+impl CaptureSessionStats {
+    pub fn new() -> CaptureSessionStats {
+        CaptureSessionStats {
+            packets_received: 0,
+            bytes_received: 0,
+            packets_captured: 0,
+            bytes_captured: 0,
+            packets_truncated: 0,
+            packets_written: 0,
+            bytes_written: 0,
+        }
+    }
+}
+
+/// Capture session for list_captures
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureSessionInfo {
+    pub id: u32,
+    pub if_name: String,
+    pub options: CaptureSessionOptions,
+    pub state: CaptureSessionState,
+    pub error: Option<String>,
+    pub stats: CaptureSessionStats,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+impl CaptureSessionInfo {
+    pub fn new(id: u32, if_name: String, options: CaptureSessionOptions) -> CaptureSessionInfo {
+        CaptureSessionInfo {
+            id: id,
+            if_name: if_name,
+            options: options,
+            state: CaptureSessionState::Running,
+            error: None,
+            stats: CaptureSessionStats::new(),
+            start_time: sys::get_sysdate(),
+            end_time: String::new(),
+        }
+    }
+}
+
+/// Frame of a capture session sent to the frontend
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureFrame {
+    pub session_id: u32,
+    pub frame: PacketFrame,
+}
+
+/// Shared state of a capture session
+#[derive(Clone)]
+pub struct CaptureSessionHandle {
+    pub info: Arc<Mutex<CaptureSessionInfo>>,
+    pub stop: Arc<Mutex<bool>>,
+    pub packets: Arc<Mutex<PacketStore>>,
+}
+
+/// Run a capture session until it is stopped or reaches a limit.
+/// Frames are forwarded to frame_tx if the sink includes events
+pub fn start_capture_session(
+    handle: &CaptureSessionHandle,
+    capture_options: PacketCaptureOptions,
+    iface: Interface,
+    frame_tx: Sender<CaptureFrame>,
+) {
+    let (id, options) = match handle.info.lock() {
+        Ok(info) => (info.id, info.options.clone()),
+        Err(e) => {
+            thread_log!(error, "capture session lock error: {:?}", e);
+            return;
+        }
+    };
+    let link = LinkType::from_parse_option(&pcap::get_parse_option(&iface));
+    let mut writer: Option<PcapngWriter> = None;
+    if let Some(path) = options.sink.pcapng_path() {
+        match PcapngWriter::create(path, &iface.name, link, options.snaplen) {
+            Ok(w) => writer = Some(w),
+            Err(e) => {
+                finish_session(handle, CaptureSessionState::Failed, Some(e), None);
+                return;
+            }
+        }
+    }
+    let (tx, rx): (Sender<PacketFrame>, Receiver<PacketFrame>) = channel();
+    let capture_stop = Arc::clone(&handle.stop);
+    let capture_handle = match thread::Builder::new()
+        .name(format!("capture-session-{}", id))
+        .spawn(move || pcap::start_capture(capture_options, tx, &capture_stop, iface))
+    {
+        Ok(h) => h,
+        Err(e) => {
+            finish_session(handle, CaptureSessionState::Failed, Some(e.to_string()), None);
+            return;
+        }
+    };
+    let mut state = CaptureSessionState::Completed;
+    let mut error: Option<String> = None;
+    let mut limit_reached = false;
+    while let Ok(frame) = rx.recv() {
+        if limit_reached {
+            // Drain frames read before the capture thread saw the stop flag
+            continue;
+        }
+        if let Some(w) = writer.as_mut() {
+            match w.write_packet(pcapng::timestamp_micros(SystemTime::now()), &frame.raw, frame.packet_len) {
+                Ok(_) => {}
+                Err(e) => {
+                    thread_log!(error, "capture session {} write error: {}", id, e);
+                    error = Some(e);
+                    state = CaptureSessionState::Failed;
+                    writer = None;
+                    set_stop(&handle.stop);
+                }
+            }
+        }
+        let (packets, bytes) = match handle.info.lock() {
+            Ok(mut info) => {
+                info.stats.packets_captured += 1;
+                info.stats.bytes_captured += frame.packet_len;
+                if frame.raw.len() < frame.packet_len {
+                    info.stats.packets_truncated += 1;
+                }
+                if let Some(w) = &writer {
+                    info.stats.packets_written = w.packets;
+                    info.stats.bytes_written = w.bytes;
+                }
+                (info.stats.packets_captured, info.stats.bytes_captured)
+            }
+            Err(_) => (0, 0),
+        };
+        if options.sink.events() {
+            match handle.packets.lock() {
+                Ok(mut store) => store.push(&frame, link),
+                Err(e) => {
+                    thread_log!(error, "packet store lock error: {:?}", e);
+                }
+            }
+            match frame_tx.send(CaptureFrame {
+                session_id: id,
+                frame: frame,
+            }) {
+                Ok(_) => {}
+                Err(_) => {}
+            }
+        }
+        let packet_limit = options.max_packets.map(|max| packets >= max).unwrap_or(false);
+        let byte_limit = options.max_bytes.map(|max| bytes >= max).unwrap_or(false);
+        if packet_limit || byte_limit {
+            limit_reached = true;
+            set_stop(&handle.stop);
+        }
+    }
+    let report = match capture_handle.join() {
+        Ok(report) => Some(report),
+        Err(e) => {
+            thread_log!(error, "Error: {:?}", e);
+            None
+        }
+    };
+    if let Some(mut w) = writer {
+        match w.flush() {
+            Ok(_) => {}
+            Err(e) => {
+                error = Some(e);
+                state = CaptureSessionState::Failed;
+            }
+        }
+    }
+    // The stop flag is only set by stop_capture unless a limit was reached
+    if state != CaptureSessionState::Failed && !limit_reached && is_stopped(&handle.stop) {
+        state = CaptureSessionState::Stopped;
+    }
+    finish_session(handle, state, error, report);
+}
+
+fn finish_session(
+    handle: &CaptureSessionHandle,
+    state: CaptureSessionState,
+    error: Option<String>,
+    report: Option<CaptureReport>,
+) {
+    match handle.info.lock() {
+        Ok(mut info) => {
+            if let Some(report) = report {
+                info.stats.packets_received = report.packets;
+                info.stats.bytes_received = report.bytes;
+            }
+            info.state = state;
+            info.error = error;
+            info.end_time = sys::get_sysdate();
+            thread_log!(
+                info,
+                "capture session {} on {} finished ({:?}): {} packets captured",
+                info.id,
+                info.if_name,
+                info.state,
+                info.stats.packets_captured
+            );
+        }
+        Err(e) => {
+            thread_log!(error, "capture session lock error: {:?}", e);
+        }
+    }
+}
+
+fn set_stop(stop: &Arc<Mutex<bool>>) {
+    match stop.lock() {
+        Ok(mut stop) => {
+            *stop = true;
+        }
+        Err(_) => {}
+    }
+}
+
+fn is_stopped(stop: &Arc<Mutex<bool>>) -> bool {
+    match stop.lock() {
+        Ok(stop) => *stop,
+        Err(_) => false,
+    }
+}
+
+/// Capture sessions by ID
+pub struct CaptureSessionStore {
+    next_id: u32,
+    sessions: HashMap<u32, CaptureSessionHandle>,
+}
+
+impl CaptureSessionStore {
+    pub fn new() -> CaptureSessionStore {
+        CaptureSessionStore {
+            next_id: 1,
+            sessions: HashMap::new(),
+        }
+    }
+    /// Register a new session. Returns its handle
+    pub fn add(&mut self, if_name: String, options: CaptureSessionOptions) -> CaptureSessionHandle {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let handle = CaptureSessionHandle {
+            info: Arc::new(Mutex::new(CaptureSessionInfo::new(id, if_name, options))),
+            stop: Arc::new(Mutex::new(false)),
+            packets: Arc::new(Mutex::new(PacketStore::new())),
+        };
+        self.sessions.insert(id, handle.clone());
+        handle
+    }
+    pub fn get(&self, id: u32) -> Result<CaptureSessionHandle, String> {
+        match self.sessions.get(&id) {
+            Some(handle) => Ok(handle.clone()),
+            None => Err(format!("Unknown capture id {}", id)),
+        }
+    }
+    /// Sessions ordered by ID
+    pub fn list(&self) -> Vec<CaptureSessionInfo> {
+        let mut sessions: Vec<CaptureSessionInfo> = self
+            .sessions
+            .values()
+            .filter_map(|handle| match handle.info.lock() {
+                Ok(info) => Some(info.clone()),
+                Err(_) => None,
+            })
+            .collect();
+        sessions.sort_by_key(|info| info.id);
+        sessions
+    }
+    /// Stop capturing. Statistics and frames are kept until removed
+    pub fn stop(&self, id: u32) -> Result<(), String> {
+        let handle = self.get(id)?;
+        let result = match handle.stop.lock() {
+            Ok(mut stop) => {
+                *stop = true;
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        result
+    }
+    /// Stop capturing and release the session
+    pub fn remove(&mut self, id: u32) -> Result<(), String> {
+        self.stop(id)?;
+        self.sessions.remove(&id);
+        Ok(())
+    }
+    pub fn get_packet_detail(&self, id: u32, capture_no: usize) -> Result<PacketDetail, String> {
+        let handle = self.get(id)?;
+        let detail = match handle.packets.lock() {
+            Ok(store) => store.get_detail(capture_no),
+            Err(e) => return Err(e.to_string()),
+        };
+        match detail {
+            Some(detail) => Ok(detail),
+            None => Err(format!("Packet {} is no longer available", capture_no)),
+        }
+    }
+}
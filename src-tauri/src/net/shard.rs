@@ -0,0 +1,742 @@
+use super::{
+    host::RemoteHostInfo,
+    packet::PacketFrame,
+    traffic::{Direction, TrafficInfo},
+};
+use crate::db::rule::RuleSet;
+use crate::net::dnstunnel::{self, DnsObservation};
+use crate::net::link::{self, LinkObservation};
+use crate::net::names::{self, NameSource, ObservedName};
+use crate::net::probe::{FlowKey, Probe, ProbeTracker};
+use crate::net::sampling::SamplingStats;
+use crate::net::signature::{self, RuleMatch};
+use crate::net::socket::{SocketConnection, TransportProtocol};
+use netdev::mac::MacAddr;
+use nex::packet::frame::IpLayer;
+use std::{
+    collections::HashMap,
+    mem,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Distinct names kept between two merges
+pub const MAX_OBSERVED_NAMES: usize = 4096;
+/// Probes kept between two merges
+pub const MAX_PROBES: usize = 65536;
+/// ARP and DHCP observations kept between two merges
+pub const MAX_LINK_OBSERVATIONS: usize = 65536;
+/// DNS messages of local clients kept between two merges
+pub const MAX_DNS_OBSERVATIONS: usize = 65536;
+/// Rule matches kept between two merges. Each holds a copy of the packet
+pub const MAX_RULE_MATCHES: usize = 1024;
+
+/// Per capture thread traffic accumulator.
+/// Each capture thread owns one shard so that packet accounting does not contend
+/// with other threads. Shards are drained and merged by NetStatStrage at tick time.
+#[derive(Debug, Clone)]
+pub struct StatShard {
+    /// Snapshot of NetStatStrage::local_ip_map
+    local_ip_map: HashMap<IpAddr, String>,
+    /// Version of the local_ip_map snapshot
+    local_ip_map_version: u64,
+    /// Shared local_ip_map and its version, used to refresh the snapshot
+    local_ip_map_source: Arc<Mutex<HashMap<IpAddr, String>>>,
+    local_ip_map_source_version: Arc<AtomicU64>,
+    /// Snapshot of NetStatStrage::rules
+    rules: Arc<RuleSet>,
+    /// Version of the rules snapshot
+    rules_version: u64,
+    /// Shared rules and their version, used to refresh the snapshot
+    rules_source: Arc<Mutex<Arc<RuleSet>>>,
+    rules_source_version: Arc<AtomicU64>,
+    pub traffic: TrafficInfo,
+    pub interface_traffic: HashMap<String, TrafficInfo>,
+    pub vlan_traffic: HashMap<u16, TrafficInfo>,
+    pub remote_hosts: HashMap<IpAddr, RemoteHostInfo>,
+    pub connection_map: HashMap<SocketConnection, TrafficInfo>,
+    pub sampling: SamplingStats,
+    /// DNS and SNI names seen in payloads
+    pub observed_names: HashMap<(String, NameSource), ObservedName>,
+    /// Handshake and UDP flow state for probe detection. Kept across merges
+    probe_tracker: ProbeTracker,
+    /// Inbound connection attempts
+    pub probes: Vec<Probe>,
+    /// ARP replies and DHCP server replies
+    pub link_observations: Vec<LinkObservation>,
+    /// DNS queries and responses of local clients
+    pub dns_observations: Vec<DnsObservation>,
+    /// Signature rules that matched captured packets
+    pub rule_matches: Vec<RuleMatch>,
+}
+
+/// Data drained from a StatShard
+#[derive(Debug, Clone)]
+pub struct ShardData {
+    pub traffic: TrafficInfo,
+    pub interface_traffic: HashMap<String, TrafficInfo>,
+    pub vlan_traffic: HashMap<u16, TrafficInfo>,
+    pub remote_hosts: HashMap<IpAddr, RemoteHostInfo>,
+    pub connection_map: HashMap<SocketConnection, TrafficInfo>,
+    pub sampling: SamplingStats,
+    /// DNS and SNI names seen in payloads
+    pub observed_names: HashMap<(String, NameSource), ObservedName>,
+    pub probes: Vec<Probe>,
+    pub link_observations: Vec<LinkObservation>,
+    pub dns_observations: Vec<DnsObservation>,
+    pub rule_matches: Vec<RuleMatch>,
+}
+
+impl ShardData {
+    pub fn new() -> Self {
+        ShardData {
+            traffic: TrafficInfo::new(),
+            interface_traffic: HashMap::new(),
+            vlan_traffic: HashMap::new(),
+            remote_hosts: HashMap::new(),
+            connection_map: HashMap::new(),
+            sampling: SamplingStats::new(),
+            observed_names: HashMap::new(),
+            probes: Vec::new(),
+            link_observations: Vec::new(),
+            dns_observations: Vec::new(),
+            rule_matches: Vec::new(),
+        }
+    }
+    /// Add traffic of other shard data. Rates are not touched.
+    pub fn add(&mut self, other: ShardData) {
+        self.traffic.add_traffic(&other.traffic);
+        add_traffic_map(&mut self.interface_traffic, other.interface_traffic);
+        add_traffic_map(&mut self.vlan_traffic, other.vlan_traffic);
+        for (ip_addr, host) in other.remote_hosts {
+            match self.remote_hosts.entry(ip_addr) {
+                std::collections::hash_map::Entry::Occupied(mut entry) => {
+                    entry.get_mut().traffic_info.add_traffic(&host.traffic_info);
+                }
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(host);
+                }
+            }
+        }
+        add_traffic_map(&mut self.connection_map, other.connection_map);
+        self.sampling.add(&other.sampling);
+        for (key, name) in other.observed_names {
+            if self.observed_names.len() >= MAX_OBSERVED_NAMES {
+                break;
+            }
+            self.observed_names.entry(key).or_insert(name);
+        }
+        self.probes.extend(other.probes);
+        self.link_observations.extend(other.link_observations);
+        self.dns_observations.extend(other.dns_observations);
+        self.rule_matches.extend(other.rule_matches);
+    }
+}
+
+/// Add traffic of each entry in src to dst
+pub fn add_traffic_map<K: std::hash::Hash + Eq>(
+    dst: &mut HashMap<K, TrafficInfo>,
+    src: HashMap<K, TrafficInfo>,
+) {
+    for (key, traffic_info) in src {
+        match dst.entry(key) {
+            std::collections::hash_map::Entry::Occupied(mut entry) => {
+                entry.get_mut().add_traffic(&traffic_info);
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(traffic_info);
+            }
+        }
+    }
+}
+
+impl StatShard {
+    pub fn new(
+        local_ip_map_source: Arc<Mutex<HashMap<IpAddr, String>>>,
+        local_ip_map_source_version: Arc<AtomicU64>,
+        rules_source: Arc<Mutex<Arc<RuleSet>>>,
+        rules_source_version: Arc<AtomicU64>,
+    ) -> Self {
+        let mut shard = StatShard {
+            local_ip_map: HashMap::new(),
+            local_ip_map_version: u64::MAX,
+            local_ip_map_source: local_ip_map_source,
+            local_ip_map_source_version: local_ip_map_source_version,
+            rules: Arc::new(RuleSet::new()),
+            rules_version: u64::MAX,
+            rules_source: rules_source,
+            rules_source_version: rules_source_version,
+            traffic: TrafficInfo::new(),
+            interface_traffic: HashMap::new(),
+            vlan_traffic: HashMap::new(),
+            remote_hosts: HashMap::new(),
+            connection_map: HashMap::new(),
+            sampling: SamplingStats::new(),
+            observed_names: HashMap::new(),
+            probe_tracker: ProbeTracker::new(),
+            probes: Vec::new(),
+            link_observations: Vec::new(),
+            dns_observations: Vec::new(),
+            rule_matches: Vec::new(),
+        };
+        shard.refresh_local_ip_map();
+        shard.refresh_rules();
+        shard
+    }
+    /// Copy the shared local_ip_map if it has changed since the last copy
+    fn refresh_local_ip_map(&mut self) {
+        let version = self.local_ip_map_source_version.load(Ordering::Acquire);
+        if version == self.local_ip_map_version {
+            return;
+        }
+        match self.local_ip_map_source.lock() {
+            Ok(local_ip_map) => {
+                self.local_ip_map = local_ip_map.clone();
+                self.local_ip_map_version = version;
+            }
+            Err(e) => {
+                crate::thread_log!(error, "Failed to lock local_ip_map: {:?}", e);
+            }
+        }
+    }
+    /// Take the rules if they have been reloaded since the last copy
+    fn refresh_rules(&mut self) {
+        let version = self.rules_source_version.load(Ordering::Acquire);
+        if version == self.rules_version {
+            return;
+        }
+        match self.rules_source.lock() {
+            Ok(rules) => {
+                self.rules = Arc::clone(&rules);
+                self.rules_version = version;
+            }
+            Err(e) => {
+                crate::thread_log!(error, "Failed to lock rules: {:?}", e);
+            }
+        }
+    }
+    /// Take the accumulated data and reset the shard
+    pub fn take(&mut self) -> ShardData {
+        ShardData {
+            traffic: mem::replace(&mut self.traffic, TrafficInfo::new()),
+            interface_traffic: mem::take(&mut self.interface_traffic),
+            vlan_traffic: mem::take(&mut self.vlan_traffic),
+            remote_hosts: mem::take(&mut self.remote_hosts),
+            connection_map: mem::take(&mut self.connection_map),
+            sampling: mem::replace(&mut self.sampling, SamplingStats::new()),
+            observed_names: mem::take(&mut self.observed_names),
+            probes: mem::take(&mut self.probes),
+            link_observations: mem::take(&mut self.link_observations),
+            dns_observations: mem::take(&mut self.dns_observations),
+            rule_matches: mem::take(&mut self.rule_matches),
+        }
+    }
+    /// Whether the frame was sent by the client of its flow.
+    /// UDP flows not seen before start with this frame
+    fn to_server(&self, frame: &PacketFrame, direction: Option<Direction>) -> Option<bool> {
+        let direction = direction?;
+        let (flow, protocol) = frame_flow(frame, direction)?;
+        match self.probe_tracker.client_direction(&flow, protocol) {
+            Some(client) => Some(client == direction),
+            None if protocol == TransportProtocol::UDP => Some(true),
+            None => None,
+        }
+    }
+    pub fn update(&mut self, frame: PacketFrame) {
+        self.refresh_local_ip_map();
+        self.refresh_rules();
+        // ARP and DHCP frames often have no local address, look at them first
+        if let Some(observation) = link::observe_frame(&frame) {
+            if self.link_observations.len() < MAX_LINK_OBSERVATIONS {
+                self.link_observations.push(observation);
+            }
+        }
+        // Sampled frames stand for sample_weight frames.
+        // Frames left out of the sample still reach the detectors but are not counted
+        let sampled: bool = frame.sample_weight > 0;
+        let weight: u64 = frame.sample_weight as u64;
+        let bytes: u64 = frame.packet_len as u64 * weight;
+        // Determine if the packet is incoming or outgoing.
+        let mut direction: Option<Direction> = match &frame.ip {
+            Some(ip_layer) => get_direction(ip_layer, &self.local_ip_map),
+            None => None,
+        };
+        // Inner addresses of a decapsulated tunnel are often not local.
+        // Use the tunnel endpoints to tell the direction in that case.
+        if direction.is_none() {
+            if let Some(tunnel) = &frame.tunnel {
+                if self.local_ip_map.contains_key(&tunnel.outer_src) {
+                    direction = Some(Direction::Egress);
+                } else if self.local_ip_map.contains_key(&tunnel.outer_dst) {
+                    direction = Some(Direction::Ingress);
+                }
+            }
+        }
+        // Rules may name any address, match them before the local address checks.
+        // Content rules search the payload, so only sampled frames are matched against them
+        if self.rule_matches.len() < MAX_RULE_MATCHES {
+            let to_server = self.to_server(&frame, direction);
+            for rule_match in signature::match_frame(&self.rules, &frame, to_server, sampled) {
+                if self.rule_matches.len() < MAX_RULE_MATCHES {
+                    self.rule_matches.push(rule_match);
+                }
+            }
+        }
+        // Update interface TrafficInfo.
+        // Every captured frame is counted so that it can be compared with the kernel counters.
+        // Frames without a local IP address (ARP, broadcast, etc.) are counted as ingress.
+        if sampled {
+            let interface_traffic: &mut TrafficInfo = self
+                .interface_traffic
+                .entry(frame.if_name.clone())
+                .or_insert(TrafficInfo::new());
+            match direction.unwrap_or(Direction::Ingress) {
+                Direction::Egress => {
+                    interface_traffic.packet_sent += weight;
+                    interface_traffic.bytes_sent += bytes;
+                }
+                Direction::Ingress => {
+                    interface_traffic.packet_received += weight;
+                    interface_traffic.bytes_received += bytes;
+                }
+            }
+        }
+        // Update VLAN TrafficInfo. Keyed by the innermost tag
+        if let Some(vlan_id) = frame.vlan_ids.last().filter(|_| sampled) {
+            let vlan_traffic: &mut TrafficInfo = self
+                .vlan_traffic
+                .entry(*vlan_id)
+                .or_insert(TrafficInfo::new());
+            match direction.unwrap_or(Direction::Ingress) {
+                Direction::Egress => {
+                    vlan_traffic.packet_sent += weight;
+                    vlan_traffic.bytes_sent += bytes;
+                }
+                Direction::Ingress => {
+                    vlan_traffic.packet_received += weight;
+                    vlan_traffic.bytes_received += bytes;
+                }
+            }
+        }
+        let direction: Direction = match direction {
+            Some(direction) => direction,
+            None => return,
+        };
+        let datalink_layer = match frame.datalink {
+            Some(datalink) => datalink,
+            None => return,
+        };
+        let ip_layer = match frame.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+        // Update TrafficInfo
+        if sampled {
+            match direction {
+                Direction::Egress => {
+                    self.traffic.packet_sent += weight;
+                    self.traffic.bytes_sent += bytes;
+                }
+                Direction::Ingress => {
+                    self.traffic.packet_received += weight;
+                    self.traffic.bytes_received += bytes;
+                }
+            }
+        }
+        let mac_addr: String = match direction {
+            Direction::Egress => {
+                if let Some(ethernet) = datalink_layer.ethernet {
+                    ethernet.destination.address()
+                } else {
+                    MacAddr::zero().to_string()
+                }
+            }
+            Direction::Ingress => {
+                if let Some(ethernet) = datalink_layer.ethernet {
+                    ethernet.source.address()
+                } else {
+                    MacAddr::zero().to_string()
+                }
+            }
+        };
+        let local_ip_addr: IpAddr = match direction {
+            Direction::Egress => {
+                if let Some(ipv4) = &ip_layer.ipv4 {
+                    IpAddr::V4(ipv4.source)
+                } else if let Some(ipv6) = &ip_layer.ipv6 {
+                    IpAddr::V6(ipv6.source)
+                } else {
+                    return;
+                }
+            }
+            Direction::Ingress => {
+                if let Some(ipv4) = &ip_layer.ipv4 {
+                    IpAddr::V4(ipv4.destination)
+                } else if let Some(ipv6) = &ip_layer.ipv6 {
+                    IpAddr::V6(ipv6.destination)
+                } else {
+                    return;
+                }
+            }
+        };
+        let interface_name = match self.local_ip_map.get(&local_ip_addr) {
+            Some(name) => name.clone(),
+            None => {
+                if frame.tunnel.is_some() {
+                    frame.if_name.clone()
+                } else {
+                    String::from("unknown")
+                }
+            }
+        };
+        let local_port: u16 = match direction {
+            Direction::Egress => {
+                if let Some(transport) = &frame.transport {
+                    if let Some(tcp) = &transport.tcp {
+                        tcp.source
+                    } else if let Some(udp) = &transport.udp {
+                        udp.source
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                }
+            }
+            Direction::Ingress => {
+                if let Some(transport) = &frame.transport {
+                    if let Some(tcp) = &transport.tcp {
+                        tcp.destination
+                    } else if let Some(udp) = &transport.udp {
+                        udp.destination
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                }
+            }
+        };
+        let remote_ip_addr: IpAddr = match direction {
+            Direction::Egress => {
+                if let Some(ipv4) = ip_layer.ipv4 {
+                    IpAddr::V4(ipv4.destination)
+                } else if let Some(ipv6) = ip_layer.ipv6 {
+                    IpAddr::V6(ipv6.destination)
+                } else {
+                    return;
+                }
+            }
+            Direction::Ingress => {
+                if let Some(ipv4) = ip_layer.ipv4 {
+                    IpAddr::V4(ipv4.source)
+                } else if let Some(ipv6) = ip_layer.ipv6 {
+                    IpAddr::V6(ipv6.source)
+                } else {
+                    return;
+                }
+            }
+        };
+        let remote_port: u16 = match direction {
+            Direction::Egress => {
+                if let Some(transport) = &frame.transport {
+                    if let Some(tcp) = &transport.tcp {
+                        tcp.destination
+                    } else if let Some(udp) = &transport.udp {
+                        udp.destination
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                }
+            }
+            Direction::Ingress => {
+                if let Some(transport) = &frame.transport {
+                    if let Some(tcp) = &transport.tcp {
+                        tcp.source
+                    } else if let Some(udp) = &transport.udp {
+                        udp.source
+                    } else {
+                        0
+                    }
+                } else {
+                    0
+                }
+            }
+        };
+        // Update or Insert RemoteHostInfo
+        if sampled {
+            let remote_host: &mut RemoteHostInfo = self
+                .remote_hosts
+                .entry(remote_ip_addr)
+                .or_insert(RemoteHostInfo::new(mac_addr, remote_ip_addr));
+            match direction {
+                Direction::Egress => {
+                    remote_host.traffic_info.packet_sent += weight;
+                    remote_host.traffic_info.bytes_sent += bytes;
+                }
+                Direction::Ingress => {
+                    remote_host.traffic_info.packet_received += weight;
+                    remote_host.traffic_info.bytes_received += bytes;
+                }
+            }
+        }
+        // Fragments whose first fragment was never seen have no ports. Count them at the IP layer only
+        if frame.fragment.is_some() && frame.transport.is_none() {
+            return;
+        }
+        // Country/ASN are resolved once per host when the shard is merged. see NetStatStrage::flush_shards
+        // Names are matched against threat feeds at merge time as well
+        if self.observed_names.len() < MAX_OBSERVED_NAMES {
+            let is_tcp = frame.transport.as_ref().map_or(false, |transport| transport.tcp.is_some());
+            for name in names::extract_names(
+                &frame.payload,
+                is_tcp,
+                local_ip_addr,
+                local_port,
+                remote_ip_addr,
+                remote_port,
+            ) {
+                self.observed_names
+                    .entry((name.name.clone(), name.source))
+                    .or_insert(name);
+            }
+        }
+        if remote_port == names::DNS_PORT && self.dns_observations.len() < MAX_DNS_OBSERVATIONS {
+            let protocol = match frame.transport.as_ref().map_or(false, |transport| transport.tcp.is_some()) {
+                true => TransportProtocol::TCP,
+                false => TransportProtocol::UDP,
+            };
+            self.dns_observations.extend(dnstunnel::observe_dns(
+                &frame.payload,
+                protocol,
+                &interface_name,
+                local_ip_addr,
+                local_port,
+                remote_ip_addr,
+                remote_port,
+            ));
+        }
+        let flow = FlowKey {
+            remote_ip_addr: remote_ip_addr,
+            remote_port: remote_port,
+            local_ip_addr: local_ip_addr,
+            local_port: local_port,
+        };
+        // Update SocketConnection if the packet is TCP or UDP.
+        if let Some(transport) = frame.transport {
+            if let Some(tcp) = transport.tcp {
+                if let Some(probe) = self.probe_tracker.observe_tcp(direction, flow, tcp.flags) {
+                    if self.probes.len() < MAX_PROBES {
+                        self.probes.push(probe);
+                    }
+                }
+                if !sampled {
+                    return;
+                }
+                let socket_connection: SocketConnection = SocketConnection {
+                    interface_name: interface_name.clone(),
+                    local_ip_addr: local_ip_addr,
+                    local_port: local_port,
+                    remote_ip_addr: remote_ip_addr,
+                    remote_port: remote_port,
+                    protocol: TransportProtocol::TCP,
+                };
+                let socket_traffic: &mut TrafficInfo = self
+                    .connection_map
+                    .entry(socket_connection)
+                    .or_insert(TrafficInfo::new());
+                match direction {
+                    Direction::Egress => {
+                        socket_traffic.packet_sent += weight;
+                        socket_traffic.bytes_sent += bytes;
+                    }
+                    Direction::Ingress => {
+                        socket_traffic.packet_received += weight;
+                        socket_traffic.bytes_received += bytes;
+                    }
+                }
+            }
+            if let Some(_udp) = transport.udp {
+                if let Some(probe) = self.probe_tracker.observe_udp(direction, flow) {
+                    if self.probes.len() < MAX_PROBES {
+                        self.probes.push(probe);
+                    }
+                }
+                if !sampled {
+                    return;
+                }
+                let socket_connection: SocketConnection = SocketConnection {
+                    interface_name: interface_name,
+                    local_ip_addr: local_ip_addr,
+                    local_port: local_port,
+                    remote_ip_addr: remote_ip_addr,
+                    remote_port: remote_port,
+                    protocol: TransportProtocol::UDP,
+                };
+                let socket_traffic: &mut TrafficInfo = self
+                    .connection_map
+                    .entry(socket_connection)
+                    .or_insert(TrafficInfo::new());
+                match direction {
+                    Direction::Egress => {
+                        socket_traffic.packet_sent += weight;
+                        socket_traffic.bytes_sent += bytes;
+                    }
+                    Direction::Ingress => {
+                        socket_traffic.packet_received += weight;
+                        socket_traffic.bytes_received += bytes;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Flow of a TCP or UDP frame with the local side given by direction
+fn frame_flow(frame: &PacketFrame, direction: Direction) -> Option<(FlowKey, TransportProtocol)> {
+    let ip_layer = frame.ip.as_ref()?;
+    let (src_ip_addr, dst_ip_addr) = if let Some(ipv4) = &ip_layer.ipv4 {
+        (IpAddr::V4(ipv4.source), IpAddr::V4(ipv4.destination))
+    } else if let Some(ipv6) = &ip_layer.ipv6 {
+        (IpAddr::V6(ipv6.source), IpAddr::V6(ipv6.destination))
+    } else {
+        return None;
+    };
+    let transport = frame.transport.as_ref()?;
+    let (src_port, dst_port, protocol) = if let Some(tcp) = &transport.tcp {
+        (tcp.source, tcp.destination, TransportProtocol::TCP)
+    } else if let Some(udp) = &transport.udp {
+        (udp.source, udp.destination, TransportProtocol::UDP)
+    } else {
+        return None;
+    };
+    let flow = match direction {
+        Direction::Egress => FlowKey {
+            remote_ip_addr: dst_ip_addr,
+            remote_port: dst_port,
+            local_ip_addr: src_ip_addr,
+            local_port: src_port,
+        },
+        Direction::Ingress => FlowKey {
+            remote_ip_addr: src_ip_addr,
+            remote_port: src_port,
+            local_ip_addr: dst_ip_addr,
+            local_port: dst_port,
+        },
+    };
+    Some((flow, protocol))
+}
+
+/// Determine if the packet is incoming or outgoing from the local IP addresses.
+pub fn get_direction(ip_layer: &IpLayer, local_ip_map: &HashMap<IpAddr, String>) -> Option<Direction> {
+    if let Some(ipv4) = &ip_layer.ipv4 {
+        if local_ip_map.contains_key(&IpAddr::V4(ipv4.source)) {
+            Some(Direction::Egress)
+        } else if local_ip_map.contains_key(&IpAddr::V4(ipv4.destination)) {
+            Some(Direction::Ingress)
+        } else {
+            None
+        }
+    } else if let Some(ipv6) = &ip_layer.ipv6 {
+        if local_ip_map.contains_key(&IpAddr::V6(ipv6.source)) {
+            Some(Direction::Egress)
+        } else if local_ip_map.contains_key(&IpAddr::V6(ipv6.destination)) {
+            Some(Direction::Ingress)
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::decap::{self, TunnelAttribution};
+    use crate::net::packet::PacketFrame;
+    use crate::net::stat::NetStatStrage;
+    use nex::packet::frame::ParseOption;
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+
+    const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const REMOTE_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+
+    /// Inbound TCP SYN from REMOTE_IP to LOCAL_IP
+    fn syn_frame(dst_port: u16, sample_weight: u32) -> PacketFrame {
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00];
+        data.extend_from_slice(&[0x45, 0x00, 0x00, 40, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        data.extend_from_slice(&REMOTE_IP.octets());
+        data.extend_from_slice(&LOCAL_IP.octets());
+        data.extend_from_slice(&40000u16.to_be_bytes());
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0]);
+        let decoded = decap::decode_frame(&data, ParseOption::default(), TunnelAttribution::Outer);
+        let mut frame = PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded);
+        frame.sample_weight = sample_weight;
+        frame
+    }
+
+    fn new_storage() -> Arc<NetStatStrage> {
+        let storage = Arc::new(NetStatStrage::new());
+        let mut local_ip_map: HashMap<IpAddr, String> = HashMap::new();
+        local_ip_map.insert(IpAddr::V4(LOCAL_IP), String::from("eth0"));
+        storage.set_local_ip_map(local_ip_map);
+        storage
+    }
+
+    #[test]
+    fn unsampled_frame_reaches_detectors_only() {
+        let storage = new_storage();
+        let shard = storage.new_shard();
+        let mut shard = shard.lock().unwrap();
+        shard.update(syn_frame(22, 0));
+        let data = shard.take();
+        assert_eq!(data.probes.len(), 1);
+        assert_eq!(data.traffic.packet_received, 0);
+        assert!(data.interface_traffic.is_empty());
+        assert!(data.remote_hosts.is_empty());
+        assert!(data.connection_map.is_empty());
+    }
+
+    #[test]
+    fn sampled_frame_is_counted_by_weight() {
+        let storage = new_storage();
+        let shard = storage.new_shard();
+        let mut shard = shard.lock().unwrap();
+        shard.update(syn_frame(22, 10));
+        let data = shard.take();
+        assert_eq!(data.probes.len(), 1);
+        assert_eq!(data.traffic.packet_received, 10);
+        assert_eq!(data.traffic.bytes_received, 540);
+        assert_eq!(data.remote_hosts[&IpAddr::V4(REMOTE_IP)].traffic_info.packet_received, 10);
+        assert_eq!(data.connection_map.len(), 1);
+    }
+
+    #[test]
+    fn fragment_without_ports_is_counted_at_ip_layer() {
+        // Last fragment of a UDP datagram from REMOTE_IP to LOCAL_IP
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00];
+        data.extend_from_slice(&[0x45, 0x00, 0x00, 28, 0, 9, 0x00, 0x02, 64, 17, 0, 0]);
+        data.extend_from_slice(&REMOTE_IP.octets());
+        data.extend_from_slice(&LOCAL_IP.octets());
+        data.extend_from_slice(&[0, 53, 0, 53, 0, 8, 0, 0]);
+        let decoded = decap::decode_frame(&data, ParseOption::default(), TunnelAttribution::Outer);
+        let frame = PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded);
+        assert!(frame.transport.is_none());
+        let storage = new_storage();
+        let shard = storage.new_shard();
+        let mut shard = shard.lock().unwrap();
+        shard.update(frame);
+        let data = shard.take();
+        assert_eq!(data.traffic.packet_received, 1);
+        assert_eq!(data.remote_hosts[&IpAddr::V4(REMOTE_IP)].traffic_info.packet_received, 1);
+        assert!(data.connection_map.is_empty());
+    }
+}
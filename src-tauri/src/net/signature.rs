@@ -0,0 +1,179 @@
+use crate::config::AppConfig;
+use crate::db::rule::{self, PacketFields, Rule, RuleAction, RuleConfig, RuleProtocol, RuleSet};
+use crate::net::packet::PacketFrame;
+use crate::net::stat::NetStatStrage;
+use crate::sys;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Matches kept in the log. The oldest matches are dropped first
+pub const RULE_MATCH_LIMIT: usize = 1000;
+/// Payload bytes kept with a match
+pub const MAX_MATCH_PAYLOAD: usize = 512;
+const RULE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A rule that matched a captured packet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleMatch {
+    pub sid: u32,
+    pub rev: u32,
+    pub msg: String,
+    pub action: RuleAction,
+    pub protocol: RuleProtocol,
+    pub src_ip_addr: IpAddr,
+    pub src_port: Option<u16>,
+    pub dst_ip_addr: IpAddr,
+    pub dst_port: Option<u16>,
+    /// Last matching packet
+    pub packet: PacketFrame,
+    /// Leading bytes of the packet payload
+    pub payload: Vec<u8>,
+    /// RFC3339 format
+    pub first_seen: String,
+    pub last_seen: String,
+    pub count: u64,
+}
+
+impl RuleMatch {
+    pub fn new(rule: &Rule, packet: &PacketFields, frame: &PacketFrame) -> RuleMatch {
+        let now = sys::get_sysdate();
+        RuleMatch {
+            sid: rule.sid,
+            rev: rule.rev,
+            msg: rule.msg.clone(),
+            action: rule.action,
+            protocol: packet.protocol,
+            src_ip_addr: packet.src_ip_addr,
+            src_port: packet.src_port,
+            dst_ip_addr: packet.dst_ip_addr,
+            dst_port: packet.dst_port,
+            packet: frame.clone(),
+            payload: frame.payload[..frame.payload.len().min(MAX_MATCH_PAYLOAD)].to_vec(),
+            first_seen: now.clone(),
+            last_seen: now,
+            count: 1,
+        }
+    }
+}
+
+/// Matches in the order they were last seen
+#[derive(Debug, Clone)]
+pub struct RuleMatchLog {
+    matches: Vec<RuleMatch>,
+}
+
+impl RuleMatchLog {
+    pub fn new() -> RuleMatchLog {
+        RuleMatchLog { matches: Vec::new() }
+    }
+    /// Add a match or count it again if the rule matched between the same hosts and server port.
+    /// Returns true for a new match
+    pub fn record(&mut self, rule_match: RuleMatch) -> bool {
+        let position = self.matches.iter().position(|m| {
+            m.sid == rule_match.sid
+                && m.src_ip_addr == rule_match.src_ip_addr
+                && m.dst_ip_addr == rule_match.dst_ip_addr
+                && m.dst_port == rule_match.dst_port
+        });
+        match position {
+            Some(index) => {
+                let mut existing = self.matches.remove(index);
+                existing.last_seen = rule_match.last_seen;
+                existing.packet = rule_match.packet;
+                existing.payload = rule_match.payload;
+                existing.src_port = rule_match.src_port;
+                existing.count += rule_match.count;
+                self.matches.push(existing);
+                false
+            }
+            None => {
+                self.matches.push(rule_match);
+                if self.matches.len() > RULE_MATCH_LIMIT {
+                    self.matches.remove(0);
+                }
+                true
+            }
+        }
+    }
+    /// Most recent first
+    pub fn recent(&self, limit: Option<usize>) -> Vec<RuleMatch> {
+        self.matches
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.matches.clear();
+    }
+}
+
+/// Fields of an IP packet rules are matched against
+pub fn packet_fields(frame: &PacketFrame) -> Option<PacketFields<'_>> {
+    let ip_layer = frame.ip.as_ref()?;
+    let (src_ip_addr, dst_ip_addr) = if let Some(ipv4) = &ip_layer.ipv4 {
+        (IpAddr::V4(ipv4.source), IpAddr::V4(ipv4.destination))
+    } else if let Some(ipv6) = &ip_layer.ipv6 {
+        (IpAddr::V6(ipv6.source), IpAddr::V6(ipv6.destination))
+    } else {
+        return None;
+    };
+    let tcp = frame.transport.as_ref().and_then(|transport| transport.tcp.as_ref());
+    let udp = frame.transport.as_ref().and_then(|transport| transport.udp.as_ref());
+    let (protocol, src_port, dst_port) = if let Some(tcp) = tcp {
+        (RuleProtocol::Tcp, Some(tcp.source), Some(tcp.destination))
+    } else if let Some(udp) = udp {
+        (RuleProtocol::Udp, Some(udp.source), Some(udp.destination))
+    } else if ip_layer.icmp.is_some() || ip_layer.icmpv6.is_some() {
+        (RuleProtocol::Icmp, None, None)
+    } else {
+        (RuleProtocol::Ip, None, None)
+    };
+    Some(PacketFields {
+        protocol: protocol,
+        src_ip_addr: src_ip_addr,
+        dst_ip_addr: dst_ip_addr,
+        src_port: src_port,
+        dst_port: dst_port,
+        tcp_flags: tcp.map(|tcp| tcp.flags),
+        to_server: None,
+        payload: &frame.payload,
+    })
+}
+
+/// Rules matching the frame. Payloads are matched per packet, without stream reassembly.
+/// to_server is the side of the flow the frame was sent from, if known.
+/// Rules with content are skipped unless with_content is set
+pub fn match_frame(rule_set: &RuleSet, frame: &PacketFrame, to_server: Option<bool>, with_content: bool) -> Vec<RuleMatch> {
+    if rule_set.is_empty() {
+        return Vec::new();
+    }
+    let mut packet = match packet_fields(frame) {
+        Some(packet) => packet,
+        None => return Vec::new(),
+    };
+    packet.to_server = to_server;
+    rule_set
+        .matches(&packet, with_content)
+        .map(|rule| RuleMatch::new(rule, &packet, frame))
+        .collect()
+}
+
+/// Reload the rules when the rule files or the rule settings change
+pub fn start_rule_update(netstat_strage: &Arc<NetStatStrage>) {
+    let mut config: Option<RuleConfig> = None;
+    let mut fingerprint = None;
+    loop {
+        let new_config = AppConfig::load().rules;
+        let new_fingerprint = rule::rule_dir_path().map(|dir| rule::rule_files_fingerprint(&dir));
+        if config.as_ref() != Some(&new_config) || fingerprint != new_fingerprint {
+            netstat_strage.load_rules(&new_config);
+        }
+        config = Some(new_config);
+        fingerprint = new_fingerprint;
+        std::thread::sleep(RULE_CHECK_INTERVAL);
+    }
+}
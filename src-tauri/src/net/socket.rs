@@ -1,4 +1,5 @@
 use crate::net::stat::NetStatStrage;
+use crate::db::label::IpLabel;
 use crate::net::traffic::{TrafficDisplayInfo, TrafficInfo};
 use crate::process::ProcessInfo;
 use crate::thread_log;
@@ -160,6 +161,9 @@ pub struct SocketDisplayInfo {
     pub ip_version: AddressFamily,
     pub process: Option<ProcessInfo>,
     pub traffic: TrafficDisplayInfo,
+    /// User-defined labels of the addresses
+    pub local_label: Option<IpLabel>,
+    pub remote_label: Option<IpLabel>,
 }
 
 impl SocketDisplayInfo {
@@ -174,6 +178,8 @@ impl SocketDisplayInfo {
             ip_version: socket_traffic_info.ip_version.clone(),
             process: socket_traffic_info.process.clone(),
             traffic: socket_traffic_info.traffic.to_display_info(),
+            local_label: None,
+            remote_label: None,
         }
     }
 }
@@ -366,11 +372,9 @@ pub fn get_sockets_info(opt: SocketInfoOption) -> Vec<SocketInfo> {
 }
 
 pub fn start_socket_info_update(netstat_strage: &mut Arc<NetStatStrage>) {
-    let mut local_ip_map: HashMap<IpAddr, String> = HashMap::new();
     loop {
-        if local_ip_map.is_empty() {
-            local_ip_map = netstat_strage.get_local_ip_map();
-        }
+        // Interface addresses may change at runtime. see net::watcher
+        let local_ip_map: HashMap<IpAddr, String> = netstat_strage.get_local_ip_map();
         let sockets_info = get_sockets_info(SocketInfoOption::default());
         // Create Vec<LocalSocket>
         let mut local_sockets: HashSet<LocalSocket> = HashSet::new();
@@ -1,30 +1,55 @@
-use super::interface;
+use super::interface::{self, InterfaceCounterInfo, InterfaceCounters, InterfaceTrafficDisplayInfo};
+use super::decap::VlanTrafficDisplayInfo;
+use super::pcap::{CaptureBackend, CaptureStats};
+use super::sampling::{SamplingInfo, SamplingMode, SamplingStats};
+use super::beacon::BeaconInfo;
+use super::dnstunnel::DnsDomainInfo;
+use super::link::GatewayInfo;
+use super::security::{SecurityConfig, SecurityEvent, SecurityMonitor};
+use super::shard::{add_traffic_map, ShardData, StatShard};
+use super::signature::{RuleMatch, RuleMatchLog};
+use super::threat::{ThreatHit, ThreatHitLog, ThreatSource};
 use super::{
-    host::{HostDisplayInfo, RemoteHostInfo},
-    packet::PacketFrame,
+    host::{AsTrafficDisplayInfo, CountryTrafficDisplayInfo, HostDisplayInfo, LabelTrafficDisplayInfo, RemoteHostInfo},
     service::ServiceDisplayInfo,
-    traffic::{Direction, TrafficDisplayInfo, TrafficInfo},
+    traffic::{RateWindows, TrafficDisplayInfo, TrafficInfo},
 };
 use crate::db::service::ServiceDatabase;
-use crate::db::ip::IpDatabase;
+use crate::db::error::DbError;
+use crate::db::ip::{IpDatabase, IpInfo};
+use crate::db::label::{IpLabel, LabelDatabase};
+use crate::db::rule::{RuleConfig, RuleSet, RuleSetStatus};
+use crate::db::threat::{ThreatDatabase, ThreatFeedStatus, ThreatIntelConfig};
 use crate::notification::Notification;
 use crate::process::{ProcessDisplayInfo, ProcessInfo};
 use crate::net::socket::{AddressFamily, LocalSocket, ProtocolPort, SocketConnection, SocketProcess, TransportProtocol, SocketInfoOption, SocketDisplayInfo};
 use crate::thread_log;
-use netdev::{mac::MacAddr, Interface};
+use netdev::Interface;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     net::IpAddr,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+/// Number of cached remote hosts before pruning
+const REMOTE_HOST_CACHE_LIMIT: usize = 65536;
+/// Cached remote hosts not seen for this duration are pruned
+const REMOTE_HOST_CACHE_TTL: Duration = Duration::from_secs(600);
+
 #[derive(Debug, Clone)]
 pub struct NetStatStrage {
     pub interface: Arc<Mutex<Interface>>,
     pub traffic: Arc<Mutex<TrafficInfo>>,
+    /// Interface Traffic Map (Interface Name -> TrafficInfo)
+    pub interface_traffic: Arc<Mutex<HashMap<String, TrafficInfo>>>,
+    /// VLAN Traffic Map (VLAN ID -> TrafficInfo)
+    pub vlan_traffic: Arc<Mutex<HashMap<u16, TrafficInfo>>>,
     /// Remote Host Traffic Info Map (IpAddr -> RemoteHostInfo)
     pub remote_hosts: Arc<Mutex<HashMap<IpAddr, RemoteHostInfo>>>,
     /// Socket Connection Traffic Map (SocketConnection -> TrafficInfo)
@@ -35,8 +60,34 @@ pub struct NetStatStrage {
     pub reverse_dns_map: Arc<Mutex<HashMap<IpAddr, String>>>,
     /// Local IP Map (IpAddr -> Interface Name)
     pub local_ip_map: Arc<Mutex<HashMap<IpAddr, String>>>,
+    /// Incremented on every local_ip_map change
+    pub local_ip_map_version: Arc<AtomicU64>,
+    /// Per capture thread accumulators, merged at tick time
+    pub shards: Arc<Mutex<Vec<Arc<Mutex<StatShard>>>>>,
+    /// Remote hosts with resolved country, ASN and hostname (IpAddr -> RemoteHostInfo)
+    pub remote_host_cache: Arc<Mutex<HashMap<IpAddr, RemoteHostInfo>>>,
+    /// Kernel capture statistics (Interface Name -> CaptureStats)
+    pub capture_stats: Arc<Mutex<HashMap<String, CaptureStats>>>,
+    /// Sampling mode of background capture
+    pub sampling_mode: Arc<Mutex<SamplingMode>>,
+    /// Frames seen and sampled since the last reset
+    pub sampling: Arc<Mutex<SamplingStats>>,
     /// IP Database for IP, ASN, Country, etc.
     pub ipdb: Arc<Mutex<IpDatabase>>,
+    /// User-defined IP/CIDR labels
+    pub labeldb: Arc<Mutex<LabelDatabase>>,
+    /// Indicators of the enabled threat feeds
+    pub threatdb: Arc<Mutex<ThreatDatabase>>,
+    /// Feed matches of remote hosts and observed names
+    pub threat_hits: Arc<Mutex<ThreatHitLog>>,
+    /// Scan and attack detectors and their events
+    pub security: Arc<Mutex<SecurityMonitor>>,
+    /// Signature rules matched by the capture threads
+    pub rules: Arc<Mutex<Arc<RuleSet>>>,
+    /// Incremented on every rules reload
+    pub rules_version: Arc<AtomicU64>,
+    /// Rule matches of captured packets
+    pub rule_matches: Arc<Mutex<RuleMatchLog>>,
 }
 
 impl NetStatStrage {
@@ -52,12 +103,27 @@ impl NetStatStrage {
         NetStatStrage {
             interface: Arc::new(Mutex::new(default_interface)),
             traffic: Arc::new(Mutex::new(TrafficInfo::new())),
+            interface_traffic: Arc::new(Mutex::new(HashMap::new())),
+            vlan_traffic: Arc::new(Mutex::new(HashMap::new())),
             remote_hosts: Arc::new(Mutex::new(HashMap::new())),
             connection_map: Arc::new(Mutex::new(HashMap::new())),
             local_socket_map: Arc::new(Mutex::new(HashMap::new())),
             reverse_dns_map: Arc::new(Mutex::new(HashMap::new())),
             local_ip_map: Arc::new(Mutex::new(local_ip_map)),
+            local_ip_map_version: Arc::new(AtomicU64::new(0)),
+            shards: Arc::new(Mutex::new(Vec::new())),
+            remote_host_cache: Arc::new(Mutex::new(HashMap::new())),
+            capture_stats: Arc::new(Mutex::new(HashMap::new())),
+            sampling_mode: Arc::new(Mutex::new(SamplingMode::Off)),
+            sampling: Arc::new(Mutex::new(SamplingStats::new())),
             ipdb: Arc::new(Mutex::new(IpDatabase::new())),
+            labeldb: Arc::new(Mutex::new(LabelDatabase::new())),
+            threatdb: Arc::new(Mutex::new(ThreatDatabase::new())),
+            threat_hits: Arc::new(Mutex::new(ThreatHitLog::new())),
+            security: Arc::new(Mutex::new(SecurityMonitor::new(SecurityConfig::new()))),
+            rules: Arc::new(Mutex::new(Arc::new(RuleSet::new()))),
+            rules_version: Arc::new(AtomicU64::new(0)),
+            rule_matches: Arc::new(Mutex::new(RuleMatchLog::new())),
         }
     }
     // Set interface
@@ -111,6 +177,60 @@ impl NetStatStrage {
             }
         }
     }
+    /// Get the interface traffic map. (thread safe clone)
+    pub fn get_interface_traffic(&self) -> HashMap<String, TrafficInfo> {
+        match self.interface_traffic.lock() {
+            Ok(interface_traffic) => interface_traffic.clone(),
+            Err(e) => {
+                thread_log!(error, "get_interface_traffic error: {:?}", e);
+                HashMap::new()
+            }
+        }
+    }
+    /// Get the VLAN traffic map. (thread safe clone)
+    pub fn get_vlan_traffic(&self) -> HashMap<u16, TrafficInfo> {
+        match self.vlan_traffic.lock() {
+            Ok(vlan_traffic) => vlan_traffic.clone(),
+            Err(e) => {
+                thread_log!(error, "get_vlan_traffic error: {:?}", e);
+                HashMap::new()
+            }
+        }
+    }
+    pub fn set_sampling_mode(&self, mode: SamplingMode) {
+        match self.sampling_mode.lock() {
+            Ok(mut sampling_mode) => {
+                *sampling_mode = mode;
+            }
+            Err(e) => {
+                thread_log!(error, "set_sampling_mode error: {:?}", e);
+            }
+        }
+    }
+    /// Sampling state since the last reset
+    pub fn get_sampling(&self) -> SamplingInfo {
+        let mode = match self.sampling_mode.lock() {
+            Ok(mode) => *mode,
+            Err(_) => SamplingMode::Off,
+        };
+        match self.sampling.lock() {
+            Ok(sampling) => SamplingInfo::from_stats(mode, &sampling),
+            Err(e) => {
+                thread_log!(error, "get_sampling error: {:?}", e);
+                SamplingInfo::new()
+            }
+        }
+    }
+    pub fn clear_sampling(&self) {
+        match self.sampling.lock() {
+            Ok(mut sampling) => {
+                *sampling = SamplingStats::new();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_sampling error: {:?}", e);
+            }
+        }
+    }
     /// Get the remote hosts. (thread safe clone)
     pub fn get_remote_hosts(&self) -> HashMap<IpAddr, RemoteHostInfo> {
         match self.remote_hosts.lock() {
@@ -142,7 +262,7 @@ impl NetStatStrage {
         }
     }
     pub fn get_local_ip_map(&self) -> HashMap<IpAddr, String> {
-        match self.local_ip_map.try_lock() {
+        match self.local_ip_map.lock() {
             Ok(local_ip_map) => local_ip_map.clone(),
             Err(e) => {
                 thread_log!(error, "get_local_ip_map error: {:?}", e);
@@ -150,6 +270,15 @@ impl NetStatStrage {
             }
         }
     }
+    pub fn get_reverse_dns_map(&self) -> HashMap<IpAddr, String> {
+        match self.reverse_dns_map.lock() {
+            Ok(reverse_dns_map) => reverse_dns_map.clone(),
+            Err(e) => {
+                thread_log!(error, "get_reverse_dns_map error: {:?}", e);
+                HashMap::new()
+            }
+        }
+    }
     fn clear_trraffic(&self) {
         match self.traffic.lock() {
             Ok(mut traffic) => {
@@ -160,6 +289,26 @@ impl NetStatStrage {
             }
         }
     }
+    fn clear_interface_traffic(&self) {
+        match self.interface_traffic.lock() {
+            Ok(mut interface_traffic) => {
+                interface_traffic.clear();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_interface_traffic error: {:?}", e);
+            }
+        }
+    }
+    fn clear_vlan_traffic(&self) {
+        match self.vlan_traffic.lock() {
+            Ok(mut vlan_traffic) => {
+                vlan_traffic.clear();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_vlan_traffic error: {:?}", e);
+            }
+        }
+    }
     fn clear_remote_hosts(&self) {
         match self.remote_hosts.lock() {
             Ok(mut remote_hosts) => {
@@ -202,13 +351,18 @@ impl NetStatStrage {
     }
     pub fn reset(&self) {
         self.clear_trraffic();
+        self.clear_interface_traffic();
+        self.clear_vlan_traffic();
         self.clear_remote_hosts();
         self.clear_connection_map();
         self.clear_local_socket_map();
         self.clear_reverse_dns_map();
     }
     pub fn reset_data(&self) {
+        self.clear_sampling();
         self.clear_trraffic();
+        self.clear_interface_traffic();
+        self.clear_vlan_traffic();
         self.clear_remote_hosts();
         self.clear_connection_map();
         self.clear_local_socket_map();
@@ -219,25 +373,50 @@ impl NetStatStrage {
         clone
     }
     pub fn clone_data_and_reset(&self) -> NetStatData {
+        self.flush_shards();
         let mut clone: NetStatData = NetStatData::new();
         clone.default_interface = self.get_interface();
         clone.traffic = self.get_trrafic();
+        clone.interface_traffic = self.get_interface_traffic();
+        clone.vlan_traffic = self.get_vlan_traffic();
         clone.remote_hosts = self.get_remote_hosts();
         clone.connection_map = self.get_connection_map();
         clone.local_socket_map = self.get_local_socket_map();
         clone.local_ip_map = self.get_local_ip_map();
+        clone.sampling = self.get_sampling();
+        clone.labeldb = self.get_labeldb();
         self.reset_data();
         clone
     }
     pub fn clone_data(&self) -> NetStatData {
+        self.flush_shards();
         let mut clone: NetStatData = NetStatData::new();
         clone.default_interface = self.get_interface();
         clone.traffic = self.get_trrafic();
+        clone.interface_traffic = self.get_interface_traffic();
+        clone.vlan_traffic = self.get_vlan_traffic();
         clone.remote_hosts = self.get_remote_hosts();
         clone.connection_map = self.get_connection_map();
         clone.local_socket_map = self.get_local_socket_map();
         clone
     }
+    // Replace the local IP map. Returns true if the map has changed
+    pub fn set_local_ip_map(&self, new_map: HashMap<IpAddr, String>) -> bool {
+        match self.local_ip_map.lock() {
+            Ok(mut local_ip_map) => {
+                if *local_ip_map == new_map {
+                    return false;
+                }
+                *local_ip_map = new_map;
+                self.local_ip_map_version.fetch_add(1, Ordering::Release);
+                true
+            }
+            Err(e) => {
+                thread_log!(error, "set_local_ip_map error: {:?}", e);
+                false
+            }
+        }
+    }
     pub fn change_interface(&self, interface: &Interface) {
         //self.reset();
         self.set_interface(interface.clone());
@@ -249,318 +428,721 @@ impl NetStatStrage {
         }
         false
     }
-    pub fn load_ipdb(&self) {
-        match IpDatabase::load() {
-            Ok(ipdb) => {
-                let mut ipdb_mutex = self.ipdb.lock().unwrap();
+    /// Load the IP database. Returns the problems found, which are also logged
+    pub fn load_ipdb(&self) -> Vec<DbError> {
+        let (ipdb, errors) = IpDatabase::load();
+        for e in &errors {
+            thread_log!(error, "load_ipdb error: {}", e);
+        }
+        match self.ipdb.lock() {
+            Ok(mut ipdb_mutex) => {
                 *ipdb_mutex = ipdb;
             }
             Err(e) => {
                 thread_log!(error, "load_ipdb error: {:?}", e);
             }
         }
+        errors
     }
-    pub fn update(&self, frame: PacketFrame) {
-        let local_ip_map_inner = match self.local_ip_map.lock() {
-            Ok(inner) => inner,
-            Err(e) => {
-                thread_log!(error, "Failed to lock local_ips: {:?}", e);
-                return;
-            }
+    /// Swap in a freshly loaded IP database and re-resolve the cached remote hosts.
+    /// Returns the problems found while loading
+    pub fn reload_ipdb(&self) -> Result<Vec<DbError>, String> {
+        let (ipdb, errors) = IpDatabase::load();
+        for e in &errors {
+            thread_log!(error, "reload_ipdb error: {}", e);
+        }
+        // Same lock order as resolve_remote_hosts: cache, then ipdb
+        let mut cache = match self.remote_host_cache.lock() {
+            Ok(cache) => cache,
+            Err(e) => return Err(e.to_string()),
         };
-        // Lock traffic field
-        let mut traffic_inner = match self.traffic.lock() {
-            Ok(inner) => inner,
+        for (ip_addr, host) in cache.iter_mut() {
+            let ip_info = match ip_addr {
+                IpAddr::V4(ipv4) => ipdb.get_ipv4_info(*ipv4),
+                IpAddr::V6(ipv6) => ipdb.get_ipv6_info(*ipv6),
+            };
+            host.set_ip_info(ip_info.unwrap_or_else(|| IpInfo::new(*ip_addr)));
+        }
+        match self.ipdb.lock() {
+            Ok(mut ipdb_mutex) => {
+                *ipdb_mutex = ipdb;
+                Ok(errors)
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    /// Load the threat feeds and re-match the cached remote hosts
+    pub fn load_threatdb(&self, config: &ThreatIntelConfig) {
+        let threatdb = ThreatDatabase::load(config);
+        for status in threatdb.status() {
+            if let Some(e) = status.error {
+                thread_log!(error, "load_threatdb error: {}: {}", status.name, e);
+            }
+        }
+        let mut hits: Vec<ThreatHit> = Vec::new();
+        // Same lock order as resolve_remote_hosts: cache, then threatdb
+        match self.remote_host_cache.lock() {
+            Ok(mut cache) => {
+                for host in cache.values_mut() {
+                    let host_hits = match_remote_host(&threatdb, host);
+                    let feeds = hit_feeds(&host_hits);
+                    if host.threat_feeds.is_empty() {
+                        hits.extend(host_hits);
+                    }
+                    host.threat_feeds = feeds;
+                }
+            }
             Err(e) => {
-                thread_log!(error, "Failed to lock traffic: {:?}", e);
-                return;
+                thread_log!(error, "load_threatdb error: {:?}", e);
+            }
+        }
+        match self.threatdb.lock() {
+            Ok(mut threatdb_mutex) => {
+                *threatdb_mutex = threatdb;
             }
-        };
-        // Lock remote_hosts field
-        let mut remote_hosts_inner = match self.remote_hosts.lock() {
-            Ok(inner) => inner,
             Err(e) => {
-                thread_log!(error, "Failed to lock remote_hosts: {:?}", e);
-                return;
+                thread_log!(error, "load_threatdb error: {:?}", e);
             }
-        };
-        // Lock connection_map field
-        let mut connections_inner = match self.connection_map.lock() {
-            Ok(inner) => inner,
+        }
+        self.record_threat_hits(hits);
+    }
+    pub fn get_threat_feed_status(&self) -> Vec<ThreatFeedStatus> {
+        match self.threatdb.lock() {
+            Ok(threatdb) => threatdb.status(),
             Err(e) => {
-                thread_log!(error, "Failed to lock connection_map: {:?}", e);
-                return;
+                thread_log!(error, "get_threat_feed_status error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    fn record_threat_hits(&self, hits: Vec<ThreatHit>) {
+        if hits.is_empty() {
+            return;
+        }
+        match self.threat_hits.lock() {
+            Ok(mut threat_hits) => {
+                for hit in hits {
+                    let message = format!(
+                        "Threat feed match: {} {} ({}) listed in {}",
+                        hit.source.name(),
+                        hit.observed,
+                        hit.remote_ip_addr,
+                        hit.feeds.join(", ")
+                    );
+                    if threat_hits.record(hit) {
+                        thread_log!(warn, "{}", message);
+                    }
+                }
             }
-        };
-        // Lock ipdb field
-        let ipdb_inner = match self.ipdb.lock() {
-            Ok(inner) => inner,
             Err(e) => {
-                thread_log!(error, "Failed to lock ipdb: {:?}", e);
-                return;
+                thread_log!(error, "record_threat_hits error: {:?}", e);
             }
-        };
-        let datalink_layer = match frame.datalink {
-            Some(datalink) => datalink,
-            None => return,
-        };
-        let ip_layer = match frame.ip {
-            Some(ip) => ip,
-            None => return,
-        };
-        // Determine if the packet is incoming or outgoing.
-        let direction: Direction = if let Some(ipv4) = &ip_layer.ipv4 {
-            if local_ip_map_inner.contains_key(&IpAddr::V4(ipv4.source)) {
-                Direction::Egress
-            } else if local_ip_map_inner.contains_key(&IpAddr::V4(ipv4.destination)) {
-                Direction::Ingress
-            } else {
-                return;
+        }
+    }
+    /// Load the rule files and hand the rules to the capture threads
+    pub fn load_rules(&self, config: &RuleConfig) {
+        let rule_set = RuleSet::load(config);
+        let status = rule_set.status();
+        if let Some(e) = &status.error {
+            thread_log!(error, "load_rules error: {}", e);
+        }
+        for file in &status.files {
+            if file.skipped > 0 {
+                thread_log!(warn, "load_rules: {}: {} rules skipped", file.name, file.skipped);
             }
-        } else if let Some(ipv6) = &ip_layer.ipv6 {
-            if local_ip_map_inner.contains_key(&IpAddr::V6(ipv6.source)) {
-                Direction::Egress
-            } else if local_ip_map_inner.contains_key(&IpAddr::V6(ipv6.destination)) {
-                Direction::Ingress
-            } else {
-                return;
+        }
+        thread_log!(info, "Signature rules loaded: {}", status.rule_count);
+        match self.rules.lock() {
+            Ok(mut rules) => {
+                *rules = Arc::new(rule_set);
+                self.rules_version.fetch_add(1, Ordering::Release);
             }
-        } else {
-            return;
-        };
-        // Update TrafficInfo
-        match direction {
-            Direction::Egress => {
-                traffic_inner.packet_sent += 1;
-                traffic_inner.bytes_sent += frame.packet_len;
-            }
-            Direction::Ingress => {
-                traffic_inner.packet_received += 1;
-                traffic_inner.bytes_received += frame.packet_len;
-            }
-        }
-        let mac_addr: String = match direction {
-            Direction::Egress => {
-                if let Some(ethernet) = datalink_layer.ethernet {
-                    ethernet.destination.address()
-                } else {
-                    MacAddr::zero().to_string()
-                }
+            Err(e) => {
+                thread_log!(error, "load_rules error: {:?}", e);
             }
-            Direction::Ingress => {
-                if let Some(ethernet) = datalink_layer.ethernet {
-                    ethernet.source.address()
-                } else {
-                    MacAddr::zero().to_string()
+        }
+    }
+    pub fn get_rule_status(&self) -> RuleSetStatus {
+        match self.rules.lock() {
+            Ok(rules) => rules.status(),
+            Err(e) => {
+                thread_log!(error, "get_rule_status error: {:?}", e);
+                RuleSet::new().status()
+            }
+        }
+    }
+    fn record_rule_matches(&self, rule_matches: Vec<RuleMatch>) {
+        match self.rule_matches.lock() {
+            Ok(mut log) => {
+                for rule_match in rule_matches {
+                    let message = format!(
+                        "Rule match: [sid:{}] {} {} -> {}",
+                        rule_match.sid, rule_match.msg, rule_match.src_ip_addr, rule_match.dst_ip_addr
+                    );
+                    if log.record(rule_match) {
+                        thread_log!(warn, "{}", message);
+                    }
                 }
             }
-        };
-        let local_ip_addr: IpAddr = match direction {
-            Direction::Egress => {
-                if let Some(ipv4) = &ip_layer.ipv4 {
-                    IpAddr::V4(ipv4.source)
-                } else if let Some(ipv6) = &ip_layer.ipv6 {
-                    IpAddr::V6(ipv6.source)
-                } else {
-                    return;
+            Err(e) => {
+                thread_log!(error, "record_rule_matches error: {:?}", e);
+            }
+        }
+    }
+    /// Most recent matches first
+    pub fn get_rule_matches(&self, limit: Option<usize>) -> Vec<RuleMatch> {
+        match self.rule_matches.lock() {
+            Ok(rule_matches) => rule_matches.recent(limit),
+            Err(e) => {
+                thread_log!(error, "get_rule_matches error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    pub fn clear_rule_matches(&self) {
+        match self.rule_matches.lock() {
+            Ok(mut rule_matches) => {
+                rule_matches.clear();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_rule_matches error: {:?}", e);
+            }
+        }
+    }
+    /// Most recent hits first
+    pub fn get_threat_hits(&self, limit: Option<usize>) -> Vec<ThreatHit> {
+        match self.threat_hits.lock() {
+            Ok(threat_hits) => threat_hits.recent(limit),
+            Err(e) => {
+                thread_log!(error, "get_threat_hits error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    pub fn clear_threat_hits(&self) {
+        match self.threat_hits.lock() {
+            Ok(mut threat_hits) => {
+                threat_hits.clear();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_threat_hits error: {:?}", e);
+            }
+        }
+    }
+    pub fn load_labeldb(&self) {
+        match LabelDatabase::load() {
+            Ok(labeldb) => match self.labeldb.lock() {
+                Ok(mut labeldb_mutex) => {
+                    *labeldb_mutex = labeldb;
+                }
+                Err(e) => {
+                    thread_log!(error, "load_labeldb error: {:?}", e);
                 }
+            },
+            Err(e) => {
+                thread_log!(error, "load_labeldb error: {:?}", e);
             }
-            Direction::Ingress => {
-                if let Some(ipv4) = &ip_layer.ipv4 {
-                    IpAddr::V4(ipv4.destination)
-                } else if let Some(ipv6) = &ip_layer.ipv6 {
-                    IpAddr::V6(ipv6.destination)
-                } else {
-                    return;
+        }
+    }
+    pub fn get_labeldb(&self) -> LabelDatabase {
+        match self.labeldb.lock() {
+            Ok(labeldb) => labeldb.clone(),
+            Err(e) => {
+                thread_log!(error, "get_labeldb error: {:?}", e);
+                LabelDatabase::new()
+            }
+        }
+    }
+    /// Validate, save and apply the labels
+    pub fn set_labels(&self, labels: Vec<IpLabel>) -> Result<(), String> {
+        let mut labeldb = LabelDatabase::new();
+        labeldb.set_labels(labels)?;
+        labeldb.save()?;
+        match self.labeldb.lock() {
+            Ok(mut labeldb_mutex) => {
+                *labeldb_mutex = labeldb;
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    pub fn get_capture_stats(&self) -> Vec<CaptureStats> {
+        match self.capture_stats.lock() {
+            Ok(capture_stats) => capture_stats.values().cloned().collect(),
+            Err(e) => {
+                thread_log!(error, "get_capture_stats error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    /// Start a new statistics entry for a capture on the interface
+    pub fn reset_capture_stats(&self, if_name: &str, backend: CaptureBackend) {
+        match self.capture_stats.lock() {
+            Ok(mut capture_stats) => {
+                capture_stats.insert(if_name.to_string(), CaptureStats::new(if_name.to_string(), backend));
+            }
+            Err(e) => {
+                thread_log!(error, "reset_capture_stats error: {:?}", e);
+            }
+        }
+    }
+    /// Add kernel counters delta to the interface statistics
+    pub fn add_capture_stats(&self, if_name: &str, packets: u64, drops: u64, freeze_q_cnt: u64) {
+        match self.capture_stats.lock() {
+            Ok(mut capture_stats) => {
+                if let Some(stats) = capture_stats.get_mut(if_name) {
+                    stats.packets += packets;
+                    stats.drops += drops;
+                    stats.freeze_q_cnt += freeze_q_cnt;
                 }
             }
-        };
-        let interface_name = match local_ip_map_inner.get(&local_ip_addr) {
-            Some(name) => name.clone(),
-            None => String::from("unknown"),
-        };
-        let local_port: u16 = match direction {
-            Direction::Egress => {
-                if let Some(transport) = &frame.transport {
-                    if let Some(tcp) = &transport.tcp {
-                        tcp.source
-                    } else if let Some(udp) = &transport.udp {
-                        udp.source
-                    } else {
-                        0
+            Err(e) => {
+                thread_log!(error, "add_capture_stats error: {:?}", e);
+            }
+        }
+    }
+    /// Register a new accumulator for a capture thread.
+    /// The shard is dropped from the registry after the owning thread releases it.
+    pub fn new_shard(&self) -> Arc<Mutex<StatShard>> {
+        let shard = Arc::new(Mutex::new(StatShard::new(
+            Arc::clone(&self.local_ip_map),
+            Arc::clone(&self.local_ip_map_version),
+            Arc::clone(&self.rules),
+            Arc::clone(&self.rules_version),
+        )));
+        match self.shards.lock() {
+            Ok(mut shards) => {
+                shards.push(Arc::clone(&shard));
+            }
+            Err(e) => {
+                thread_log!(error, "new_shard error: {:?}", e);
+            }
+        }
+        shard
+    }
+    /// Drain all shards and merge them into the storage.
+    /// Country/ASN lookup is done only for hosts not yet in remote_host_cache.
+    pub fn flush_shards(&self) {
+        let mut data = ShardData::new();
+        match self.shards.lock() {
+            Ok(mut shards) => {
+                for shard in shards.iter() {
+                    match shard.lock() {
+                        Ok(mut shard) => {
+                            data.add(shard.take());
+                        }
+                        Err(e) => {
+                            thread_log!(error, "flush_shards error: {:?}", e);
+                        }
                     }
-                } else {
-                    0
                 }
+                // Capture thread has exited
+                shards.retain(|shard| Arc::strong_count(shard) > 1);
+            }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+                return;
+            }
+        }
+        if data.remote_hosts.len() > 0 {
+            self.resolve_remote_hosts(&mut data.remote_hosts);
+        }
+        if data.observed_names.len() > 0 {
+            self.match_observed_names(&data);
+        }
+        self.update_security(&mut data);
+        if data.rule_matches.len() > 0 {
+            self.record_rule_matches(std::mem::take(&mut data.rule_matches));
+        }
+        match self.sampling.lock() {
+            Ok(mut sampling) => {
+                sampling.add(&data.sampling);
             }
-            Direction::Ingress => {
-                if let Some(transport) = &frame.transport {
-                    if let Some(tcp) = &transport.tcp {
-                        tcp.destination
-                    } else if let Some(udp) = &transport.udp {
-                        udp.destination
-                    } else {
-                        0
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+        match self.traffic.lock() {
+            Ok(mut traffic) => {
+                traffic.add_traffic(&data.traffic);
+            }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+        match self.interface_traffic.lock() {
+            Ok(mut interface_traffic) => {
+                add_traffic_map(&mut interface_traffic, data.interface_traffic);
+            }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+        match self.vlan_traffic.lock() {
+            Ok(mut vlan_traffic) => {
+                add_traffic_map(&mut vlan_traffic, data.vlan_traffic);
+            }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+        match self.remote_hosts.lock() {
+            Ok(mut remote_hosts) => {
+                for (ip_addr, host) in data.remote_hosts {
+                    match remote_hosts.entry(ip_addr) {
+                        std::collections::hash_map::Entry::Occupied(mut entry) => {
+                            entry.get_mut().traffic_info.add_traffic(&host.traffic_info);
+                        }
+                        std::collections::hash_map::Entry::Vacant(entry) => {
+                            entry.insert(host);
+                        }
                     }
-                } else {
-                    0
                 }
             }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+        match self.connection_map.lock() {
+            Ok(mut connection_map) => {
+                add_traffic_map(&mut connection_map, data.connection_map);
+            }
+            Err(e) => {
+                thread_log!(error, "flush_shards error: {:?}", e);
+            }
+        }
+    }
+    /// Feed the detectors and log their new events
+    fn update_security(&self, data: &mut ShardData) {
+        let new_events = match self.security.lock() {
+            Ok(mut security) => {
+                security.add_probes(std::mem::take(&mut data.probes));
+                security.add_link_observations(std::mem::take(&mut data.link_observations));
+                security.add_dns_observations(std::mem::take(&mut data.dns_observations));
+                security.evaluate()
+            }
+            Err(e) => {
+                thread_log!(error, "update_security error: {:?}", e);
+                return;
+            }
         };
-        let remote_ip_addr: IpAddr = match direction {
-            Direction::Egress => {
-                if let Some(ipv4) = ip_layer.ipv4 {
-                    IpAddr::V4(ipv4.destination)
-                } else if let Some(ipv6) = ip_layer.ipv6 {
-                    IpAddr::V6(ipv6.destination)
-                } else {
-                    return;
-                }
+        for event in new_events {
+            thread_log!(warn, "Security event: {}: {}", event.title, event.description);
+        }
+    }
+    /// Run the detectors that need the merged connections and their processes, and log their new events
+    pub fn update_process_security(&self, data: &NetStatData) {
+        let reverse_dns_map = self.get_reverse_dns_map();
+        let new_events = match self.security.lock() {
+            Ok(mut security) => {
+                let mut new_events =
+                    security.update_beacons(&data.connection_map, &data.local_socket_map, &reverse_dns_map);
+                new_events.extend(security.update_dns_tunneling(&data.local_socket_map));
+                new_events
+            }
+            Err(e) => {
+                thread_log!(error, "update_process_security error: {:?}", e);
+                return;
+            }
+        };
+        for event in new_events {
+            thread_log!(warn, "Security event: {}: {}", event.title, event.description);
+        }
+    }
+    /// Connection series with interval statistics, highest beacon score first
+    pub fn get_beacons(&self) -> Vec<BeaconInfo> {
+        let reverse_dns_map = self.get_reverse_dns_map();
+        match self.security.lock() {
+            Ok(security) => security.get_beacons(&reverse_dns_map),
+            Err(e) => {
+                thread_log!(error, "get_beacons error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    /// Domains queried per process with their tunneling heuristics, most indicators first
+    pub fn get_dns_domains(&self) -> Vec<DnsDomainInfo> {
+        match self.security.lock() {
+            Ok(security) => security.get_dns_domains(),
+            Err(e) => {
+                thread_log!(error, "get_dns_domains error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    /// Gateways of the captured interfaces, keyed by interface name
+    pub fn set_gateways(&self, gateways: HashMap<String, GatewayInfo>) {
+        match self.security.lock() {
+            Ok(mut security) => {
+                security.set_gateways(gateways);
+            }
+            Err(e) => {
+                thread_log!(error, "set_gateways error: {:?}", e);
+            }
+        }
+    }
+    pub fn get_security_config(&self) -> SecurityConfig {
+        match self.security.lock() {
+            Ok(security) => security.get_config(),
+            Err(e) => {
+                thread_log!(error, "get_security_config error: {:?}", e);
+                SecurityConfig::new()
+            }
+        }
+    }
+    pub fn set_security_config(&self, config: SecurityConfig) {
+        match self.security.lock() {
+            Ok(mut security) => {
+                security.set_config(config);
+            }
+            Err(e) => {
+                thread_log!(error, "set_security_config error: {:?}", e);
             }
-            Direction::Ingress => {
-                if let Some(ipv4) = ip_layer.ipv4 {
-                    IpAddr::V4(ipv4.source)
-                } else if let Some(ipv6) = ip_layer.ipv6 {
-                    IpAddr::V6(ipv6.source)
-                } else {
+        }
+    }
+    /// Most recent events first
+    pub fn get_security_events(&self, limit: Option<usize>) -> Vec<SecurityEvent> {
+        match self.security.lock() {
+            Ok(security) => security.get_events(limit),
+            Err(e) => {
+                thread_log!(error, "get_security_events error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+    pub fn clear_security_events(&self) {
+        match self.security.lock() {
+            Ok(mut security) => {
+                security.clear_events();
+            }
+            Err(e) => {
+                thread_log!(error, "clear_security_events error: {:?}", e);
+            }
+        }
+    }
+    /// Record DNS and SNI names listed in the threat feeds
+    fn match_observed_names(&self, data: &ShardData) {
+        let hits: Vec<ThreatHit> = match self.threatdb.lock() {
+            Ok(threatdb) => {
+                if threatdb.is_empty() {
                     return;
                 }
+                data.observed_names
+                    .values()
+                    .filter_map(|observed| {
+                        threatdb.lookup_domain(&observed.name).map(|threat_match| {
+                            ThreatHit::new(
+                                threat_match,
+                                observed.name.clone(),
+                                ThreatSource::from(observed.source),
+                                Some(observed.local_ip_addr),
+                                observed.remote_ip_addr,
+                            )
+                        })
+                    })
+                    .collect()
+            }
+            Err(e) => {
+                thread_log!(error, "match_observed_names error: {:?}", e);
+                return;
             }
         };
-        let remote_port: u16 = match direction {
-            Direction::Egress => {
-                if let Some(transport) = &frame.transport {
-                    if let Some(tcp) = &transport.tcp {
-                        tcp.destination
-                    } else if let Some(udp) = &transport.udp {
-                        udp.destination
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                }
+        self.record_threat_hits(hits);
+    }
+    /// Fill country, ASN and hostname of remote hosts using remote_host_cache
+    fn resolve_remote_hosts(&self, remote_hosts: &mut HashMap<IpAddr, RemoteHostInfo>) {
+        let mut cache = match self.remote_host_cache.lock() {
+            Ok(cache) => cache,
+            Err(e) => {
+                thread_log!(error, "resolve_remote_hosts error: {:?}", e);
+                return;
             }
-            Direction::Ingress => {
-                if let Some(transport) = &frame.transport {
-                    if let Some(tcp) = &transport.tcp {
-                        tcp.source
-                    } else if let Some(udp) = &transport.udp {
-                        udp.source
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                }
+        };
+        let reverse_dns_map = match self.reverse_dns_map.lock() {
+            Ok(reverse_dns_map) => reverse_dns_map,
+            Err(e) => {
+                thread_log!(error, "resolve_remote_hosts error: {:?}", e);
+                return;
             }
         };
-        // Update or Insert RemoteHostInfo
-        let remote_host: &mut RemoteHostInfo = remote_hosts_inner
-            .entry(remote_ip_addr)
-            .or_insert(RemoteHostInfo::new(mac_addr, remote_ip_addr));
-        match direction {
-            Direction::Egress => {
-                remote_host.traffic_info.packet_sent += 1;
-                remote_host.traffic_info.bytes_sent += frame.packet_len;
-            }
-            Direction::Ingress => {
-                remote_host.traffic_info.packet_received += 1;
-                remote_host.traffic_info.bytes_received += frame.packet_len;
-            }
-        }
-        match remote_host.ip_addr {
-            IpAddr::V4(ipv4) => {
-                if let Some(ipv4_info) = ipdb_inner.get_ipv4_info(ipv4) {
-                    remote_host.country_code = ipv4_info.country_code;
-                    remote_host.country_name = ipv4_info.country_name;
-                    remote_host.asn = ipv4_info.asn;
-                    remote_host.as_name = ipv4_info.as_name;
-                }
+        let threatdb = match self.threatdb.lock() {
+            Ok(threatdb) => threatdb,
+            Err(e) => {
+                thread_log!(error, "resolve_remote_hosts error: {:?}", e);
+                return;
             }
-            IpAddr::V6(ipv6) => {
-                if let Some(ipv6_info) = ipdb_inner.get_ipv6_info(ipv6) {
-                    remote_host.country_code = ipv6_info.country_code;
-                    remote_host.country_name = ipv6_info.country_name;
-                    remote_host.asn = ipv6_info.asn;
-                    remote_host.as_name = ipv6_info.as_name;
+        };
+        let mut hits: Vec<ThreatHit> = Vec::new();
+        let new_hosts: Vec<IpAddr> = remote_hosts
+            .keys()
+            .filter(|ip_addr| !cache.contains_key(*ip_addr))
+            .cloned()
+            .collect();
+        if new_hosts.len() > 0 {
+            match self.ipdb.lock() {
+                Ok(ipdb) => {
+                    for ip_addr in new_hosts {
+                        let mut host = RemoteHostInfo::new(String::new(), ip_addr);
+                        let ip_info = match ip_addr {
+                            IpAddr::V4(ipv4) => ipdb.get_ipv4_info(ipv4),
+                            IpAddr::V6(ipv6) => ipdb.get_ipv6_info(ipv6),
+                        };
+                        if let Some(ip_info) = ip_info {
+                            host.set_ip_info(ip_info);
+                        }
+                        let host_hits = match_remote_host(&threatdb, &host);
+                        host.threat_feeds = hit_feeds(&host_hits);
+                        hits.extend(host_hits);
+                        cache.insert(ip_addr, host);
+                    }
+                }
+                Err(e) => {
+                    thread_log!(error, "resolve_remote_hosts error: {:?}", e);
                 }
             }
         }
-        // Update SocketConnection if the packet is TCP or UDP.
-        if let Some(transport) = frame.transport {
-            if let Some(_tcp) = transport.tcp {
-                let socket_connection: SocketConnection = SocketConnection {
-                    interface_name: interface_name.clone(),
-                    local_ip_addr: local_ip_addr,
-                    local_port: local_port,
-                    remote_ip_addr: remote_ip_addr,
-                    remote_port: remote_port,
-                    protocol: TransportProtocol::TCP,
-                };
-                let socket_traffic: &mut TrafficInfo = connections_inner
-                    .entry(socket_connection)
-                    .or_insert(TrafficInfo::new());
-                match direction {
-                    Direction::Egress => {
-                        socket_traffic.packet_sent += 1;
-                        socket_traffic.bytes_sent += frame.packet_len;
-                    }
-                    Direction::Ingress => {
-                        socket_traffic.packet_received += 1;
-                        socket_traffic.bytes_received += frame.packet_len;
+        let now = Instant::now();
+        for (ip_addr, host) in remote_hosts.iter_mut() {
+            if let Some(cached) = cache.get_mut(ip_addr) {
+                if cached.hostname.is_empty() {
+                    if let Some(hostname) = reverse_dns_map.get(ip_addr) {
+                        cached.hostname = hostname.clone();
+                        // The address was matched when the host was added
+                        if let Some(threat_match) = threatdb.lookup_domain(hostname) {
+                            for feed in &threat_match.feeds {
+                                if !cached.threat_feeds.contains(feed) {
+                                    cached.threat_feeds.push(feed.clone());
+                                }
+                            }
+                            hits.push(ThreatHit::new(
+                                threat_match,
+                                hostname.clone(),
+                                ThreatSource::ReverseDns,
+                                None,
+                                *ip_addr,
+                            ));
+                        }
                     }
                 }
+                cached.traffic_info.last_seen = now;
+                host.hostname = cached.hostname.clone();
+                host.country_code = cached.country_code.clone();
+                host.country_name = cached.country_name.clone();
+                host.asn = cached.asn;
+                host.as_name = cached.as_name.clone();
+                host.city = cached.city.clone();
+                host.latitude = cached.latitude;
+                host.longitude = cached.longitude;
+                host.organization = cached.organization.clone();
+                host.threat_feeds = cached.threat_feeds.clone();
             }
-            if let Some(_udp) = transport.udp {
-                let socket_connection: SocketConnection = SocketConnection {
-                    interface_name: interface_name,
-                    local_ip_addr: local_ip_addr,
-                    local_port: local_port,
-                    remote_ip_addr: remote_ip_addr,
-                    remote_port: remote_port,
-                    protocol: TransportProtocol::UDP,
-                };
-                let socket_traffic: &mut TrafficInfo = connections_inner
-                    .entry(socket_connection)
-                    .or_insert(TrafficInfo::new());
-                match direction {
-                    Direction::Egress => {
-                        socket_traffic.packet_sent += 1;
-                        socket_traffic.bytes_sent += frame.packet_len;
-                    }
-                    Direction::Ingress => {
-                        socket_traffic.packet_received += 1;
-                        socket_traffic.bytes_received += frame.packet_len;
-                    }
-                }
+        }
+        prune_remote_host_cache(&mut cache, now, REMOTE_HOST_CACHE_LIMIT);
+        drop(threatdb);
+        drop(reverse_dns_map);
+        drop(cache);
+        self.record_threat_hits(hits);
+    }
+    /// Remote addresses seen recently
+    pub fn get_known_remote_ips(&self) -> Vec<IpAddr> {
+        match self.remote_host_cache.lock() {
+            Ok(cache) => cache.keys().cloned().collect(),
+            Err(e) => {
+                thread_log!(error, "get_known_remote_ips error: {:?}", e);
+                Vec::new()
             }
         }
-        // Drop the locks
-        drop(traffic_inner);
-        drop(remote_hosts_inner);
-        drop(connections_inner);
-        drop(ipdb_inner);
     }
 }
 
+/// Drop hosts not seen within the TTL once the cache is over limit,
+/// then the least recently seen hosts until it is back at limit
+fn prune_remote_host_cache(cache: &mut HashMap<IpAddr, RemoteHostInfo>, now: Instant, limit: usize) {
+    if cache.len() <= limit {
+        return;
+    }
+    cache.retain(|_, host| now.duration_since(host.traffic_info.last_seen) < REMOTE_HOST_CACHE_TTL);
+    if cache.len() <= limit {
+        return;
+    }
+    let excess = cache.len() - limit;
+    let mut last_seen: Vec<(Instant, IpAddr)> = cache
+        .iter()
+        .map(|(ip_addr, host)| (host.traffic_info.last_seen, *ip_addr))
+        .collect();
+    last_seen.select_nth_unstable(excess - 1);
+    for (_, ip_addr) in &last_seen[..excess] {
+        cache.remove(ip_addr);
+    }
+}
+
+/// Feed matches of a remote host by address and by reverse DNS name
+fn match_remote_host(threatdb: &ThreatDatabase, host: &RemoteHostInfo) -> Vec<ThreatHit> {
+    let mut hits: Vec<ThreatHit> = Vec::new();
+    if let Some(threat_match) = threatdb.lookup_ip(&host.ip_addr) {
+        hits.push(ThreatHit::new(
+            threat_match,
+            host.ip_addr.to_string(),
+            ThreatSource::RemoteHost,
+            None,
+            host.ip_addr,
+        ));
+    }
+    if !host.hostname.is_empty() {
+        if let Some(threat_match) = threatdb.lookup_domain(&host.hostname) {
+            hits.push(ThreatHit::new(
+                threat_match,
+                host.hostname.clone(),
+                ThreatSource::ReverseDns,
+                None,
+                host.ip_addr,
+            ));
+        }
+    }
+    hits
+}
+
+fn hit_feeds(hits: &[ThreatHit]) -> Vec<String> {
+    let mut feeds: Vec<String> = Vec::new();
+    for feed in hits.iter().flat_map(|hit| hit.feeds.iter()) {
+        if !feeds.contains(feed) {
+            feeds.push(feed.clone());
+        }
+    }
+    feeds
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Overview {
     pub traffic: TrafficDisplayInfo,
+    pub interfaces: Vec<InterfaceTrafficDisplayInfo>,
+    /// False if kernel interface counters cannot be read on this platform
+    pub interface_counters_available: bool,
+    /// Kernel capture statistics per captured interface
+    pub capture_stats: Vec<CaptureStats>,
     pub top_processes: Vec<ProcessDisplayInfo>,
     pub top_remote_hosts: Vec<HostDisplayInfo>,
     pub top_app_protocols: Vec<ServiceDisplayInfo>,
-    pub notificatons: Vec<Notification>,
+    pub notifications: Vec<Notification>,
+    /// Recent threat feed matches, most recent first
+    pub threat_hits: Vec<ThreatHit>,
+    /// Counters are extrapolated from samples if sampling.estimated is set
+    pub sampling: SamplingInfo,
 }
 
 impl Overview {
     pub fn new() -> Self {
         Overview {
             traffic: TrafficDisplayInfo::new(),
+            interfaces: Vec::new(),
+            interface_counters_available: interface::interface_counters_available(),
+            capture_stats: Vec::new(),
             top_processes: Vec::new(),
             top_remote_hosts: Vec::new(),
             top_app_protocols: Vec::new(),
-            notificatons: Vec::new(),
+            notifications: Vec::new(),
+            threat_hits: Vec::new(),
+            sampling: SamplingInfo::new(),
         }
     }
 }
@@ -569,10 +1151,21 @@ impl Overview {
 pub struct NetStatData {
     pub default_interface: Interface,
     pub traffic: TrafficInfo,
+    pub interface_traffic: HashMap<String, TrafficInfo>,
+    pub interface_counters: HashMap<String, InterfaceCounterInfo>,
+    pub vlan_traffic: HashMap<u16, TrafficInfo>,
     pub remote_hosts: HashMap<IpAddr, RemoteHostInfo>,
     pub connection_map: HashMap<SocketConnection, TrafficInfo>,
     pub local_socket_map: HashMap<LocalSocket, SocketProcess>,
     pub local_ip_map: HashMap<IpAddr, String>,
+    /// Sampling state of the last interval
+    pub sampling: SamplingInfo,
+    /// Rate state per process ID. Counters are summed from connection_map on demand
+    pub process_traffic: HashMap<u32, TrafficInfo>,
+    pub rate_windows: RateWindows,
+    /// Labels as of the last merge
+    #[serde(skip, default = "LabelDatabase::new")]
+    pub labeldb: LabelDatabase,
 }
 
 impl NetStatData {
@@ -587,48 +1180,55 @@ impl NetStatData {
         NetStatData {
             default_interface: default_interface,
             traffic: TrafficInfo::new(),
+            interface_traffic: HashMap::new(),
+            interface_counters: HashMap::new(),
+            vlan_traffic: HashMap::new(),
             remote_hosts: HashMap::new(),
             connection_map: HashMap::new(),
             local_socket_map: HashMap::new(),
             local_ip_map: HashMap::new(),
+            sampling: SamplingInfo::new(),
+            process_traffic: HashMap::new(),
+            rate_windows: RateWindows::new(),
+            labeldb: LabelDatabase::new(),
         }
     }
     // merge using entry method to merge traffic info.
     pub fn merge(&mut self, other: NetStatData, duration: Duration) {
         // Update Interface Info
         self.default_interface = other.default_interface;
+        self.sampling = other.sampling;
+        self.labeldb = other.labeldb;
+        let windows = self.rate_windows;
         // Update Traffic Info
-        self.traffic.update_bytes_per_sec(&other.traffic, duration);
+        self.traffic.update_bytes_per_sec(&other.traffic, duration, &windows);
         self.traffic.add_traffic(&other.traffic);
+        // Update interface Traffic Info
+        merge_traffic_map(&mut self.interface_traffic, &other.interface_traffic, duration, &windows);
+        // Update VLAN Traffic Info
+        merge_traffic_map(&mut self.vlan_traffic, &other.vlan_traffic, duration, &windows);
         // Update RemoteHostInfo
         other
             .remote_hosts
             .iter()
-            .for_each(|(ip, host)| match self.remote_hosts.entry(*ip) {
-                std::collections::hash_map::Entry::Occupied(mut entry) => {
-                    let host_entry = entry.get_mut();
-                    host_entry.merge(host, duration);
-                }
-                std::collections::hash_map::Entry::Vacant(entry) => {
-                    entry.insert(host.clone());
-                }
+            .for_each(|(ip, host)| {
+                let host_entry = self.remote_hosts.entry(*ip).or_insert_with(|| {
+                    let mut new_host = host.clone();
+                    new_host.traffic_info = TrafficInfo::new();
+                    new_host
+                });
+                host_entry.merge(host, duration, &windows);
+            });
+        // Hosts without traffic in this interval decay towards zero
+        let idle = TrafficInfo::new();
+        self.remote_hosts
+            .iter_mut()
+            .filter(|(ip, _)| !other.remote_hosts.contains_key(*ip))
+            .for_each(|(_, host)| {
+                host.traffic_info.update_bytes_per_sec(&idle, duration, &windows);
             });
         // Update SocketConnection Traffic Info
-        other
-            .connection_map
-            .iter()
-            .for_each(
-                |(conn, traffic_info)| match self.connection_map.entry(conn.clone()) {
-                    std::collections::hash_map::Entry::Occupied(mut entry) => {
-                        let traffic_info_entry = entry.get_mut();
-                        traffic_info_entry.update_bytes_per_sec(traffic_info, duration);
-                        traffic_info_entry.add_traffic(traffic_info);
-                    }
-                    std::collections::hash_map::Entry::Vacant(entry) => {
-                        entry.insert(traffic_info.clone());
-                    }
-                },
-            );
+        merge_traffic_map(&mut self.connection_map, &other.connection_map, duration, &windows);
         // Update local_socket_map
         other
             .local_socket_map
@@ -646,72 +1246,82 @@ impl NetStatData {
             });
         // Update local_ip_map
         self.local_ip_map = other.local_ip_map;
+        // Update per-process rates
+        let mut process_interval: HashMap<u32, TrafficInfo> = HashMap::new();
+        other.connection_map.iter().for_each(|(conn, traffic_info)| {
+            if let Some(pid) = self.get_connection_pid(conn) {
+                process_interval
+                    .entry(pid)
+                    .or_insert(TrafficInfo::new())
+                    .add_traffic(traffic_info);
+            }
+        });
+        merge_traffic_map(&mut self.process_traffic, &process_interval, duration, &windows);
     }
 
-    // Remove old entries from remote_hosts, connection_map, local_socket_map
+    fn get_connection_pid(&self, conn: &SocketConnection) -> Option<u32> {
+        let local_socket: LocalSocket = LocalSocket {
+            interface_name: conn.interface_name.clone(),
+            port: conn.local_port,
+            protocol: conn.protocol,
+        };
+        match self.local_socket_map.get(&local_socket) {
+            Some(socket_process) => match &socket_process.process {
+                Some(process) => Some(process.pid),
+                None => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Update kernel interface counters and their rates
+    pub fn update_interface_counters(
+        &mut self,
+        counters: HashMap<String, InterfaceCounters>,
+        duration: Duration,
+    ) {
+        self.interface_counters
+            .retain(|if_name, _| counters.contains_key(if_name));
+        for (if_name, counter) in counters {
+            match self.interface_counters.entry(if_name) {
+                std::collections::hash_map::Entry::Occupied(mut entry) => {
+                    entry.get_mut().update(counter, duration);
+                }
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(InterfaceCounterInfo::new(counter));
+                }
+            }
+        }
+    }
+
+    // Remove old entries from remote_hosts, connection_map, local_socket_map, process_traffic
     // TrafficInfo.last_seen is used to determine if the entry is old.
     // If the entry is older than ttl, it will be removed.
     pub fn remove_old_entries(&mut self, ttl: Duration) {
-        let now = std::time::SystemTime::now();
-        let remote_hosts: HashMap<IpAddr, RemoteHostInfo> = self
-            .remote_hosts
-            .iter()
-            .filter(
-                |(_ip, host)| match now.duration_since(host.traffic_info.last_seen) {
-                    Ok(duration) => {
-                        if duration > ttl {
-                            return false;
-                        }
-                        true
-                    }
-                    Err(e) => {
-                        thread_log!(error, "remove_old_entries error: {:?}", e);
-                        false
-                    }
-                },
-            )
-            .map(|(ip, host)| (*ip, host.clone()))
-            .collect();
-        self.remote_hosts = remote_hosts;
+        let now = Instant::now();
+        self.remote_hosts
+            .retain(|_ip, host| now.duration_since(host.traffic_info.last_seen) <= ttl);
         let mut remove_local_socket: Vec<LocalSocket> = Vec::new();
-        let connection_map: HashMap<SocketConnection, TrafficInfo> = self
-            .connection_map
-            .iter()
-            .filter(
-                |(conn, traffic_info)| match now.duration_since(traffic_info.last_seen) {
-                    Ok(duration) => {
-                        if duration > ttl {
-                            remove_local_socket.push(LocalSocket {
-                                interface_name: conn.interface_name.clone(),
-                                port: conn.local_port,
-                                protocol: conn.protocol,
-                            });
-                            return false;
-                        }
-                        true
-                    }
-                    Err(e) => {
-                        thread_log!(error, "remove_old_entries error: {:?}", e);
-                        remove_local_socket.push(LocalSocket {
-                            interface_name: conn.interface_name.clone(),
-                            port: conn.local_port,
-                            protocol: conn.protocol,
-                        });
-                        false
-                    }
-                },
-            )
-            .map(|(conn, traffic_info)| (conn.clone(), traffic_info.clone()))
-            .collect();
-        self.connection_map = connection_map;
-
+        self.connection_map.retain(|conn, traffic_info| {
+            if now.duration_since(traffic_info.last_seen) > ttl {
+                remove_local_socket.push(LocalSocket {
+                    interface_name: conn.interface_name.clone(),
+                    port: conn.local_port,
+                    protocol: conn.protocol,
+                });
+                return false;
+            }
+            true
+        });
         for local_socket in remove_local_socket {
             self.local_socket_map.remove(&local_socket);
         }
+        self.process_traffic
+            .retain(|_pid, traffic_info| now.duration_since(traffic_info.last_seen) <= ttl);
     }
 
     pub fn get_remote_hosts(&self, limit: Option<usize>) -> Vec<HostDisplayInfo> {
-        let mut host_traffic_map: HashMap<IpAddr, usize> = HashMap::new();
+        let mut host_traffic_map: HashMap<IpAddr, u64> = HashMap::new();
         self.remote_hosts.iter().for_each(|(_ip, host)| {
             match host_traffic_map.get(&host.ip_addr) {
                 Some(traffic) => {
@@ -728,7 +1338,7 @@ impl NetStatData {
                 }
             }
         });
-        let mut host_traffic_vec: Vec<(&IpAddr, &usize)> = host_traffic_map.iter().collect();
+        let mut host_traffic_vec: Vec<(&IpAddr, &u64)> = host_traffic_map.iter().collect();
         host_traffic_vec.sort_by(|a, b| b.1.cmp(a.1));
         let mut remote_hosts: Vec<HostDisplayInfo> = Vec::new();
         // limit : if limit is None, return all remote hosts.
@@ -737,21 +1347,170 @@ impl NetStatData {
             .take(limit.unwrap_or(host_traffic_vec.len()))
         {
             if let Some(host) = self.remote_hosts.get(ip) {
-                let host = HostDisplayInfo {
-                    ip_addr: host.ip_addr,
-                    host_name: host.hostname.clone(),
-                    country_code: host.country_code.clone(),
-                    country_name: host.country_name.clone(),
-                    asn: host.asn.clone(),
-                    as_name: host.as_name.clone(),
-                    traffic: host.traffic_info.to_display_info(),
-                };
-                remote_hosts.push(host);
+                remote_hosts.push(HostDisplayInfo::from_remote_host(host, self.labeldb.lookup(&host.ip_addr)));
             }
         }
         remote_hosts
     }
 
+    /// Group the remote hosts by key and sum their traffic.
+    /// Groups are sorted by total bytes, hosts in each group too
+    fn group_remote_hosts<K, F>(&self, key_fn: F) -> Vec<(TrafficInfo, Vec<&RemoteHostInfo>)>
+    where
+        K: Eq + std::hash::Hash,
+        F: Fn(&RemoteHostInfo) -> K,
+    {
+        let mut group_map: HashMap<K, (TrafficInfo, Vec<&RemoteHostInfo>)> = HashMap::new();
+        for host in self.remote_hosts.values() {
+            let group = group_map
+                .entry(key_fn(host))
+                .or_insert((TrafficInfo::new(), Vec::new()));
+            group.0.add_traffic(&host.traffic_info);
+            group.0.add_rates(&host.traffic_info);
+            group.1.push(host);
+        }
+        let mut groups: Vec<(TrafficInfo, Vec<&RemoteHostInfo>)> = group_map.into_values().collect();
+        for (_, hosts) in groups.iter_mut() {
+            hosts.sort_by(|a, b| b.traffic_info.total_bytes().cmp(&a.traffic_info.total_bytes()));
+        }
+        groups.sort_by(|a, b| b.0.total_bytes().cmp(&a.0.total_bytes()));
+        groups
+    }
+
+    /// Traffic per country. limit: number of countries, top_hosts: number of hosts per country
+    pub fn get_countries(&self, limit: Option<usize>, top_hosts: usize) -> Vec<CountryTrafficDisplayInfo> {
+        let groups = self.group_remote_hosts(|host| host.country_code.clone());
+        groups
+            .iter()
+            .take(limit.unwrap_or(groups.len()))
+            .map(|(traffic, hosts)| CountryTrafficDisplayInfo {
+                country_code: hosts[0].country_code.clone(),
+                country_name: match hosts.iter().find(|host| !host.country_name.is_empty()) {
+                    Some(host) => host.country_name.clone(),
+                    None => String::new(),
+                },
+                host_count: hosts.len(),
+                traffic: traffic.to_display_info(),
+                top_hosts: hosts
+                    .iter()
+                    .take(top_hosts)
+                    .map(|host| HostDisplayInfo::from_remote_host(host, self.labeldb.lookup(&host.ip_addr)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Traffic per label name. Hosts without a label are grouped under an empty name
+    pub fn get_label_groups(&self, top_hosts: usize) -> Vec<LabelTrafficDisplayInfo> {
+        let labels = self.labeldb.labels();
+        let groups = self.group_remote_hosts(|host| match self.labeldb.lookup(&host.ip_addr) {
+            Some(label) => label.name.clone(),
+            None => String::new(),
+        });
+        groups
+            .iter()
+            .map(|(traffic, hosts)| {
+                let name = match self.labeldb.lookup(&hosts[0].ip_addr) {
+                    Some(label) => label.name.clone(),
+                    None => String::new(),
+                };
+                let mut cidrs: Vec<String> = Vec::new();
+                let mut tags: Vec<String> = Vec::new();
+                for label in labels.iter().filter(|label| !name.is_empty() && label.name == name) {
+                    cidrs.push(label.cidr.clone());
+                    for tag in &label.tags {
+                        if !tags.contains(tag) {
+                            tags.push(tag.clone());
+                        }
+                    }
+                }
+                LabelTrafficDisplayInfo {
+                    name: name,
+                    cidrs: cidrs,
+                    tags: tags,
+                    host_count: hosts.len(),
+                    traffic: traffic.to_display_info(),
+                    top_hosts: hosts
+                        .iter()
+                        .take(top_hosts)
+                        .map(|host| HostDisplayInfo::from_remote_host(host, self.labeldb.lookup(&host.ip_addr)))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Traffic per autonomous system. limit: number of ASes, top_hosts: number of hosts per AS
+    pub fn get_autonomous_systems(&self, limit: Option<usize>, top_hosts: usize) -> Vec<AsTrafficDisplayInfo> {
+        let groups = self.group_remote_hosts(|host| host.asn);
+        groups
+            .iter()
+            .take(limit.unwrap_or(groups.len()))
+            .map(|(traffic, hosts)| AsTrafficDisplayInfo {
+                asn: hosts[0].asn,
+                as_name: match hosts.iter().find(|host| !host.as_name.is_empty()) {
+                    Some(host) => host.as_name.clone(),
+                    None => String::new(),
+                },
+                host_count: hosts.len(),
+                traffic: traffic.to_display_info(),
+                top_hosts: hosts
+                    .iter()
+                    .take(top_hosts)
+                    .map(|host| HostDisplayInfo::from_remote_host(host, self.labeldb.lookup(&host.ip_addr)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Per-interface traffic (capture-derived) with the kernel counters.
+    /// Interfaces without captured traffic are listed if the kernel has seen traffic on them.
+    pub fn get_interface_traffic(&self) -> Vec<InterfaceTrafficDisplayInfo> {
+        let mut if_names: Vec<&String> = self.interface_traffic.keys().collect();
+        for (if_name, counter_info) in &self.interface_counters {
+            if !self.interface_traffic.contains_key(if_name)
+                && (counter_info.counters.rx_bytes > 0 || counter_info.counters.tx_bytes > 0)
+            {
+                if_names.push(if_name);
+            }
+        }
+        let mut interfaces: Vec<InterfaceTrafficDisplayInfo> = if_names
+            .into_iter()
+            .map(|if_name| InterfaceTrafficDisplayInfo {
+                name: if_name.clone(),
+                traffic: match self.interface_traffic.get(if_name) {
+                    Some(traffic) => traffic.to_display_info(),
+                    None => TrafficInfo::new().to_display_info(),
+                },
+                counters: self.interface_counters.get(if_name).cloned(),
+            })
+            .collect();
+        interfaces.sort_by(|a, b| {
+            (b.traffic.bytes_sent + b.traffic.bytes_received)
+                .cmp(&(a.traffic.bytes_sent + a.traffic.bytes_received))
+                .then(a.name.cmp(&b.name))
+        });
+        interfaces
+    }
+
+    /// Traffic per VLAN ID, sorted by total bytes
+    pub fn get_vlan_traffic(&self) -> Vec<VlanTrafficDisplayInfo> {
+        let mut vlans: Vec<VlanTrafficDisplayInfo> = self
+            .vlan_traffic
+            .iter()
+            .map(|(vlan_id, traffic)| VlanTrafficDisplayInfo {
+                vlan_id: *vlan_id,
+                traffic: traffic.to_display_info(),
+            })
+            .collect();
+        vlans.sort_by(|a, b| {
+            (b.traffic.bytes_sent + b.traffic.bytes_received)
+                .cmp(&(a.traffic.bytes_sent + a.traffic.bytes_received))
+                .then(a.vlan_id.cmp(&b.vlan_id))
+        });
+        vlans
+    }
+
     pub fn get_processes(&self, limit: Option<usize>) -> Vec<ProcessDisplayInfo> {
         let mut process_traffic_map: HashMap<u32, TrafficInfo> = HashMap::new();
         let mut process_map: HashMap<u32, ProcessInfo> = HashMap::new();
@@ -781,12 +1540,12 @@ impl NetStatData {
             }
         });
         // Create process total traffic map from process_traffic_map
-        let process_total_traffic_map: HashMap<u32, usize> = process_traffic_map
+        let process_total_traffic_map: HashMap<u32, u64> = process_traffic_map
             .iter()
             .map(|(pid, traffic)| (*pid, traffic.total_bytes()))
             .collect();
         // Sort process_total_traffic_map by traffic
-        let mut process_total_traffic_vec: Vec<(&u32, &usize)> =
+        let mut process_total_traffic_vec: Vec<(&u32, &u64)> =
             process_total_traffic_map.iter().collect();
         process_total_traffic_vec.sort_by(|a, b| b.1.cmp(a.1));
         // Create top processes from process_total_traffic_vec
@@ -796,7 +1555,10 @@ impl NetStatData {
             .iter()
             .take(limit.unwrap_or(process_total_traffic_vec.len()))
         {
-            if let Some(traffic) = process_traffic_map.get(pid) {
+            if let Some(traffic) = process_traffic_map.get_mut(pid) {
+                if let Some(process_traffic) = self.process_traffic.get(pid) {
+                    traffic.set_rates(process_traffic);
+                }
                 if let Some(process) = process_map.get(pid) {
                     let process = ProcessDisplayInfo {
                         pid: process.pid,
@@ -811,12 +1573,12 @@ impl NetStatData {
     }
 
     pub fn get_connections(&self, limit: Option<usize>) -> Vec<SocketDisplayInfo> {
-        let connection_total_traffic_map: HashMap<SocketConnection, usize> = self
+        let connection_total_traffic_map: HashMap<SocketConnection, u64> = self
             .connection_map
             .iter()
             .map(|(conn, traffic)| (conn.clone(), traffic.total_bytes()))
             .collect();
-        let mut connection_total_traffic_vec: Vec<(&SocketConnection, &usize)> =
+        let mut connection_total_traffic_vec: Vec<(&SocketConnection, &u64)> =
             connection_total_traffic_map.iter().collect();
         connection_total_traffic_vec.sort_by(|a, b| b.1.cmp(a.1));
         let mut top_connections: Vec<SocketDisplayInfo> = Vec::new();
@@ -848,6 +1610,8 @@ impl NetStatData {
                     },
                     traffic: traffic.to_display_info(),
                     process: process,
+                    local_label: self.labeldb.lookup(&conn.local_ip_addr).cloned(),
+                    remote_label: self.labeldb.lookup(&conn.remote_ip_addr).cloned(),
                 };
                 top_connections.push(socket_traffic_info);
             }
@@ -860,12 +1624,12 @@ impl NetStatData {
         limit: Option<usize>,
         opt: SocketInfoOption,
     ) -> Vec<SocketDisplayInfo> {
-        let connection_total_traffic_map: HashMap<SocketConnection, usize> = self
+        let connection_total_traffic_map: HashMap<SocketConnection, u64> = self
             .connection_map
             .iter()
             .map(|(conn, traffic)| (conn.clone(), traffic.total_bytes()))
             .collect();
-        let mut connection_total_traffic_vec: Vec<(&SocketConnection, &usize)> =
+        let mut connection_total_traffic_vec: Vec<(&SocketConnection, &u64)> =
             connection_total_traffic_map.iter().collect();
         connection_total_traffic_vec.sort_by(|a, b| b.1.cmp(a.1));
         let mut top_connections: Vec<SocketDisplayInfo> = Vec::new();
@@ -897,6 +1661,8 @@ impl NetStatData {
                     },
                     traffic: traffic.to_display_info(),
                     process: process,
+                    local_label: self.labeldb.lookup(&conn.local_ip_addr).cloned(),
+                    remote_label: self.labeldb.lookup(&conn.remote_ip_addr).cloned(),
                 };
                 if opt.address_family.contains(&socket_traffic_info.ip_version)
                     && opt
@@ -911,13 +1677,10 @@ impl NetStatData {
     }
 
     pub fn get_app_protocols(&self, limit: Option<usize>) -> Vec<ServiceDisplayInfo> {
-        let service_db: ServiceDatabase = match crate::db::service::ServiceDatabase::load() {
-            Ok(db) => db,
-            Err(e) => {
-                thread_log!(error, "get_app_protocols load service db error: {:?}", e);
-                ServiceDatabase::new()
-            }
-        };
+        let (service_db, errors) = ServiceDatabase::load();
+        for e in errors {
+            thread_log!(error, "get_app_protocols load service db error: {}", e);
+        }
         let mut protocol_port_map: HashMap<ProtocolPort, TrafficInfo> = HashMap::new();
         self.connection_map.iter().for_each(|(conn, traffic_info)| {
             let protocol_port: ProtocolPort = ProtocolPort {
@@ -928,6 +1691,7 @@ impl NetStatData {
                 Some(traffic) => {
                     let mut traffic = traffic.clone();
                     traffic.add_traffic(traffic_info);
+                    traffic.add_rates(traffic_info);
                     protocol_port_map.insert(protocol_port, traffic);
                 }
                 None => {
@@ -935,11 +1699,11 @@ impl NetStatData {
                 }
             }
         });
-        let protocol_total_traffic_map: HashMap<ProtocolPort, usize> = protocol_port_map
+        let protocol_total_traffic_map: HashMap<ProtocolPort, u64> = protocol_port_map
             .iter()
             .map(|(protocol_port, traffic)| (*protocol_port, traffic.total_bytes()))
             .collect();
-        let mut protocol_total_traffic_vec: Vec<(&ProtocolPort, &usize)> =
+        let mut protocol_total_traffic_vec: Vec<(&ProtocolPort, &u64)> =
             protocol_total_traffic_map.iter().collect();
         protocol_total_traffic_vec.sort_by(|a, b| b.1.cmp(a.1));
         let mut top_app_protocols: Vec<ServiceDisplayInfo> = Vec::new();
@@ -979,25 +1743,56 @@ impl NetStatData {
     pub fn get_overview(&self) -> Overview {
         let mut overview = Overview::new();
         overview.traffic = TrafficDisplayInfo::from_traffic(&self.traffic);
+        // Get per-interface traffic
+        overview.interfaces = self.get_interface_traffic();
         // Get top remote hosts
         overview.top_remote_hosts = self.get_remote_hosts(Some(10));
         // Get top processes
         overview.top_processes = self.get_processes(Some(10));
         // Get top app protocols
         overview.top_app_protocols = self.get_app_protocols(Some(10));
+        overview.sampling = self.sampling.clone();
         overview
     }
 }
 
+/// Merge the traffic of an interval into dst and update the rates.
+/// Entries without traffic in the interval decay towards zero
+fn merge_traffic_map<K: Eq + std::hash::Hash + Clone>(
+    dst: &mut HashMap<K, TrafficInfo>,
+    src: &HashMap<K, TrafficInfo>,
+    duration: Duration,
+    windows: &RateWindows,
+) {
+    for (key, traffic_info) in src {
+        // New entries keep the time the traffic was first seen
+        let entry = dst.entry(key.clone()).or_insert_with(|| {
+            let mut new_traffic = TrafficInfo::new();
+            new_traffic.first_seen = traffic_info.first_seen;
+            new_traffic
+        });
+        entry.update_bytes_per_sec(traffic_info, duration, windows);
+        entry.add_traffic(traffic_info);
+    }
+    let idle = TrafficInfo::new();
+    dst.iter_mut()
+        .filter(|(key, _)| !src.contains_key(*key))
+        .for_each(|(_, traffic_info)| {
+            traffic_info.update_bytes_per_sec(&idle, duration, windows);
+        });
+}
+
 pub fn update_netstat_data(
     netstat_strage: &mut Arc<NetStatStrage>,
     netstat_data: &mut Arc<Mutex<NetStatData>>,
     interval: Duration,
 ) {
+    let mut last_merge = Instant::now();
     loop {
         match netstat_data.lock() {
             Ok(mut data) => {
-                data.merge(netstat_strage.clone_data_and_reset(), interval);
+                data.merge(netstat_strage.clone_data_and_reset(), last_merge.elapsed());
+                last_merge = Instant::now();
             }
             Err(e) => {
                 thread_log!(error, "Error: {:?}", e);
@@ -1006,4 +1801,47 @@ pub fn update_netstat_data(
         }
         thread::sleep(interval);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_host(ip_addr: IpAddr, last_seen: Instant) -> (IpAddr, RemoteHostInfo) {
+        let mut host = RemoteHostInfo::new(String::new(), ip_addr);
+        host.traffic_info.last_seen = last_seen;
+        (ip_addr, host)
+    }
+
+    #[test]
+    fn remote_host_cache_evicts_least_recently_seen() {
+        let start = Instant::now();
+        let mut cache: HashMap<IpAddr, RemoteHostInfo> = (0..10u8)
+            .map(|i| cached_host(IpAddr::from([192, 0, 2, i]), start + Duration::from_secs(i as u64)))
+            .collect();
+        let now = start + Duration::from_secs(100);
+        // Within the limit nothing is dropped
+        prune_remote_host_cache(&mut cache, now, 10);
+        assert_eq!(cache.len(), 10);
+        // All hosts are within the TTL, the oldest go first
+        prune_remote_host_cache(&mut cache, now, 6);
+        let mut remaining: Vec<IpAddr> = cache.keys().cloned().collect();
+        remaining.sort();
+        let expected: Vec<IpAddr> = (4..10u8).map(|i| IpAddr::from([192, 0, 2, i])).collect();
+        assert_eq!(remaining, expected);
+    }
+
+    #[test]
+    fn remote_host_cache_drops_expired_before_evicting() {
+        let expired = Instant::now();
+        let now = expired + REMOTE_HOST_CACHE_TTL + Duration::from_secs(10);
+        let mut cache: HashMap<IpAddr, RemoteHostInfo> = HashMap::new();
+        cache.extend([
+            cached_host(IpAddr::from([192, 0, 2, 1]), expired),
+            cached_host(IpAddr::from([192, 0, 2, 2]), now - Duration::from_secs(5)),
+            cached_host(IpAddr::from([192, 0, 2, 3]), now),
+        ]);
+        prune_remote_host_cache(&mut cache, now, 2);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key(&IpAddr::from([192, 0, 2, 1])));
+    }
+}
@@ -0,0 +1,673 @@
+use crate::config::AppConfig;
+use crate::net::interface;
+use crate::net::packet::PacketFrame;
+use crate::net::pcap::PacketCaptureOptions;
+use crate::net::socket::{SocketConnection, TransportProtocol};
+use crate::net::traffic::Direction;
+use crate::thread_log;
+use nex::packet::ip::IpNextLevelProtocol;
+use nex::packet::tcp::TcpFlags;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default cap of stored payload bytes per stream
+pub const DEFAULT_STREAM_MAX_BYTES: usize = 4 * 1024 * 1024;
+/// Upper bound of the requested cap so a stream cannot hold unbounded memory
+pub const MAX_STREAM_BYTES: usize = 64 * 1024 * 1024;
+/// Out-of-order bytes buffered per direction before skipping the missing range
+const MAX_OUT_OF_ORDER_BYTES: usize = 1024 * 1024;
+/// Segments further ahead of the expected sequence number are ignored
+const MAX_SEQ_AHEAD: u32 = 16 * 1024 * 1024;
+/// Interval of progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const HEX_BYTES_PER_LINE: usize = 16;
+
+/// Stream view and save format
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Printable ASCII. Other bytes are shown as '.'
+    Ascii,
+    /// Hex dump with offset and ASCII columns
+    Hex,
+    /// Bytes as is. Shown as lossy UTF-8
+    Raw,
+}
+
+/// Contiguous payload sent in one direction
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamChunk {
+    pub direction: Direction,
+    /// Arrival time of the first packet in this chunk. RFC3339 format
+    pub timestamp: String,
+    pub data: Vec<u8>,
+}
+
+/// Rendered stream chunk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamChunkView {
+    pub direction: Direction,
+    pub timestamp: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StreamSummary {
+    pub packets: u64,
+    /// Payload bytes sent by the local side
+    pub bytes_sent: u64,
+    /// Payload bytes received from the remote side
+    pub bytes_received: u64,
+    /// Payload bytes kept in memory
+    pub stored_bytes: usize,
+    /// Stored bytes reached the cap. Later payload is counted but not kept
+    pub truncated: bool,
+    /// Segments that carried only already received bytes
+    pub retransmissions: u64,
+    /// Segments received ahead of a missing one
+    pub out_of_order: u64,
+    /// Bytes never captured and skipped
+    pub missing_bytes: u64,
+    /// Capture has ended
+    pub finished: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamView {
+    pub connection: SocketConnection,
+    pub summary: StreamSummary,
+    pub chunks: Vec<StreamChunkView>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamProgress {
+    pub id: u32,
+    pub summary: StreamSummary,
+}
+
+/// One direction of a TCP byte stream
+struct TcpHalfStream {
+    next_seq: Option<u32>,
+    /// Segments ahead of next_seq
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    out_of_order_bytes: usize,
+}
+
+impl TcpHalfStream {
+    fn new() -> TcpHalfStream {
+        TcpHalfStream {
+            next_seq: None,
+            out_of_order: Vec::new(),
+            out_of_order_bytes: 0,
+        }
+    }
+    /// Add a segment and return the bytes that became contiguous
+    fn add_segment(&mut self, seq: u32, syn: bool, payload: &[u8], summary: &mut StreamSummary) -> Vec<u8> {
+        let mut delivered: Vec<u8> = Vec::new();
+        // SYN consumes one sequence number
+        let seq = if syn { seq.wrapping_add(1) } else { seq };
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        if payload.is_empty() {
+            return delivered;
+        }
+        let ahead = seq.wrapping_sub(next_seq);
+        if ahead == 0 || ahead > u32::MAX - MAX_SEQ_AHEAD {
+            // In order, or starts before next_seq (retransmission possibly with new bytes)
+            let behind = next_seq.wrapping_sub(seq) as usize;
+            if behind >= payload.len() {
+                summary.retransmissions += 1;
+                return delivered;
+            }
+            self.deliver(&payload[behind..], &mut delivered);
+            self.drain_out_of_order(&mut delivered, summary);
+        } else if ahead < MAX_SEQ_AHEAD {
+            summary.out_of_order += 1;
+            if !self.out_of_order.iter().any(|(s, d)| *s == seq && d.len() >= payload.len()) {
+                self.out_of_order_bytes += payload.len();
+                self.out_of_order.push((seq, payload.to_vec()));
+            }
+            if self.out_of_order_bytes > MAX_OUT_OF_ORDER_BYTES {
+                self.skip_gap(summary);
+                self.drain_out_of_order(&mut delivered, summary);
+            }
+        }
+        delivered
+    }
+    fn deliver(&mut self, data: &[u8], delivered: &mut Vec<u8>) {
+        delivered.extend_from_slice(data);
+        if let Some(next_seq) = self.next_seq.as_mut() {
+            *next_seq = next_seq.wrapping_add(data.len() as u32);
+        }
+    }
+    /// Deliver buffered segments that start at or before next_seq
+    fn drain_out_of_order(&mut self, delivered: &mut Vec<u8>, summary: &mut StreamSummary) {
+        loop {
+            let next_seq = match self.next_seq {
+                Some(seq) => seq,
+                None => return,
+            };
+            let pos = self
+                .out_of_order
+                .iter()
+                .position(|(seq, _)| next_seq.wrapping_sub(*seq) < MAX_SEQ_AHEAD);
+            let (seq, data) = match pos {
+                Some(pos) => self.out_of_order.swap_remove(pos),
+                None => return,
+            };
+            self.out_of_order_bytes -= data.len();
+            let behind = next_seq.wrapping_sub(seq) as usize;
+            if behind < data.len() {
+                self.deliver(&data[behind..], delivered);
+            } else {
+                summary.retransmissions += 1;
+            }
+        }
+    }
+    /// Move next_seq to the earliest buffered segment
+    fn skip_gap(&mut self, summary: &mut StreamSummary) {
+        let next_seq = match self.next_seq {
+            Some(seq) => seq,
+            None => return,
+        };
+        let earliest = self
+            .out_of_order
+            .iter()
+            .map(|(seq, _)| seq.wrapping_sub(next_seq))
+            .min();
+        if let Some(gap) = earliest {
+            summary.missing_bytes += gap as u64;
+            self.next_seq = Some(next_seq.wrapping_add(gap));
+        }
+    }
+    /// Deliver everything still buffered, skipping missing ranges
+    fn flush(&mut self, delivered: &mut Vec<u8>, summary: &mut StreamSummary) {
+        while !self.out_of_order.is_empty() {
+            self.skip_gap(summary);
+            self.drain_out_of_order(delivered, summary);
+        }
+    }
+}
+
+/// Payload of one TCP connection (both directions reassembled) or UDP flow (datagrams)
+pub struct FollowStream {
+    pub connection: SocketConnection,
+    max_bytes: usize,
+    chunks: Vec<StreamChunk>,
+    egress: TcpHalfStream,
+    ingress: TcpHalfStream,
+    summary: StreamSummary,
+}
+
+impl FollowStream {
+    pub fn new(connection: SocketConnection, max_bytes: usize) -> FollowStream {
+        FollowStream {
+            connection: connection,
+            max_bytes: max_bytes,
+            chunks: Vec::new(),
+            egress: TcpHalfStream::new(),
+            ingress: TcpHalfStream::new(),
+            summary: StreamSummary::default(),
+        }
+    }
+    /// Direction of the frame relative to the local side, or None if it belongs to another flow
+    fn get_direction(&self, src: (IpAddr, u16), dst: (IpAddr, u16)) -> Option<Direction> {
+        let local = (self.connection.local_ip_addr, self.connection.local_port);
+        let remote = (self.connection.remote_ip_addr, self.connection.remote_port);
+        if src == local && dst == remote {
+            Some(Direction::Egress)
+        } else if src == remote && dst == local {
+            Some(Direction::Ingress)
+        } else {
+            None
+        }
+    }
+    pub fn update(&mut self, frame: &PacketFrame) {
+        let (src_ip, dst_ip) = match &frame.ip {
+            Some(ip) => {
+                if let Some(ipv4) = &ip.ipv4 {
+                    (IpAddr::V4(ipv4.source), IpAddr::V4(ipv4.destination))
+                } else if let Some(ipv6) = &ip.ipv6 {
+                    (IpAddr::V6(ipv6.source), IpAddr::V6(ipv6.destination))
+                } else {
+                    return;
+                }
+            }
+            None => return,
+        };
+        let transport = match &frame.transport {
+            Some(transport) => transport,
+            None => return,
+        };
+        match self.connection.protocol {
+            TransportProtocol::TCP => {
+                let tcp = match &transport.tcp {
+                    Some(tcp) => tcp,
+                    None => return,
+                };
+                let direction = match self.get_direction((src_ip, tcp.source), (dst_ip, tcp.destination)) {
+                    Some(direction) => direction,
+                    None => return,
+                };
+                self.summary.packets += 1;
+                let syn = tcp.flags & TcpFlags::SYN != 0;
+                let delivered = match direction {
+                    Direction::Egress => {
+                        self.egress
+                            .add_segment(tcp.sequence, syn, &frame.payload, &mut self.summary)
+                    }
+                    Direction::Ingress => {
+                        self.ingress
+                            .add_segment(tcp.sequence, syn, &frame.payload, &mut self.summary)
+                    }
+                };
+                self.append(direction, &frame.timestamp, delivered, true);
+            }
+            TransportProtocol::UDP => {
+                let udp = match &transport.udp {
+                    Some(udp) => udp,
+                    None => return,
+                };
+                let direction = match self.get_direction((src_ip, udp.source), (dst_ip, udp.destination)) {
+                    Some(direction) => direction,
+                    None => return,
+                };
+                self.summary.packets += 1;
+                self.append(direction, &frame.timestamp, frame.payload.clone(), false);
+            }
+        }
+    }
+    /// Store delivered payload up to max_bytes. TCP payload is merged into the previous chunk
+    /// of the same direction, each UDP datagram is kept as its own chunk.
+    fn append(&mut self, direction: Direction, timestamp: &str, mut data: Vec<u8>, merge: bool) {
+        if data.is_empty() {
+            return;
+        }
+        match direction {
+            Direction::Egress => self.summary.bytes_sent += data.len() as u64,
+            Direction::Ingress => self.summary.bytes_received += data.len() as u64,
+        }
+        let available = self.max_bytes.saturating_sub(self.summary.stored_bytes);
+        if data.len() > available {
+            self.summary.truncated = true;
+            data.truncate(available);
+            if data.is_empty() {
+                return;
+            }
+        }
+        self.summary.stored_bytes += data.len();
+        if merge {
+            if let Some(last) = self.chunks.last_mut() {
+                if last.direction == direction {
+                    last.data.extend_from_slice(&data);
+                    return;
+                }
+            }
+        }
+        self.chunks.push(StreamChunk {
+            direction: direction,
+            timestamp: timestamp.to_string(),
+            data: data,
+        });
+    }
+    /// Deliver buffered out-of-order data and mark the stream as finished
+    pub fn finish(&mut self) {
+        let timestamp = crate::sys::get_sysdate();
+        let mut delivered: Vec<u8> = Vec::new();
+        self.egress.flush(&mut delivered, &mut self.summary);
+        self.append(Direction::Egress, &timestamp, delivered, true);
+        let mut delivered: Vec<u8> = Vec::new();
+        self.ingress.flush(&mut delivered, &mut self.summary);
+        self.append(Direction::Ingress, &timestamp, delivered, true);
+        self.summary.finished = true;
+    }
+    pub fn summary(&self) -> StreamSummary {
+        self.summary.clone()
+    }
+    pub fn chunks(&self) -> &Vec<StreamChunk> {
+        &self.chunks
+    }
+    pub fn to_view(&self, format: StreamFormat) -> StreamView {
+        let mut offsets: HashMap<Direction, usize> = HashMap::new();
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let offset = offsets.entry(chunk.direction).or_insert(0);
+                let text = render(&chunk.data, format, *offset);
+                *offset += chunk.data.len();
+                StreamChunkView {
+                    direction: chunk.direction,
+                    timestamp: chunk.timestamp.clone(),
+                    text: text,
+                }
+            })
+            .collect();
+        StreamView {
+            connection: self.connection.clone(),
+            summary: self.summary(),
+            chunks: chunks,
+        }
+    }
+    /// Save the stream. Raw writes the payload of both directions in arrival order,
+    /// Ascii and Hex write the rendered chunks with a direction header.
+    pub fn save(&self, path: &str, format: StreamFormat) -> Result<(), String> {
+        let content: Vec<u8> = match format {
+            StreamFormat::Raw => self.chunks.iter().flat_map(|c| c.data.iter().copied()).collect(),
+            _ => {
+                let view = self.to_view(format);
+                let mut text = String::new();
+                for chunk in view.chunks {
+                    let arrow = match chunk.direction {
+                        Direction::Egress => "local -> remote",
+                        Direction::Ingress => "remote -> local",
+                    };
+                    text.push_str(&format!("==== {} {} ====\n", chunk.timestamp, arrow));
+                    text.push_str(&chunk.text);
+                    if !chunk.text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                }
+                text.into_bytes()
+            }
+        };
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+}
+
+/// Render bytes in the given format. offset is the position of data in its direction
+pub fn render(data: &[u8], format: StreamFormat, offset: usize) -> String {
+    match format {
+        StreamFormat::Ascii => data
+            .iter()
+            .map(|b| match *b {
+                b'\n' | b'\r' | b'\t' | 0x20..=0x7E => *b as char,
+                _ => '.',
+            })
+            .collect(),
+        StreamFormat::Hex => {
+            let mut text = String::new();
+            for (i, line) in data.chunks(HEX_BYTES_PER_LINE).enumerate() {
+                text.push_str(&format!("{:08x}  ", offset + i * HEX_BYTES_PER_LINE));
+                for j in 0..HEX_BYTES_PER_LINE {
+                    match line.get(j) {
+                        Some(b) => text.push_str(&format!("{:02x} ", b)),
+                        None => text.push_str("   "),
+                    }
+                    if j == 7 {
+                        text.push(' ');
+                    }
+                }
+                text.push_str(" |");
+                for b in line {
+                    text.push(if (0x20..=0x7E).contains(b) { *b as char } else { '.' });
+                }
+                text.push_str("|\n");
+            }
+            text
+        }
+        StreamFormat::Raw => String::from_utf8_lossy(data).to_string(),
+    }
+}
+
+/// Capture the packets of the stream's connection until stop is set.
+/// Progress is sent every second and when the capture ends.
+pub fn start_follow_stream(
+    id: u32,
+    stream: &Arc<Mutex<FollowStream>>,
+    stop: &Arc<Mutex<bool>>,
+    progress_tx: Sender<StreamProgress>,
+) -> Result<(), String> {
+    let connection = match stream.lock() {
+        Ok(stream) => stream.connection.clone(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let iface = match interface::get_interface_by_name(connection.interface_name.clone()) {
+        Some(iface) => iface,
+        None => match netdev::get_default_interface() {
+            Ok(iface) => iface,
+            Err(e) => return Err(format!("No interface for {}: {}", connection.interface_name, e)),
+        },
+    };
+    let mut options = PacketCaptureOptions::from_interface(&iface);
+    options.connection = Some(connection.clone());
+    options.ip_protocols.insert(match connection.protocol {
+        TransportProtocol::TCP => IpNextLevelProtocol::Tcp,
+        TransportProtocol::UDP => IpNextLevelProtocol::Udp,
+    });
+    options.tunnel_attribution = AppConfig::load().network.tunnel_attribution;
+    let (frame_tx, frame_rx): (Sender<PacketFrame>, Receiver<PacketFrame>) = channel();
+    let capture_stop = Arc::clone(stop);
+    let capture_handle = thread::Builder::new()
+        .name(format!("follow-stream-{}", id))
+        .spawn(move || crate::net::pcap::start_capture(options, frame_tx, &capture_stop, iface))
+        .map_err(|e| e.to_string())?;
+    thread_log!(info, "follow stream {} started: {:?}", id, connection);
+    let mut last_progress = Instant::now();
+    loop {
+        match frame_rx.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(frame) => match stream.lock() {
+                Ok(mut stream) => stream.update(&frame),
+                Err(e) => {
+                    thread_log!(error, "follow stream lock error: {:?}", e);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            send_progress(id, stream, &progress_tx);
+            last_progress = Instant::now();
+        }
+    }
+    match capture_handle.join() {
+        Ok(report) => {
+            thread_log!(info, "follow stream {} finished: {} packets", id, report.packets);
+        }
+        Err(e) => {
+            thread_log!(error, "Error: {:?}", e);
+        }
+    }
+    match stream.lock() {
+        Ok(mut stream) => stream.finish(),
+        Err(e) => {
+            thread_log!(error, "follow stream lock error: {:?}", e);
+        }
+    }
+    send_progress(id, stream, &progress_tx);
+    Ok(())
+}
+
+fn send_progress(id: u32, stream: &Arc<Mutex<FollowStream>>, progress_tx: &Sender<StreamProgress>) {
+    let summary = match stream.lock() {
+        Ok(stream) => stream.summary(),
+        Err(_) => return,
+    };
+    match progress_tx.send(StreamProgress { id: id, summary: summary }) {
+        Ok(_) => {}
+        Err(_) => {}
+    }
+}
+
+struct FollowStreamSession {
+    stream: Arc<Mutex<FollowStream>>,
+    stop: Arc<Mutex<bool>>,
+}
+
+/// Follow stream sessions by ID
+pub struct FollowStreamStore {
+    next_id: u32,
+    sessions: HashMap<u32, FollowStreamSession>,
+}
+
+impl FollowStreamStore {
+    pub fn new() -> FollowStreamStore {
+        FollowStreamStore {
+            next_id: 1,
+            sessions: HashMap::new(),
+        }
+    }
+    /// Register a new stream. Returns its ID, the stream and its stop flag
+    pub fn add(&mut self, stream: FollowStream) -> (u32, Arc<Mutex<FollowStream>>, Arc<Mutex<bool>>) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let session = FollowStreamSession {
+            stream: Arc::new(Mutex::new(stream)),
+            stop: Arc::new(Mutex::new(false)),
+        };
+        let handles = (id, Arc::clone(&session.stream), Arc::clone(&session.stop));
+        self.sessions.insert(id, session);
+        handles
+    }
+    pub fn get(&self, id: u32) -> Result<Arc<Mutex<FollowStream>>, String> {
+        match self.sessions.get(&id) {
+            Some(session) => Ok(Arc::clone(&session.stream)),
+            None => Err(format!("Unknown stream id {}", id)),
+        }
+    }
+    /// Stop capturing. The stream data is kept until removed
+    pub fn stop(&self, id: u32) -> Result<(), String> {
+        match self.sessions.get(&id) {
+            Some(session) => match session.stop.lock() {
+                Ok(mut stop) => {
+                    *stop = true;
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            None => Err(format!("Unknown stream id {}", id)),
+        }
+    }
+    /// Stop capturing and release the stream data
+    pub fn remove(&mut self, id: u32) -> Result<(), String> {
+        self.stop(id)?;
+        self.sessions.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::decap::{self, TunnelAttribution};
+    use nex::packet::frame::ParseOption;
+    use std::net::Ipv4Addr;
+
+    const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const REMOTE_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+    const LOCAL_PORT: u16 = 40000;
+    const REMOTE_PORT: u16 = 80;
+
+    fn connection() -> SocketConnection {
+        SocketConnection {
+            interface_name: String::from("eth0"),
+            local_ip_addr: IpAddr::V4(LOCAL_IP),
+            local_port: LOCAL_PORT,
+            remote_ip_addr: IpAddr::V4(REMOTE_IP),
+            remote_port: REMOTE_PORT,
+            protocol: TransportProtocol::TCP,
+        }
+    }
+
+    fn segment_ports(egress: bool, local_port: u16, seq: u32, flags: u8, payload: &[u8]) -> PacketFrame {
+        let (src, dst, src_port, dst_port) = if egress {
+            (LOCAL_IP, REMOTE_IP, local_port, REMOTE_PORT)
+        } else {
+            (REMOTE_IP, LOCAL_IP, REMOTE_PORT, local_port)
+        };
+        let mut data: Vec<u8> = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00, 0x45, 0x00];
+        data.extend_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        data.extend_from_slice(&src.octets());
+        data.extend_from_slice(&dst.octets());
+        data.extend_from_slice(&src_port.to_be_bytes());
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        data.extend_from_slice(payload);
+        let decoded = decap::decode_frame(&data, ParseOption::default(), TunnelAttribution::Outer);
+        PacketFrame::from_decoded_frame(0, 1, String::from("eth0"), decoded)
+    }
+
+    fn segment(egress: bool, seq: u32, payload: &[u8]) -> PacketFrame {
+        segment_ports(egress, LOCAL_PORT, seq, TcpFlags::ACK, payload)
+    }
+
+    fn stream_data(stream: &FollowStream, direction: Direction) -> Vec<u8> {
+        stream
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.direction == direction)
+            .flat_map(|chunk| chunk.data.clone())
+            .collect()
+    }
+
+    #[test]
+    fn out_of_order_segments_are_reordered() {
+        let mut stream = FollowStream::new(connection(), DEFAULT_STREAM_MAX_BYTES);
+        stream.update(&segment_ports(true, LOCAL_PORT, 100, TcpFlags::SYN, b""));
+        stream.update(&segment(true, 106, b"world"));
+        assert!(stream.chunks().is_empty());
+        stream.update(&segment(true, 101, b"hello"));
+        assert_eq!(stream_data(&stream, Direction::Egress), b"helloworld");
+        let summary = stream.summary();
+        assert_eq!(summary.out_of_order, 1);
+        assert_eq!(summary.bytes_sent, 10);
+        assert_eq!(summary.packets, 3);
+    }
+
+    #[test]
+    fn retransmitted_bytes_are_stored_once() {
+        let mut stream = FollowStream::new(connection(), DEFAULT_STREAM_MAX_BYTES);
+        stream.update(&segment(false, 1000, b"hello"));
+        stream.update(&segment(false, 1000, b"hello"));
+        // Overlaps the end of the previous segment
+        stream.update(&segment(false, 1003, b"lo!!"));
+        assert_eq!(stream_data(&stream, Direction::Ingress), b"hello!!");
+        let summary = stream.summary();
+        assert_eq!(summary.retransmissions, 1);
+        assert_eq!(summary.bytes_received, 7);
+    }
+
+    #[test]
+    fn directions_are_kept_apart() {
+        let mut stream = FollowStream::new(connection(), DEFAULT_STREAM_MAX_BYTES);
+        stream.update(&segment(true, 1, b"GET / HTTP/1.1\r\n\r\n"));
+        stream.update(&segment(false, 500, b"HTTP/1.1 200 OK\r\n"));
+        stream.update(&segment(true, 19, b"GET /2"));
+        assert_eq!(stream.chunks().len(), 3);
+        assert_eq!(stream_data(&stream, Direction::Egress), b"GET / HTTP/1.1\r\n\r\nGET /2");
+        // Another connection between the same hosts
+        stream.update(&segment_ports(true, LOCAL_PORT + 1, 1, TcpFlags::ACK, b"other"));
+        assert_eq!(stream.summary().packets, 3);
+    }
+
+    #[test]
+    fn finish_skips_missing_bytes() {
+        let mut stream = FollowStream::new(connection(), DEFAULT_STREAM_MAX_BYTES);
+        stream.update(&segment(true, 1, b"abc"));
+        stream.update(&segment(true, 14, b"xyz"));
+        stream.finish();
+        assert_eq!(stream_data(&stream, Direction::Egress), b"abcxyz");
+        let summary = stream.summary();
+        assert_eq!(summary.missing_bytes, 10);
+        assert!(summary.finished);
+    }
+
+    #[test]
+    fn stored_bytes_are_capped() {
+        let mut stream = FollowStream::new(connection(), 8);
+        stream.update(&segment(true, 1, b"0123456789"));
+        stream.update(&segment(false, 1, b"abc"));
+        assert_eq!(stream_data(&stream, Direction::Egress), b"01234567");
+        assert!(stream_data(&stream, Direction::Ingress).is_empty());
+        let summary = stream.summary();
+        assert!(summary.truncated);
+        assert_eq!(summary.stored_bytes, 8);
+        assert_eq!(summary.bytes_sent, 10);
+        assert_eq!(summary.bytes_received, 3);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::config::AppConfig;
+use crate::db::threat::{ThreatFeedConfig, ThreatIntelConfig, ThreatMatch};
+use crate::net::names::NameSource;
+use crate::net::stat::NetStatStrage;
+use crate::sys;
+use crate::thread_log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Hits kept in the log. The oldest hits are dropped first
+pub const THREAT_HIT_LIMIT: usize = 1000;
+/// Hits shown in the overview
+pub const OVERVIEW_THREAT_HITS: usize = 10;
+const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Limit of a whole feed download, including a slow or stalled transfer
+const FEED_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+/// Longest wait before a failing feed is retried
+const FEED_MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+const DOWNLOAD_SUFFIX: &str = ".download";
+/// Largest feed accepted. Larger downloads are abandoned
+const FEED_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// What matched a feed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThreatSource {
+    /// Address of a remote host
+    RemoteHost,
+    Dns,
+    Sni,
+    ReverseDns,
+}
+
+impl ThreatSource {
+    pub fn name(&self) -> String {
+        match self {
+            ThreatSource::RemoteHost => "Remote Host".to_string(),
+            ThreatSource::Dns => "DNS".to_string(),
+            ThreatSource::Sni => "SNI".to_string(),
+            ThreatSource::ReverseDns => "Reverse DNS".to_string(),
+        }
+    }
+}
+
+impl From<NameSource> for ThreatSource {
+    fn from(source: NameSource) -> ThreatSource {
+        match source {
+            NameSource::Dns => ThreatSource::Dns,
+            NameSource::Sni => ThreatSource::Sni,
+            NameSource::ReverseDns => ThreatSource::ReverseDns,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreatHit {
+    /// Network or domain listed in the feeds
+    pub indicator: String,
+    /// Address or name seen in traffic
+    pub observed: String,
+    pub source: ThreatSource,
+    pub feeds: Vec<String>,
+    pub local_ip_addr: Option<IpAddr>,
+    pub remote_ip_addr: IpAddr,
+    /// RFC3339 format
+    pub first_seen: String,
+    pub last_seen: String,
+    pub count: u64,
+}
+
+impl ThreatHit {
+    pub fn new(
+        threat_match: ThreatMatch,
+        observed: String,
+        source: ThreatSource,
+        local_ip_addr: Option<IpAddr>,
+        remote_ip_addr: IpAddr,
+    ) -> ThreatHit {
+        let now = sys::get_sysdate();
+        ThreatHit {
+            indicator: threat_match.indicator,
+            observed: observed,
+            source: source,
+            feeds: threat_match.feeds,
+            local_ip_addr: local_ip_addr,
+            remote_ip_addr: remote_ip_addr,
+            first_seen: now.clone(),
+            last_seen: now,
+            count: 1,
+        }
+    }
+}
+
+/// Hits in the order they were last seen
+#[derive(Debug, Clone)]
+pub struct ThreatHitLog {
+    hits: Vec<ThreatHit>,
+}
+
+impl ThreatHitLog {
+    pub fn new() -> ThreatHitLog {
+        ThreatHitLog { hits: Vec::new() }
+    }
+    /// Add a hit or count it again if the same name was seen for the same remote address.
+    /// Returns true for a new hit
+    pub fn record(&mut self, hit: ThreatHit) -> bool {
+        let position = self.hits.iter().position(|h| {
+            h.observed == hit.observed && h.source == hit.source && h.remote_ip_addr == hit.remote_ip_addr
+        });
+        match position {
+            Some(index) => {
+                let mut existing = self.hits.remove(index);
+                existing.last_seen = hit.last_seen;
+                existing.feeds = hit.feeds;
+                existing.indicator = hit.indicator;
+                existing.count += hit.count;
+                self.hits.push(existing);
+                false
+            }
+            None => {
+                self.hits.push(hit);
+                if self.hits.len() > THREAT_HIT_LIMIT {
+                    self.hits.remove(0);
+                }
+                true
+            }
+        }
+    }
+    /// Most recent first
+    pub fn recent(&self, limit: Option<usize>) -> Vec<ThreatHit> {
+        self.hits
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.hits.clear();
+    }
+}
+
+/// Whether the downloaded copy of a remote feed is older than the refresh interval
+fn feed_due(feed: &ThreatFeedConfig, path: &PathBuf, refresh_interval: Duration) -> bool {
+    if !feed.enabled || !feed.is_remote() {
+        return false;
+    }
+    match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => match SystemTime::now().duration_since(modified) {
+            Ok(age) => age >= refresh_interval,
+            Err(_) => false,
+        },
+        Err(_) => true,
+    }
+}
+
+/// Consecutive download failures per feed source.
+/// A failing feed waits FEED_CHECK_INTERVAL, doubled on each failure up to FEED_MAX_BACKOFF
+pub struct FeedBackoff {
+    failures: HashMap<String, (u32, Instant)>,
+}
+
+impl FeedBackoff {
+    pub fn new() -> FeedBackoff {
+        FeedBackoff {
+            failures: HashMap::new(),
+        }
+    }
+    /// Whether the source may be downloaded at now
+    pub fn ready(&self, source: &str, now: Instant) -> bool {
+        match self.failures.get(source) {
+            Some((_, retry_at)) => now >= *retry_at,
+            None => true,
+        }
+    }
+    /// Record a failed download. Returns the delay before the next attempt
+    pub fn fail(&mut self, source: &str, now: Instant) -> Duration {
+        let failures = match self.failures.get(source) {
+            Some((failures, _)) => failures.saturating_add(1),
+            None => 1,
+        };
+        let delay = FEED_CHECK_INTERVAL
+            .checked_mul(1u32 << (failures - 1).min(16))
+            .unwrap_or(FEED_MAX_BACKOFF)
+            .min(FEED_MAX_BACKOFF);
+        self.failures.insert(source.to_string(), (failures, now + delay));
+        delay
+    }
+    pub fn succeed(&mut self, source: &str) {
+        self.failures.remove(source);
+    }
+}
+
+impl Default for FeedBackoff {
+    fn default() -> Self {
+        FeedBackoff::new()
+    }
+}
+
+async fn download_feed(client: &reqwest::Client, url: &str, path: &PathBuf) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(format!("Failed to create {}: {}", dir.display(), e));
+        }
+    }
+    let mut response = match client.get(url).send().await {
+        Ok(response) => response,
+        Err(e) => return Err(format!("Failed to fetch {}: {}", url, e)),
+    };
+    if !response.status().is_success() {
+        return Err(format!("Failed to fetch {}: {}", url, response.status()));
+    }
+    if response.content_length().unwrap_or(0) > FEED_MAX_BYTES as u64 {
+        return Err(format!("Feed {} is larger than {} bytes", url, FEED_MAX_BYTES));
+    }
+    // The length header may be missing or wrong
+    let mut content: Vec<u8> = Vec::new();
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                if content.len() + chunk.len() > FEED_MAX_BYTES {
+                    return Err(format!("Feed {} is larger than {} bytes", url, FEED_MAX_BYTES));
+                }
+                content.extend_from_slice(&chunk);
+            }
+            Ok(None) => break,
+            Err(e) => return Err(format!("Failed to fetch {}: {}", url, e)),
+        }
+    }
+    // Keep the previous copy until the download is complete
+    let temp_path = path.with_extension(format!("feed{}", DOWNLOAD_SUFFIX));
+    if let Err(e) = fs::write(&temp_path, &content) {
+        return Err(format!("Failed to write {}: {}", temp_path.display(), e));
+    }
+    match fs::rename(&temp_path, path) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Download remote feeds that are due, or all remote feeds if force is set.
+/// Returns the number of feeds downloaded and the errors
+pub fn refresh_feeds(config: &ThreatIntelConfig, force: bool) -> (usize, Vec<String>) {
+    refresh_feeds_with_backoff(config, force, &mut FeedBackoff::new())
+}
+
+/// refresh_feeds that skips feeds still backing off from a failure unless force is set
+fn refresh_feeds_with_backoff(
+    config: &ThreatIntelConfig,
+    force: bool,
+    backoff: &mut FeedBackoff,
+) -> (usize, Vec<String>) {
+    let refresh_interval = Duration::from_secs(config.refresh_interval);
+    let now = Instant::now();
+    let due: Vec<(&ThreatFeedConfig, PathBuf)> = config
+        .feeds
+        .iter()
+        .filter(|feed| feed.enabled && feed.is_remote())
+        .filter_map(|feed| feed.file_path().map(|path| (feed, path)))
+        .filter(|(feed, path)| {
+            force || (feed_due(feed, path, refresh_interval) && backoff.ready(feed.source.trim(), now))
+        })
+        .collect();
+    if due.is_empty() {
+        return (0, Vec::new());
+    }
+    let rt = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => return (0, vec![e.to_string()]),
+    };
+    let client = match reqwest::Client::builder().timeout(FEED_DOWNLOAD_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => return (0, vec![e.to_string()]),
+    };
+    let mut downloaded: usize = 0;
+    let mut errors: Vec<String> = Vec::new();
+    for (feed, path) in due {
+        let source = feed.source.trim();
+        match rt.block_on(download_feed(&client, source, &path)) {
+            Ok(_) => {
+                backoff.succeed(source);
+                downloaded += 1;
+            }
+            Err(e) => {
+                let delay = backoff.fail(source, Instant::now());
+                errors.push(format!("{}: {} (retry in {}s)", feed.name, e, delay.as_secs()));
+            }
+        }
+    }
+    (downloaded, errors)
+}
+
+/// Download remote feeds on schedule and reload the threat database when they change
+pub fn start_threat_feed_update(netstat_strage: &Arc<NetStatStrage>) {
+    let mut config: Option<ThreatIntelConfig> = None;
+    let mut backoff = FeedBackoff::new();
+    loop {
+        // Feeds may have been edited in the settings
+        let new_config = AppConfig::load().threat_intel;
+        let config_changed = match &config {
+            Some(config) => config.feeds != new_config.feeds,
+            None => true,
+        };
+        let (downloaded, errors) = refresh_feeds_with_backoff(&new_config, false, &mut backoff);
+        for e in &errors {
+            thread_log!(error, "[threat_feed_update] {}", e);
+        }
+        if downloaded > 0 || config_changed {
+            netstat_strage.load_threatdb(&new_config);
+        }
+        config = Some(new_config);
+        std::thread::sleep(FEED_CHECK_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn backoff_doubles_and_resets() {
+        let mut backoff = FeedBackoff::new();
+        let url = "https://example.com/feed.txt";
+        let now = Instant::now();
+        assert!(backoff.ready(url, now));
+        assert_eq!(backoff.fail(url, now), FEED_CHECK_INTERVAL);
+        assert!(!backoff.ready(url, now));
+        assert!(backoff.ready(url, now + FEED_CHECK_INTERVAL));
+        assert_eq!(backoff.fail(url, now), FEED_CHECK_INTERVAL * 2);
+        assert_eq!(backoff.fail(url, now), FEED_CHECK_INTERVAL * 4);
+        // Other feeds are not affected
+        assert!(backoff.ready("https://example.org/feed.txt", now));
+        backoff.succeed(url);
+        assert!(backoff.ready(url, now));
+        assert_eq!(backoff.fail(url, now), FEED_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut backoff = FeedBackoff::new();
+        let now = Instant::now();
+        let mut delay = Duration::ZERO;
+        for _ in 0..100 {
+            delay = backoff.fail("https://example.com/feed.txt", now);
+        }
+        assert_eq!(delay, FEED_MAX_BACKOFF);
+    }
+
+    #[test]
+    fn stalled_download_times_out() {
+        // Accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.txt", listener.local_addr().unwrap());
+        let path = std::env::temp_dir().join(format!("netpulsar-feed-timeout-{}.txt", std::process::id()));
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let start = Instant::now();
+        let result = rt.block_on(download_feed(&client, &url, &path));
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!path.exists());
+        drop(listener);
+    }
+
+    #[test]
+    fn oversized_download_is_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.txt", listener.local_addr().unwrap());
+        let path = std::env::temp_dir().join(format!("netpulsar-feed-oversized-{}.txt", std::process::id()));
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", FEED_MAX_BYTES + 1);
+            let _ = stream.write_all(header.as_bytes());
+        });
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(download_feed(&client, &url, &path));
+        server.join().unwrap();
+        assert!(result.unwrap_err().contains("larger than"));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,403 @@
+//! Linux AF_PACKET capture using a memory-mapped TPACKET_V3 ring.
+//! Kernel structures are defined here to avoid depending on a specific libc version.
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+use std::time::Duration;
+
+const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_STATISTICS: libc::c_int = 6;
+const PACKET_VERSION: libc::c_int = 10;
+const PACKET_FANOUT: libc::c_int = 18;
+const PACKET_MR_PROMISC: libc::c_ushort = 1;
+const TPACKET_V3: libc::c_int = 2;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+const ETH_P_8021Q: u16 = 0x8100;
+/// Destination and source MAC addresses before the VLAN tag
+const ETH_ADDRS_LEN: usize = 12;
+/// Distribute packets by flow hash so that a flow stays on one worker
+const PACKET_FANOUT_HASH: u32 = 0;
+const PACKET_FANOUT_FLAG_DEFRAG: u32 = 0x8000;
+/// Group ids tried before giving up on joining a fanout group
+const FANOUT_GROUP_ATTEMPTS: usize = 16;
+/// BPF_RET | BPF_K
+const BPF_RET_K: u16 = 0x06;
+
+/// Fanout group ids handed out by this process
+static NEXT_FANOUT_GROUP: AtomicU32 = AtomicU32::new(0);
+
+// Offsets in struct tpacket_block_desc (hdr is tpacket_hdr_v1)
+const BLOCK_STATUS_OFFSET: usize = 8;
+const BLOCK_NUM_PKTS_OFFSET: usize = 12;
+const BLOCK_FIRST_PKT_OFFSET: usize = 16;
+// Offsets in struct tpacket3_hdr
+const PKT_NEXT_OFFSET: usize = 0;
+const PKT_SNAPLEN_OFFSET: usize = 12;
+const PKT_LEN_OFFSET: usize = 16;
+const PKT_STATUS_OFFSET: usize = 20;
+const PKT_MAC_OFFSET: usize = 24;
+const PKT_VLAN_TCI_OFFSET: usize = 32;
+const PKT_VLAN_TPID_OFFSET: usize = 36;
+const PKT_HDR_LEN: usize = 48;
+
+#[repr(C)]
+struct TpacketReq3 {
+    tp_block_size: u32,
+    tp_block_nr: u32,
+    tp_frame_size: u32,
+    tp_frame_nr: u32,
+    tp_retire_blk_tov: u32,
+    tp_sizeof_priv: u32,
+    tp_feature_req_word: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStatsV3 {
+    tp_packets: u32,
+    tp_drops: u32,
+    tp_freeze_q_cnt: u32,
+}
+
+#[repr(C)]
+struct PacketMreq {
+    mr_ifindex: libc::c_int,
+    mr_type: libc::c_ushort,
+    mr_alen: libc::c_ushort,
+    mr_address: [libc::c_uchar; 8],
+}
+
+/// TPACKET_V3 ring configuration
+#[derive(Debug, Clone)]
+pub struct RingConfig {
+    /// Size of each block. Must be a multiple of the page size
+    pub block_size: u32,
+    /// Number of blocks in the ring
+    pub block_count: u32,
+    /// Frame size hint used by the kernel to compute tp_frame_nr
+    pub frame_size: u32,
+    /// Time in milliseconds after which a partially filled block is handed to user space
+    pub retire_timeout_ms: u32,
+    /// Maximum bytes kept per frame. 0 keeps the whole frame
+    pub snaplen: usize,
+    /// Capture in promiscuous mode
+    pub promiscuous: bool,
+    /// PACKET_FANOUT group id. Sockets in the same group share the traffic
+    pub fanout_group: Option<u16>,
+}
+
+impl RingConfig {
+    pub fn new() -> RingConfig {
+        RingConfig {
+            block_size: 1 << 20,
+            block_count: 8,
+            frame_size: 2048,
+            retire_timeout_ms: 60,
+            snaplen: 0,
+            promiscuous: false,
+            fanout_group: None,
+        }
+    }
+}
+
+/// Kernel socket statistics (PACKET_STATISTICS)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RingStats {
+    /// Packets received by the socket, including dropped ones
+    pub packets: u64,
+    /// Packets dropped because the ring was full
+    pub drops: u64,
+    /// Number of times the ring was frozen
+    pub freeze_q_cnt: u64,
+}
+
+/// Memory-mapped TPACKET_V3 receive ring bound to one interface
+pub struct TpacketRing {
+    fd: libc::c_int,
+    ring: *mut u8,
+    ring_len: usize,
+    block_size: usize,
+    block_count: usize,
+    current_block: usize,
+    /// Frame with its VLAN tag put back
+    vlan_frame: Vec<u8>,
+}
+
+// The mapped ring is only accessed by the owning thread
+unsafe impl Send for TpacketRing {}
+
+impl TpacketRing {
+    pub fn open(if_index: u32, config: &RingConfig) -> io::Result<TpacketRing> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol as libc::c_int) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // From here the fd is closed by Drop on error
+        let mut ring = TpacketRing {
+            fd: fd,
+            ring: ptr::null_mut(),
+            ring_len: 0,
+            block_size: config.block_size as usize,
+            block_count: config.block_count as usize,
+            current_block: 0,
+            vlan_frame: Vec::new(),
+        };
+        setsockopt(fd, libc::SOL_PACKET, PACKET_VERSION, &TPACKET_V3)?;
+        if config.snaplen > 0 {
+            ring.set_snaplen(config.snaplen)?;
+        }
+        let req = TpacketReq3 {
+            tp_block_size: config.block_size,
+            tp_block_nr: config.block_count,
+            tp_frame_size: config.frame_size,
+            tp_frame_nr: (config.block_size / config.frame_size) * config.block_count,
+            tp_retire_blk_tov: config.retire_timeout_ms,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        setsockopt(fd, libc::SOL_PACKET, PACKET_RX_RING, &req)?;
+        let ring_len = ring.block_size * ring.block_count;
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                ring_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        ring.ring = addr as *mut u8;
+        ring.ring_len = ring_len;
+        let mut sll: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        sll.sll_family = libc::AF_PACKET as libc::c_ushort;
+        sll.sll_protocol = protocol;
+        sll.sll_ifindex = if_index as libc::c_int;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &sll as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if config.promiscuous {
+            let mreq = PacketMreq {
+                mr_ifindex: if_index as libc::c_int,
+                mr_type: PACKET_MR_PROMISC,
+                mr_alen: 0,
+                mr_address: [0; 8],
+            };
+            setsockopt(fd, libc::SOL_PACKET, PACKET_ADD_MEMBERSHIP, &mreq)?;
+        }
+        // Fanout must be set after bind
+        if let Some(group) = config.fanout_group {
+            let arg: u32 = (group as u32) | ((PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_DEFRAG) << 16);
+            match setsockopt(fd, libc::SOL_PACKET, PACKET_FANOUT, &arg) {
+                Ok(_) => {}
+                // The id is held by a group with other settings
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EBUSY) | Some(libc::EINVAL)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("fanout group {} is in use: {}", group, e),
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ring)
+    }
+    /// Open count rings joined to a new fanout group.
+    /// Group ids are shared by all processes, so ids in use elsewhere are skipped
+    pub fn open_fanout(if_index: u32, config: &RingConfig, count: usize) -> io::Result<Vec<TpacketRing>> {
+        let mut config = config.clone();
+        let mut last_error = io::Error::new(io::ErrorKind::AddrInUse, "no fanout group id tried");
+        'group: for _ in 0..FANOUT_GROUP_ATTEMPTS {
+            config.fanout_group = Some(next_fanout_group());
+            let mut rings: Vec<TpacketRing> = Vec::new();
+            for _ in 0..count {
+                match TpacketRing::open(if_index, &config) {
+                    Ok(ring) => rings.push(ring),
+                    Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                        last_error = e;
+                        continue 'group;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(rings);
+        }
+        Err(last_error)
+    }
+    /// Truncate frames in the kernel with a classic BPF program returning snaplen
+    fn set_snaplen(&self, snaplen: usize) -> io::Result<()> {
+        let mut filter = [libc::sock_filter {
+            code: BPF_RET_K,
+            jt: 0,
+            jf: 0,
+            k: snaplen.min(u32::MAX as usize) as u32,
+        }];
+        let prog = libc::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_mut_ptr(),
+        };
+        setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &prog)
+    }
+    /// Wait up to timeout for the next block and call f with each frame and its original length.
+    /// Returns the number of frames in the block.
+    pub fn read_block<F: FnMut(&[u8], usize)>(&mut self, timeout: Duration, mut f: F) -> usize {
+        let block = unsafe { self.ring.add(self.current_block * self.block_size) };
+        let status_ptr = unsafe { block.add(BLOCK_STATUS_OFFSET) as *mut u32 };
+        if unsafe { ptr::read_volatile(status_ptr) } & TP_STATUS_USER == 0 {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            unsafe {
+                libc::poll(&mut pfd, 1, timeout_ms);
+            }
+            if unsafe { ptr::read_volatile(status_ptr) } & TP_STATUS_USER == 0 {
+                return 0;
+            }
+        }
+        fence(Ordering::Acquire);
+        let num_pkts = unsafe { read_u32(block, BLOCK_NUM_PKTS_OFFSET) } as usize;
+        let mut offset = unsafe { read_u32(block, BLOCK_FIRST_PKT_OFFSET) } as usize;
+        for _ in 0..num_pkts {
+            if offset + PKT_HDR_LEN > self.block_size {
+                break;
+            }
+            let next_offset = unsafe { read_u32(block, offset + PKT_NEXT_OFFSET) } as usize;
+            let snaplen = unsafe { read_u32(block, offset + PKT_SNAPLEN_OFFSET) } as usize;
+            let len = unsafe { read_u32(block, offset + PKT_LEN_OFFSET) } as usize;
+            let mac = unsafe { ptr::read_unaligned(block.add(offset + PKT_MAC_OFFSET) as *const u16) } as usize;
+            if offset + mac + snaplen > self.block_size {
+                break;
+            }
+            let data = unsafe { std::slice::from_raw_parts(block.add(offset + mac), snaplen) };
+            // The kernel strips the outer VLAN tag into the header
+            let status = unsafe { read_u32(block, offset + PKT_STATUS_OFFSET) };
+            if status & TP_STATUS_VLAN_VALID != 0 && data.len() >= ETH_ADDRS_LEN {
+                let tci = unsafe { read_u32(block, offset + PKT_VLAN_TCI_OFFSET) } as u16;
+                let tpid = if status & TP_STATUS_VLAN_TPID_VALID != 0 {
+                    unsafe { ptr::read_unaligned(block.add(offset + PKT_VLAN_TPID_OFFSET) as *const u16) }
+                } else {
+                    ETH_P_8021Q
+                };
+                insert_vlan_tag(&mut self.vlan_frame, data, tpid, tci);
+                f(&self.vlan_frame, len + 4);
+            } else {
+                f(data, len);
+            }
+            if next_offset == 0 {
+                break;
+            }
+            offset += next_offset;
+        }
+        // Hand the block back to the kernel
+        fence(Ordering::Release);
+        unsafe {
+            ptr::write_volatile(status_ptr, TP_STATUS_KERNEL);
+        }
+        self.current_block = (self.current_block + 1) % self.block_count;
+        num_pkts
+    }
+    /// Read PACKET_STATISTICS. The kernel resets the counters on each read,
+    /// so the returned values are the delta since the previous call.
+    pub fn statistics(&self) -> io::Result<RingStats> {
+        let mut stats = TpacketStatsV3::default();
+        let mut len = mem::size_of::<TpacketStatsV3>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                self.fd,
+                libc::SOL_PACKET,
+                PACKET_STATISTICS,
+                &mut stats as *mut TpacketStatsV3 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RingStats {
+            packets: stats.tp_packets as u64,
+            drops: stats.tp_drops as u64,
+            freeze_q_cnt: stats.tp_freeze_q_cnt as u64,
+        })
+    }
+}
+
+impl Drop for TpacketRing {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                libc::munmap(self.ring as *mut libc::c_void, self.ring_len);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Next fanout group id. Starts from the process id so that processes start apart
+fn next_fanout_group() -> u16 {
+    let n = NEXT_FANOUT_GROUP.fetch_add(1, Ordering::Relaxed);
+    (std::process::id() as u16).wrapping_add(n as u16)
+}
+
+/// Copy an Ethernet frame into buf with an 802.1Q tag after the MAC addresses
+fn insert_vlan_tag(buf: &mut Vec<u8>, data: &[u8], tpid: u16, tci: u16) {
+    buf.clear();
+    buf.extend_from_slice(&data[..ETH_ADDRS_LEN]);
+    buf.extend_from_slice(&tpid.to_be_bytes());
+    buf.extend_from_slice(&tci.to_be_bytes());
+    buf.extend_from_slice(&data[ETH_ADDRS_LEN..]);
+}
+
+unsafe fn read_u32(base: *const u8, offset: usize) -> u32 {
+    ptr::read_volatile(base.add(offset) as *const u32)
+}
+
+fn setsockopt<T>(fd: libc::c_int, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vlan_tag_is_put_back_after_mac_addresses() {
+        let data: Vec<u8> = vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 0x08, 0x00, 0x45];
+        let mut buf: Vec<u8> = Vec::new();
+        insert_vlan_tag(&mut buf, &data, ETH_P_8021Q, 0x2064);
+        assert_eq!(&buf[..12], &data[..12]);
+        assert_eq!(&buf[12..16], &[0x81, 0x00, 0x20, 0x64]);
+        assert_eq!(&buf[16..], &data[12..]);
+    }
+}
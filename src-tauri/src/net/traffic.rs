@@ -1,4 +1,4 @@
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
@@ -8,18 +8,99 @@ pub enum Direction {
     Ingress,
 }
 
+/// Time constants of the smoothed rates in seconds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateWindows {
+    pub short_secs: f64,
+    pub medium_secs: f64,
+    pub long_secs: f64,
+}
+
+impl RateWindows {
+    pub fn new() -> RateWindows {
+        RateWindows {
+            short_secs: 1.0,
+            medium_secs: 10.0,
+            long_secs: 60.0,
+        }
+    }
+}
+
+impl Default for RateWindows {
+    fn default() -> Self {
+        RateWindows::new()
+    }
+}
+
+/// Smoothed, peak and average per-second rate of a counter
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct RateStats {
+    /// EWMA over RateWindows.short_secs
+    pub short: f64,
+    /// EWMA over RateWindows.medium_secs
+    pub medium: f64,
+    /// EWMA over RateWindows.long_secs
+    pub long: f64,
+    /// Highest rate of a single interval
+    pub peak: f64,
+    /// Total divided by the time the entry has been tracked
+    pub average: f64,
+}
+
+impl RateStats {
+    pub fn new() -> RateStats {
+        RateStats::default()
+    }
+    /// Add the amount counted over elapsed. total and active_secs include this interval
+    pub fn update(&mut self, amount: u64, elapsed: Duration, total: u64, active_secs: f64, windows: &RateWindows) {
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+        let rate = amount as f64 / secs;
+        self.short = ewma(self.short, rate, secs, windows.short_secs);
+        self.medium = ewma(self.medium, rate, secs, windows.medium_secs);
+        self.long = ewma(self.long, rate, secs, windows.long_secs);
+        if rate > self.peak {
+            self.peak = rate;
+        }
+        if active_secs > 0.0 {
+            self.average = total as f64 / active_secs;
+        }
+    }
+}
+
+/// Exponentially weighted moving average for irregular intervals
+fn ewma(current: f64, rate: f64, secs: f64, window_secs: f64) -> f64 {
+    if window_secs <= 0.0 {
+        return rate;
+    }
+    let alpha = 1.0 - (-secs / window_secs).exp();
+    current + alpha * (rate - current)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TrafficInfo {
-    pub packet_sent: usize,
-    pub packet_received: usize,
-    pub bytes_sent: usize,
-    pub bytes_received: usize,
-    pub egress_packets_per_sec: usize,
-    pub egress_bytes_per_sec: usize,
-    pub ingress_packets_per_sec: usize,
-    pub ingress_bytes_per_sec: usize,
-    pub first_seen: SystemTime,
-    pub last_seen: SystemTime,
+    pub packet_sent: u64,
+    pub packet_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Short window rates
+    pub egress_packets_per_sec: u64,
+    pub egress_bytes_per_sec: u64,
+    pub ingress_packets_per_sec: u64,
+    pub ingress_bytes_per_sec: u64,
+    pub egress_packets_rate: RateStats,
+    pub egress_bytes_rate: RateStats,
+    pub ingress_packets_rate: RateStats,
+    pub ingress_bytes_rate: RateStats,
+    /// Sum of the rate intervals since the entry was created
+    pub active_secs: f64,
+    /// Monotonic. Not affected by wall clock changes
+    #[serde(skip, default = "Instant::now")]
+    pub first_seen: Instant,
+    #[serde(skip, default = "Instant::now")]
+    pub last_seen: Instant,
 }
 
 impl TrafficInfo {
@@ -33,8 +114,13 @@ impl TrafficInfo {
             egress_bytes_per_sec: 0,
             ingress_packets_per_sec: 0,
             ingress_bytes_per_sec: 0,
-            first_seen: SystemTime::now(),
-            last_seen: SystemTime::now(),
+            egress_packets_rate: RateStats::new(),
+            egress_bytes_rate: RateStats::new(),
+            ingress_packets_rate: RateStats::new(),
+            ingress_bytes_rate: RateStats::new(),
+            active_secs: 0.0,
+            first_seen: Instant::now(),
+            last_seen: Instant::now(),
         }
     }
     pub fn add_traffic(&mut self, traffic: &TrafficInfo) {
@@ -42,36 +128,78 @@ impl TrafficInfo {
         self.packet_received += traffic.packet_received;
         self.bytes_sent += traffic.bytes_sent;
         self.bytes_received += traffic.bytes_received;
-        self.last_seen = SystemTime::now();
-    }
-    pub fn update_egress_packets_per_sec(&mut self, sent_packets: usize, duration: Duration) {
-        self.egress_packets_per_sec = (sent_packets as f64 / duration.as_secs_f64()) as usize;
+        self.last_seen = Instant::now();
     }
-    pub fn update_egress_bytes_per_sec(&mut self, sent_bytes: usize, duration: Duration) {
-        self.egress_bytes_per_sec = (sent_bytes as f64 / duration.as_secs_f64()) as usize;
+    /// Add the rates of another entry. Smoothed and average rates are additive, peaks are not
+    pub fn add_rates(&mut self, traffic: &TrafficInfo) {
+        self.egress_packets_per_sec += traffic.egress_packets_per_sec;
+        self.egress_bytes_per_sec += traffic.egress_bytes_per_sec;
+        self.ingress_packets_per_sec += traffic.ingress_packets_per_sec;
+        self.ingress_bytes_per_sec += traffic.ingress_bytes_per_sec;
+        add_rate_stats(&mut self.egress_packets_rate, &traffic.egress_packets_rate);
+        add_rate_stats(&mut self.egress_bytes_rate, &traffic.egress_bytes_rate);
+        add_rate_stats(&mut self.ingress_packets_rate, &traffic.ingress_packets_rate);
+        add_rate_stats(&mut self.ingress_bytes_rate, &traffic.ingress_bytes_rate);
     }
-    pub fn update_ingress_packets_per_sec(&mut self, received_packets: usize, duration: Duration) {
-        self.ingress_packets_per_sec = (received_packets as f64 / duration.as_secs_f64()) as usize;
+    /// Take the rates of an entry that tracks the same traffic
+    pub fn set_rates(&mut self, traffic: &TrafficInfo) {
+        self.egress_packets_per_sec = traffic.egress_packets_per_sec;
+        self.egress_bytes_per_sec = traffic.egress_bytes_per_sec;
+        self.ingress_packets_per_sec = traffic.ingress_packets_per_sec;
+        self.ingress_bytes_per_sec = traffic.ingress_bytes_per_sec;
+        self.egress_packets_rate = traffic.egress_packets_rate;
+        self.egress_bytes_rate = traffic.egress_bytes_rate;
+        self.ingress_packets_rate = traffic.ingress_packets_rate;
+        self.ingress_bytes_rate = traffic.ingress_bytes_rate;
     }
-    pub fn update_ingress_bytes_per_sec(&mut self, received_bytes: usize, duration: Duration) {
-        self.ingress_bytes_per_sec = (received_bytes as f64 / duration.as_secs_f64()) as usize;
+    /// Update the rates with the traffic counted over elapsed.
+    /// Call before add_traffic so that the averages include this interval
+    pub fn update_bytes_per_sec(&mut self, traffic: &TrafficInfo, elapsed: Duration, windows: &RateWindows) {
+        self.active_secs += elapsed.as_secs_f64();
+        let active_secs = self.active_secs;
+        self.egress_packets_rate.update(
+            traffic.packet_sent,
+            elapsed,
+            self.packet_sent + traffic.packet_sent,
+            active_secs,
+            windows,
+        );
+        self.ingress_packets_rate.update(
+            traffic.packet_received,
+            elapsed,
+            self.packet_received + traffic.packet_received,
+            active_secs,
+            windows,
+        );
+        self.egress_bytes_rate.update(
+            traffic.bytes_sent,
+            elapsed,
+            self.bytes_sent + traffic.bytes_sent,
+            active_secs,
+            windows,
+        );
+        self.ingress_bytes_rate.update(
+            traffic.bytes_received,
+            elapsed,
+            self.bytes_received + traffic.bytes_received,
+            active_secs,
+            windows,
+        );
+        self.egress_packets_per_sec = self.egress_packets_rate.short.round() as u64;
+        self.ingress_packets_per_sec = self.ingress_packets_rate.short.round() as u64;
+        self.egress_bytes_per_sec = self.egress_bytes_rate.short.round() as u64;
+        self.ingress_bytes_per_sec = self.ingress_bytes_rate.short.round() as u64;
     }
-    pub fn update_bytes_per_sec(&mut self, traffic: &TrafficInfo, duration: Duration) {
-        self.update_egress_packets_per_sec(traffic.packet_sent, duration);
-        self.update_ingress_packets_per_sec(traffic.packet_received, duration);
-        self.update_egress_bytes_per_sec(traffic.bytes_sent, duration);
-        self.update_ingress_bytes_per_sec(traffic.bytes_received, duration);
-    }
-    pub fn total_packet(&self) -> usize {
+    pub fn total_packet(&self) -> u64 {
         self.packet_sent + self.packet_received
     }
-    pub fn total_bytes(&self) -> usize {
+    pub fn total_bytes(&self) -> u64 {
         self.bytes_sent + self.bytes_received
     }
-    pub fn format_bytes(bytes: usize) -> String {
-        const KB: usize = 1024;
-        const MB: usize = KB * 1024;
-        const GB: usize = MB * 1024;
+    pub fn format_bytes(bytes: u64) -> String {
+        const KB: u64 = 1024;
+        const MB: u64 = KB * 1024;
+        const GB: u64 = MB * 1024;
 
         if bytes >= GB {
             format!("{:.2} GB", bytes as f64 / GB as f64)
@@ -83,7 +211,7 @@ impl TrafficInfo {
             format!("{} B", bytes)
         }
     }
-    pub fn format_packets(packets: usize) -> String {
+    pub fn format_packets(packets: u64) -> String {
         if packets >= 1000 {
             format!("{:.2} Kp", packets as f64 / 1000.0)
         } else if packets >= 1000000 {
@@ -127,16 +255,28 @@ impl TrafficInfo {
     }
 }
 
+fn add_rate_stats(dst: &mut RateStats, src: &RateStats) {
+    dst.short += src.short;
+    dst.medium += src.medium;
+    dst.long += src.long;
+    dst.peak = dst.peak.max(src.peak);
+    dst.average += src.average;
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TrafficDisplayInfo {
-    pub packet_sent: usize,
-    pub packet_received: usize,
-    pub bytes_sent: usize,
-    pub bytes_received: usize,
-    pub egress_packets_per_sec: usize,
-    pub egress_bytes_per_sec: usize,
-    pub ingress_packets_per_sec: usize,
-    pub ingress_bytes_per_sec: usize,
+    pub packet_sent: u64,
+    pub packet_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub egress_packets_per_sec: u64,
+    pub egress_bytes_per_sec: u64,
+    pub ingress_packets_per_sec: u64,
+    pub ingress_bytes_per_sec: u64,
+    pub egress_packets_rate: RateStats,
+    pub egress_bytes_rate: RateStats,
+    pub ingress_packets_rate: RateStats,
+    pub ingress_bytes_rate: RateStats,
     pub formatted_sent_bytes: String,
     pub formatted_received_bytes: String,
     pub formatted_total_bytes: String,
@@ -157,6 +297,10 @@ impl TrafficDisplayInfo {
             ingress_packets_per_sec: 0,
             egress_bytes_per_sec: 0,
             ingress_bytes_per_sec: 0,
+            egress_packets_rate: RateStats::new(),
+            egress_bytes_rate: RateStats::new(),
+            ingress_packets_rate: RateStats::new(),
+            ingress_bytes_rate: RateStats::new(),
             formatted_sent_bytes: String::new(),
             formatted_received_bytes: String::new(),
             formatted_total_bytes: String::new(),
@@ -176,6 +320,10 @@ impl TrafficDisplayInfo {
             ingress_packets_per_sec: traffic.ingress_packets_per_sec,
             egress_bytes_per_sec: traffic.egress_bytes_per_sec,
             ingress_bytes_per_sec: traffic.ingress_bytes_per_sec,
+            egress_packets_rate: traffic.egress_packets_rate,
+            egress_bytes_rate: traffic.egress_bytes_rate,
+            ingress_packets_rate: traffic.ingress_packets_rate,
+            ingress_bytes_rate: traffic.ingress_bytes_rate,
             formatted_sent_bytes: traffic.formatted_sent_bytes(),
             formatted_received_bytes: traffic.formatted_received_bytes(),
             formatted_total_bytes: traffic.formatted_total_bytes(),
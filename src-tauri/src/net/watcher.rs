@@ -0,0 +1,417 @@
+use crate::config::{AppConfig, NetworkConfig};
+use crate::net::decap::TunnelAttribution;
+use crate::net::interface;
+use crate::net::ring::CaptureRing;
+use crate::net::sampling::SamplingConfig;
+use crate::net::stat::NetStatStrage;
+use crate::thread_log;
+use nex::net::interface::Interface;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::Manager;
+
+/// Interval for re-checking interfaces when no change notification is available
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Wait time for coalescing a burst of link/address events
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Background capture thread for a single interface
+struct CaptureThread {
+    if_name: String,
+    stop: Arc<Mutex<bool>>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CaptureThread {
+    fn spawn(
+        iface: &Interface,
+        netstat_strage: &Arc<NetStatStrage>,
+        capture_ring: &Arc<CaptureRing>,
+        fanout_workers: usize,
+        tunnel_attribution: TunnelAttribution,
+        sampling: SamplingConfig,
+        snaplen: usize,
+    ) -> Option<CaptureThread> {
+        let stop = Arc::new(Mutex::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let mut netstat_strage_pcap = Arc::clone(netstat_strage);
+        let capture_ring_pcap = Arc::clone(capture_ring);
+        let thread_iface = iface.clone();
+        let mut pcap_option = crate::net::pcap::PacketCaptureOptions::from_interface(iface);
+        pcap_option.backend = crate::net::pcap::CaptureBackend::preferred();
+        pcap_option.snaplen = snaplen;
+        pcap_option.fanout_workers = fanout_workers;
+        pcap_option.tunnel_attribution = tunnel_attribution;
+        pcap_option.sampling = sampling;
+        let thread_name = format!("pcap-thread-{}", iface.name);
+        let spawn_result = thread::Builder::new()
+            .name(thread_name.clone())
+            .spawn(move || {
+                crate::net::pcap::start_background_capture(
+                    pcap_option,
+                    &mut netstat_strage_pcap,
+                    &capture_ring_pcap,
+                    thread_iface,
+                    &thread_stop,
+                );
+            });
+        match spawn_result {
+            Ok(handle) => {
+                thread_log!(info, "start thread {:?}", thread_name);
+                Some(CaptureThread {
+                    if_name: iface.name.clone(),
+                    stop: stop,
+                    handle: handle,
+                })
+            }
+            Err(e) => {
+                thread_log!(error, "Error: {:?}", e);
+                None
+            }
+        }
+    }
+    /// Signal the thread to stop and wait for it. The capture loop checks the flag every read timeout
+    fn stop(self) {
+        match self.stop.lock() {
+            Ok(mut stop) => {
+                *stop = true;
+            }
+            Err(e) => {
+                thread_log!(error, "[interface_watcher] lock error: {}", e);
+            }
+        }
+        match self.handle.join() {
+            Ok(_) => {
+                thread_log!(info, "stop thread pcap-thread-{}", self.if_name);
+            }
+            Err(e) => {
+                thread_log!(error, "pcap-thread-{} panicked: {:?}", self.if_name, e);
+            }
+        }
+    }
+}
+
+/// Number of fanout workers for the default interface
+fn default_fanout_workers() -> usize {
+    match thread::available_parallelism() {
+        Ok(n) => (n.get() / 2).clamp(1, 4),
+        Err(_) => 1,
+    }
+}
+
+/// Keeps local IP map, default interface and capture threads in sync with the system
+pub struct InterfaceWatcher {
+    netstat_strage: Arc<NetStatStrage>,
+    capture_ring: Arc<CaptureRing>,
+    captures: HashMap<u32, CaptureThread>,
+}
+
+impl InterfaceWatcher {
+    pub fn new(netstat_strage: Arc<NetStatStrage>, capture_ring: Arc<CaptureRing>) -> InterfaceWatcher {
+        InterfaceWatcher {
+            netstat_strage: netstat_strage,
+            capture_ring: capture_ring,
+            captures: HashMap::new(),
+        }
+    }
+    /// Re-read the interface list and apply the differences. Returns true if anything changed
+    pub fn sync(&mut self) -> bool {
+        let interfaces = nex::net::interface::get_interfaces();
+        let mut changed = false;
+        if self
+            .netstat_strage
+            .set_local_ip_map(interface::build_local_ip_map(&interfaces))
+        {
+            thread_log!(info, "local ip map updated");
+            changed = true;
+        }
+        self.netstat_strage
+            .set_gateways(interface::build_gateway_map(&interfaces));
+        match netdev::get_default_interface() {
+            Ok(iface) => {
+                if self.netstat_strage.interface_changed(iface.index) {
+                    thread_log!(info, "default interface changed: {}", iface.name);
+                    self.netstat_strage.change_interface(&iface);
+                    changed = true;
+                }
+            }
+            Err(e) => {
+                thread_log!(warn, "get_default_interface error: {:?}", e);
+            }
+        }
+        let usable_interfaces: HashMap<u32, Interface> = interfaces
+            .into_iter()
+            .filter(|iface| interface::is_usable_interface(iface))
+            .map(|iface| (iface.index, iface))
+            .collect();
+        // Stop captures on interfaces that went away or were renamed.
+        // Captures whose thread exited are removed too, and restarted below if the interface is still usable
+        let mut remove_keys: Vec<u32> = vec![];
+        for (if_index, capture) in self.captures.iter() {
+            match usable_interfaces.get(if_index) {
+                Some(iface) => {
+                    if iface.name != capture.if_name {
+                        remove_keys.push(*if_index);
+                    } else if capture.handle.is_finished() {
+                        thread_log!(warn, "pcap-thread-{} exited. restarting", capture.if_name);
+                        remove_keys.push(*if_index);
+                    }
+                }
+                None => {
+                    remove_keys.push(*if_index);
+                }
+            }
+        }
+        for if_index in remove_keys {
+            if let Some(capture) = self.captures.remove(&if_index) {
+                capture.stop();
+                changed = true;
+            }
+        }
+        // Start captures on new interfaces
+        // Loaded only when a capture is started
+        let mut network_config: Option<NetworkConfig> = None;
+        for (if_index, iface) in usable_interfaces.iter() {
+            if self.captures.contains_key(if_index) {
+                continue;
+            }
+            let network_config = network_config.get_or_insert_with(|| {
+                let config = AppConfig::load().network;
+                self.netstat_strage.set_sampling_mode(config.sampling.mode);
+                config
+            });
+            // Spread the default interface across fanout workers
+            let fanout_workers = if *if_index == self.netstat_strage.get_if_index() {
+                default_fanout_workers()
+            } else {
+                1
+            };
+            if let Some(capture) =
+                CaptureThread::spawn(
+                    iface,
+                    &self.netstat_strage,
+                    &self.capture_ring,
+                    fanout_workers,
+                    network_config.tunnel_attribution,
+                    network_config.sampling.clone(),
+                    network_config.snaplen,
+                )
+            {
+                self.captures.insert(*if_index, capture);
+                changed = true;
+            }
+        }
+        changed
+    }
+    /// Names of the interfaces currently being captured
+    pub fn capture_interfaces(&self) -> Vec<String> {
+        self.captures.values().map(|c| c.if_name.clone()).collect()
+    }
+    pub fn stop_all(&mut self) {
+        for (_, capture) in self.captures.drain() {
+            capture.stop();
+        }
+    }
+}
+
+impl Drop for InterfaceWatcher {
+    fn drop(&mut self) {
+        self.stop_all();
+    }
+}
+
+/// Watch interface and address changes and keep capture state up to date.
+/// Uses rtnetlink notifications on Linux, falls back to polling elsewhere.
+pub fn start_interface_watch(app_handle: tauri::AppHandle, netstat_strage: Arc<NetStatStrage>) {
+    let capture_ring = Arc::clone(&app_handle.state::<Arc<CaptureRing>>());
+    let mut watcher = InterfaceWatcher::new(netstat_strage, capture_ring);
+    watcher.sync();
+    let monitor = ChangeMonitor::new();
+    loop {
+        if !monitor.wait(POLL_INTERVAL) && monitor.is_event_driven() {
+            continue;
+        }
+        // Coalesce bursts (e.g. link up followed by several address events)
+        thread::sleep(DEBOUNCE_INTERVAL);
+        monitor.drain();
+        if watcher.sync() {
+            thread_log!(
+                info,
+                "interfaces changed. capturing on {:?}",
+                watcher.capture_interfaces()
+            );
+            let default_interface = watcher.netstat_strage.get_interface();
+            match app_handle.emit_all("interface_changed", default_interface) {
+                Ok(_) => {}
+                Err(e) => {
+                    thread_log!(error, "Error: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Source of interface change notifications
+enum ChangeMonitor {
+    #[cfg(target_os = "linux")]
+    Netlink(netlink::NetlinkMonitor),
+    Poll,
+}
+
+impl ChangeMonitor {
+    #[cfg(target_os = "linux")]
+    fn new() -> ChangeMonitor {
+        match netlink::NetlinkMonitor::new() {
+            Ok(monitor) => ChangeMonitor::Netlink(monitor),
+            Err(e) => {
+                thread_log!(warn, "rtnetlink unavailable, fallback to polling: {}", e);
+                ChangeMonitor::Poll
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    fn new() -> ChangeMonitor {
+        ChangeMonitor::Poll
+    }
+    fn is_event_driven(&self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            ChangeMonitor::Netlink(_) => true,
+            ChangeMonitor::Poll => false,
+        }
+    }
+    /// Block until a change notification arrives or the timeout expires.
+    /// Returns true if a change was notified
+    fn wait(&self, timeout: Duration) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            ChangeMonitor::Netlink(monitor) => monitor.wait(timeout),
+            ChangeMonitor::Poll => {
+                thread::sleep(timeout);
+                false
+            }
+        }
+    }
+    /// Discard pending notifications
+    fn drain(&self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            ChangeMonitor::Netlink(monitor) => {
+                while monitor.wait(Duration::ZERO) {}
+            }
+            ChangeMonitor::Poll => {}
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::mem;
+    use std::time::Duration;
+
+    /// rtnetlink socket subscribed to link, address and route events
+    pub struct NetlinkMonitor {
+        fd: libc::c_int,
+    }
+
+    impl NetlinkMonitor {
+        pub fn new() -> io::Result<NetlinkMonitor> {
+            unsafe {
+                let fd = libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                );
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut addr: libc::sockaddr_nl = mem::zeroed();
+                addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+                addr.nl_groups = (libc::RTMGRP_LINK
+                    | libc::RTMGRP_IPV4_IFADDR
+                    | libc::RTMGRP_IPV6_IFADDR
+                    | libc::RTMGRP_IPV4_ROUTE
+                    | libc::RTMGRP_IPV6_ROUTE) as u32;
+                let ret = libc::bind(
+                    fd,
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                );
+                if ret < 0 {
+                    let e = io::Error::last_os_error();
+                    libc::close(fd);
+                    return Err(e);
+                }
+                Ok(NetlinkMonitor { fd: fd })
+            }
+        }
+        /// Wait for a netlink message. Returns true if a link/address/route event was received
+        pub fn wait(&self, timeout: Duration) -> bool {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            let ready = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+            if ready <= 0 || pfd.revents & libc::POLLIN == 0 {
+                return false;
+            }
+            let mut buf = [0u8; 8192];
+            let len = unsafe {
+                libc::recv(
+                    self.fd,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len < 0 {
+                // ENOBUFS means events were dropped. Treat it as a change to force resync
+                return io::Error::last_os_error().raw_os_error() == Some(libc::ENOBUFS);
+            }
+            contains_change_event(&buf[..len as usize])
+        }
+    }
+
+    impl Drop for NetlinkMonitor {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    /// Walk the nlmsghdr chain and look for link/address/route messages
+    fn contains_change_event(buf: &[u8]) -> bool {
+        let hdr_len = mem::size_of::<libc::nlmsghdr>();
+        let mut offset = 0;
+        while offset + hdr_len <= buf.len() {
+            let msg_len = u32::from_ne_bytes([
+                buf[offset],
+                buf[offset + 1],
+                buf[offset + 2],
+                buf[offset + 3],
+            ]) as usize;
+            let msg_type = u16::from_ne_bytes([buf[offset + 4], buf[offset + 5]]);
+            if msg_len < hdr_len {
+                break;
+            }
+            match msg_type {
+                libc::RTM_NEWLINK
+                | libc::RTM_DELLINK
+                | libc::RTM_NEWADDR
+                | libc::RTM_DELADDR
+                | libc::RTM_NEWROUTE
+                | libc::RTM_DELROUTE => return true,
+                _ => {}
+            }
+            // NLMSG_ALIGN
+            offset += (msg_len + 3) & !3;
+        }
+        false
+    }
+}
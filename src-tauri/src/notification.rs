@@ -7,6 +7,7 @@ pub enum NotificationType {
     Traffic,
     RemoteHost,
     Protocol,
+    Security,
 }
 
 impl NotificationType {
@@ -15,6 +16,7 @@ impl NotificationType {
             NotificationType::Traffic => "Traffic".to_string(),
             NotificationType::RemoteHost => "Remote Host".to_string(),
             NotificationType::Protocol => "Protocol".to_string(),
+            NotificationType::Security => "Security".to_string(),
         }
     }
 }
@@ -36,6 +36,21 @@ pub fn get_os_type() -> String {
     "macos".to_owned()
 }
 
+/// Resident set size of this process in bytes
+#[cfg(target_os = "linux")]
+pub fn get_process_resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    // e.g. "VmRSS:     12345 kB"
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_process_resident_memory() -> Option<u64> {
+    None
+}
+
 pub fn get_sysdate() -> String {
     let now = chrono::Local::now();
     now.to_rfc3339()
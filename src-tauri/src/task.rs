@@ -1,3 +1,5 @@
+use crate::config::AppConfig;
+use crate::net::ring::CaptureRing;
 use crate::net::stat::{NetStatData, NetStatStrage};
 use crate::thread_log;
 use std::sync::Arc;
@@ -9,10 +11,14 @@ use tauri::Manager;
 pub fn start_netstat_data_update(
     netstat_strage: &mut Arc<NetStatStrage>,
     netstat_data: &mut Arc<Mutex<NetStatData>>,
+    capture_ring: &Arc<CaptureRing>,
     interval: Duration,
 ) {
     let entry_ttl = Duration::from_millis(60000);
+    let rate_windows = AppConfig::load().network.rate_windows;
     let mut last_clear = Instant::now();
+    // Rates use the measured interval, not the nominal one
+    let mut last_merge = Instant::now();
     loop {
         match netstat_data.lock() {
             Ok(mut data) => {
@@ -20,13 +26,23 @@ pub fn start_netstat_data_update(
                     data.remove_old_entries(entry_ttl);
                     last_clear = Instant::now();
                 }
-                data.merge(netstat_strage.clone_data_and_reset(), interval);
+                data.rate_windows = rate_windows;
+                let elapsed = last_merge.elapsed();
+                last_merge = Instant::now();
+                data.merge(netstat_strage.clone_data_and_reset(), elapsed);
+                data.update_interface_counters(
+                    crate::net::interface::get_interface_counters(),
+                    elapsed,
+                );
+                capture_ring.check_triggers(&data);
+                netstat_strage.update_process_security(&data);
             }
             Err(e) => {
                 thread_log!(error, "Error: {:?}", e);
                 continue;
             }
         }
+        capture_ring.finish_expired();
         thread::sleep(interval);
     }
 }
@@ -35,41 +51,32 @@ pub fn start_background_task(handle: &tauri::AppHandle) {
     let netstat_strage = handle.state::<Arc<NetStatStrage>>();
     let netstat_data = handle.state::<Arc<Mutex<NetStatData>>>();
     let thread_handles = handle.state::<Arc<Mutex<Vec<thread::JoinHandle<()>>>>>();
+    let capture_ring = handle.state::<Arc<CaptureRing>>();
+    // For interface watch
+    let netstat_strage_watcher = Arc::clone(&netstat_strage);
     // For socket info update
     let mut netstat_strage_socket = Arc::clone(&netstat_strage);
     // For DNS Map update
     let mut netstat_strage_dns = Arc::clone(&netstat_strage);
+    // For threat feed update
+    let netstat_strage_threat = Arc::clone(&netstat_strage);
+    // For signature rule reload
+    let netstat_strage_rule = Arc::clone(&netstat_strage);
     // For Data update
     let mut netstat_strage_update = Arc::clone(&netstat_strage);
     let mut netstat_data_update = Arc::clone(&netstat_data);
+    let capture_ring_update = Arc::clone(&capture_ring);
 
     // Thread handle management
     let thread_handles = Arc::clone(&thread_handles);
     let mut thread_handles = thread_handles.lock().unwrap();
     //let mut threads: Vec<thread::JoinHandle<()>> = vec![];
-    let usable_interfaces = crate::net::interface::get_usable_interfaces();
-    let mut pcap_thread_index = 0;
-    let pcap_handlers = usable_interfaces
-        .iter()
-        .map(|iface| {
-            let mut netstat_strage_pcap = Arc::clone(&netstat_strage);
-            let iface = iface.clone();
-            let pcap_option = crate::net::pcap::PacketCaptureOptions::from_interface(&iface);
-            let thread_name = format!("pcap-thread-{}", iface.name.clone());
-            let pcap_thread =
-                thread::Builder::new().name(thread_name.clone());
-            let pcap_handler = pcap_thread.spawn(move || {
-                crate::net::pcap::start_background_capture(
-                    pcap_option,
-                    &mut netstat_strage_pcap,
-                    iface,
-                );
-            });
-            thread_log!(info, "start thread {:?}", thread_name);
-            pcap_thread_index += 1;
-            pcap_handler
-        })
-        .collect::<Vec<_>>();
+    // Interface watcher starts and stops per-interface capture threads
+    let watcher_app_handle = handle.clone();
+    let watcher_handler = thread::spawn(move || {
+        thread_log!(info, "start thread interface_watch");
+        crate::net::watcher::start_interface_watch(watcher_app_handle, netstat_strage_watcher);
+    });
 
     let socket_handler = thread::spawn(move || {
         thread_log!(info, "start thread socket_info_update");
@@ -81,26 +88,30 @@ pub fn start_background_task(handle: &tauri::AppHandle) {
         crate::net::dns::start_dns_map_update(&mut netstat_strage_dns);
     });
 
+    let threat_handler = thread::spawn(move || {
+        thread_log!(info, "start thread threat_feed_update");
+        crate::net::threat::start_threat_feed_update(&netstat_strage_threat);
+    });
+
+    let rule_handler = thread::spawn(move || {
+        thread_log!(info, "start thread rule_update");
+        crate::net::signature::start_rule_update(&netstat_strage_rule);
+    });
+
     let update_handler = thread::spawn(move || {
         thread_log!(info, "start thread netstat_data_update");
         start_netstat_data_update(
             &mut netstat_strage_update,
             &mut netstat_data_update,
+            &capture_ring_update,
             Duration::from_secs(1),
         );
     });
 
-    for pcap_handler in pcap_handlers {
-        match pcap_handler {
-            Ok(handle) => {
-                thread_handles.push(handle);
-            }
-            Err(e) => {
-                thread_log!(error, "Error: {:?}", e);
-            }
-        }
-    }
+    thread_handles.push(watcher_handler);
     thread_handles.push(socket_handler);
     thread_handles.push(dns_handler);
+    thread_handles.push(threat_handler);
+    thread_handles.push(rule_handler);
     thread_handles.push(update_handler);
 }
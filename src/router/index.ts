@@ -5,6 +5,7 @@ import Socket from '../components/Socket.vue';
 import Process from '../components/Process.vue';
 import Interface from '../components/Interface.vue';
 import NetRoute from '../components/NetRoute.vue';
+import SecurityEvents from '../components/SecurityEvents.vue';
 
 const routes = [
   {
@@ -32,6 +33,11 @@ const routes = [
     name: 'Process',
     component: Process,
   },
+  {
+    path: '/security',
+    name: 'Security',
+    component: SecurityEvents,
+  },
   {
     path: '/interface',
     name: 'Interface',
@@ -1,3 +1,5 @@
+import { DownloadProgress } from './network';
+
 export interface KVItem {
     key: string,
     value: string,
@@ -16,6 +18,12 @@ export interface AppInfo {
     repository: string,
 }
 
+export interface InitStatus {
+    message: string,
+    // Database problems found while loading. Fallbacks are in use
+    errors: string[],
+}
+
 export interface LoggingConfig {
     level: string,
     file_path: string,
@@ -25,6 +33,24 @@ export interface NetworkConfig {
     interfaces: string[],
     reverse_dns: boolean,
     entry_ttl: number,
+    tunnel_attribution: string,
+    sampling: SamplingConfig,
+    snaplen: number,
+    rate_windows: RateWindows,
+}
+
+export interface RateWindows {
+    short_secs: number,
+    medium_secs: number,
+    long_secs: number,
+}
+
+export interface SamplingConfig {
+    mode: string,
+    rate: number,
+    probability: number,
+    flow_exact_pps: number,
+    flow_max_rate: number,
 }
 
 export interface DisplayConfig {
@@ -39,11 +65,155 @@ export interface PrivacyConfig {
     hide_public_ip_info: boolean,
 }
 
+export interface ConnectionWatch {
+    ip_addr: string | null,
+    port: number | null,
+    protocol: string | null,
+}
+
+export interface CaptureRingConfig {
+    enabled: boolean,
+    max_bytes: number,
+    window_secs: number,
+    post_trigger_secs: number,
+    output_dir: string | null,
+    host_bandwidth_threshold: number,
+    watch_connections: ConnectionWatch[],
+    trigger_cooldown_secs: number,
+    snaplen: number,
+}
+
+export interface DatabaseUpdateConfig {
+    base_url: string,
+}
+
+export type ThreatFeedFormat = 'PlainText' | 'Csv' | 'StixJson';
+
+export interface ThreatFeedConfig {
+    name: string,
+    // Local file path or http(s) URL
+    source: string,
+    // Detected from the file name and content if null
+    format: ThreatFeedFormat | null,
+    enabled: boolean,
+}
+
+export interface ThreatIntelConfig {
+    feeds: ThreatFeedConfig[],
+    // Seconds between downloads of remote feeds
+    refresh_interval: number,
+}
+
+export interface RuleConfig {
+    enabled: boolean,
+    // Value of $HOME_NET
+    home_net: string,
+    // Value of $EXTERNAL_NET
+    external_net: string,
+}
+
+export interface RuleFileStatus {
+    name: string,
+    rule_count: number,
+    // Rules that failed to parse or use unsupported keywords
+    skipped: number,
+    // Line number and reason of the first skipped rules
+    errors: string[],
+    updated: string,
+}
+
+export interface RuleSetStatus {
+    enabled: boolean,
+    dir: string,
+    rule_count: number,
+    files: RuleFileStatus[],
+    loaded: string,
+    error: string | null,
+}
+
+export interface PortScanConfig {
+    enabled: boolean,
+    window_secs: number,
+    // Distinct ports of one local host probed by one source
+    vertical_port_threshold: number,
+    // Distinct local hosts probed on the same port by one source
+    horizontal_host_threshold: number,
+    // Half-open SYNs to one local port from any source
+    syn_flood_threshold: number,
+}
+
+export interface BeaconConfig {
+    enabled: boolean,
+    // Connections needed before a series is scored
+    min_connections: number,
+    min_interval_secs: number,
+    max_interval_secs: number,
+    // Highest interval standard deviation relative to the mean
+    max_jitter: number,
+    max_bytes_per_connection: number,
+    // Lowest score that raises an event, 0.0 to 1.0
+    score_threshold: number,
+    // Process names, IP addresses or host names of known updaters
+    allowlist: string[],
+}
+
+export interface LinkConfig {
+    enabled: boolean,
+    window_secs: number,
+    // Distinct IP addresses claimed by one MAC address within the window
+    mac_ip_threshold: number,
+    // Gratuitous ARP packets on one interface within the window
+    gratuitous_arp_threshold: number,
+    // IP or MAC addresses of the allowed DHCP servers. When empty, the first server seen is trusted
+    dhcp_server_allowlist: string[],
+}
+
+export interface DnsTunnelConfig {
+    enabled: boolean,
+    window_secs: number,
+    // Labels longer than this are suspicious. DNS allows up to 63
+    max_label_length: number,
+    // Shannon entropy in bits per character above which a subdomain looks encoded
+    entropy_threshold: number,
+    // Distinct subdomains of one base domain within the window
+    unique_subdomain_threshold: number,
+    // TXT and NULL queries for one base domain within the window
+    txt_null_threshold: number,
+    // Response bytes for one base domain within the window
+    response_bytes_threshold: number,
+    // Indicators needed to flag a domain
+    min_indicators: number,
+    // Base domains never flagged
+    allowlist: string[],
+}
+
+export interface SecurityConfig {
+    event_cooldown_secs: number,
+    port_scan: PortScanConfig,
+    beacon: BeaconConfig,
+    link: LinkConfig,
+    dns_tunnel: DnsTunnelConfig,
+}
+
+export interface ThreatFeedStatus {
+    name: string,
+    source: string,
+    ip_count: number,
+    domain_count: number,
+    updated: string,
+    error: string | null,
+}
+
 export class AppConfig {
     logging: LoggingConfig;
     network: NetworkConfig;
     display: DisplayConfig;
     privacy: PrivacyConfig;
+    capture_ring: CaptureRingConfig;
+    database: DatabaseUpdateConfig;
+    threat_intel: ThreatIntelConfig;
+    security: SecurityConfig;
+    rules: RuleConfig;
 
     constructor() {
         this.logging = {
@@ -54,6 +224,20 @@ export class AppConfig {
             interfaces: [],
             reverse_dns: false,
             entry_ttl: 60000,
+            tunnel_attribution: 'Outer',
+            sampling: {
+                mode: 'Off',
+                rate: 10,
+                probability: 0.1,
+                flow_exact_pps: 100,
+                flow_max_rate: 1000,
+            },
+            snaplen: 0,
+            rate_windows: {
+                short_secs: 1,
+                medium_secs: 10,
+                long_secs: 60,
+            },
         };
         this.display = {
             top_remote_hosts: 10,
@@ -65,9 +249,142 @@ export class AppConfig {
             hide_private_ip_info: true,
             hide_public_ip_info: true,
         };
+        this.capture_ring = {
+            enabled: true,
+            max_bytes: 33554432,
+            window_secs: 60,
+            post_trigger_secs: 10,
+            output_dir: null,
+            host_bandwidth_threshold: 0,
+            watch_connections: [],
+            trigger_cooldown_secs: 300,
+            snaplen: 0,
+        };
+        this.database = {
+            base_url: 'https://r2.ntap.io',
+        };
+        this.threat_intel = {
+            feeds: [],
+            refresh_interval: 21600,
+        };
+        this.security = {
+            event_cooldown_secs: 300,
+            port_scan: {
+                enabled: true,
+                window_secs: 60,
+                vertical_port_threshold: 20,
+                horizontal_host_threshold: 10,
+                syn_flood_threshold: 200,
+            },
+            beacon: {
+                enabled: true,
+                min_connections: 8,
+                min_interval_secs: 10,
+                max_interval_secs: 3600,
+                max_jitter: 0.2,
+                max_bytes_per_connection: 16384,
+                score_threshold: 0.75,
+                allowlist: ['GoogleUpdate.exe', 'MicrosoftEdgeUpdate.exe', 'OneDrive.exe', 'softwareupdated', 'packagekitd'],
+            },
+            link: {
+                enabled: true,
+                window_secs: 60,
+                mac_ip_threshold: 8,
+                gratuitous_arp_threshold: 30,
+                dhcp_server_allowlist: [],
+            },
+            dns_tunnel: {
+                enabled: true,
+                window_secs: 300,
+                max_label_length: 40,
+                entropy_threshold: 3.8,
+                unique_subdomain_threshold: 60,
+                txt_null_threshold: 20,
+                response_bytes_threshold: 262144,
+                min_indicators: 2,
+                allowlist: ['in-addr.arpa', 'ip6.arpa', 'local', 'akamaiedge.net', 'cloudfront.net', 'amazonaws.com'],
+            },
+        };
+        this.rules = {
+            enabled: true,
+            home_net: '[10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,fc00::/7,fe80::/10]',
+            external_net: '!$HOME_NET',
+        };
     }
 }
 
+export type DatabaseKind = 'As' | 'Country' | 'Ipv4Asn' | 'Ipv6Asn' | 'Ipv4Country' | 'Ipv6Country' | 'Oui' | 'TcpService' | 'UdpService';
+
+export interface DatabaseStatus {
+    kind: DatabaseKind,
+    file_name: string,
+    source: 'Embedded' | 'File',
+    path: string,
+    version: string,
+    build_date: string,
+    sha256: string,
+}
+
+export interface DatabaseUpdateInfo {
+    kind: DatabaseKind,
+    file_name: string,
+    installed_version: string | null,
+    available_version: string,
+    build_date: string,
+    size: number,
+    update_available: boolean,
+}
+
+export interface DatabaseUpdateProgress {
+    file_name: string,
+    progress: DownloadProgress,
+}
+
+export interface DatabaseUpdateResult {
+    updated: DatabaseKind[],
+    failed: [string, string][],
+}
+
+export type IpImportFormat = 'Mmdb' | 'GeoIpCsv' | 'Ip2AsnTsv' | 'Ip2LocationCsv';
+
+export interface IpImportOptions {
+    format: IpImportFormat | null,
+    path: string,
+    locations_path: string | null,
+}
+
+export interface IpImportSummary {
+    ipv4_country_ranges: number,
+    ipv6_country_ranges: number,
+    ipv4_asn_ranges: number,
+    ipv6_asn_ranges: number,
+    ipv4_geo_ranges: number,
+    ipv6_geo_ranges: number,
+    countries: number,
+    autonomous_systems: number,
+    locations: number,
+}
+
+export interface TableMemoryUsage {
+    name: string,
+    entries: number,
+    bytes: number,
+}
+
+export interface IpDatabaseMemoryReport {
+    tables: TableMemoryUsage[],
+    total_bytes: number,
+    process_resident_bytes: number | null,
+}
+
+export interface LookupBenchmark {
+    iterations: number,
+    ipv4_ns_per_lookup: number,
+    ipv6_ns_per_lookup: number,
+    ipv4_hits: number,
+    ipv6_hits: number,
+}
+
 export class DatabaseConfig {
     ipv4_asn_db_path: string;
     ipv6_asn_db_path: string;
@@ -76,6 +393,9 @@ export class DatabaseConfig {
     country_db_path: string
     asn_db_path: string;
     tcp_service_db_path: string;
+    base_url: string;
+    databases: DatabaseStatus[];
+    imported_db_path: string;
 
     constructor() {
         this.ipv4_asn_db_path = '';
@@ -85,5 +405,8 @@ export class DatabaseConfig {
         this.country_db_path = '';
         this.asn_db_path = '';
         this.tcp_service_db_path = '';
+        this.base_url = '';
+        this.databases = [];
+        this.imported_db_path = '';
     }
 }
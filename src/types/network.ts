@@ -116,7 +116,27 @@ export interface PacketFrame {
     ip: IpLayer | null,
     transport: TransportLayer | null,
     packet_len: number,
+    vlan_ids: number[],
+    tunnel: TunnelInfo | null,
+    fragment: FragmentInfo | null,
     timestamp: string,
+    sample_weight: number,
+}
+
+export interface DissectNode {
+    name: string,
+    value: string,
+    offset: number,
+    len: number,
+    children: DissectNode[],
+}
+
+export interface PacketDetail {
+    capture_no: number,
+    if_name: string,
+    timestamp: string,
+    raw: number[],
+    tree: DissectNode[],
 }
 
 export interface PacketDisplayData {
@@ -227,6 +247,12 @@ export class TrafficInfo {
         }
     }
 
+    // Smoothed rates followed by the average and the peak
+    public static format_bytes_rate(rate: RateStats): string {
+        const fmt = (value: number) => TrafficInfo.format_bytes(Math.round(value)) + "/s";
+        return `${fmt(rate.short)} / ${fmt(rate.medium)} / ${fmt(rate.long)} (avg ${fmt(rate.average)}, peak ${fmt(rate.peak)})`;
+    }
+
     public formatted_total_bytes(): string {
         return TrafficInfo.format_bytes(this.total_bytes());
     }
@@ -240,6 +266,14 @@ export class TrafficInfo {
     }
 }
 
+export interface RateStats {
+    short: number,
+    medium: number,
+    long: number,
+    peak: number,
+    average: number,
+}
+
 export interface TrafficDisplayInfo {
     packet_sent: number,
     packet_received: number,
@@ -249,6 +283,10 @@ export interface TrafficDisplayInfo {
     egress_bytes_per_sec: number,
     ingress_packets_per_sec: number,
     ingress_bytes_per_sec: number,
+    egress_packets_rate: RateStats,
+    egress_bytes_rate: RateStats,
+    ingress_packets_rate: RateStats,
+    ingress_bytes_rate: RateStats,
     formatted_sent_bytes: string,
     formatted_received_bytes: string,
     formatted_total_bytes: string,
@@ -302,7 +340,14 @@ export interface HostDisplayInfo {
     country_name: string,
     asn: number,
     as_name: string,
+    city: string,
+    latitude: number | null,
+    longitude: number | null,
+    organization: string,
     traffic: TrafficDisplayInfo,
+    label: IpLabel | null,
+    // Threat feeds listing the address or host name
+    threat_feeds: string[],
 }
 
 export interface ServiceDisplayInfo {
@@ -316,6 +361,7 @@ export enum NotificationType {
     Traffic,
     RemoteHost,
     Protocol,
+    Security,
 }
 
 export interface Notification {
@@ -325,14 +371,300 @@ export interface Notification {
     timestamp: string,
 }
 
+export interface InterfaceCounters {
+    rx_bytes: number,
+    rx_packets: number,
+    rx_errors: number,
+    rx_dropped: number,
+    rx_multicast: number,
+    tx_bytes: number,
+    tx_packets: number,
+    tx_errors: number,
+    tx_dropped: number,
+}
+
+export interface InterfaceCounterInfo {
+    counters: InterfaceCounters,
+    rx_bytes_per_sec: number,
+    tx_bytes_per_sec: number,
+    rx_packets_per_sec: number,
+    tx_packets_per_sec: number,
+}
+
+export interface InterfaceTrafficDisplayInfo {
+    name: string,
+    traffic: TrafficDisplayInfo,
+    counters: InterfaceCounterInfo | null,
+}
+
+export interface VlanTrafficDisplayInfo {
+    vlan_id: number,
+    traffic: TrafficDisplayInfo,
+}
+
+export interface TunnelInfo {
+    tunnel_type: 'Gre' | 'Vxlan' | 'Geneve' | 'IpInIp' | 'WireGuard',
+    outer_src: string,
+    outer_dst: string,
+    vni: number | null,
+    decapsulated: boolean,
+}
+
+export interface FragmentInfo {
+    src: string,
+    dst: string,
+    id: number,
+    protocol: number,
+    offset: number,
+    length: number,
+    more_fragments: boolean,
+}
+
+export interface CaptureStats {
+    if_name: string,
+    backend: 'Datalink' | 'PacketMmap',
+    packets: number,
+    drops: number,
+    freeze_q_cnt: number,
+}
+
+export type CaptureSink =
+    | 'Events'
+    | { Pcapng: { path: string } }
+    | { EventsAndPcapng: { path: string } };
+
+export interface CaptureSessionOptions {
+    interface_name: string | null,
+    src_ips: string[],
+    dst_ips: string[],
+    src_ports: number[],
+    dst_ports: number[],
+    ether_types: string[],
+    ip_protocols: string[],
+    promiscuous: boolean,
+    snaplen: number,
+    max_packets: number | null,
+    max_bytes: number | null,
+    duration_secs: number | null,
+    sink: CaptureSink,
+}
+
+export interface CaptureSessionStats {
+    packets_received: number,
+    bytes_received: number,
+    packets_captured: number,
+    bytes_captured: number,
+    packets_truncated: number,
+    packets_written: number,
+    bytes_written: number,
+}
+
+export interface CaptureSessionInfo {
+    id: number,
+    if_name: string,
+    options: CaptureSessionOptions,
+    state: 'Running' | 'Stopped' | 'Completed' | 'Failed',
+    error: string | null,
+    stats: CaptureSessionStats,
+    start_time: string,
+    end_time: string,
+}
+
+export interface CaptureFrame {
+    session_id: number,
+    frame: PacketFrame,
+}
+
+export type RingTrigger =
+    | 'Manual'
+    | { HostBandwidth: { ip_addr: string, bytes_per_sec: number } }
+    | { Connection: { connection: SocketConnection } };
+
+export interface RingDumpInfo {
+    id: number,
+    if_name: string,
+    trigger: RingTrigger,
+    path: string,
+    state: 'Recording' | 'Completed' | 'Failed',
+    error: string | null,
+    pre_trigger_packets: number,
+    packets: number,
+    bytes: number,
+    trigger_time: string,
+    end_time: string,
+}
+
 export interface Overview {
     //default_interface: NetworkInterface,
     //captured_packets: number,
     traffic: TrafficDisplayInfo,
+    interfaces: InterfaceTrafficDisplayInfo[],
+    // False if kernel interface counters cannot be read on this platform
+    interface_counters_available: boolean,
+    capture_stats: CaptureStats[],
     top_processes: ProcessDisplayInfo[],
     top_remote_hosts: HostDisplayInfo[],
     top_app_protocols: ServiceDisplayInfo[],
     notifications: Notification[],
+    // Recent threat feed matches, most recent first
+    threat_hits: ThreatHit[],
+    sampling: SamplingInfo,
+}
+
+export type SecurityEventKind = 'VerticalScan' | 'HorizontalSweep' | 'SynFlood' | 'Beaconing'
+    | 'GatewayMacChange' | 'ArpSpoofing' | 'GratuitousArpFlood' | 'RogueDhcpServer'
+    | 'DnsTunneling';
+
+export type Severity = 'Low' | 'Medium' | 'High';
+
+export interface SecurityEvent {
+    id: number,
+    kind: SecurityEventKind,
+    severity: Severity,
+    title: string,
+    description: string,
+    // Remote address the activity came from
+    source_ip_addr: string | null,
+    // Local address the activity was aimed at
+    target_ip_addr: string | null,
+    // Link layer address the activity came from
+    source_mac_addr: string | null,
+    // Interface the activity was seen on, for link layer events
+    if_name: string | null,
+    // Domain the activity involved
+    domain: string | null,
+    evidence: string[],
+    first_seen: string,
+    last_seen: string,
+    count: number,
+}
+
+export interface BeaconKey {
+    process_name: string | null,
+    local_ip_addr: string,
+    remote_ip_addr: string,
+    remote_port: number,
+    protocol: string,
+}
+
+export interface IntervalStats {
+    count: number,
+    mean_secs: number,
+    // Standard deviation of the intervals relative to the mean
+    jitter: number,
+    min_secs: number,
+    max_secs: number,
+}
+
+export interface BeaconInfo {
+    key: BeaconKey,
+    stats: IntervalStats,
+    mean_bytes: number,
+    // null when the series is outside the configured limits
+    score: number | null,
+    allowlisted: boolean,
+}
+
+export interface DnsDomainKey {
+    // null when the process was not resolved
+    process_name: string | null,
+    base_domain: string,
+}
+
+export interface DnsDomainInfo {
+    key: DnsDomainKey,
+    queries: number,
+    unique_subdomains: number,
+    // Unique subdomains per minute
+    unique_subdomain_rate: number,
+    longest_label: number,
+    // Highest entropy of a subdomain long enough to be scored
+    max_entropy: number,
+    txt_null_queries: number,
+    response_bytes: number,
+    // Names of the triggered heuristics
+    indicators: string[],
+    // Queries with the longest or most random subdomains
+    sample_queries: string[],
+    allowlisted: boolean,
+}
+
+export type RuleAction = 'Alert' | 'Log' | 'Drop' | 'Reject';
+
+export type RuleProtocol = 'Ip' | 'Tcp' | 'Udp' | 'Icmp';
+
+export interface RuleMatch {
+    sid: number,
+    rev: number,
+    msg: string,
+    action: RuleAction,
+    protocol: RuleProtocol,
+    src_ip_addr: string,
+    src_port: number | null,
+    dst_ip_addr: string,
+    dst_port: number | null,
+    // Last matching packet
+    packet: PacketFrame,
+    // Leading bytes of the packet payload
+    payload: number[],
+    first_seen: string,
+    last_seen: string,
+    count: number,
+}
+
+export type ThreatSource = 'RemoteHost' | 'Dns' | 'Sni' | 'ReverseDns';
+
+export interface ThreatHit {
+    // Network or domain listed in the feeds
+    indicator: string,
+    // Address or name seen in traffic
+    observed: string,
+    source: ThreatSource,
+    feeds: string[],
+    local_ip_addr: string | null,
+    remote_ip_addr: string,
+    first_seen: string,
+    last_seen: string,
+    count: number,
+}
+
+export interface SamplingInfo {
+    mode: string,
+    estimated: boolean,
+    effective_rate: number,
+    seen_packets: number,
+    sampled_packets: number,
+}
+
+export interface IpLabel {
+    cidr: string,
+    name: string,
+    tags: string[],
+}
+
+export interface LabelTrafficDisplayInfo {
+    name: string,
+    cidrs: string[],
+    tags: string[],
+    host_count: number,
+    traffic: TrafficDisplayInfo,
+    top_hosts: HostDisplayInfo[],
+}
+
+export interface CountryTrafficDisplayInfo {
+    country_code: string,
+    country_name: string,
+    host_count: number,
+    traffic: TrafficDisplayInfo,
+    top_hosts: HostDisplayInfo[],
+}
+
+export interface AsTrafficDisplayInfo {
+    asn: number,
+    as_name: string,
+    host_count: number,
+    traffic: TrafficDisplayInfo,
+    top_hosts: HostDisplayInfo[],
 }
 
 export interface IpInfo {
@@ -397,6 +729,8 @@ export interface SocketDisplayInfo {
     ip_version: string,
     process: ProcessInfo | null,
     traffic: TrafficDisplayInfo,
+    local_label: IpLabel | null,
+    remote_label: IpLabel | null,
 }
 
 export interface IpInfoDisplayData {
@@ -428,3 +762,85 @@ export interface NetRoute {
     netmask: string,
     gateway: string,
 }
+
+export interface PortScanOption {
+    targets: string[],
+    ports: string,
+    scan_type: string,
+    concurrency: number,
+    timeout: number,
+    banner_grab: boolean,
+}
+
+export interface ScannedPort {
+    port: number,
+    protocol: string,
+    status: string,
+    service_name: string,
+    banner: string | null,
+}
+
+export interface HostScanResult {
+    ip_addr: string,
+    hostname: string,
+    ports: ScannedPort[],
+}
+
+export interface PortScanResult {
+    scan_id: number,
+    hosts: HostScanResult[],
+    probes: number,
+    cancelled: boolean,
+    start_time: string,
+    end_time: string,
+    duration: { secs: number, nanos: number },
+}
+
+export interface PortScanProgress {
+    scan_id: number,
+    completed: number,
+    total: number,
+    ip_addr: string | null,
+    port: number | null,
+    status: string | null,
+}
+
+export interface SocketConnection {
+    interface_name: string,
+    local_ip_addr: string,
+    local_port: number,
+    remote_ip_addr: string,
+    remote_port: number,
+    protocol: 'TCP' | 'UDP',
+}
+
+export type StreamFormat = 'Ascii' | 'Hex' | 'Raw';
+
+export interface StreamSummary {
+    packets: number,
+    bytes_sent: number,
+    bytes_received: number,
+    stored_bytes: number,
+    truncated: boolean,
+    retransmissions: number,
+    out_of_order: number,
+    missing_bytes: number,
+    finished: boolean,
+}
+
+export interface StreamChunkView {
+    direction: 'Egress' | 'Ingress',
+    timestamp: string,
+    text: string,
+}
+
+export interface StreamView {
+    connection: SocketConnection,
+    summary: StreamSummary,
+    chunks: StreamChunkView[],
+}
+
+export interface StreamProgress {
+    id: number,
+    summary: StreamSummary,
+}